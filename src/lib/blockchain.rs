@@ -1,252 +1,1282 @@
 
 use chrono;
 
-use lib::hasher::*;
-use lib::transaction::Transaction;
-use std::collections::BTreeSet;
-use std::collections::HashSet;
+use lib::error::{Error, Result};
+use lib::encoding;
+use lib::genesis::Genesis;
+use lib::hasher::{Hasher, HashAlgorithm, Domain};
+use lib::memory::{self, Caps, Usage};
+use lib::merkle::{self, ProofStep};
+use lib::selection::{self, Strategy, TransactionSelector};
+use lib::state::State;
+use lib::transaction::{Transaction, Address, REWARD};
+use lib::forks::{self, Deployment};
+use lib::versions::{Policy, FIRST_VERSION, rules_version};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::mem;
+use std::sync::Arc;
+use std::thread;
 use self::chrono::offset::Utc;
-use url::{Url};
 
-pub type Chain = BTreeSet<Block>;
+pub use lib::chain::Chain;
+
+///
+/// The outcome of checking a chain from genesis
+/// 
+/// length: Blocks checked
+/// valid: Blocks from genesis that are valid
+/// error: What's wrong with the first invalid block, if there is one
+/// 
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Verification {
+    pub length: usize,
+    pub valid: usize,
+    pub error: Option<String>
+}
 
 ///
 /// The representation of a block chain.
 /// 
-/// chain: An ordered set of `Block`s
-/// nodes: Other nodes in the network
+/// chain: An ordered set of `Block`s. Clones are cheap snapshots
 /// current_transactions: Transactions that will be added to the next block
-/// difficulty: Mining difficulty 3=hashes that start with '000'
+/// difficulty: Mining difficulty for the next block 3=hashes that start with '000'
+/// miner_address: Who receives the reward for blocks this node mines
+/// hasher: The digest used for block hashes, transaction hashes and Merkle roots
+/// state: Balances at the tip of the chain. Kept up to date as blocks are added
+/// orphans: Blocks we abandoned when our chain was replaced. Kept (up to
+///          `MAX_ORPHANS`, and `caps.orphans` bytes) so forks can be shown,
+///          never validated or built on
+/// incoming: The heights of the blocks paying each address, lowest first. Kept
+///           up to date like `state`
+/// transactions: The height of the block each transaction id is in. Kept up to
+///               date like `incoming`
+/// prune_depth: Blocks more than this far below the tip are dropped from
+///              `incoming` and `transactions`, and scanned when looked up. None
+///              indexes every block
+/// pruned: The height of the highest block dropped from the indexes, 0 if none are
+/// policy: Which version blocks at each height are, and the rules of each, as
+///         configured
+/// deployments: Versions activated by miners signaling for them, see `lib::forks`
+/// active: `policy`, with the deployments our chain has locked in. Kept up to
+///         date like `state`
+/// hashes: The hash of each block of `chain`, in the same order, so they're
+///         worked out once rather than whenever a block is looked up or compared.
+///         Worked out again when the chain is replaced or the policy changes
+///         which hasher a version uses
+/// by_hash: Where each hash is in `hashes`
+/// work: The `block_work` of every block of `chain`, added up as blocks are added
+/// caps: How much the mempool and orphans may hold, and how big a peer's chain
+///       may be, see `lib::memory`
+/// chain_bytes: The `memory::block_bytes` of every block of `chain`, added up like
+///              `work`
+/// arrivals: When each of `current_transactions` arrived, counting up from 0.
+///           Ones requeued from abandoned blocks have none, and count as oldest
+/// selector: Picks the transactions for blocks we mine, see `lib::selection`
+/// max_block_transactions: The most transactions (not counting the reward) a
+///                         block we mine holds. None holds every one pending
 /// 
 #[derive(Debug)]
 pub struct Blockchain {
     chain: Chain,
     //not a lot of sorted options in stdlib...
     current_transactions: BTreeSet<Transaction>,
-    nodes: HashSet<Url>,
-    difficulty: u64
+    difficulty: u64,
+    miner_address: Address,
+    hasher: HashAlgorithm,
+    state: State,
+    orphans: BTreeSet<Block>,
+    incoming: HashMap<Address, Vec<usize>>,
+    transactions: HashMap<String, usize>,
+    prune_depth: Option<usize>,
+    pruned: usize,
+    policy: Policy,
+    deployments: Vec<Deployment>,
+    active: Policy,
+    hashes: Vec<String>,
+    by_hash: HashMap<String, usize>,
+    work: u64,
+    caps: Caps,
+    chain_bytes: usize,
+    arrivals: BTreeMap<Transaction, u64>,
+    next_arrival: u64,
+    selector: Arc<TransactionSelector>,
+    max_block_transactions: Option<usize>
+}
+
+///
+/// Blocks mined below this difficulty are never valid (0 would be free to mine).
+/// A peer's new blocks also have to meet our difficulty, see `meets_difficulty`
+/// 
+pub const MIN_DIFFICULTY: u64 = 1;
+
+///
+/// The most `set_difficulty` allows. Past it `block_work` saturates, and a miner
+/// would grind for years, holding the lock on the chain all the while
+/// 
+pub const MAX_DIFFICULTY: u64 = 10;
+
+///
+/// Reward recipient until one is configured
+/// 
+pub const DEFAULT_MINER_ADDRESS: &'static str = "my node address";
+
+///
+/// How many abandoned blocks to remember. The lowest are forgotten first
+/// 
+pub const MAX_ORPHANS: usize = 100;
+
+///
+/// The timestamp of every genesis block, so nodes started with the same
+/// difficulty, hasher and genesis file start from the same block
+/// 
+pub const GENESIS_TIMESTAMP: i64 = 1508000000;
+
+///
+/// How many threads hash the blocks of a chain being verified
+/// 
+pub const VALIDATION_THREADS: usize = 4;
+
+///
+/// Chains shorter than this are hashed on the calling thread, it isn't worth
+/// starting threads for
+/// 
+pub const PARALLEL_MIN_BLOCKS: usize = 64;
+
+///
+/// The work a block of `difficulty` proves, the hashes it takes to find on
+/// average: 64 to the power of the difficulty, since hashes are base64. Too
+/// much work to count saturates
+/// 
+pub fn block_work(difficulty: u64) -> u64 {
+    if difficulty > 10 {
+        u64::max_value()
+    } else {
+        1 << (6 * difficulty)
+    }
+}
+
+///
+/// The work `chain` proves, going by the difficulty each block says it was
+/// mined at. Only a valid chain's blocks are known to meet it
+/// 
+pub fn chain_work(chain: &[Block]) -> u64 {
+    chain.iter().fold(0, |work: u64, block| work.saturating_add(block_work(block.header.difficulty)))
+}
+
+//what's worked out for a block that doesn't depend on any other block
+struct Digests {
+    hash: String,
+    merkle_root: String
+}
+
+impl Digests {
+    fn of(header_hasher: HashAlgorithm, hasher: HashAlgorithm, block: &Block) -> Digests {
+        Digests { hash: header_hasher.hash(Domain::Block, &block.header), merkle_root: merkle::root(&hasher, &block.transactions) }
+    }
 }
 
 ///
 /// Represents a `Block` on the chain.
 /// 
+/// header: Everything that gets hashed
+/// transactions: The body. Committed to by the header's `merkle_root`
+/// 
 #[derive(Debug)]
+#[derive(Clone)]
 #[derive(Serialize, Deserialize)]
 #[derive(PartialEq, Eq, PartialOrd, Ord)]
 pub struct Block {
+    pub header: BlockHeader,
+    pub transactions: BTreeSet<Transaction>
+}
+
+///
+/// The header of a `Block`. Hashing, proof of work and linking blocks together
+/// only ever look at the header, so a chain of headers can be synced and checked
+/// without any transactions.
+/// 
+/// index: Position in the chain, starting at 1 for the genesis block
+/// previous_hash: Hash of the previous block's header
+/// merkle_root: Merkle root of the block's transactions
+/// state_root: Hash of the balances after applying the block's transactions
+/// timestamp: When the block was created (unix seconds)
+/// difficulty: The difficulty the block was mined at
+/// nonce: Makes the header's hash meet the difficulty
+/// version: The version of the rules the block follows, see `lib::versions`.
+///          Headers from before versions existed are `FIRST_VERSION`
+/// 
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Serialize, Deserialize)]
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+pub struct BlockHeader {
     pub index: usize,
-    pub timestamp: i64,
-    pub proof: u64,
     pub previous_hash: String,
-    pub transactions: BTreeSet<Transaction>
+    pub merkle_root: String,
+    pub state_root: String,
+    pub timestamp: i64,
+    pub difficulty: u64,
+    pub nonce: u64,
+    #[serde(default = "first_version")]
+    pub version: u64
+}
+
+fn first_version() -> u64 {
+    FIRST_VERSION
+}
+
+impl Block {
+    ///
+    /// The mining reward paid out in this block (the genesis block has none)
+    /// 
+    pub fn reward(&self) -> Option<&Transaction> {
+        self.transactions.iter().find(|txn| txn.is_reward())
+    }
+
+    ///
+    /// Bytes in its canonical encoding, as stored
+    /// 
+    pub fn size(&self) -> usize {
+        encoding::encode(self).len()
+    }
 }
 
-impl Blockchain {
+impl Blockchain {
+
+    #[cfg(test)]
+    pub fn new() -> Blockchain {
+        Self::new_with(3)
+    }
+    pub fn new_with(difficulty: u64) -> Blockchain {
+        Self::new_with_hasher(difficulty, HashAlgorithm::default())
+    }
+
+    pub fn new_with_hasher(difficulty: u64, hasher: HashAlgorithm) -> Blockchain {
+        Self::new_with_genesis(difficulty, hasher, &Genesis::default())
+    }
+
+    ///
+    /// A chain whose genesis block pays out `genesis`'s allocations, so they
+    /// can be spent straight away
+    /// 
+    pub fn new_with_genesis(difficulty: u64, hasher: HashAlgorithm, genesis: &Genesis) -> Blockchain {
+        let mut blockchain = Blockchain {
+            chain: Chain::new(),
+            current_transactions: BTreeSet::new(),
+            difficulty: difficulty,
+            miner_address: DEFAULT_MINER_ADDRESS.into(),
+            hasher: hasher,
+            state: State::new(),
+            orphans: BTreeSet::new(),
+            incoming: HashMap::new(),
+            transactions: HashMap::new(),
+            prune_depth: None,
+            pruned: 0,
+            policy: Policy::new(),
+            deployments: Vec::new(),
+            active: Policy::new(),
+            hashes: Vec::new(),
+            by_hash: HashMap::new(),
+            work: 0,
+            caps: Caps::default(),
+            chain_bytes: 0,
+            arrivals: BTreeMap::new(),
+            next_arrival: 0,
+            selector: Arc::new(Strategy::default()),
+            max_block_transactions: None
+        };
+        blockchain.current_transactions.extend(genesis.transactions());
+        let mut block = blockchain.create_block(100, String::from("Genesis block."));
+        block.header.timestamp = GENESIS_TIMESTAMP;
+        blockchain.add_block(block);
+        blockchain
+    }
+    
+    ///
+    /// Add a new transaction
+    /// 
+    /// returns: the index of the block it will be added to
+    pub fn new_transaction(&mut self, transaction: Transaction) -> usize {        
+        if !self.current_transactions.contains(&transaction) {
+            self.arrivals.insert(transaction.clone(), self.next_arrival);
+            self.next_arrival += 1;
+        }
+        self.current_transactions.insert(transaction);
+        //It will be added to the index of the next block
+        self.last_block().header.index + 1
+    }
+
+    ///
+    /// Mine a new block
+    /// 
+   pub fn mine(&mut self) -> Result<&Block> {
+        //Give ourselves the new coin (block?) in the block we're about to mine
+        self.drop_expired();
+        let mut block = self.preview();
+        // We run the proof of work algorithm to get the nonce...
+        block.header.nonce = self.proof_of_work(&block.header);
+        //Got it. Forge the new Block by adding it to the chain
+        Ok(self.add_block(block))
+    }
+
+    ///
+    /// The block `mine` would mine now, before its proof of work: the pending
+    /// transactions that haven't expired, as many as `selector` picks, and our
+    /// reward. A reward someone else put in the pool isn't, it'd make the block
+    /// invalid. Nothing changes
+    /// 
+    pub fn preview(&self) -> Block {
+        let next = self.chain.len() + 1;
+        let mut pending: Vec<&Transaction> = self.current_transactions.iter().filter(|txn| !txn.is_expired(next) && !txn.is_reward()).collect();
+        pending.sort_by_key(|txn| self.arrivals.get(*txn).cloned().unwrap_or(0));
+        let limit = self.max_block_transactions.unwrap_or(pending.len());
+        let previous_hash = self.hash_last_block();
+        let mut txns: BTreeSet<Transaction> = self.selector
+                                                  .select(&pending, limit, selection::seed(&previous_hash))
+                                                  .into_iter()
+                                                  .take(limit)
+                                                  .cloned()
+                                                  .collect();
+        txns.insert(Transaction::reward(self.miner_address.clone()));
+        self.create_block_of(txns, 0, previous_hash)
+    }
+
+    ///
+    /// Pick the transactions for blocks we mine with `selector` from now on
+    /// 
+    pub fn set_selector(&mut self, selector: Arc<TransactionSelector>) {
+        self.selector = selector;
+    }
+
+    ///
+    /// The most transactions a block we mine holds, besides the reward. None
+    /// holds every one pending
+    /// 
+    pub fn set_max_block_transactions(&mut self, max: Option<usize>) {
+        self.max_block_transactions = max;
+    }
+
+    ///
+    /// Drop blocks more than `depth` below the tip from the indexes looked up by
+    /// `find_transaction` and `incoming`, which scan those blocks instead. None
+    /// indexes every block
+    /// 
+    pub fn set_prune_depth(&mut self, depth: Option<usize>) {
+        self.prune_depth = depth;
+        self.reindex();
+    }
+
+    ///
+    /// The height of the highest block dropped from the indexes, 0 if none are
+    /// 
+    pub fn pruned(&self) -> usize {
+        self.pruned
+    }
+
+    ///
+    /// Add a block mined elsewhere (or earlier) on top of our chain. It must link
+    /// to our tip, have a valid proof, merkle root and state root, no expired
+    /// transactions, and at most one reward
+    /// 
+    pub fn append(&mut self, block: Block) -> Result<&Block> {
+        if block.header.index != self.len() + 1 {
+            return Err(Error::Validation(format!("block {} doesn't go on top of block {}", block.header.index, self.len())));
+        }
+        if let Err(problem) = self.active.check(block.header.index, block.header.version) {
+            return Err(Error::Validation(format!("block {}: {}", block.header.index, problem)));
+        }
+        let mut state = self.state.clone();
+        state.apply_block(&block);
+        let digests = Digests::of(self.header_hasher(&block.header), self.hasher, &block);
+        if !check_hash(&self.hash_last_block(), &block) || !check_proof(&block, &digests.hash) ||
+           !check_merkle_root(&block, &digests.merkle_root) || !check_state_root(&self.policy, self.hasher, &block, &state) ||
+           !check_expiry(&block) || !check_rewards(&block) {
+            return Err(Error::Validation(format!("block {} is invalid", block.header.index)));
+        }
+        Ok(self.add_block(block))
+    }
+
+    ///
+    /// Who receives the reward for blocks this node mines
+    /// 
+    pub fn miner_address(&self) -> &Address {
+        &self.miner_address
+    }
+
+    ///
+    /// Change who receives the reward for subsequently mined blocks
+    /// 
+    pub fn set_miner_address(&mut self, miner_address: Address) {
+        self.miner_address = miner_address;
+    }
+
+    ///
+    /// Transactions that will be added to the next block
+    /// 
+    pub fn current_transactions(&self) -> &BTreeSet<Transaction> {
+        &self.current_transactions
+    }
+
+    ///
+    /// How much the mempool and orphans may hold, and how big a peer's chain may be
+    /// 
+    pub fn caps(&self) -> &Caps {
+        &self.caps
+    }
+
+    ///
+    /// Change the caps, dropping whatever's over the new ones straight away
+    /// 
+    pub fn set_caps(&mut self, caps: Caps) {
+        self.caps = caps;
+        self.trim_mempool();
+        self.trim_orphans();
+    }
+
+    ///
+    /// Approximately how much the chain, the mempool and the orphans hold. The
+    /// chain's is kept up to date, the others are added up when asked
+    /// 
+    pub fn usage(&self) -> Usage {
+        Usage {
+            chain: self.chain_bytes,
+            mempool: memory::transactions_bytes(&self.current_transactions),
+            orphans: memory::blocks_bytes(&self.orphans)
+        }
+    }
+
+    //transactions have no fees to rank them by, the last in the set's order go first
+    fn trim_mempool(&mut self) {
+        let mut bytes = memory::transactions_bytes(&self.current_transactions);
+        while bytes > self.caps.mempool {
+            let last = self.current_transactions.iter().next_back().cloned().expect("invariant: over the cap");
+            bytes -= memory::transaction_bytes(&last);
+            warn!("Dropping transaction {} -> {} {}. The mempool is over {} bytes", last.sender, last.recipient, last.amount, self.caps.mempool);
+            self.current_transactions.remove(&last);
+        }
+    }
+
+    fn trim_orphans(&mut self) {
+        let mut bytes = memory::blocks_bytes(&self.orphans);
+        while self.orphans.len() > MAX_ORPHANS || bytes > self.caps.orphans {
+            let lowest = self.orphans.iter().next().cloned().expect("invariant: over a cap");
+            bytes -= memory::block_bytes(&lowest);
+            self.orphans.remove(&lowest);
+        }
+    }
+
+    ///
+    /// Drop every pending transaction
+    /// 
+    /// returns: How many were dropped
+    pub fn clear_transactions(&mut self) -> usize {
+        self.arrivals.clear();
+        mem::replace(&mut self.current_transactions, BTreeSet::new()).len()
+    }
+
+    ///
+    /// Mining difficulty for the next block
+    /// 
+    pub fn difficulty(&self) -> u64 {
+        self.difficulty
+    }
+
+    ///
+    /// Change the difficulty subsequently mined blocks are mined at, from
+    /// `MIN_DIFFICULTY` to `MAX_DIFFICULTY`. Each block records its own
+    /// difficulty, so blocks already mined stay valid.
+    /// 
+    pub fn set_difficulty(&mut self, difficulty: u64) -> Result<()> {
+        if difficulty < MIN_DIFFICULTY {
+            return Err(Error::Validation(format!("Difficulty must be at least {}", MIN_DIFFICULTY)));
+        }
+        if difficulty > MAX_DIFFICULTY {
+            return Err(Error::Validation(format!("Difficulty must be at most {}", MAX_DIFFICULTY)));
+        }
+        self.difficulty = difficulty;
+        Ok(())
+    }
+
+    ///
+    /// Which version blocks at each height are, and the rules of each, including
+    /// deployments locked in on our chain
+    /// 
+    pub fn policy(&self) -> &Policy {
+        &self.active
+    }
+
+    ///
+    /// Check and mine blocks by `policy` from now on. Blocks already on the chain
+    /// must be the versions it says, so an upgrade can't activate below the tip
+    /// 
+    pub fn set_policy(&mut self, mut policy: Policy) -> Result<()> {
+        for deployment in self.deployments.iter() {
+            policy.define(deployment.version, deployment.rules.clone())?;
+        }
+        let active = self.lock_ins(&policy, &self.chain);
+        if let Some(block) = self.chain.iter().find(|block| active.version_at(block.header.index) != rules_version(block.header.version)) {
+            return Err(Error::Validation(format!("Block {} is version {}, the policy says {}", block.header.index, rules_version(block.header.version), active.version_at(block.header.index))));
+        }
+        self.policy = policy;
+        self.active = active;
+        self.rehash();
+        Ok(())
+    }
+
+    ///
+    /// Versions activated by miners signaling for them
+    /// 
+    pub fn deployments(&self) -> &[Deployment] {
+        &self.deployments
+    }
+
+    ///
+    /// Signal for `deployment` in the blocks we mine, and activate it once enough
+    /// blocks do. Its version must be above every other deployment's
+    /// 
+    pub fn add_deployment(&mut self, deployment: Deployment) -> Result<()> {
+        deployment.check()?;
+        if let Some(other) = self.deployments.iter().find(|other| other.bit == deployment.bit || other.version >= deployment.version) {
+            return Err(Error::Validation(format!("Deployment {} must have another bit and a higher version than {}", deployment.name, other.name)));
+        }
+        self.policy.define(deployment.version, deployment.rules.clone())?;
+        self.deployments.push(deployment);
+        self.active = self.lock_ins(&self.policy, &self.chain);
+        self.rehash();
+        Ok(())
+    }
+
+    ///
+    /// `policy`, with the deployments `chain` locks in
+    /// 
+    fn lock_ins(&self, policy: &Policy, chain: &Chain) -> Policy {
+        let mut active = policy.clone();
+        let mut versions = Vec::with_capacity(chain.len());
+        for block in chain.iter() {
+            versions.push(block.header.version);
+            forks::lock_in(&self.deployments, &mut active, &versions);
+        }
+        active
+    }
+
+    ///
+    /// Balances at the tip of the chain
+    /// 
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    ///
+    /// Balances after the block at `height` (1 is the genesis block). The tip's
+    /// state is kept up to date, any other height is rebuilt from genesis
+    /// 
+    pub fn state_at(&self, height: usize) -> Option<State> {
+        if height == 0 || height > self.len() {
+            return None;
+        }
+        if height == self.len() {
+            return Some(self.state.clone());
+        }
+        let mut state = State::new();
+        for block in self.chain.iter().take(height) {
+            state.apply_block(block);
+        }
+        Some(state)
+    }
+
+    ///
+    /// The block at `height` (1 is the genesis block)
+    /// 
+    pub fn block_at(&self, height: usize) -> Option<&Block> {
+        if height == 0 {
+            return None;
+        }
+        self.chain.get(height - 1)
+    }
+
+    ///
+    /// The block on our chain whose header hashes to `hash`
+    /// 
+    pub fn block_by_hash(&self, hash: &str) -> Option<&Block> {
+        self.by_hash.get(hash).and_then(|position| self.chain.get(*position))
+    }
+
+    ///
+    /// The hash of the block at `height`
+    /// 
+    pub fn hash_at(&self, height: usize) -> Option<&str> {
+        if height == 0 {
+            return None;
+        }
+        self.hashes.get(height - 1).map(|hash| hash.as_str())
+    }
+
+    ///
+    /// The hash of each block of our chain, lowest first
+    /// 
+    pub fn hashes(&self) -> &[String] {
+        &self.hashes
+    }
+
+    ///
+    /// A transaction's id is its hash, the same one its block's Merkle tree uses
+    /// 
+    pub fn transaction_id(&self, transaction: &Transaction) -> String {
+        self.hasher.hash(Domain::Transaction, transaction)
+    }
+
+    ///
+    /// The Merkle proof that `transaction` is in `block`, see `merkle::verify`
+    /// 
+    pub fn merkle_proof(&self, block: &Block, transaction: &Transaction) -> Option<Vec<ProofStep>> {
+        merkle::proof(&self.hasher, &block.transactions, transaction)
+    }
+
+    ///
+    /// Find a transaction by id, on the chain or pending
+    /// 
+    /// returns: the transaction, and the block it's in (None while pending)
+    pub fn find_transaction(&self, id: &str) -> Option<(&Transaction, Option<&Block>)> {
+        let indexed = self.transactions.get(id).and_then(|height| self.block_at(*height));
+        //the index starts above `pruned`, the blocks below are scanned
+        for block in indexed.into_iter().chain(self.chain.iter().take(self.pruned)) {
+            if let Some(txn) = block.transactions.iter().find(|txn| self.transaction_id(txn) == id) {
+                return Some((txn, Some(block)));
+            }
+        }
+        self.current_transactions
+            .iter()
+            .find(|txn| self.transaction_id(txn) == id)
+            .map(|txn| (txn, None))
+    }
+
+    ///
+    /// Transactions paying `address` in blocks above `since_height`, lowest first,
+    /// without looking at any other block, unless it's at or below `pruned`
+    /// 
+    pub fn incoming(&self, address: &str, since_height: usize) -> Vec<(&Block, &Transaction)> {
+        let no_heights = Vec::new();
+        let heights = self.incoming.get(address).unwrap_or(&no_heights);
+        let first = match heights.binary_search(&(since_height + 1)) {
+            Ok(first) | Err(first) => first
+        };
+        let scanned = self.chain.iter().skip(since_height).take(self.pruned.saturating_sub(since_height));
+        scanned.chain(heights[first..].iter().filter_map(|height| self.block_at(*height)))
+            .flat_map(|block| block.transactions.iter()
+                                   .filter(move |txn| txn.recipient == address)
+                                   .map(move |txn| (block, txn)))
+            .collect()
+    }
+
+    ///
+    /// Returns the `Chain`
+    /// 
+    pub fn chain(&self) -> &Chain {
+        &self.chain
+    }
+
+    ///
+    /// The `Chain` as it is now, to read without holding any lock. Shares the
+    /// chain's segments rather than copying blocks
+    /// 
+    pub fn snapshot(&self) -> Chain {
+        self.chain.clone()
+    }
+
+    #[cfg(test)]
+    pub fn into_chain(self) -> Chain {
+        self.chain
+    }
+
+    ///
+    /// Replace our chain with a new one (such as during consensus)
+    /// 
+    /// Transactions that were only confirmed in the blocks we're abandoning go back
+    /// into the current transactions so they get mined again rather than lost.
+    /// Mining rewards for abandoned blocks are dropped. The abandoned blocks become
+    /// orphans, except a genesis block; a different genesis is a different network,
+    /// not a fork.
+    /// 
+    pub fn replace(&mut self, new_chain: Chain) {
+        let old_chain = mem::replace(&mut self.chain, new_chain);
+        self.rehash();
+        self.work = chain_work(&self.chain);
+        self.chain_bytes = memory::blocks_bytes(&self.chain);
+        self.state = State::from_chain(&self.chain);
+        self.active = self.lock_ins(&self.policy, &self.chain);
+        self.reindex();
+        let abandoned: Vec<Block> = old_chain
+                                        .iter()
+                                        .filter(|block| !self.chain.contains(*block))
+                                        .cloned()
+                                        .collect();
+        {
+            let confirmed: BTreeSet<&Transaction> = self.chain
+                                                        .iter()
+                                                        .flat_map(|block| block.transactions.iter())
+                                                        .collect();
+            let orphaned = abandoned
+                                .iter()
+                                .flat_map(|block| block.transactions.iter())
+                                .filter(|txn| !txn.is_reward())
+                                .cloned();
+            let pending = mem::replace(&mut self.current_transactions, BTreeSet::new());
+            self.current_transactions = pending
+                                            .into_iter()
+                                            .chain(orphaned)
+                                            .filter(|txn| !confirmed.contains(txn))
+                                            .collect();
+        }
+        self.drop_expired();
+        self.trim_mempool();
+        for block in abandoned.into_iter().filter(|block| block.header.index > 1) {
+            self.orphans.insert(block);
+        }
+        self.trim_orphans();
+    }
+
+    ///
+    /// Blocks abandoned when our chain was replaced, lowest first
+    /// 
+    pub fn orphans(&self) -> &BTreeSet<Block> {
+        &self.orphans
+    }
+
+    ///
+    /// Number of blocks in the chain
+    /// 
+    pub fn len(&self) -> usize {
+        self.chain.len()
+    }
+
+    ///
+    /// The work our chain proves, see `block_work`. Kept up to date as blocks are
+    /// added, like `len`
+    /// 
+    pub fn work(&self) -> u64 {
+        self.work
+    }
+
+    ///
+    /// A Block holding the current transactions, on top of the last block
+    /// 
+    fn create_block(&self, nonce: u64, previous_hash: String) -> Block {
+        self.create_block_of(self.current_transactions.clone(), nonce, previous_hash)
+    }
+
+    fn create_block_of(&self, txns: BTreeSet<Transaction>, nonce: u64, previous_hash: String) -> Block {
+        let merkle_root = merkle::root(&self.hasher, &txns);
+        let mut state = self.state.clone();
+        for txn in txns.iter() {
+            state.apply(txn);
+        }
+        let index = self.chain.len() + 1;
+        let version = self.active.version_at(index);
+        let commits_state = self.policy.rules(version).map(|rules| rules.state_root).unwrap_or(true);
+        //signal for whatever's still waiting for signals
+        let signals = self.deployments
+                          .iter()
+                          .filter(|deployment| self.active.activation(deployment.version).is_none())
+                          .fold(0, |signals, deployment| signals | deployment.signal());
+        Block {
+            header: BlockHeader {
+                index: index,
+                previous_hash: previous_hash,
+                merkle_root: merkle_root,
+                state_root: if commits_state { state.root(&self.hasher) } else { String::new() },
+                timestamp: Utc::now().timestamp(),
+                difficulty: self.difficulty,
+                nonce: nonce,
+                version: version | signals
+            },
+            transactions: txns
+        }
+    }
+
+    ///
+    /// Add a Block to the chain. Its transactions are cleared from the current
+    /// transactions to start collecting the next block's transactions, along
+    /// with any that can't go in the next block because they've expired
+    /// 
+    fn add_block(&mut self, block: Block) -> &Block {
+        for txn in block.transactions.iter() {
+            self.current_transactions.remove(txn);
+            self.arrivals.remove(txn);
+        }
+        self.forget_arrivals();
+        self.state.apply_block(&block);
+        index_incoming(&mut self.incoming, &block);
+        index_transactions(&mut self.transactions, self.hasher, &block);
+        let height = block.header.index;
+        let hash = self.hash(&block.header);
+        let on_top = self.chain.last().map(|last| *last < block).unwrap_or(true);
+        let work = block_work(block.header.difficulty);
+        let bytes = memory::block_bytes(&block);
+        if self.chain.insert(block) {
+            self.work = self.work.saturating_add(work);
+            self.chain_bytes += bytes;
+            if on_top {
+                self.remember(hash);
+                self.prune();
+            } else {
+                self.rehash();
+                self.reindex();
+            }
+        }
+        if self.deployments.iter().any(|deployment| height % deployment.window == 0) {
+            let versions: Vec<u64> = self.chain.iter().map(|block| block.header.version).collect();
+            for deployment in forks::lock_in(&self.deployments, &mut self.active, &versions) {
+                info!("Deployment {} locked in at block {}. Version {} activates at block {}",
+                      deployment.name, height, deployment.version, height + deployment.window + 1);
+            }
+        }
+        self.drop_expired();
+        self.chain.last().expect("invariant: just added element")
+    }
+
+    ///
+    /// Build the indexes again for the blocks of the chain that aren't pruned
+    /// 
+    fn reindex(&mut self) {
+        self.incoming = HashMap::new();
+        self.transactions = HashMap::new();
+        let len = self.chain.len();
+        self.pruned = self.prune_depth.map(|depth| len.saturating_sub(depth)).unwrap_or(0);
+        for block in self.chain.iter().skip(self.pruned) {
+            index_incoming(&mut self.incoming, block);
+            index_transactions(&mut self.transactions, self.hasher, block);
+        }
+    }
+
+    ///
+    /// Drop the blocks that have fallen more than `prune_depth` below the tip from
+    /// the indexes, one at a time as the chain grows
+    /// 
+    fn prune(&mut self) {
+        let below = match self.prune_depth {
+            Some(depth) => self.chain.len().saturating_sub(depth),
+            None => return
+        };
+        while self.pruned < below {
+            if let Some(block) = self.chain.get(self.pruned) {
+                unindex(&mut self.incoming, &mut self.transactions, self.hasher, block);
+            }
+            self.pruned += 1;
+        }
+    }
+
+    ///
+    /// Drop current transactions that expire before the next block
+    /// 
+    fn drop_expired(&mut self) {
+        let next = self.chain.len() + 1;
+        let expired: Vec<Transaction> = self.current_transactions
+                                            .iter()
+                                            .filter(|txn| txn.is_expired(next))
+                                            .cloned()
+                                            .collect();
+        for txn in expired {
+            info!("Dropping transaction {} -> {} {}. It expired at block {:?}", txn.sender, txn.recipient, txn.amount, txn.expires_at);
+            self.current_transactions.remove(&txn);
+        }
+    }
+    
+    ///
+    ///Create a new Block 
+    ///
+    fn new_block(&mut self, nonce: u64, previous_hash: String) -> &Block {
+        let block = self.create_block(nonce, previous_hash);
+        self.add_block(block)
+    }
+  
+    ///
+    /// The last mined block
+    /// 
+    pub fn last_block(&self) -> &Block {
+        //it's a double-ended iterator, and it's sorted, so it should be fast
+        self.chain.last().expect("invariant: Chain empty. Expected genesis block")
+    }
+
+    ///
+    /// The hash of a block is the hash of its header's canonical encoding, by the
+    /// hasher of its version if that has one
+    /// 
+    pub fn hash(&self, header: &BlockHeader) -> String {
+        self.header_hasher(header).hash(Domain::Block, header)
+    }
+
+    fn header_hasher(&self, header: &BlockHeader) -> HashAlgorithm {
+        header_hasher(&self.policy, self.hasher, header)
+    }
+
+    ///
+    ///Simple Proof of Work Algorithm:
+    /// Find a nonce such that the hash of the header starts with `difficulty` zeroes.
+    /// The header already contains the previous hash and the merkle root, so the
+    /// work covers the link to the chain and every transaction in the block.
+    /// 
+    fn proof_of_work(&self, header: &BlockHeader) -> u64 {
+        
+        info!("Mining block {}...", header.index);
+        let mut candidate = header.clone();
+        candidate.nonce = 0;
+        while !Self::valid_proof(&self.hash(&candidate), candidate.difficulty) {
+             candidate.nonce += 1;
+        }
+        debug!("Took {} iterations", candidate.nonce);
+        candidate.nonce
+    }
+
+    /// Validates the Proof
+    /// i.e. does the hash of the header start with 000?
+    /// 
+    /// Counts the zeroes rather than building a "000" prefix, since a peer's block
+    /// can claim any difficulty (a prefix of u64::MAX zeroes would abort the node)
+    fn valid_proof(header_hash: &str, difficulty: u64) -> bool {
+        
+        let zeroes = header_hash.chars().take_while(|c| *c == '0').count() as u64;
+        let is_valid = zeroes >= difficulty;
+        if is_valid {
+            info!("valid header hash: {}", header_hash);
+        } else {
+            debug!("invalid header hash: {}", header_hash);
+        }
+        is_valid
+    }
+
+    ///
+    /// Hash of the tip of the chain
+    /// 
+    pub fn hash_last_block(&self) -> String {
+        self.hashes.last().cloned().expect("invariant: Chain empty. Expected genesis block")
+    }
+
+    //of transactions dropped from the mempool some other way
+    fn forget_arrivals(&mut self) {
+        if self.arrivals.len() > self.current_transactions.len() {
+            let current = &self.current_transactions;
+            self.arrivals = mem::replace(&mut self.arrivals, BTreeMap::new()).into_iter().filter(|&(ref txn, _)| current.contains(txn)).collect();
+        }
+    }
 
-    #[cfg(test)]
-    pub fn new() -> Blockchain {
-        Self::new_with(3)
+    fn remember(&mut self, hash: String) {
+        let position = self.hashes.len();
+        //the first of two blocks with the same hash is the one found, as before
+        self.by_hash.entry(hash.clone()).or_insert(position);
+        self.hashes.push(hash);
     }
-    pub fn new_with(difficulty: u64) -> Blockchain {
-        let mut blockchain = Blockchain {
-            chain: BTreeSet::new(),
-            current_transactions: BTreeSet::new(),
-            nodes: HashSet::new(),
-            difficulty: difficulty
-        };
-        blockchain.new_block(100, String::from("Genesis block."));
-        blockchain
+
+    fn rehash(&mut self) {
+        let hashes: Vec<String> = self.chain.iter().map(|block| self.hash(&block.header)).collect();
+        self.hashes = Vec::with_capacity(hashes.len());
+        self.by_hash = HashMap::new();
+        for hash in hashes {
+            self.remember(hash);
+        }
     }
-    
+
     ///
-    /// Add a new transaction
+    /// Whether every block of `chain` above the ones we have too is mined at
+    /// our difficulty or higher. Blocks record their own difficulty, so without
+    /// this a peer could mine a long chain at MIN_DIFFICULTY for next to nothing
+    /// and have it adopted for its work. The blocks we share stay valid at
+    /// whatever they were mined at
     /// 
-    /// returns: the index of the block it will be added to
-    pub fn new_transaction(&mut self, transaction: Transaction) -> usize {        
-        self.current_transactions.insert(transaction);
-        //It will be added to the index of the next block
-        self.last_block().index + 1
+    pub fn meets_difficulty(&self, chain: &Chain) -> bool {
+        chain.iter()
+             .zip(self.chain.iter().map(Some).chain(::std::iter::repeat(None)))
+             .skip_while(|&(theirs, ours)| ours == Some(theirs))
+             .all(|(block, _)| block.header.difficulty >= self.difficulty)
     }
 
     ///
-    /// Mine a new block
+    /// Determine if a given blockchain is valid
     /// 
-   pub fn mine(&mut self) -> Result<&Block, String> {
-        // We run the proof of work algorithm to get the next proof...    
-        let new_block_proof = self.proof_of_work()?;
-        //Got it. Give ourselves the new coin (block?)
-        //The sender is "0" to signify that this node has mined a new coin.
-        self.new_transaction(Transaction::new("0".into(), "my node address".into(), 1));
-        let previous_hash = self.hash_last_block()?;
-        //Forge the new Block by adding it to the chain
-        let mined_block = self.new_block(new_block_proof, previous_hash);
-        Ok(&mined_block)
+    pub fn valid_chain(&self, chain: &Chain) -> bool {        
+        debug!("{} blocks in chain.", chain.len());
+        self.valid_prefix(chain) == chain.len()
     }
 
     ///
-    /// Returns the `Chain`
+    /// How many blocks from the start of `chain` are valid. Nothing after the
+    /// first invalid block counts, even if it checks out on its own
     /// 
-    pub fn chain(&self) -> &Chain {
-        &self.chain
-    }
-
-    #[cfg(test)]
-    pub fn into_chain(self) -> Chain {
-        self.chain
+    pub fn valid_prefix<'a, I>(&self, chain: I) -> usize where I: IntoIterator<Item = &'a Block> {
+        self.verify(chain).valid
     }
 
     ///
-    /// Add a new node
+    /// Check every block of `chain` from genesis, like `valid_prefix`, and say
+    /// what's wrong with the first invalid one
     /// 
-    pub fn register_node(&mut self, address: Url) -> bool {
-        self.nodes.insert(address)
+    pub fn verify<'a, I>(&self, chain: I) -> Verification where I: IntoIterator<Item = &'a Block> {
+        let blocks: Vec<&Block> = chain.into_iter().collect();
+        let digests = self.digests(&blocks);
+        let mut verifier = self.verifier();
+        for (block, digest) in blocks.iter().zip(digests.iter()) {
+            if verifier.check_with(block, digest).is_err() {
+                break;
+            }
+        }
+        Verification { length: blocks.len(), valid: verifier.valid(), error: verifier.error().map(String::from) }
     }
 
     ///
-    /// Returns the other nodes in the network
+    /// A `Verifier` for chains from genesis, by our rules
     /// 
-    pub fn nodes(&self) -> &HashSet<Url> {
-        &self.nodes
+    pub fn verifier(&self) -> Verifier {
+        Verifier {
+            hasher: self.hasher,
+            policy: self.policy.clone(),
+            deployments: self.deployments.clone(),
+            active: self.policy.clone(),
+            versions: Vec::new(),
+            state: State::new(),
+            previous_hash: None,
+            work: 0,
+            error: None
+        }
     }
 
     ///
-    /// Replace our chain with a new one (such as during consensus)
+    /// The `Digests` of each of `blocks`. They're independent of each other, so
+    /// a long chain is split between `VALIDATION_THREADS` threads, while the
+    /// checks that link blocks together stay in order in `verify`. Headers are
+    /// hashed here, and the threads only get the encoded transactions to build
+    /// merkle roots from, not copies of the blocks
     /// 
-    pub fn replace(&mut self, new_chain: Chain) {
-        self.chain = new_chain;
+    fn digests(&self, blocks: &[&Block]) -> Vec<Digests> {
+        if blocks.len() < PARALLEL_MIN_BLOCKS {
+            return blocks.iter().map(|block| Digests::of(self.header_hasher(&block.header), self.hasher, block)).collect();
+        }
+        let per_thread = (blocks.len() + VALIDATION_THREADS - 1) / VALIDATION_THREADS;
+        let workers: Vec<thread::JoinHandle<Vec<Digests>>> = blocks.chunks(per_thread).map(|chunk| {
+            let hasher = self.hasher;
+            let jobs: Vec<(String, Vec<Vec<u8>>)> = chunk.iter()
+                                                        .map(|block| (self.header_hasher(&block.header).hash(Domain::Block, &block.header),
+                                                                      block.transactions.iter().map(encoding::encode).collect()))
+                                                        .collect();
+            thread::spawn(move || {
+                jobs.into_iter()
+                    .map(|(hash, transactions)| Digests { hash: hash, merkle_root: merkle::root_of_encoded(&hasher, &transactions) })
+                    .collect()
+            })
+        }).collect();
+        workers.into_iter()
+               .flat_map(|worker| worker.join().expect("invariant: hashing doesn't panic"))
+               .collect()
     }
+}
+
+///
+/// Checks a chain a block at a time from genesis, like `Blockchain::verify`, for
+/// blocks that arrive one by one. It has its own copy of the rules, so no lock
+/// is held while they arrive
+/// 
+/// active: `policy`, with the deployments locked in so far, as they were for
+///         whoever mined the blocks
+/// versions: Of the valid blocks so far
+/// error: What's wrong with the first invalid block. Nothing after it is checked
+/// 
+#[derive(Debug, Clone)]
+pub struct Verifier {
+    hasher: HashAlgorithm,
+    policy: Policy,
+    deployments: Vec<Deployment>,
+    active: Policy,
+    versions: Vec<u64>,
+    state: State,
+    previous_hash: Option<String>,
+    work: u64,
+    error: Option<String>
+}
 
+impl Verifier {
     ///
-    /// Number of blocks in the chain
+    /// Check the next block
     /// 
-    pub fn len(&self) -> usize {
-        self.chain.len()
+    /// returns: What's wrong with it, or with an earlier one
+    pub fn check(&mut self, block: &Block) -> ::std::result::Result<(), String> {
+        let digests = Digests::of(header_hasher(&self.policy, self.hasher, &block.header), self.hasher, block);
+        self.check_with(block, &digests)
     }
 
-    fn create_block(&mut self, proof: u64, previous_hash: String) -> Block {
-        //Current transactions get moved to this block and are cleared to start
-        //collecting the next block's transactions
-        let mut txns = BTreeSet::new();
-        txns.append(&mut self.current_transactions);
-        Block {
-            index: self.chain.len() + 1,
-            timestamp: Utc::now().timestamp(),
-            proof: proof,
-            previous_hash: previous_hash,
-            transactions: txns
+    fn check_with(&mut self, block: &Block, digests: &Digests) -> ::std::result::Result<(), String> {
+        if let Some(ref error) = self.error {
+            return Err(error.clone());
+        }
+        self.state.apply_block(block);
+        let height = self.versions.len() + 1;
+        let problem = if block.header.index != height {
+            Some(format!("index is {}", block.header.index))
+        } else if let Err(problem) = self.active.check(height, block.header.version) {
+            Some(problem)
+        } else if !check_merkle_root(block, &digests.merkle_root) {
+            Some(String::from("merkle root doesn't match its transactions"))
+        } else if !check_state_root(&self.policy, self.hasher, block, &self.state) {
+            Some(String::from("state root doesn't match the balances"))
+        } else if !check_expiry(block) {
+            Some(String::from("contains an expired transaction"))
+        } else if !check_rewards(block) {
+            Some(format!("pays more than one reward, or one other than {}", REWARD))
+        } else {
+            //Check the hash and proof
+            match self.previous_hash {
+                Some(ref previous_hash) if !check_hash(previous_hash, block) => Some(format!("previous hash isn't the hash of block {}", height - 1)),
+                Some(_) if !check_proof(block, &digests.hash) => Some(String::from("proof of work is invalid")),
+                _ => None
+            }
+        };
+        if let Some(problem) = problem {
+            let error = format!("block {}: {}", height, problem);
+            self.error = Some(error.clone());
+            return Err(error);
         }
+        self.previous_hash = Some(digests.hash.clone());
+        self.versions.push(block.header.version);
+        forks::lock_in(&self.deployments, &mut self.active, &self.versions);
+        self.work = self.work.saturating_add(block_work(block.header.difficulty));
+        Ok(())
     }
-    
-    ///
-    ///Create a new Block 
+
     ///
-    fn new_block(&mut self, proof: u64, previous_hash: String) -> &Block {
-        let block = self.create_block(proof, previous_hash);
-        self.chain.insert(block);
-        &self.chain.iter().next_back().expect("invariant: just added element")
+    /// How many blocks from genesis are valid
+    /// 
+    pub fn valid(&self) -> usize {
+        self.versions.len()
     }
-  
+
     ///
-    /// The last mined block
+    /// The work the valid blocks prove, see `block_work`
     /// 
-    fn last_block(&self) -> &Block {
-        //it's a double-ended iterator, and it's sorted, so it should be fast
-        self.chain.iter().next_back().expect("invariant: Chain empty. Expected genesis block")
+    pub fn work(&self) -> u64 {
+        self.work
     }
 
-    //todo: get away from string errors
-    fn hash(block: &Block) -> Result<String, String> {
-       self::hash(block)
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_ref().map(|error| error.as_str())
     }
+}
 
-    ///
-    ///Simple Proof of Work Algorithm:
-    /// Simple PoW algo:                                                                                                                                
-    /// Find a number p' (new proof) s. t. hash(pp'h) contains 4 leading zeroes, where p is the 
-    /// previous proof and h is the hash of the previous block.      
-    /// 
-    fn proof_of_work(&self) -> Result<u64, String> {
-        
-        let last_block = self.last_block();
-        let last_proof = last_block.proof;
+//which hasher a header is hashed with, by `policy`
+fn header_hasher(policy: &Policy, hasher: HashAlgorithm, header: &BlockHeader) -> HashAlgorithm {
+    policy.rules(rules_version(header.version)).and_then(|rules| rules.hasher).unwrap_or(hasher)
+}
 
-        info!("Mining from last_proof {}...", last_proof);
-        let mut proof = 0;
-        let previous_hash = self.hash_last_block()?;
-        while !Self::valid_proof(last_proof, proof, self.difficulty, &previous_hash) {
-             proof += 1;
-        }
-        debug!("Took {} iterations", proof);
-        Ok(proof)
+fn check_hash(previous_block_hash: &str, current_block: &Block) -> bool {
+    if current_block.header.previous_hash != previous_block_hash {
+        warn!("HASH MISMATCH {} <> {}", current_block.header.previous_hash, previous_block_hash);
+        return false
     }
+    true
+}
 
-    /// Validates the Proof
-    /// i.e. does the hash of last_proof and this proof start with 000?
-    fn valid_proof(last_proof: u64, proof: u64, difficulty: u64, previous_hash: &String) -> bool {
-        
-        //todo: don't recalculate every time
-        let hash_prefix = "0".repeat(difficulty as usize); //"000"
+fn check_proof(current_block: &Block, header_hash: &str) -> bool {
+    let header = &current_block.header;
+    if header.difficulty < MIN_DIFFICULTY {
+        warn!("DIFFICULTY TOO LOW block {} difficulty {}", header.index, header.difficulty);
+        return false
+    }
+    if !Blockchain::valid_proof(header_hash, header.difficulty) {
+        warn!("PROOF MISMATCH block {} nonce {} -> {}", header.index, header.nonce, header_hash);
+        return false
+    }
+    true
+}
 
-        let guess = format!("{}{}{}", last_proof, proof, previous_hash);
-        let guess_hash =  self::hash_string(guess);
-        let is_valid = guess_hash.starts_with(hash_prefix.as_str());
-        if is_valid {
-            info!("proof {} -> guess_hash: {}", proof, guess_hash);
-        } else {
-            debug!("proof {} -> guess_hash: {}", proof, guess_hash);
+fn check_state_root(policy: &Policy, hasher: HashAlgorithm, block: &Block, state: &State) -> bool {
+    let commits_state = policy.rules(rules_version(block.header.version)).map(|rules| rules.state_root).unwrap_or(true);
+    let state_root = if commits_state { state.root(&hasher) } else { String::new() };
+    if block.header.state_root != state_root {
+        warn!("STATE ROOT MISMATCH block {} {} <> {}", block.header.index, block.header.state_root, state_root);
+        return false
+    }
+    true
+}
+
+//a block pays its miner at most once, and `REWARD` exactly, or anyone who mines
+//could mint as much as they like. Genesis pays out the allocations instead
+fn check_rewards(block: &Block) -> bool {
+    if block.header.index == 1 {
+        return true;
+    }
+    let mut rewards = block.transactions.iter().filter(|txn| txn.is_reward());
+    match (rewards.next(), rewards.next()) {
+        (None, _) => true,
+        (Some(reward), None) if reward.amount == REWARD => true,
+        _ => {
+            warn!("BAD REWARD block {} pays more than one reward, or one other than {}", block.header.index, REWARD);
+            false
         }
-        is_valid
     }
+}
 
-    fn hash_last_block(&self) -> Result<String, String> {
-        let last_block = self.last_block();
-        Self::hash(last_block)
+fn check_expiry(block: &Block) -> bool {
+    if let Some(txn) = block.transactions.iter().find(|txn| txn.is_expired(block.header.index)) {
+        warn!("EXPIRED TRANSACTION block {} {} -> {} expired at {:?}", block.header.index, txn.sender, txn.recipient, txn.expires_at);
+        return false
     }
+    true
+}
 
-    ///
-    /// Determine if a given blockchain is valid
-    /// 
-    pub fn valid_chain(&self, chain: &Chain) -> bool {        
-        debug!("{} blocks in chain.", chain.len());
-        let mut previous_block_opt: Option<&Block> = None;        
-        for block in chain {
-            if let Some(previous_block) = previous_block_opt {
-                //Check the hash and proof
-                if !Self::check_hash(previous_block, block) || 
-                   !Self::check_proof(previous_block, block, self.difficulty) {
-                    return false;
-                }               
-            }
-            previous_block_opt = Some(&block);
-        }
-        true
+fn check_merkle_root(block: &Block, merkle_root: &str) -> bool {
+    if block.header.merkle_root != merkle_root {
+        warn!("MERKLE ROOT MISMATCH block {} {} <> {}", block.header.index, block.header.merkle_root, merkle_root);
+        return false
     }
+    true
+}
 
-    fn check_hash(previous_block: &Block, current_block: &Block) -> bool {
-        let previous_block_hash = Self::hash(previous_block).unwrap_or_else(|e| format!("hash failure: {}", e));
-        if current_block.previous_hash != previous_block_hash {
-            warn!("HASH MISMATCH {} <> {}", current_block.previous_hash, previous_block_hash);
-            return false
+fn index_incoming(incoming: &mut HashMap<Address, Vec<usize>>, block: &Block) {
+    for txn in block.transactions.iter() {
+        let heights = incoming.entry(txn.recipient.clone()).or_insert_with(Vec::new);
+        if heights.last() != Some(&block.header.index) {
+            heights.push(block.header.index);
         }
-        true
     }
+}
+
+fn index_transactions(transactions: &mut HashMap<String, usize>, hasher: HashAlgorithm, block: &Block) {
+    for txn in block.transactions.iter() {
+        transactions.entry(hasher.hash(Domain::Transaction, txn)).or_insert(block.header.index);
+    }
+}
 
-    fn check_proof(previous_block: &Block, current_block: &Block, difficulty: u64) -> bool {
-        let previous_hash = Self::hash(previous_block).unwrap_or_else(|e| format!("hash failure: {}", e));
-        if !Self::valid_proof(previous_block.proof, current_block.proof, difficulty, &previous_hash) {                
-            warn!("PROOF MISMATCH {} <> {}", previous_block.proof, current_block.proof);
-            return false
+//`block` must be the lowest block indexed
+fn unindex(incoming: &mut HashMap<Address, Vec<usize>>, transactions: &mut HashMap<String, usize>, hasher: HashAlgorithm, block: &Block) {
+    let height = block.header.index;
+    for txn in block.transactions.iter() {
+        let spent = match incoming.get_mut(&txn.recipient) {
+            Some(heights) => {
+                heights.retain(|indexed| *indexed > height);
+                heights.is_empty()
+            },
+            None => false
+        };
+        if spent {
+            incoming.remove(&txn.recipient);
+        }
+        let id = hasher.hash(Domain::Transaction, txn);
+        if transactions.get(&id) == Some(&height) {
+            transactions.remove(&id);
         }
-        true
     }
 }
 
 #[cfg(test)]
 mod tests {
     //use env_logger;
-    use lib::blockchain::Blockchain;
+    use lib::blockchain::{Blockchain, Block, Chain, Verification, MAX_DIFFICULTY, PARALLEL_MIN_BLOCKS, block_work};
+    use lib::genesis::Genesis;
+    use lib::hasher::HashAlgorithm;
+    use lib::memory::{self, Caps};
+    use lib::random::xorshift;
+    use lib::selection::Strategy;
     use lib::transaction::Transaction;
-    use url::Url;
+    use lib::forks::Deployment;
+    use lib::versions::{Policy, Rules, FIRST_VERSION};
+    use std::collections::BTreeSet;
+    use std::sync::Arc;
 
     #[test]
     fn new_transaction() {
@@ -273,23 +1303,151 @@ mod tests {
         let b = blockchain.current_transactions.len();
         assert_eq!(0, b, "New block should clear transactions (which were on the previous block");    
     }
+
+    #[test]
+    fn snapshot() {
+        let mut blockchain = Blockchain::new_with(1);
+        let snapshot = blockchain.snapshot();
+        blockchain.mine().expect("mined");
+        assert_eq!(snapshot.len(), 1, "a snapshot doesn't change");
+        assert_eq!(blockchain.len(), 2);
+        assert_eq!(blockchain.snapshot(), *blockchain.chain());
+    }
     
     #[test]
     fn hash() {
         let mut blockchain = Blockchain::new();       
         blockchain.new_block(2, String::from("abc"));
         let block = blockchain.last_block();
-        let hash = Blockchain::hash(block);
-        let hash2 = Blockchain::hash(block);
+        let hash = blockchain.hash(&block.header);
+        let hash2 = blockchain.hash(&block.header);
         println!("{:?}", hash);
-        assert!(hash.is_ok());
-        assert_eq!(hash.unwrap(), hash2.unwrap(), "Expected same block to hash to the same value");
+        assert_eq!(hash, hash2, "Expected same block to hash to the same value");
         //assert!(hash.unwrap().len() > 10, "expected a longer hash");       
     }
 
+    #[test]
+    fn hashes_are_remembered() {
+        let recomputed = |blockchain: &Blockchain| -> Vec<String> {
+            blockchain.chain().iter().map(|block| blockchain.hash(&block.header)).collect()
+        };
+        let mut blockchain = Blockchain::new_with(1);
+        blockchain.mine().unwrap();
+        assert_eq!(blockchain.hashes().to_vec(), recomputed(&blockchain));
+        assert_eq!(blockchain.hash_at(2), Some(blockchain.hash_last_block().as_str()));
+        assert_eq!(blockchain.hash_at(3), None);
+        let tip = blockchain.hash_last_block();
+        assert_eq!(blockchain.block_by_hash(&tip).map(|block| block.header.index), Some(2));
+
+        let mut other = Blockchain::new_with(1);
+        other.set_miner_address("other miner".into());
+        other.mine().unwrap();
+        other.mine().unwrap();
+        blockchain.replace(other.chain().clone());
+        assert_eq!(blockchain.hashes().to_vec(), recomputed(&blockchain));
+        assert_eq!(blockchain.block_by_hash(&tip), None, "forgotten with its block");
+    }
+
+    #[test]
+    fn work() {
+        assert_eq!(block_work(1), 64);
+        assert_eq!(block_work(10), 1 << 60);
+        assert_eq!(block_work(11), u64::max_value());
+
+        let mut blockchain = Blockchain::new_with(1);
+        assert_eq!(blockchain.work(), 64, "genesis");
+        blockchain.mine().unwrap();
+        blockchain.set_difficulty(2).unwrap();
+        blockchain.mine().unwrap();
+        assert_eq!(blockchain.work(), 64 + 64 + 4096);
+
+        let mut other = Blockchain::new_with(1);
+        other.replace(blockchain.chain().clone());
+        assert_eq!(other.work(), blockchain.work());
+    }
+
     #[test]
     fn valid_proof_false() {
-        assert_eq!(Blockchain::valid_proof(100,1, 3, &String::from("some hash")), false);
+        assert_eq!(Blockchain::valid_proof("some hash", 3), false);
+        assert_eq!(Blockchain::valid_proof("00some hash", 3), false);
+        assert_eq!(Blockchain::valid_proof("000some hash", 3), true);
+        assert_eq!(Blockchain::valid_proof("000some hash", u64::max_value()), false);
+    }
+
+    ///
+    /// Garbage from a malicious peer has to be rejected, not crash the node.
+    /// Mutates a valid chain at random (deterministically) and validates it
+    /// 
+    #[test]
+    fn valid_chain_garbage() {
+        let mut blockchain = Blockchain::new_with(1);
+        blockchain.new_transaction(Transaction::new("a".into(), "b".into(), 1));
+        blockchain.mine().unwrap();
+        blockchain.mine().unwrap();
+
+        let mut seed: u64 = 0x2545F4914F6CDD1D;
+        let mut random = move || xorshift(&mut seed);
+        let extremes = [0, 1, u64::max_value(), i64::max_value() as u64, i64::min_value() as u64];
+        let original: Vec<Block> = blockchain.chain().iter().cloned().collect();
+        for _ in 0..500 {
+            let mut blocks = original.clone();
+            let target = (random() % blocks.len() as u64) as usize;
+            let value = if random() % 2 == 0 { extremes[(random() % extremes.len() as u64) as usize] } else { random() };
+            let mutation = random() % 6;
+            {
+                let block = &mut blocks[target];
+                match mutation {
+                    0 => block.header.difficulty = value,
+                    1 => block.header.index = value as usize,
+                    2 => block.header.timestamp = value as i64,
+                    3 => block.header.nonce = value,
+                    4 => block.header.previous_hash = format!("{:x}", value),
+                    _ => {
+                        let txns: Vec<Transaction> = block.transactions.iter().cloned().collect();
+                        block.transactions = txns.into_iter().map(|mut txn| { txn.amount = value as i64; txn }).collect();
+                        block.transactions.insert(Transaction::new("x".into(), "y".into(), value as i64));
+                    }
+                }
+            }
+            //a new difficulty, timestamp or nonce on the last block is still valid
+            //if its new hash happens to meet its difficulty, nothing else is
+            let last = blocks.len() - 1;
+            let lucky = target == last && (mutation == 0 || mutation == 2 || mutation == 3)
+                        && Blockchain::valid_proof(&blockchain.hash(&blocks[last].header), blocks[last].header.difficulty);
+            let changed = blocks != original;
+            let chain: Chain = blocks.into_iter().collect();
+            assert!(!blockchain.valid_chain(&chain) || !changed || lucky, "mutation {} of block {} to {} was accepted", mutation, target + 1, value);
+        }
+    }
+
+    ///
+    /// A miner can't pay itself twice, or more than `REWARD`, even in a block
+    /// whose proof and roots are all valid
+    /// 
+    #[test]
+    fn valid_chain_rewards() {
+        let mut blockchain = Blockchain::new_with(1);
+        blockchain.set_miner_address("miner".into());
+        blockchain.mine().unwrap();
+        let mut other = Blockchain::new_with(1);
+        other.replace(blockchain.chain().clone());
+
+        let twice: BTreeSet<Transaction> = vec![Transaction::reward("miner".into()), Transaction::reward("also miner".into())].into_iter().collect();
+        let plenty: BTreeSet<Transaction> = vec![Transaction::new("0".into(), "miner".into(), 1000)].into_iter().collect();
+        for txns in vec![twice, plenty] {
+            let mut block = blockchain.create_block_of(txns, 0, blockchain.hash_last_block());
+            block.header.nonce = blockchain.proof_of_work(&block.header);
+            let mut blocks: Vec<Block> = blockchain.chain().iter().cloned().collect();
+            blocks.push(block.clone());
+            let tampered: Chain = blocks.into_iter().collect();
+            assert!(!blockchain.valid_chain(&tampered), "{:?} was accepted", block.transactions);
+            assert!(other.append(block).is_err());
+        }
+
+        //a reward someone else sent us doesn't go in blocks we mine
+        blockchain.new_transaction(Transaction::reward("someone".into()));
+        assert_eq!(blockchain.mine().unwrap().transactions.iter().filter(|txn| txn.is_reward()).count(), 1);
+        assert!(blockchain.valid_chain(blockchain.chain()));
     }
     
     #[cfg(feature = "mining-tests")]    
@@ -297,13 +1455,16 @@ mod tests {
     fn proof_of_work() {
         let difficulty = 2;
         let blockchain = Blockchain::new_with(difficulty);     
+        let previous_hash = blockchain.hash_last_block();
+        let mut header = blockchain.create_block(0, previous_hash).header;
         println!("Starting proof of work... (long running)");
-        let proof = blockchain.proof_of_work().unwrap();
-        println!("Finished proof of work: {}", proof);
-        assert!(proof > 1, "expected a higher proof");
-        let previous_hash = blockchain.hash_last_block().unwrap();
-        assert!(Blockchain::valid_proof(100, proof, difficulty, &previous_hash));
-        assert!(!Blockchain::valid_proof(100, proof, difficulty, &String::from("invalid hash")));
+        let nonce = blockchain.proof_of_work(&header);
+        println!("Finished proof of work: {}", nonce);
+        assert!(nonce > 1, "expected a higher nonce");
+        header.nonce = nonce;
+        assert!(Blockchain::valid_proof(&blockchain.hash(&header), difficulty));
+        header.merkle_root = String::from("tampered");
+        assert!(!Blockchain::valid_proof(&blockchain.hash(&header), difficulty));
     }
 
     #[test]
@@ -315,16 +1476,6 @@ mod tests {
         assert_eq!(blockchain.chain().len(),  2, "Expected 2 blocks");
     }
 
-    #[test]
-    fn register_node() {
-        let mut blockchain = Blockchain::new();     
-        let test_local_url = Url::parse("http://localhost:9000").expect("valid url");
-        blockchain.register_node(test_local_url.clone());
-        assert_eq!(blockchain.nodes().len(),  1, "Expected 1 node");
-        blockchain.register_node(test_local_url);
-        assert_eq!(blockchain.nodes().len(),  1, "Expected 1 node after dupe add (idempotent)");
-    }
-
     #[test]
     fn valid_chain_invalid_hash() {
         //env_logger::init().unwrap();
@@ -343,12 +1494,406 @@ mod tests {
         let txn = Transaction::new(String::from("a"), String::from("b"), 100);
         blockchain.new_transaction(txn);
         //valid hash, invalid proof
-        let hash = blockchain.hash_last_block().unwrap();
+        let hash = blockchain.hash_last_block();
         blockchain.new_block(2, hash);
 
         assert!(!blockchain.valid_chain(&blockchain.chain), "blockchain not valid (proof mismatch)");
     }
 
+    #[test]
+    fn genesis_allocations() {
+        let mut genesis = Genesis::default();
+        genesis.allocations.insert("alice".into(), 100);
+        let mut blockchain = Blockchain::new_with_genesis(1, HashAlgorithm::default(), &genesis);
+        assert_eq!(blockchain.len(), 1);
+        assert_eq!(blockchain.state().balance("alice"), 100, "spendable at genesis");
+        assert!(blockchain.current_transactions().is_empty());
+
+        blockchain.new_transaction(Transaction::new("alice".into(), "bob".into(), 30));
+        blockchain.mine().unwrap();
+        assert_eq!(blockchain.state().balance("alice"), 70);
+        assert!(Blockchain::new_with(1).valid_chain(blockchain.chain()), "any node accepts it");
+    }
+
+    #[test]
+    fn valid_chain_invalid_merkle_root() {
+        let mut blockchain = Blockchain::new_with(1);
+        blockchain.mine().unwrap();
+        assert!(blockchain.valid_chain(&blockchain.chain), "mined chain should be valid");
+
+        //slip an extra transaction into the mined block's body
+        let mut blocks: Vec<Block> = blockchain.chain.iter().cloned().collect();
+        blocks[1].transactions.insert(Transaction::new("a".into(), "b".into(), 1000));
+        let tampered: Chain = blocks.into_iter().collect();
+        assert!(!blockchain.valid_chain(&tampered), "blockchain not valid (merkle root mismatch)");
+    }
+
+    #[test]
+    fn set_difficulty() {
+        let mut blockchain = Blockchain::new_with(1);
+        blockchain.mine().unwrap();
+        blockchain.set_difficulty(2).unwrap();
+        assert_eq!(blockchain.mine().unwrap().header.difficulty, 2);
+        assert!(blockchain.valid_chain(&blockchain.chain), "blocks at different difficulties are valid");
+
+        assert!(blockchain.set_difficulty(0).is_err());
+        assert!(blockchain.set_difficulty(MAX_DIFFICULTY + 1).is_err());
+        assert_eq!(blockchain.difficulty(), 2);
+        blockchain.set_difficulty(MAX_DIFFICULTY).unwrap();
+        assert_eq!(blockchain.difficulty(), MAX_DIFFICULTY);
+    }
+
+    #[test]
+    fn valid_chain_difficulty_too_low() {
+        let mut blockchain = Blockchain::new_with(1);
+        blockchain.difficulty = 0;
+        blockchain.mine().unwrap();
+        assert!(!blockchain.valid_chain(&blockchain.chain), "blockchain not valid (difficulty 0)");
+    }
+
+    #[test]
+    fn state_root() {
+        let mut blockchain = Blockchain::new_with(1);
+        blockchain.set_miner_address("miner".into());
+        blockchain.new_transaction(Transaction::new("a".into(), "b".into(), 5));
+        blockchain.mine().unwrap();
+        assert_eq!(blockchain.state().balance("b"), 5);
+        assert_eq!(blockchain.state().balance("miner"), 1);
+        assert!(blockchain.valid_chain(&blockchain.chain), "mined chain should be valid");
+
+        //a header committing to different balances (same transactions) is caught
+        let mut blocks: Vec<Block> = blockchain.chain.iter().cloned().collect();
+        blocks[1].header.state_root = blocks[0].header.state_root.clone();
+        let tampered: Chain = blocks.into_iter().collect();
+        assert!(!blockchain.valid_chain(&tampered), "blockchain not valid (state root mismatch)");
+    }
+
+    #[test]
+    fn hash_algorithm() {
+        let mut sha256 = Blockchain::new_with_hasher(1, HashAlgorithm::Sha256);
+        let mut sha512 = Blockchain::new_with_hasher(1, HashAlgorithm::Sha512);
+        sha256.mine().unwrap();
+        sha512.mine().unwrap();
+        assert!(sha512.valid_chain(&sha512.chain), "sha512 chain valid under sha512");
+        assert!(!sha256.valid_chain(&sha512.chain), "sha512 chain not valid under sha256");
+        assert!(!sha512.valid_chain(&sha256.chain), "sha256 chain not valid under sha512");
+    }
+
+    #[test]
+    fn versions() {
+        let mut blockchain = Blockchain::new_with(1);
+        blockchain.mine().unwrap();
+        let mut policy = Policy::new();
+        policy.activate(2, 4, Rules { hasher: Some(HashAlgorithm::Sha512), state_root: false }).unwrap();
+        blockchain.set_policy(policy).unwrap();
+        blockchain.mine().unwrap();
+        blockchain.mine().unwrap();
+        assert_eq!(blockchain.block_at(3).unwrap().header.version, FIRST_VERSION);
+        assert_eq!(blockchain.last_block().header.version, 2);
+        assert!(blockchain.last_block().header.state_root.is_empty());
+        assert!(blockchain.valid_chain(&blockchain.chain), "valid across the upgrade");
+
+        let not_upgraded = Blockchain::new_with(1);
+        assert_eq!(not_upgraded.valid_prefix(blockchain.chain()), 3, "blocks before the upgrade still validate");
+        let mut too_late = Policy::new();
+        too_late.activate(2, 3, Rules::first()).unwrap();
+        assert!(blockchain.set_policy(too_late).is_err(), "can't activate below the tip");
+    }
+
+    #[test]
+    fn deployment_signaling() {
+        let mut blockchain = Blockchain::new_with(1);
+        let mut deployment = Deployment::new("sha512".into(), 0, 2, Rules { hasher: Some(HashAlgorithm::Sha512), state_root: true });
+        deployment.window = 2;
+        deployment.threshold = 1;
+        blockchain.add_deployment(deployment.clone()).unwrap();
+        assert!(blockchain.add_deployment(Deployment::new("same bit".into(), 0, 3, Rules::first())).is_err());
+
+        //genesis doesn't signal, block 2 does and ends the first window
+        blockchain.mine().unwrap();
+        assert!(deployment.signaled_by(blockchain.last_block().header.version));
+        assert_eq!(blockchain.policy().activation(2), Some(5));
+        blockchain.mine().unwrap();
+        blockchain.mine().unwrap();
+        assert_eq!(blockchain.block_at(4).unwrap().header.version, FIRST_VERSION, "locked in, no more signals");
+        blockchain.mine().unwrap();
+        assert_eq!(blockchain.last_block().header.version, 2);
+        assert!(blockchain.valid_chain(&blockchain.chain), "valid across the activation");
+
+        let mut other = Blockchain::new_with(1);
+        other.add_deployment(deployment).unwrap();
+        other.replace(blockchain.chain().clone());
+        assert_eq!(other.policy().activation(2), Some(5), "locked in from the chain");
+        assert_eq!(Blockchain::new_with(1).valid_prefix(blockchain.chain()), 4, "without the deployment");
+    }
+
+    #[test]
+    fn mine_rewards_miner_address() {
+        let mut blockchain = Blockchain::new_with(1);
+        blockchain.set_miner_address("miner".into());
+        let block = blockchain.mine().unwrap();
+        let reward = block.reward().expect("expected a reward");
+        assert_eq!(reward.recipient, String::from("miner"));
+        assert!(reward.is_reward());
+    }
+
+    #[test]
+    fn append() {
+        let mut blockchain = Blockchain::new_with(1);
+        blockchain.new_transaction(Transaction::new("a".into(), "b".into(), 1));
+        let mut other = Blockchain::new_with(1);
+        other.replace(blockchain.chain().clone());
+        let mined = blockchain.mine().unwrap().clone();
+
+        assert!(other.append(mined.clone()).is_ok());
+        assert_eq!(other.chain(), blockchain.chain());
+        assert_eq!(other.state(), blockchain.state());
+        assert!(other.append(mined).is_err(), "already on the chain");
+    }
+
+    #[test]
+    fn state_at() {
+        let mut blockchain = Blockchain::new_with(1);
+        blockchain.set_miner_address("miner".into());
+        blockchain.mine().unwrap();
+        blockchain.mine().unwrap();
+
+        assert_eq!(blockchain.state_at(1).unwrap().balance("miner"), 0);
+        assert_eq!(blockchain.state_at(2).unwrap().balance("miner"), 1);
+        assert_eq!(blockchain.state_at(3).as_ref(), Some(blockchain.state()));
+        assert!(blockchain.state_at(0).is_none());
+        assert!(blockchain.state_at(4).is_none());
+        assert_eq!(blockchain.block_at(2).unwrap().header.index, 2);
+    }
+
+    #[test]
+    fn find_by_hash_and_id() {
+        let mut blockchain = Blockchain::new_with(1);
+        let confirmed = Transaction::new("a".into(), "b".into(), 1);
+        blockchain.new_transaction(confirmed.clone());
+        blockchain.mine().unwrap();
+        let pending = Transaction::new("c".into(), "d".into(), 2);
+        blockchain.new_transaction(pending.clone());
+
+        let tip = blockchain.hash_last_block();
+        assert_eq!(blockchain.block_by_hash(&tip).map(|block| block.header.index), Some(2));
+        assert!(blockchain.block_by_hash("nope").is_none());
+
+        let (txn, block) = blockchain.find_transaction(&blockchain.transaction_id(&confirmed)).expect("confirmed");
+        assert_eq!(txn, &confirmed);
+        assert_eq!(block.map(|block| block.header.index), Some(2));
+        let (txn, block) = blockchain.find_transaction(&blockchain.transaction_id(&pending)).expect("pending");
+        assert_eq!(txn, &pending);
+        assert!(block.is_none());
+    }
+
+    #[test]
+    fn incoming() {
+        let mut blockchain = Blockchain::new_with(1);
+        let first = Transaction::new("a".into(), "shop".into(), 1);
+        let second = Transaction::new("b".into(), "shop".into(), 2);
+        blockchain.new_transaction(first.clone());
+        blockchain.new_transaction(second.clone());
+        blockchain.mine().unwrap();
+        blockchain.mine().unwrap();
+        let third = Transaction::new("c".into(), "shop".into(), 3);
+        blockchain.new_transaction(third.clone());
+        blockchain.mine().unwrap();
+
+        let paid: Vec<(usize, &Transaction)> = blockchain.incoming("shop", 0).into_iter()
+                                                         .map(|(block, txn)| (block.header.index, txn))
+                                                         .collect();
+        assert_eq!(paid.len(), 3);
+        assert_eq!(paid[2], (4, &third));
+        assert!(paid.iter().all(|&(height, _)| height == 2 || height == 4));
+        assert_eq!(blockchain.incoming("shop", 2).len(), 1);
+        assert!(blockchain.incoming("shop", 4).is_empty());
+        assert!(blockchain.incoming("nobody", 0).is_empty());
+
+        let chain = blockchain.chain().clone();
+        let mut other = Blockchain::new_with(1);
+        other.replace(chain);
+        assert_eq!(other.incoming("shop", 0).len(), 3, "rebuilt for a new chain");
+    }
+
+    #[test]
+    fn pruned_indexes() {
+        let mut blockchain = Blockchain::new_with(1);
+        let old = Transaction::new("a".into(), "shop".into(), 1);
+        blockchain.new_transaction(old.clone());
+        blockchain.mine().unwrap();
+        blockchain.set_prune_depth(Some(2));
+        for amount in 2..5 {
+            blockchain.new_transaction(Transaction::new("b".into(), "shop".into(), amount));
+            blockchain.mine().unwrap();
+        }
+        assert_eq!(blockchain.pruned(), 3);
+
+        let (txn, block) = blockchain.find_transaction(&blockchain.transaction_id(&old)).expect("scanned");
+        assert_eq!(txn, &old);
+        assert_eq!(block.map(|block| block.header.index), Some(2));
+        let heights: Vec<usize> = blockchain.incoming("shop", 0).into_iter().map(|(block, _)| block.header.index).collect();
+        assert_eq!(heights, vec![2, 3, 4, 5], "pruned blocks scanned, the rest indexed");
+        assert_eq!(blockchain.incoming("shop", 3).len(), 2);
+
+        blockchain.set_prune_depth(None);
+        assert_eq!(blockchain.pruned(), 0);
+        assert_eq!(blockchain.incoming("shop", 1).len(), 4);
+    }
+
+    #[test]
+    fn expired_transactions() {
+        let mut blockchain = Blockchain::new_with(1);
+        let expiring = Transaction::new("a".into(), "b".into(), 1).expiring(2);
+        let later = Transaction::new("c".into(), "d".into(), 1).expiring(3);
+        blockchain.new_transaction(expiring.clone());
+        blockchain.new_transaction(later.clone());
+        blockchain.mine().unwrap();
+        assert!(blockchain.last_block().transactions.contains(&expiring), "block 2 is in time");
+
+        blockchain.new_transaction(Transaction::new("e".into(), "f".into(), 1).expiring(2));
+        assert_eq!(blockchain.current_transactions().len(), 1);
+        blockchain.mine().unwrap();
+        assert_eq!(blockchain.last_block().transactions.len(), 1, "only the reward, the expired transaction was dropped");
+
+        //a peer's block with an expired transaction
+        let mut other = Blockchain::new_with(1);
+        other.replace(blockchain.chain().iter().take(2).cloned().collect());
+        other.current_transactions.insert(later.clone().expiring(2));
+        let previous_hash = other.hash_last_block();
+        let mut block = other.create_block(0, previous_hash);
+        block.header.nonce = other.proof_of_work(&block.header);
+        let mut chain = other.chain().clone();
+        chain.insert(block.clone());
+        assert!(!blockchain.valid_chain(&chain));
+        assert!(other.append(block).is_err());
+    }
+
+    #[test]
+    fn block_limit() {
+        let mut blockchain = Blockchain::new_with(1);
+        let txn = |sender: &str, amount| Transaction::new(sender.into(), "z".into(), amount);
+        //arriving out of the set's order
+        blockchain.new_transaction(txn("b", 1));
+        blockchain.new_transaction(txn("a", 2));
+        blockchain.new_transaction(txn("a", 3));
+        blockchain.set_max_block_transactions(Some(2));
+        blockchain.mine().expect("mined");
+        assert!(blockchain.last_block().transactions.contains(&txn("b", 1)), "oldest first");
+        assert_eq!(blockchain.last_block().transactions.len(), 3, "two and the reward");
+        assert_eq!(blockchain.current_transactions().iter().cloned().collect::<Vec<_>>(), vec![txn("a", 3)], "left for the next block");
+
+        blockchain.new_transaction(txn("a", 4));
+        blockchain.new_transaction(txn("c", 5));
+        blockchain.set_selector(Arc::new(Strategy::PerSender));
+        assert!(blockchain.preview().transactions.contains(&txn("c", 5)), "a turn each, not both of a's");
+        blockchain.set_max_block_transactions(None);
+        assert_eq!(blockchain.preview().transactions.len(), 4);
+    }
+
+    #[test]
+    fn verify() {
+        let mut blockchain = Blockchain::new_with(1);
+        blockchain.mine().unwrap();
+        blockchain.mine().unwrap();
+        assert_eq!(blockchain.verify(blockchain.chain()), Verification { length: 3, valid: 3, error: None });
+
+        let mut blocks: Vec<Block> = blockchain.chain().iter().cloned().collect();
+        blocks[1].header.previous_hash = "bogus".into();
+        assert_eq!(blockchain.verify(&blocks), Verification {
+            length: 3,
+            valid: 1,
+            error: Some(String::from("block 2: previous hash isn't the hash of block 1"))
+        });
+
+        let skipped = vec![blocks[0].clone(), blocks[2].clone()];
+        assert_eq!(blockchain.verify(&skipped).error, Some(String::from("block 2: index is 3")));
+    }
+
+    #[test]
+    fn verify_in_parallel() {
+        let mut blockchain = Blockchain::new_with(1);
+        for _ in 0..PARALLEL_MIN_BLOCKS {
+            blockchain.mine().unwrap();
+        }
+        let length = PARALLEL_MIN_BLOCKS + 1;
+        assert_eq!(blockchain.verify(blockchain.chain()), Verification { length: length, valid: length, error: None });
+
+        //in the last thread's share, and after it
+        let mut blocks: Vec<Block> = blockchain.chain().iter().cloned().collect();
+        blocks[length - 3].transactions.insert(Transaction::new("a".into(), "b".into(), 1000));
+        blocks[length - 1].header.nonce += 1;
+        assert_eq!(blockchain.verify(&blocks), Verification {
+            length: length,
+            valid: length - 3,
+            error: Some(format!("block {}: merkle root doesn't match its transactions", length - 2))
+        });
+    }
+
+    #[test]
+    fn replace_keeps_orphans() {
+        let mut blockchain = Blockchain::new_with(1);
+        blockchain.mine().unwrap();
+        let abandoned = blockchain.last_block().clone();
+
+        let mut other = Blockchain::new_with(1);
+        other.set_miner_address("other miner".into());
+        other.replace(blockchain.chain().iter().take(1).cloned().collect());
+        other.mine().unwrap();
+        other.mine().unwrap();
+        blockchain.replace(other.chain().clone());
+
+        assert_eq!(blockchain.orphans().len(), 1, "the genesis block was shared, only block 2 was abandoned");
+        assert!(blockchain.orphans().contains(&abandoned));
+
+        //a different genesis isn't a fork. Genesis blocks are the same at the same difficulty
+        let mut stranger = Blockchain::new_with(2);
+        stranger.replace(blockchain.chain().clone());
+        assert!(stranger.orphans().is_empty());
+    }
+
+    #[test]
+    fn memory_caps() {
+        let mut blockchain = Blockchain::new_with(1);
+        let txn = |sender: &str| Transaction::new(sender.into(), "b".into(), 5);
+        blockchain.new_transaction(txn("a"));
+        blockchain.mine().unwrap();
+        assert_eq!(blockchain.usage().chain, memory::blocks_bytes(blockchain.chain()));
+        let abandoned = blockchain.last_block().clone();
+
+        let mut other = Blockchain::new_with(1);
+        other.mine().unwrap();
+        other.mine().unwrap();
+        blockchain.set_caps(Caps { orphans: memory::block_bytes(&abandoned) - 1, ..Caps::default() });
+        blockchain.new_transaction(txn("c"));
+        blockchain.replace(other.chain().clone());
+        assert_eq!(blockchain.usage().chain, memory::blocks_bytes(other.chain()));
+        assert!(blockchain.orphans().is_empty(), "block 2 is over the orphans' cap");
+        assert_eq!(blockchain.current_transactions().len(), 2, "a's requeued");
+
+        blockchain.set_caps(Caps { mempool: memory::transaction_bytes(&txn("a")), ..Caps::default() });
+        assert_eq!(blockchain.current_transactions().len(), 1);
+        assert_eq!(blockchain.usage().mempool, memory::transaction_bytes(&txn("a")));
+    }
+
+    #[test]
+    fn replace_requeues_orphaned_transactions() {
+        let mut blockchain = Blockchain::new_with(1);
+        blockchain.new_transaction(Transaction::new("a".into(), "b".into(), 1));
+        blockchain.mine().unwrap();
+        blockchain.new_transaction(Transaction::new("c".into(), "d".into(), 2));
+
+        let mut other = Blockchain::new_with(1);
+        other.new_transaction(Transaction::new("c".into(), "d".into(), 2));
+        other.mine().unwrap();
+        other.mine().unwrap();
+
+        blockchain.replace(other.into_chain());
+        assert_eq!(blockchain.current_transactions.len(), 1, "a->b requeued, c->d already confirmed, reward dropped");
+        let requeued = blockchain.current_transactions.iter().next().unwrap();
+        assert_eq!(requeued.sender, String::from("a"));
+    }
+
     #[test]
     #[cfg(feature = "mining-tests")]    
     fn valid_chain_ok() {
@@ -0,0 +1,120 @@
+use chrono::offset::Utc;
+use lib::blockchain::{Blockchain, MIN_DIFFICULTY};
+use lib::consensus::{Candidate, Consensus};
+use lib::error::{Error, Result};
+use lib::hasher::HashAlgorithm;
+use lib::peers::Peers;
+use lib::store::{self, BlockFile};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+use url::Url;
+
+//`init --from` sets up a data dir from a peer in one go: it downloads the peer's
+//chain, genesis included, checking each block as it arrives, like a consensus
+//round would, stores it, and saves the peer as the only one we know. A node
+//started on the data dir loads the chain and the peer, so it needs no --seed
+//and its first consensus round only has to catch up on what's been mined since.
+//Nothing is written until the whole chain has checked out.
+
+///
+/// What `init` set up
+///
+/// height: The length of the chain stored
+/// tip: The hash of its last block
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Initialized {
+    pub height: usize,
+    pub tip: String
+}
+
+///
+/// Fill the fresh data dir `dir` from `peer`'s chain, at most `limit` bytes of
+/// it, and save `peer` to `peers_file`
+///
+pub fn init(dir: &Path, peers_file: &Path, peer: &Url, timeout: Duration, network: &str, hasher: HashAlgorithm, limit: u64) -> Result<Initialized> {
+    //so one mistyped path can't mix two chains
+    match fs::read_dir(dir) {
+        Ok(mut entries) => if entries.next().is_some() {
+            return Err(Error::Validation(format!("{} isn't empty, init only sets up a fresh data dir", dir.display())));
+        },
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {},
+        Err(e) => return Err(e.into())
+    }
+    let mut blockchain = Blockchain::new_with_hasher(MIN_DIFFICULTY, hasher);
+    let candidate = Candidate { node: String::from(peer.as_str().trim_right_matches('/')), work: None, addresses: Vec::new() };
+    let fetched = Consensus::fetch(&[candidate], timeout, network, &blockchain.verifier(), limit, |_| true);
+    let chain = match fetched.into_iter().next().map(|fetched| fetched.result) {
+        Some(Ok(ref chain)) if chain.is_empty() => return Err(Error::Validation(format!("{} sent an empty chain", peer))),
+        Some(chain) => chain?,
+        None => return Err(Error::Network(format!("Couldn't ask {} for its chain", peer)))
+    };
+    blockchain.replace(chain);
+
+    let mut block_file = BlockFile::open(dir)?;
+    store::sync(&mut block_file, &blockchain)?;
+    let mut peers = Peers::new();
+    peers.register(peer.clone());
+    peers.seen(peer, Utc::now().timestamp());
+    peers.save(peers_file)?;
+    Ok(Initialized { height: blockchain.len(), tip: blockchain.hash_last_block() })
+}
+
+#[cfg(test)]
+mod tests {
+    use lib::blockchain::Blockchain;
+    use lib::bootstrap::*;
+    use lib::consensus::MAX_CHAIN_BYTES;
+    use lib::peers::Peers;
+    use lib::protocol::{DEFAULT_NETWORK, NETWORK_HEADER, PROTOCOL_VERSION, VERSION_HEADER};
+    use lib::store::{BlockFile, ChainStore};
+    use serde_json;
+    use std::env;
+    use std::fs;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+    use url::Url;
+
+    //answers one request with `theirs` as GET /chain does
+    fn serve(theirs: &Blockchain) -> Url {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("listening");
+        let url = Url::parse(&format!("http://{}", listener.local_addr().expect("an address"))).expect("valid url");
+        let body = format!("{{\"chain\": {}, \"length\": {}}}", serde_json::to_string(theirs.chain()).expect("serialized"), theirs.len());
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().expect("a request");
+            let mut reader = BufReader::new(stream.try_clone().expect("cloned"));
+            let mut line = String::new();
+            while reader.read_line(&mut line).expect("read") > 2 {
+                line.clear();
+            }
+            let head = format!("HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n{}: {}\r\n{}: {}\r\nConnection: close\r\n\r\n",
+                               body.len(), NETWORK_HEADER, DEFAULT_NETWORK, VERSION_HEADER, PROTOCOL_VERSION);
+            stream.write_all(head.as_bytes()).and_then(|_| stream.write_all(body.as_bytes())).expect("answered");
+        });
+        url
+    }
+
+    #[test]
+    fn from_peer() {
+        let dir = env::temp_dir().join("learnnet-bootstrap-from_peer");
+        let _ = fs::remove_dir_all(&dir);
+        let peers_file = dir.join("peers.json");
+        let mut theirs = Blockchain::new_with(1);
+        theirs.mine().expect("mined");
+        theirs.mine().expect("mined");
+        let peer = serve(&theirs);
+
+        let initialized = init(&dir, &peers_file, &peer, Duration::from_secs(5), DEFAULT_NETWORK, Default::default(), MAX_CHAIN_BYTES).expect("initialized");
+        assert_eq!(initialized, Initialized { height: 3, tip: theirs.hash_last_block() });
+        assert_eq!(BlockFile::open(&dir).expect("opened").len(), 3);
+        let peers = Peers::load(&peers_file).expect("loaded");
+        assert!(peers.info(&peer).and_then(|info| info.last_seen).is_some());
+
+        let again = init(&dir, &peers_file, &peer, Duration::from_secs(1), DEFAULT_NETWORK, Default::default(), MAX_CHAIN_BYTES);
+        assert!(again.is_err(), "not a fresh data dir");
+    }
+}
@@ -0,0 +1,265 @@
+use lib::blockchain::Block;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::ser::SerializeSeq;
+use std::collections::BTreeSet;
+use std::iter::FromIterator;
+use std::slice;
+use std::sync::Arc;
+
+///
+/// Blocks per segment. Adding a block copies at most one segment
+///
+pub const SEGMENT_LEN: usize = 256;
+
+///
+/// An ordered set of `Block`s, lowest first, kept in shared segments of
+/// `SEGMENT_LEN` blocks.
+///
+/// Cloning a `Chain` only clones its list of segments, so a clone is a snapshot
+/// readers can keep without holding any lock. Adding a block to the end copies
+/// the last segment if a snapshot still shares it, never the whole chain.
+/// Inserting anywhere else rebuilds the segments, which only happens while
+/// putting a chain together from unordered blocks.
+///
+#[derive(Debug, Clone, Default)]
+pub struct Chain {
+    //every segment but the last is full, so a block's position says which segment it's in
+    segments: Vec<Arc<Vec<Block>>>,
+    len: usize
+}
+
+impl Chain {
+    pub fn new() -> Chain {
+        Chain::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    ///
+    /// The block at `position`, counting from 0
+    ///
+    pub fn get(&self, position: usize) -> Option<&Block> {
+        self.segments.get(position / SEGMENT_LEN).and_then(|segment| segment.get(position % SEGMENT_LEN))
+    }
+
+    pub fn last(&self) -> Option<&Block> {
+        self.segments.last().and_then(|segment| segment.last())
+    }
+
+    pub fn iter(&self) -> Iter {
+        Iter {
+            segments: self.segments.iter(),
+            current: None,
+            remaining: self.len
+        }
+    }
+
+    pub fn contains(&self, block: &Block) -> bool {
+        self.position(block).is_ok()
+    }
+
+    ///
+    /// Add a block, unless it's already there
+    ///
+    /// returns: false if it was already there
+    pub fn insert(&mut self, block: Block) -> bool {
+        match self.position(&block) {
+            Ok(_) => false,
+            Err(position) if position == self.len => {
+                self.push(block);
+                true
+            },
+            Err(position) => {
+                let mut blocks: Vec<Block> = self.iter().cloned().collect();
+                blocks.insert(position, block);
+                *self = Chain::from_sorted(blocks);
+                true
+            }
+        }
+    }
+
+    fn push(&mut self, block: Block) {
+        let full = self.segments.last().map(|segment| segment.len() == SEGMENT_LEN).unwrap_or(true);
+        if full {
+            self.segments.push(Arc::new(Vec::with_capacity(SEGMENT_LEN)));
+        }
+        Arc::make_mut(self.segments.last_mut().expect("invariant: a segment with room")).push(block);
+        self.len += 1;
+    }
+
+    fn from_sorted<I>(blocks: I) -> Chain where I: IntoIterator<Item = Block> {
+        let mut chain = Chain::new();
+        for block in blocks {
+            chain.push(block);
+        }
+        chain
+    }
+
+    ///
+    /// Binary search for `block`
+    ///
+    /// returns: Its position, or where it would go
+    fn position(&self, block: &Block) -> Result<usize, usize> {
+        let (mut low, mut high) = (0, self.len);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            let other = self.get(middle).expect("invariant: middle < len");
+            if other == block {
+                return Ok(middle);
+            } else if other < block {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+        Err(low)
+    }
+}
+
+///
+/// The blocks of a `Chain`, lowest first
+///
+pub struct Iter<'a> {
+    segments: slice::Iter<'a, Arc<Vec<Block>>>,
+    current: Option<slice::Iter<'a, Block>>,
+    remaining: usize
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a Block;
+
+    fn next(&mut self) -> Option<&'a Block> {
+        loop {
+            if let Some(block) = self.current.as_mut().and_then(|current| current.next()) {
+                self.remaining -= 1;
+                return Some(block);
+            }
+            match self.segments.next() {
+                Some(segment) => self.current = Some(segment.iter()),
+                None => return None
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a> IntoIterator for &'a Chain {
+    type Item = &'a Block;
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+///
+/// Blocks in any order, like a `BTreeSet`. Duplicates are dropped
+///
+impl FromIterator<Block> for Chain {
+    fn from_iter<I>(blocks: I) -> Chain where I: IntoIterator<Item = Block> {
+        let sorted: BTreeSet<Block> = blocks.into_iter().collect();
+        Chain::from_sorted(sorted)
+    }
+}
+
+impl PartialEq for Chain {
+    fn eq(&self, other: &Chain) -> bool {
+        self.len == other.len && self.iter().eq(other.iter())
+    }
+}
+
+impl Eq for Chain {}
+
+///
+/// A json array of blocks, the same as the `BTreeSet<Block>` chains used to be
+///
+impl Serialize for Chain {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        let mut seq = serializer.serialize_seq(Some(self.len))?;
+        for block in self.iter() {
+            seq.serialize_element(block)?;
+        }
+        seq.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Chain {
+    fn deserialize<D>(deserializer: D) -> Result<Chain, D::Error> where D: Deserializer<'de> {
+        let blocks: BTreeSet<Block> = BTreeSet::deserialize(deserializer)?;
+        Ok(Chain::from_sorted(blocks))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lib::blockchain::{Block, Blockchain};
+    use lib::chain::*;
+    use serde_json;
+    use std::collections::BTreeSet;
+    use std::sync::Arc;
+
+    fn blocks(count: usize) -> Vec<Block> {
+        let genesis = Blockchain::new_with(1).last_block().clone();
+        (1..count + 1).map(|index| {
+            let mut block = genesis.clone();
+            block.header.index = index;
+            block
+        }).collect()
+    }
+
+    #[test]
+    fn segments() {
+        let count = SEGMENT_LEN * 2 + 3;
+        let mut chain = Chain::new();
+        for block in blocks(count) {
+            assert!(chain.insert(block));
+        }
+        assert_eq!(chain.len(), count);
+        assert_eq!(chain.segments.len(), 3);
+        assert_eq!(chain.get(SEGMENT_LEN).map(|block| block.header.index), Some(SEGMENT_LEN + 1));
+        assert_eq!(chain.last().map(|block| block.header.index), Some(count));
+        assert!(chain.get(count).is_none());
+        assert_eq!(chain.iter().count(), count);
+        assert!(!chain.insert(blocks(1).remove(0)), "already there");
+    }
+
+    #[test]
+    fn snapshots_share_segments() {
+        let mut blocks = blocks(SEGMENT_LEN + 2);
+        let last = blocks.pop().expect("a block");
+        let mut chain: Chain = blocks.into_iter().collect();
+        let snapshot = chain.clone();
+        chain.insert(last);
+        assert_eq!(snapshot.len(), SEGMENT_LEN + 1, "a snapshot doesn't change");
+        assert_eq!(chain.len(), SEGMENT_LEN + 2);
+        assert!(Arc::ptr_eq(&chain.segments[0], &snapshot.segments[0]), "full segments aren't copied");
+    }
+
+    #[test]
+    fn like_a_set() {
+        let mut shuffled = blocks(5);
+        shuffled.swap(0, 4);
+        let duplicate = shuffled[1].clone();
+        shuffled.push(duplicate);
+        let chain: Chain = shuffled.iter().cloned().collect();
+        let set: BTreeSet<Block> = shuffled.into_iter().collect();
+        assert!(chain.iter().eq(set.iter()));
+
+        let mut gappy: Chain = blocks(5).into_iter().filter(|block| block.header.index != 3).collect();
+        assert!(gappy.insert(blocks(3).remove(2)));
+        assert!(gappy.iter().eq(set.iter()), "inserted in the middle");
+
+        let json = serde_json::to_string(&chain).expect("serialized");
+        assert_eq!(json, serde_json::to_string(&set).expect("serialized"));
+        assert_eq!(serde_json::from_str::<Chain>(&json).expect("deserialized"), chain);
+    }
+}
@@ -0,0 +1,252 @@
+use chrono::NaiveDateTime;
+use lib::blockchain::{Blockchain, Chain, Verification, MIN_DIFFICULTY};
+use lib::consensus::Consensus;
+use lib::error::{Error, Result};
+use lib::hasher::HashAlgorithm;
+use serde_json;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::time::Duration;
+use url::Url;
+
+//`chain compare` puts two chains side by side, for working out after the fact
+//what happened in a fork: say the chains of two nodes that disagreed, exported
+//with `chain export --format blocks` or saved from GET /chain, or still on the
+//nodes. Each is checked from genesis by our rules, then the report says where
+//they part ways, what each has above that, and which a consensus round would
+//pick: the longer, as long as it's valid.
+
+///
+/// A block above where the chains part ways
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct BranchBlock {
+    pub height: usize,
+    pub hash: String,
+    pub timestamp: i64,
+    pub difficulty: u64,
+    pub transactions: usize
+}
+
+///
+/// One of the chains compared
+///
+/// source: The file or url it came from
+/// work: The work it proves, see `blockchain::block_work`
+/// verification: How much of it is valid, from genesis
+/// blocks: Its blocks above the last one both chains have
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Branch {
+    pub source: String,
+    pub length: usize,
+    pub work: u64,
+    pub verification: Verification,
+    pub blocks: Vec<BranchBlock>
+}
+
+impl Branch {
+    fn valid(&self) -> bool {
+        self.verification.error.is_none()
+    }
+}
+
+///
+/// Two chains, side by side
+///
+/// common_height: The last block both have. 0 if they don't even share genesis
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    pub common_height: usize,
+    pub a: Branch,
+    pub b: Branch
+}
+
+impl Comparison {
+    pub fn identical(&self) -> bool {
+        self.a.blocks.is_empty() && self.b.blocks.is_empty()
+    }
+}
+
+///
+/// The chain at `source`: a node's url, or a file of blocks, a json block a line,
+/// or a saved GET /chain response
+///
+pub fn load(source: &str, timeout: Duration, network: &str, limit: u64) -> Result<Chain> {
+    let chain = match Url::parse(source) {
+        Ok(ref url) if url.scheme() == "http" || url.scheme() == "https" => Consensus::get_one(url.as_str(), timeout, network, limit)?,
+        _ => read(Path::new(source))?
+    };
+    if chain.is_empty() {
+        return Err(Error::Validation(format!("{} has no blocks", source)));
+    }
+    Ok(chain)
+}
+
+#[derive(Deserialize)]
+struct ChainResponse {
+    chain: Chain
+}
+
+fn read(path: &Path) -> Result<Chain> {
+    let mut text = String::new();
+    File::open(path)?.read_to_string(&mut text)?;
+    if let Ok(response) = serde_json::from_str::<ChainResponse>(&text) {
+        return Ok(response.chain);
+    }
+    let mut blocks = Vec::new();
+    for (number, line) in text.lines().enumerate().filter(|&(_, line)| !line.trim().is_empty()) {
+        let block = serde_json::from_str(line).map_err(|e| Error::Validation(format!("{} line {} isn't a block. {}", path.display(), number + 1, e)))?;
+        blocks.push(block);
+    }
+    Ok(blocks.into_iter().collect())
+}
+
+///
+/// Check `a` and `b`, which came from `sources`, and find where they part ways
+///
+pub fn compare(a: Chain, b: Chain, sources: (&str, &str), hasher: HashAlgorithm) -> Comparison {
+    let mut ours = Blockchain::new_with_hasher(MIN_DIFFICULTY, hasher);
+    ours.replace(a);
+    let mut theirs = Blockchain::new_with_hasher(MIN_DIFFICULTY, hasher);
+    theirs.replace(b);
+    let headers: Vec<_> = theirs.chain().iter().map(|block| block.header.clone()).collect();
+    let diff = Consensus::diff(&ours, &headers);
+    Comparison {
+        common_height: diff.common_height,
+        a: branch(sources.0, &ours, diff.common_height),
+        b: branch(sources.1, &theirs, diff.common_height)
+    }
+}
+
+fn branch(source: &str, blockchain: &Blockchain, common_height: usize) -> Branch {
+    Branch {
+        source: String::from(source),
+        length: blockchain.len(),
+        work: blockchain.work(),
+        verification: blockchain.verify(blockchain.chain()),
+        blocks: blockchain.chain().iter().zip(blockchain.hashes()).skip(common_height).map(|(block, hash)| BranchBlock {
+            height: block.header.index,
+            hash: hash.clone(),
+            timestamp: block.header.timestamp,
+            difficulty: block.header.difficulty,
+            transactions: block.transactions.len()
+        }).collect()
+    }
+}
+
+const RED: &'static str = "\x1b[31m";
+const GREEN: &'static str = "\x1b[32m";
+const YELLOW: &'static str = "\x1b[33m";
+const BOLD: &'static str = "\x1b[1m";
+const RESET: &'static str = "\x1b[0m";
+
+///
+/// Write `comparison` for reading, `a`'s blocks marked - and `b`'s +, like a
+/// diff. With `color` they're red and green, and problems yellow
+///
+pub fn report<W>(comparison: &Comparison, color: bool, out: &mut W) -> Result<()> where W: Write {
+    let paint = |code: &str, text: String| if color { format!("{}{}{}", code, text, RESET) } else { text };
+    for &(name, branch) in [("a", &comparison.a), ("b", &comparison.b)].iter() {
+        let validity = match branch.verification.error {
+            None => String::from("valid"),
+            Some(ref error) => paint(YELLOW, format!("invalid from block {}, {}", branch.verification.valid + 1, error))
+        };
+        writeln!(out, "{} {}: {} blocks, work {}, {}", paint(BOLD, String::from(name)), branch.source, branch.length, branch.work, validity)?;
+    }
+    if comparison.identical() {
+        writeln!(out, "the chains are the same")?;
+        return Ok(());
+    }
+    match comparison.common_height {
+        0 => writeln!(out, "{}", paint(YELLOW, String::from("no blocks in common, not even genesis: they're different networks")))?,
+        height => writeln!(out, "both have the first {} blocks, they part ways above block {}", height, height)?
+    }
+    for &(name, mark, code, branch) in [("a", '-', RED, &comparison.a), ("b", '+', GREEN, &comparison.b)].iter() {
+        writeln!(out, "{} only, {} blocks:", name, branch.blocks.len())?;
+        for block in branch.blocks.iter() {
+            let at = NaiveDateTime::from_timestamp_opt(block.timestamp, 0).map(|at| at.to_string()).unwrap_or_else(|| block.timestamp.to_string());
+            writeln!(out, "{}", paint(code, format!("{} {:>6} {} {} difficulty {}, {} transactions",
+                                                     mark, block.height, block.hash, at, block.difficulty, block.transactions)))?;
+        }
+    }
+    let (a, b) = (&comparison.a, &comparison.b);
+    if a.work != b.work {
+        let (more, less) = if a.work > b.work { ("a", b.work) } else { ("b", a.work) };
+        writeln!(out, "{} proves {} more work", more, a.work.max(b.work) - less)?;
+    } else {
+        writeln!(out, "both prove the same work")?;
+    }
+    let verdict = match (a.valid(), b.valid()) {
+        (false, false) => String::from("neither is valid, consensus would take neither"),
+        (true, false) => String::from("only a is valid, consensus would keep a"),
+        (false, true) => String::from("only b is valid, consensus would keep b"),
+        _ if a.length == b.length => String::from("they're the same length, consensus leaves each node on the one it has"),
+        _ => {
+            let (longer, shorter) = if a.length > b.length { ("a", "b") } else { ("b", "a") };
+            format!("consensus would move a node on {} to {}, the longer", shorter, longer)
+        }
+    };
+    writeln!(out, "{}", paint(BOLD, verdict))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use lib::blockchain::Blockchain;
+    use lib::compare::*;
+    use lib::transaction::Transaction;
+    use serde_json;
+    use std::env;
+    use std::fs::File;
+    use std::io::Write;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    #[test]
+    fn fork() {
+        let mut a = Blockchain::new_with(1);
+        a.mine().expect("mined");
+        let mut b = Blockchain::new_with(1);
+        b.replace(a.chain().clone());
+        a.mine().expect("mined");
+        a.mine().expect("mined");
+        b.new_transaction(Transaction::new("x".into(), "y".into(), 1));
+        b.mine().expect("mined");
+
+        //one as a file of blocks, the other as GET /chain answered it
+        let dir = env::temp_dir();
+        let (a_path, b_path) = (dir.join("learnnet-compare-fork-a.jsonl"), dir.join("learnnet-compare-fork-b.json"));
+        {
+            let mut file = File::create(&a_path).expect("created");
+            for block in a.chain().iter() {
+                writeln!(file, "{}", serde_json::to_string(block).expect("serialized")).expect("written");
+            }
+            let response = format!("{{\"chain\": {}, \"length\": {}}}", serde_json::to_string(b.chain()).expect("serialized"), b.len());
+            File::create(&b_path).and_then(|mut file| file.write_all(response.as_bytes())).expect("written");
+        }
+        let loaded = |path: &PathBuf| load(path.to_str().expect("a path"), Duration::from_secs(1), "learnnet", u64::max_value()).expect("loaded");
+        let comparison = compare(loaded(&a_path), loaded(&b_path), ("a.jsonl", "b.json"), Default::default());
+        assert_eq!(comparison.common_height, 2);
+        assert_eq!(comparison.a.blocks.iter().map(|block| block.height).collect::<Vec<_>>(), vec![3, 4]);
+        assert_eq!(comparison.b.blocks.len(), 1);
+        assert_eq!(comparison.b.blocks[0].transactions, 2, "with the reward");
+        assert!(comparison.a.work > comparison.b.work);
+        assert!(!comparison.identical());
+
+        let mut plain = Vec::new();
+        report(&comparison, false, &mut plain).expect("reported");
+        let plain = String::from_utf8(plain).expect("text");
+        assert!(plain.contains("they part ways above block 2"), "{}", plain);
+        assert!(plain.contains("consensus would move a node on b to a"), "{}", plain);
+        assert!(!plain.contains("\x1b["), "no color");
+        let mut colored = Vec::new();
+        report(&comparison, true, &mut colored).expect("reported");
+        assert!(String::from_utf8(colored).expect("text").contains("\x1b[31m-"));
+
+        let same = compare(a.chain().clone(), a.chain().clone(), ("a", "a"), Default::default());
+        assert!(same.identical());
+    }
+}
@@ -1,100 +1,530 @@
 
-use lib::blockchain::{Chain,Blockchain};
+use lib::blockchain::{chain_work, Block, Chain, Blockchain, BlockHeader, Verifier};
+use lib::error::{Error, Result};
+use lib::protocol::{self, Limited, NETWORK_HEADER, VERSION_HEADER, PROTOCOL_VERSION};
+use serde::de::{self, Deserializer, DeserializeSeed, IgnoredAny, MapAccess, SeqAccess, Visitor};
 use serde_json;
-use reqwest::{Client, StatusCode};
-use std::io::{Read};
+use reqwest::{Client, Response, StatusCode};
+use reqwest::header::{ContentType, Headers};
+use std::cell::Cell;
+use std::fmt;
+use std::io::{self, Read};
+use std::net::SocketAddr;
+use std::result;
+use std::str;
+use std::time::{Duration, Instant};
+use url::Url;
+
+///
+/// The most json any response from a peer may be, and a chain download unless
+/// `memory::Caps` says otherwise. The download is dropped once more than this
+/// has arrived
+/// 
+pub const MAX_CHAIN_BYTES: u64 = 64 * 1024 * 1024;
 
 #[derive(Deserialize)]
 struct ChainResponse {
     chain: Chain
 }
 
+#[derive(Deserialize)]
+struct HeadersResponse {
+    headers: Vec<BlockHeader>
+}
+
+//a POST /nodes/register body
+#[derive(Serialize)]
+struct Announcement<'a> {
+    nodes: Vec<&'a str>
+}
+
+///
+/// Where our chain and a node's part ways, going by header hashes
+/// 
+/// common_height: The last block both have. 0 if they don't even share genesis
+/// ours: Our blocks above it
+/// theirs: Their blocks above it
+/// 
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ChainDiff {
+    pub common_height: usize,
+    pub ours: Vec<DiffBlock>,
+    pub theirs: Vec<DiffBlock>
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DiffBlock {
+    pub height: usize,
+    pub hash: String
+}
+
+///
+/// A node to ask for its chain
+/// 
+/// work: The work of the chain it sent last time, if it ever has, see `chain_work`
+/// addresses: Where its host resolved to, tried in turn. Empty leaves resolving
+///            to the http client
+/// 
+#[derive(Debug, Clone, PartialEq)]
+pub struct Candidate {
+    pub node: String,
+    pub work: Option<u64>,
+    pub addresses: Vec<SocketAddr>
+}
+
+///
+/// What a node sent when asked for its chain, how that went, and how long it
+/// took
+/// 
+/// bytes: How much of its answer was read, however it went
+/// 
+#[derive(Debug)]
+pub struct Fetched {
+    pub node: String,
+    pub result: Result<Chain>,
+    pub outcome: Outcome,
+    pub latency: Duration,
+    pub bytes: u64
+}
+
+///
+/// How asking a node for its chain went. A node that fails to answer may do
+/// better next round. A node we reject answered, but with something we won't
+/// take, and will likely send it again
+/// 
+/// Sent: A chain that checked out
+/// Timeout: No answer within the timeout
+/// Unreachable: Couldn't connect, or answered with an http error
+/// Malformed: Answered with something that isn't a chain
+/// TooBig: Sent more than we'd download
+/// Rejected: On another network, or speaking another protocol version
+/// Invalid: Sent a chain that breaks the rules
+/// 
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Sent,
+    Timeout,
+    Unreachable,
+    Malformed,
+    TooBig,
+    Rejected,
+    Invalid
+}
+
+impl Outcome {
+    ///
+    /// How a request that took `latency` went, with a client that gives up after
+    /// `timeout`
+    /// 
+    pub fn of(result: &Result<Chain>, latency: Duration, timeout: Duration) -> Outcome {
+        match *result {
+            Ok(_) => Outcome::Sent,
+            Err(Error::Network(_)) if latency >= timeout => Outcome::Timeout,
+            Err(Error::Network(_)) | Err(Error::Storage(_)) | Err(Error::Lock(_)) | Err(Error::Cluster(_)) => Outcome::Unreachable,
+            Err(Error::Serialization(_)) => Outcome::Malformed,
+            Err(Error::Limit(_)) => Outcome::TooBig,
+            Err(Error::Protocol(_)) => Outcome::Rejected,
+            Err(Error::Validation(_)) => Outcome::Invalid
+        }
+    }
+
+    ///
+    /// Whether the node failed to answer, rather than being rejected for what it
+    /// answered
+    /// 
+    pub fn is_failure(&self) -> bool {
+        match *self {
+            Outcome::Timeout | Outcome::Unreachable | Outcome::Malformed => true,
+            _ => false
+        }
+    }
+}
+
 pub struct Consensus;
 impl Consensus {
 
-    pub fn resolve_conflicts(blockchain: &mut Blockchain) -> bool {
-        
-        let nodes: Vec<String> = blockchain
-                                    .nodes()
-                                    .iter()
-                                    .cloned()
-                                    .map(|node| node.into_string())
-                                    .collect();
-        
-        let neighbour_chains = Self::get(nodes.as_slice());
-        Self::take_authoritive(blockchain, neighbour_chains)
+    ///
+    /// Replace our chain with the valid one that proves the most work, if it
+    /// proves more than ours
+    /// 
+    /// returns: true if our chain was replaced
+    pub fn take_authoritive(blockchain: &mut Blockchain, chains: Vec<Chain>) -> bool {
+        Self::adopt(blockchain, chains).0.is_some()
     }
 
-    fn take_authoritive(blockchain: &mut Blockchain, chains: Vec<Chain>) -> bool {
+    ///
+    /// `take_authoritive`, also saying which chain was adopted, and which claimed
+    /// more work than it (or ours) but were invalid, or mined below our
+    /// difficulty (see `Blockchain::meets_difficulty`). Chains are checked most
+    /// work first, and none after the first valid one is, so chains with less
+    /// work are never validated. A chain's work is read off its headers, so
+    /// ordering them costs no hashing, and checking it proves the work it claims
+    /// 
+    /// returns: The position of the chain that replaced ours, if one did, which
+    ///          is the only chain checked and found valid, and the positions of
+    ///          the invalid chains
+    pub fn adopt(blockchain: &mut Blockchain, chains: Vec<Chain>) -> (Option<usize>, Vec<usize>) {
         
-        let mut is_replaced = false;
+        let mut adopted = None;
         let mut new_chain: Option<Chain> = None;
-        let mut max_length = blockchain.len();
+        let mut invalid = Vec::new();
+        let ours = blockchain.work();
+        let mut most_work_first: Vec<(usize, u64, Chain)> = chains.into_iter()
+                                                                  .enumerate()
+                                                                  .map(|(i, chain)| (i, chain_work(&chain), chain))
+                                                                  .filter(|&(_, work, _)| work > ours)
+                                                                  .collect();
+        //stable, so of chains with as much work as each other the first sent is adopted
+        most_work_first.sort_by(|a, b| b.1.cmp(&a.1));
         
-        for chain in chains {
-            if chain.len() > max_length && blockchain.valid_chain(&chain) {
-                max_length = chain.len();
+        for (i, _, chain) in most_work_first {
+            if blockchain.meets_difficulty(&chain) && blockchain.valid_chain(&chain) {
                 new_chain = Some(chain);
+                adopted = Some(i);
+                break;
             }
+            invalid.push(i);
         }
+        invalid.sort();
         
-        if let Some(longest_chain) = new_chain {
-            blockchain.replace(longest_chain);
-            is_replaced = true;
+        if let Some(best) = new_chain {
+            blockchain.replace(best);
         }
-        is_replaced
+        (adopted, invalid)
     }
    
-    fn get(nodes: &[String]) -> Vec<Chain> {
-        let chains_raw = Self::get_neighbour_chains(nodes);
-        Self::deserialize(chains_raw)
-    }
-
-    fn get_neighbour_chains(nodes: &[String]) -> Vec<String> {
-        let mut chains = Vec::<String>::new();
-        let client = Client::new();
+    ///
+    /// Download the chains of the given nodes, in order. Each request gives up
+    /// after `timeout` so one unresponsive node can't stall the whole round. Nodes
+    /// that fail, or that aren't on `network` at our protocol version, are logged.
+    /// 
+    /// Each chain is checked by a clone of `verifier` as it arrives, and dropped at
+    /// its first invalid block, or once it's more than `limit` bytes, without
+    /// downloading the rest. Once a chain that
+    /// `adoptable` accepts proves more work than every node still to ask last
+    /// sent, the rest are skipped. Nodes that have never sent a chain are always
+    /// asked.
+    /// 
+    /// returns: Each node asked with its chain, or why it failed, so callers can
+    ///          keep track of which peers answer
+    pub fn fetch<F>(candidates: &[Candidate], timeout: Duration, network: &str, verifier: &Verifier, limit: u64, adoptable: F) -> Vec<Fetched>
+        where F: Fn(&Chain) -> bool {
+        let client = match Client::builder().timeout(timeout).build() {
+            Ok(client) => client,
+            Err(e) => {
+                error!("Failed to build http client {:?}", e);
+                return Vec::new();
+            }
+        };
         //upgrade: rayon or tokio-hyper to request async
-        for node in nodes {
-            let url = format!("{}/chain", node);
-
-            match client.get(url.as_str()).send() {
-                Ok(mut res) => {
-                    if res.status() == StatusCode::Ok {
-                        let mut buffer = String::new();
-                        let bytes_read = res.read_to_string(&mut buffer).unwrap_or_else(|e| {
-                            error!("Couldnt' read buffer {}", e);
-                            return 0;
-                        });
-                        if bytes_read > 0 {
-                            chains.push(buffer);
-                        }                        
-                    } else {
-                        error!("Failed to get chain from {}. Response was {:?}. Ignoring", url, res)
+        let mut fetched = Vec::with_capacity(candidates.len());
+        let mut best: Option<u64> = None;
+        for (i, candidate) in candidates.iter().enumerate() {
+            if let Some(best) = best {
+                if beats_all(best, &candidates[i..]) {
+                    info!("Adopted a chain proving {} work. Skipping {} nodes with less", best, candidates.len() - i);
+                    break;
+                }
+            }
+            let started = Instant::now();
+            let (result, bytes) = Self::get_chain(&client, candidate, network, verifier, limit);
+            let latency = started.elapsed();
+            let outcome = Outcome::of(&result, latency, timeout);
+            match result {
+                Ok(ref chain) => {
+                    let work = chain_work(chain);
+                    if best.map(|best| work > best).unwrap_or(true) && adoptable(chain) {
+                        best = Some(work);
                     }
                 },
-                Err(e) => error!("Failed to get chain from {}. Error was {:?}. Ignoring", url, e)
+                Err(ref e) if outcome.is_failure() => warn!("Failed to get chain from {}. {}. Carrying on with the rest", candidate.node, e),
+                Err(ref e) => warn!("Rejected the chain from {}. {}. Carrying on with the rest", candidate.node, e)
+            }
+            fetched.push(Fetched { node: candidate.node.clone(), result: result, outcome: outcome, latency: latency, bytes: bytes });
+        }
+        fetched
+    }
+
+    ///
+    /// Download one node's whole chain, outside of a round, if it's at most
+    /// `limit` bytes
+    /// 
+    pub fn get_one(node: &str, timeout: Duration, network: &str, limit: u64) -> Result<Chain> {
+        let client = Client::builder().timeout(timeout).build()?;
+        let url = format!("{}/chain", node.trim_right_matches('/'));
+        let raw = Self::send_at(&client, &url, None, network).and_then(|response| read_body(&url, response, limit))?;
+        let chain_res: ChainResponse = serde_json::from_str(raw.as_str())?;
+        Ok(chain_res.chain)
+    }
+
+    ///
+    /// Download a node's block headers, which is all `diff` needs
+    /// 
+    pub fn get_headers(node: &str, timeout: Duration, network: &str) -> Result<Vec<BlockHeader>> {
+        let client = Client::builder().timeout(timeout).build()?;
+        let raw = Self::get(&client, &format!("{}/chain/headers", node.trim_right_matches('/')), network)?;
+        let headers_res: HeadersResponse = serde_json::from_str(raw.as_str())?;
+        Ok(headers_res.headers)
+    }
+
+    ///
+    /// Ask `node` to register us, at `ours`: where other nodes can reach us,
+    /// which needn't be the address we listen on
+    /// 
+    /// returns: The bytes sent
+    pub fn announce(node: &str, ours: &Url, timeout: Duration, network: &str) -> Result<u64> {
+        let client = Client::builder().timeout(timeout).build()?;
+        let url = format!("{}/nodes/register", node.trim_right_matches('/'));
+        let body = serde_json::to_string(&Announcement { nodes: vec![ours.as_str()] })?;
+        let sent = body.len() as u64;
+        let mut headers = headers(network, None);
+        headers.set(ContentType::json());
+        let res = client.post(&url).headers(headers).body(body).send()?;
+        check_protocol(&url, network, &res)?;
+        //a node with the tutorial's schema answers 201
+        if !res.status().is_success() {
+            return Err(Error::Network(format!("{} responded {}", url, res.status())));
+        }
+        Ok(sent)
+    }
+
+    ///
+    /// Compare our chain with `theirs`, block by block from genesis
+    /// 
+    pub fn diff(blockchain: &Blockchain, theirs: &[BlockHeader]) -> ChainDiff {
+        let ours: Vec<DiffBlock> = blockchain.chain().iter().zip(blockchain.hashes()).map(|(block, hash)| DiffBlock {
+            height: block.header.index,
+            hash: hash.clone()
+        }).collect();
+        let theirs: Vec<DiffBlock> = theirs.iter().map(|header| DiffBlock {
+            height: header.index,
+            hash: blockchain.hash(header)
+        }).collect();
+        let common_height = ours.iter().zip(theirs.iter()).take_while(|&(a, b)| a == b).count();
+        ChainDiff {
+            common_height: common_height,
+            ours: ours[common_height..].to_vec(),
+            theirs: theirs[common_height..].to_vec()
+        }
+    }
+
+    //the chain, and how many bytes were read getting it
+    fn get_chain(client: &Client, candidate: &Candidate, network: &str, verifier: &Verifier, limit: u64) -> (Result<Chain>, u64) {
+        let url = format!("{}/chain", candidate.node);
+        let received = Cell::new(0);
+        let result = Self::send_from(client, &url, &candidate.addresses, network, |response| {
+            read_chain(&url, Counted { inner: response, count: &received }, limit, verifier)
+        });
+        (result, received.get())
+    }
+
+    ///
+    /// GET `url` from a peer, with our network headers, checking theirs
+    /// 
+    pub fn get(client: &Client, url: &str, network: &str) -> Result<String> {
+        Self::send_at(client, url, None, network).and_then(|response| read_body(url, response, MAX_CHAIN_BYTES))
+    }
+
+    ///
+    /// `get`, from each of `addresses` in turn until one answers, for peers whose
+    /// host resolves to more than one. The url keeps its host in the Host header.
+    /// Https is left to the client, certificates are for host names
+    /// 
+    pub fn get_from(client: &Client, url: &str, addresses: &[SocketAddr], network: &str) -> Result<String> {
+        Self::send_from(client, url, addresses, network, |response| read_body(url, response, MAX_CHAIN_BYTES))
+    }
+
+    //`get_from`, reading the response with `read`
+    fn send_from<T, F>(client: &Client, url: &str, addresses: &[SocketAddr], network: &str, read: F) -> Result<T>
+        where F: Fn(Response) -> Result<T> {
+        let parsed = Url::parse(url).map_err(|e| Error::Validation(format!("Invalid node url {}. {}", url, e)))?;
+        if addresses.is_empty() || parsed.scheme() != "http" {
+            return Self::send_at(client, url, None, network).and_then(read);
+        }
+        let host = match parsed.port() {
+            Some(port) => format!("{}:{}", parsed.host_str().unwrap_or(""), port),
+            None => String::from(parsed.host_str().unwrap_or(""))
+        };
+        let mut last = None;
+        for address in addresses {
+            let mut at = parsed.clone();
+            if at.set_ip_host(address.ip()).is_err() || at.set_port(Some(address.port())).is_err() {
+                continue;
+            }
+            match Self::send_at(client, at.as_str(), Some(&host), network).and_then(&read) {
+                //unreachable at this address, maybe not at the next
+                Err(Error::Network(e)) => {
+                    debug!("{} via {} failed. {}", url, address, e);
+                    last = Some(Error::Network(e));
+                },
+                result => return result
+            }
+        }
+        Err(last.unwrap_or_else(|| Error::Network(format!("{} has no usable address", url))))
+    }
+
+    fn send_at(client: &Client, url: &str, host: Option<&str>, network: &str) -> Result<Response> {
+        let res = client.get(url).headers(headers(network, host)).send()?;
+        //checked first, a peer on another network answers with an error
+        check_protocol(url, network, &res)?;
+        if res.status() != StatusCode::Ok {
+            return Err(Error::Network(format!("{} responded {}", url, res.status())));
+        }
+        Ok(res)
+    }
+}
+
+//our network headers, for every request to a peer
+fn headers(network: &str, host: Option<&str>) -> Headers {
+    let mut headers = Headers::new();
+    headers.set_raw(NETWORK_HEADER, network.to_string());
+    headers.set_raw(VERSION_HEADER, PROTOCOL_VERSION.to_string());
+    if let Some(host) = host {
+        headers.set_raw("Host", host.to_string());
+    }
+    headers
+}
+
+fn check_protocol(url: &str, network: &str, res: &Response) -> Result<()> {
+    protocol::check(network, header(res.headers(), NETWORK_HEADER), header(res.headers(), VERSION_HEADER))
+        .map_err(|e| Error::Protocol(format!("{} {}", url, e)))
+}
+
+//counts the bytes read through it
+struct Counted<'a, R> {
+    inner: R,
+    count: &'a Cell<u64>
+}
+
+impl<'a, R> Read for Counted<'a, R> where R: Read {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.count.set(self.count.get() + read as u64);
+        Ok(read)
+    }
+}
+
+fn read_body(url: &str, response: Response, limit: u64) -> Result<String> {
+    let mut body = Limited::new(response, limit);
+    let mut raw = String::new();
+    let read = body.read_to_string(&mut raw);
+    match read {
+        Ok(_) => Ok(raw),
+        Err(_) if body.exceeded().is_some() => Err(Error::Limit(format!("{} sent json {}", url, body.exceeded().unwrap_or_default()))),
+        Err(e) => Err(Error::Network(format!("Couldn't read {}. {}", url, e)))
+    }
+}
+
+///
+/// Parse a GET /chain body as it's read, checking each block with a clone of
+/// `verifier` as soon as it's parsed, so blocks must come in height order as
+/// /chain sends them. Reading stops at the first invalid block, or once more than
+/// `limit` bytes have been read, or the json nests too deep
+/// 
+fn read_chain<R>(url: &str, body: R, limit: u64, verifier: &Verifier) -> Result<Chain> where R: Read {
+    let mut verifier = verifier.clone();
+    let mut body = Limited::new(body, limit);
+    let parsed = {
+        let mut deserializer = serde_json::Deserializer::from_reader(&mut body);
+        StreamedChain { verifier: &mut verifier }.deserialize(&mut deserializer)
+    };
+    match parsed {
+        Ok(chain) => Ok(chain),
+        Err(_) if verifier.error().is_some() => Err(Error::Validation(format!("{} sent an invalid chain, {}", url, verifier.error().unwrap_or_default()))),
+        Err(_) if body.exceeded().is_some() => Err(Error::Limit(format!("{} sent a chain {}", url, body.exceeded().unwrap_or_default()))),
+        Err(e) => Err(e.into())
+    }
+}
+
+//a GET /chain body, `{"chain": [..], "length": n}`
+struct StreamedChain<'a> {
+    verifier: &'a mut Verifier
+}
+
+impl<'de, 'a> DeserializeSeed<'de> for StreamedChain<'a> {
+    type Value = Chain;
+
+    fn deserialize<D>(self, deserializer: D) -> result::Result<Chain, D::Error> where D: Deserializer<'de> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, 'a> Visitor<'de> for StreamedChain<'a> {
+    type Value = Chain;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a chain")
+    }
+
+    fn visit_map<A>(self, mut map: A) -> result::Result<Chain, A::Error> where A: MapAccess<'de> {
+        let verifier = self.verifier;
+        let mut chain = None;
+        while let Some(key) = map.next_key::<String>()? {
+            if key == "chain" {
+                chain = Some(map.next_value_seed(Blocks { verifier: &mut *verifier })?);
+            } else {
+                map.next_value::<IgnoredAny>()?;
             }
         }
-        chains
+        chain.ok_or_else(|| de::Error::missing_field("chain"))
+    }
+}
+
+//the blocks of a chain, from genesis, each checked as soon as it's parsed
+struct Blocks<'a> {
+    verifier: &'a mut Verifier
+}
+
+impl<'de, 'a> DeserializeSeed<'de> for Blocks<'a> {
+    type Value = Chain;
+
+    fn deserialize<D>(self, deserializer: D) -> result::Result<Chain, D::Error> where D: Deserializer<'de> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, 'a> Visitor<'de> for Blocks<'a> {
+    type Value = Chain;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a list of blocks")
     }
 
-    fn deserialize(chains_raw: Vec<String>) -> Vec<Chain> {
-        let mut chains = Vec::<Chain>::new();
-        for raw in chains_raw {
-            //upgrade: remove nodes who return invalid chains?
-            match serde_json::from_str::<ChainResponse>(raw.as_str()) {
-                Ok(chain_res) => chains.push(chain_res.chain),
-                Err(e) => error!("Unable to deserialize chain {:?} raw: {}", e, raw)
-            }            
+    fn visit_seq<A>(self, mut seq: A) -> result::Result<Chain, A::Error> where A: SeqAccess<'de> {
+        let mut chain = Chain::new();
+        while let Some(block) = seq.next_element::<Block>()? {
+            self.verifier.check(&block).map_err(de::Error::custom)?;
+            chain.insert(block);
         }
-        chains
+        Ok(chain)
     }
 }
 
+///
+/// Whether a chain proving `work` proves more than every one of `remaining`
+/// could send, going by the chains they sent last time. `work` is kept as
+/// chains are checked, so this costs nothing per block
+/// 
+fn beats_all(work: u64, remaining: &[Candidate]) -> bool {
+    remaining.iter().all(|candidate| candidate.work.map(|theirs| theirs < work).unwrap_or(false))
+}
+
+fn header<'a>(headers: &'a Headers, name: &str) -> Option<&'a str> {
+    headers.get_raw(name).and_then(|raw| raw.one()).and_then(|value| str::from_utf8(value).ok())
+}
+
 #[cfg(test)]
 mod tests {    
-    use lib::blockchain::Blockchain;
-    use lib::consensus::Consensus;
+    use lib::blockchain::{Block, Blockchain, BlockHeader, Chain};
+    use lib::consensus::{beats_all, read_chain, Candidate, Consensus, Outcome, MAX_CHAIN_BYTES};
+    use lib::error::Error;
+    use lib::transaction::Transaction;
+    use serde_json;
+    use std::iter;
+    use std::time::Duration;
+    #[cfg(feature = "integration")]
+    use lib::protocol;
     //use env_logger;
     
     #[cfg(feature = "integration")]   
@@ -102,11 +532,61 @@ mod tests {
     fn get_neighbour_chains() {
         //env_logger::init().unwrap();
         let url = "http://localhost:8000";
-        let urls = vec![String::from(url)];
-        let chains = Consensus::get(urls.as_slice());
+        let candidates = vec![Candidate { node: String::from(url), work: None, addresses: Vec::new() }];
+        let chains: Vec<_> = Consensus::fetch(candidates.as_slice(), Duration::from_secs(10), protocol::DEFAULT_NETWORK, &Blockchain::new_with(1).verifier(), MAX_CHAIN_BYTES, |_| true)
+                                 .into_iter()
+                                 .filter_map(|fetched| fetched.result.ok())
+                                 .collect();
         assert!(chains.len() > 0, format!("expected a populated chain. do you have a node running at {} ?", url));
     }
 
+    #[test]
+    fn outcomes() {
+        let timeout = Duration::from_secs(10);
+        let quick = Duration::from_millis(5);
+        let of = |result: Result<Chain, Error>, latency| Outcome::of(&result, latency, timeout);
+        assert_eq!(of(Ok(Chain::new()), quick), Outcome::Sent);
+        assert_eq!(of(Err(Error::Network("responded 500".into())), quick), Outcome::Unreachable);
+        assert_eq!(of(Err(Error::Network("timed out".into())), timeout), Outcome::Timeout);
+        assert_eq!(of(Err(Error::Serialization("expected value".into())), quick), Outcome::Malformed);
+        assert_eq!(of(Err(Error::Limit("too big".into())), quick), Outcome::TooBig);
+        assert_eq!(of(Err(Error::Protocol("classroom".into())), quick), Outcome::Rejected);
+        assert_eq!(of(Err(Error::Validation("block 2".into())), quick), Outcome::Invalid);
+        assert!(Outcome::Timeout.is_failure() && Outcome::Malformed.is_failure());
+        assert!(!Outcome::Rejected.is_failure() && !Outcome::Invalid.is_failure());
+    }
+
+    #[test]
+    fn short_circuit() {
+        let candidate = |work| Candidate { node: String::from("http://localhost:9000"), work: work, addresses: Vec::new() };
+        assert!(beats_all(5, &[]));
+        assert!(beats_all(5, &[candidate(Some(4)), candidate(Some(1))]));
+        assert!(!beats_all(5, &[candidate(Some(4)), candidate(Some(5))]), "strictly more");
+        assert!(!beats_all(5, &[candidate(Some(4)), candidate(None)]), "nodes that never sent a chain are always asked");
+    }
+
+    #[test]
+    fn diff() {
+        let mut blockchain = Blockchain::new_with(1);
+        blockchain.mine().unwrap();
+        blockchain.mine().unwrap();
+        blockchain.mine().unwrap();
+        let mut theirs: Vec<BlockHeader> = blockchain.chain().iter().take(3).map(|block| block.header.clone()).collect();
+        theirs[2].nonce += 1;
+
+        let diff = Consensus::diff(&blockchain, &theirs);
+        assert_eq!(diff.common_height, 2);
+        assert_eq!(diff.ours.iter().map(|block| block.height).collect::<Vec<_>>(), vec![3, 4]);
+        assert_eq!(diff.theirs.len(), 1);
+        assert_eq!(diff.theirs[0].hash, blockchain.hash(&theirs[2]));
+
+        let same: Vec<BlockHeader> = blockchain.chain().iter().map(|block| block.header.clone()).collect();
+        let diff = Consensus::diff(&blockchain, &same);
+        assert_eq!(diff.common_height, 4);
+        assert!(diff.ours.is_empty() && diff.theirs.is_empty());
+        assert_eq!(Consensus::diff(&blockchain, &[]).common_height, 0);
+    }
+
     #[test]
     fn take_authoritive() {
         //Same or less blocks we keep our own. Longer we replace
@@ -129,5 +609,122 @@ mod tests {
         blockchain_2.mine().unwrap();
         assert!(Consensus::take_authoritive(&mut blockchain_1, vec![blockchain_2.into_chain()]), "1 block vs 2 blocks (replace)");
     }
+
+    //a GET /chain body, with `blocks` in the order given
+    fn body(blocks: &[Block]) -> String {
+        format!("{{\"chain\":{},\"length\":{}}}", serde_json::to_string(blocks).unwrap(), blocks.len())
+    }
+
+    fn rejected(body: &str, limit: u64) -> Error {
+        match read_chain("peer", body.as_bytes(), limit, &Blockchain::new_with(1).verifier()) {
+            Ok(chain) => panic!("accepted {} blocks", chain.len()),
+            Err(e) => e
+        }
+    }
+
+    #[test]
+    fn stream_chain() {
+        let ours = Blockchain::new_with(1);
+        let mut theirs = Blockchain::new_with(1);
+        theirs.mine().unwrap();
+        theirs.mine().unwrap();
+
+        let valid = body(&theirs.chain().iter().cloned().collect::<Vec<Block>>());
+        let chain = read_chain("peer", valid.as_bytes(), valid.len() as u64, &ours.verifier()).expect("valid");
+        assert_eq!(&chain, theirs.chain());
+
+        let mut blocks: Vec<Block> = theirs.chain().iter().cloned().collect();
+        blocks[1].transactions.insert(Transaction::new("a".into(), "b".into(), 1000));
+        match rejected(&body(&blocks), MAX_CHAIN_BYTES) {
+            Error::Validation(e) => assert!(e.contains("block 2"), "got {}", e),
+            e => panic!("expected the tampered block, got {}", e)
+        }
+
+        match rejected(&valid, valid.len() as u64 / 2) {
+            Error::Limit(_) => {},
+            e => panic!("expected the limit, got {}", e)
+        }
+    }
+
+    #[test]
+    fn absurd_chains() {
+        let mut theirs = Blockchain::new_with(1);
+        theirs.mine().unwrap();
+        theirs.mine().unwrap();
+        let blocks: Vec<Block> = theirs.chain().iter().cloned().collect();
+        let invalid = |blocks: &[Block]| match rejected(&body(blocks), MAX_CHAIN_BYTES) {
+            Error::Validation(e) => e,
+            e => panic!("expected an invalid block, got {}", e)
+        };
+
+        let duplicated = vec![blocks[0].clone(), blocks[1].clone(), blocks[1].clone()];
+        assert!(invalid(&duplicated).contains("block 3: index is 2"));
+
+        let mut zero = blocks[0].clone();
+        zero.header.index = 0;
+        assert!(invalid(&[zero]).contains("block 1: index is 0"));
+
+        //block 2 links to block 3, which links back to block 2
+        let mut cycle = blocks.clone();
+        cycle[1].header.previous_hash = theirs.hash(&cycle[2].header);
+        assert!(invalid(&cycle).contains("block 2: previous hash"));
+
+        let mut gigantic = blocks.clone();
+        for amount in 0..20000 {
+            gigantic[1].transactions.insert(Transaction::new("a".into(), "b".into(), amount));
+        }
+        match rejected(&body(&gigantic), 64 * 1024) {
+            Error::Limit(e) => assert!(e.contains("more than 65536 bytes"), "got {}", e),
+            e => panic!("expected the limit, got {}", e)
+        }
+
+        let nested = format!("{{\"chain\":{}", iter::repeat('[').take(100000).collect::<String>());
+        match rejected(&nested, MAX_CHAIN_BYTES) {
+            Error::Limit(e) => assert!(e.contains("nested deeper than"), "got {}", e),
+            e => panic!("expected the limit, got {}", e)
+        }
+    }
+
+    #[test]
+    fn adopt_reports_invalid() {
+        let mut ours = Blockchain::new_with(1);
+        let mut longer = Blockchain::new_with(1);
+        longer.mine().unwrap();
+        longer.mine().unwrap();
+        let mut blocks: Vec<Block> = longer.chain().iter().cloned().collect();
+        blocks[1].transactions.insert(Transaction::new("a".into(), "b".into(), 1000));
+        let tampered: Chain = blocks.into_iter().collect();
+
+        let (adopted, invalid) = Consensus::adopt(&mut ours, vec![tampered, Blockchain::new_with(1).into_chain(), longer.into_chain()]);
+        assert_eq!(adopted, Some(2));
+        assert_eq!(invalid, vec![0], "the short chain isn't checked");
+    }
+
+    #[test]
+    fn adopt_needs_our_difficulty() {
+        let mut ours = Blockchain::new_with(2);
+        ours.mine().unwrap();
+        let mut cheap = Blockchain::new_with(1);
+        cheap.replace(ours.chain().clone());
+        //enough blocks at 1 to claim more work than one more at 2
+        for _ in 0..65 {
+            cheap.mine().unwrap();
+        }
+        assert!(!ours.meets_difficulty(cheap.chain()), "mined at 1, ours is 2");
+        assert!(ours.valid_chain(cheap.chain()), "valid by the blocks' own difficulty");
+        let mut honest = Blockchain::new_with(2);
+        honest.replace(ours.chain().clone());
+        honest.mine().unwrap();
+        assert!(ours.meets_difficulty(honest.chain()));
+
+        let (adopted, invalid) = Consensus::adopt(&mut ours, vec![cheap.into_chain(), honest.into_chain()]);
+        assert_eq!(adopted, Some(1), "the chain that claims more work was mined below our difficulty");
+        assert_eq!(invalid, vec![0]);
+
+        //once we're easier, blocks already mined harder still count
+        ours.set_difficulty(1).unwrap();
+        let mine = ours.chain().clone();
+        assert!(ours.meets_difficulty(&mine));
+    }
 }
   
\ No newline at end of file
@@ -0,0 +1,74 @@
+use lib::blockchain::{Block, Blockchain};
+use std::collections::HashSet;
+use std::fmt::Write;
+
+///
+/// How much of each hash to show in a node's label
+///
+const LABEL_HASH_LEN: usize = 8;
+
+///
+/// The blocks we know about as a Graphviz DOT digraph. Each block points at its
+/// parent. Blocks on our chain are solid, orphans from abandoned forks are dashed
+/// and grey, so a reorg shows up as a dashed branch.
+///
+/// Render with `dot -Tsvg`.
+///
+pub fn graph(blockchain: &Blockchain) -> String {
+    let mut hashes = HashSet::new();
+    let mut out = String::new();
+    writeln!(out, "digraph chain {{").unwrap();
+    writeln!(out, "    rankdir=RL;").unwrap();
+    writeln!(out, "    node [shape=box, fontname=monospace];").unwrap();
+
+    for (block, hash) in blockchain.chain().iter().zip(blockchain.hashes()) {
+        writeln!(out, "    \"{}\" [label=\"{}\"];", hash, label(block, hash)).unwrap();
+        hashes.insert(hash.clone());
+    }
+    for block in blockchain.orphans() {
+        let hash = blockchain.hash(&block.header);
+        writeln!(out, "    \"{}\" [label=\"{}\", style=dashed, color=grey];", hash, label(block, &hash)).unwrap();
+        hashes.insert(hash);
+    }
+
+    //only link to blocks we know, an orphan's parent may have been forgotten
+    for block in blockchain.chain().iter().chain(blockchain.orphans().iter()) {
+        if block.header.index > 1 && hashes.contains(&block.header.previous_hash) {
+            writeln!(out, "    \"{}\" -> \"{}\";", blockchain.hash(&block.header), block.header.previous_hash).unwrap();
+        }
+    }
+    writeln!(out, "}}").unwrap();
+    out
+}
+
+fn label(block: &Block, hash: &str) -> String {
+    let short: String = hash.chars().take(LABEL_HASH_LEN).collect();
+    format!("#{} {}\\n{} txns", block.header.index, short, block.transactions.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use lib::blockchain::Blockchain;
+    use lib::dot;
+
+    #[test]
+    fn graph_with_fork() {
+        let mut blockchain = Blockchain::new_with(1);
+        blockchain.mine().unwrap();
+        let abandoned = blockchain.hash_last_block();
+
+        let mut other = Blockchain::new_with(1);
+        other.set_miner_address("other miner".into());
+        other.replace(blockchain.chain().iter().take(1).cloned().collect());
+        other.mine().unwrap();
+        other.mine().unwrap();
+        blockchain.replace(other.chain().clone());
+
+        let graph = dot::graph(&blockchain);
+        assert!(graph.starts_with("digraph chain {"));
+        assert_eq!(graph.matches("[label=").count(), 4, "3 on the chain, 1 orphan");
+        assert_eq!(graph.matches(" -> ").count(), 3);
+        assert!(graph.contains(&format!("\"{}\" [label=", abandoned)));
+        assert_eq!(graph.matches("style=dashed").count(), 1);
+    }
+}
@@ -0,0 +1,377 @@
+use lib::blockchain::{Block, BlockHeader};
+use lib::transaction::Transaction;
+use lib::versions::FIRST_VERSION;
+use std::collections::BTreeSet;
+
+///
+/// Canonical byte encoding, used for hashing, signing and the block file.
+/// 
+/// serde_json output depends on field declaration order and number formatting,
+/// so two builds (or two implementations) can hash the same block differently.
+/// This encoding is fixed: fields are written in a documented order, integers are
+/// 8 byte big-endian and strings are prefixed with their length in bytes.
+/// 
+pub trait Encode {
+    fn encode(&self, out: &mut Vec<u8>);
+}
+
+///
+/// Encode into a new buffer
+/// 
+pub fn encode<T>(t: &T) -> Vec<u8> where T: Encode {
+    let mut out = Vec::new();
+    t.encode(&mut out);
+    out
+}
+
+pub fn encode_u64(value: u64, out: &mut Vec<u8>) {
+    for shift in (0..8).rev() {
+        out.push((value >> (shift * 8)) as u8);
+    }
+}
+
+pub fn encode_i64(value: i64, out: &mut Vec<u8>) {
+    encode_u64(value as u64, out);
+}
+
+pub fn encode_str(value: &str, out: &mut Vec<u8>) {
+    encode_u64(value.len() as u64, out);
+    out.extend_from_slice(value.as_bytes());
+}
+
+///
+/// The reverse of `Encode`, for reading blocks back from storage
+/// 
+pub trait Decode: Sized {
+    fn decode(input: &mut Decoder) -> Result<Self, String>;
+}
+
+///
+/// Decode a whole buffer. Trailing bytes are an error
+/// 
+pub fn decode<T>(bytes: &[u8]) -> Result<T, String> where T: Decode {
+    let mut input = Decoder::new(bytes);
+    let t = T::decode(&mut input)?;
+    if !input.is_empty() {
+        return Err(format!("{} trailing bytes", input.remaining()));
+    }
+    Ok(t)
+}
+
+///
+/// Reads the primitives written by `encode_u64`, `encode_i64` and `encode_str`
+/// 
+pub struct Decoder<'a> {
+    input: &'a [u8]
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(input: &'a [u8]) -> Decoder<'a> {
+        Decoder { input: input }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.input.is_empty()
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.input.len()
+    }
+
+    pub fn u64(&mut self) -> Result<u64, String> {
+        let bytes = self.take(8)?;
+        Ok(bytes.iter().fold(0, |value, byte| (value << 8) | *byte as u64))
+    }
+
+    pub fn i64(&mut self) -> Result<i64, String> {
+        self.u64().map(|value| value as i64)
+    }
+
+    pub fn str(&mut self) -> Result<String, String> {
+        let len = self.u64()?;
+        if len > self.input.len() as u64 {
+            return Err(format!("string of {} bytes, only {} left", len, self.input.len()));
+        }
+        let bytes = self.take(len as usize)?;
+        String::from_utf8(bytes.to_vec()).map_err(|e| format!("invalid utf-8. {}", e))
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.input.len() < len {
+            return Err(format!("needed {} bytes, only {} left", len, self.input.len()));
+        }
+        let (taken, rest) = self.input.split_at(len);
+        self.input = rest;
+        Ok(taken)
+    }
+}
+
+///
+/// Set in a header's index when the version follows it. `FIRST_VERSION` headers
+/// are written as they were before versions existed, so they hash the same
+/// 
+const VERSION_FLAG: u64 = 1 << 63;
+
+///
+/// index, version (with `VERSION_FLAG` set, unless it's `FIRST_VERSION`),
+/// previous_hash, merkle_root, state_root, timestamp, difficulty, nonce
+/// 
+impl Encode for BlockHeader {
+    fn encode(&self, out: &mut Vec<u8>) {
+        if self.version == FIRST_VERSION {
+            encode_u64(self.index as u64, out);
+        } else {
+            encode_u64(self.index as u64 | VERSION_FLAG, out);
+            encode_u64(self.version, out);
+        }
+        encode_str(&self.previous_hash, out);
+        encode_str(&self.merkle_root, out);
+        encode_str(&self.state_root, out);
+        encode_i64(self.timestamp, out);
+        encode_u64(self.difficulty, out);
+        encode_u64(self.nonce, out);
+    }
+}
+
+impl Decode for BlockHeader {
+    fn decode(input: &mut Decoder) -> Result<BlockHeader, String> {
+        let index = input.u64()?;
+        let version = if index & VERSION_FLAG == 0 {
+            FIRST_VERSION
+        } else {
+            match input.u64()? {
+                //only one way to write a header, or it would have two hashes
+                FIRST_VERSION => return Err(format!("version {} written out", FIRST_VERSION)),
+                version => version
+            }
+        };
+        Ok(BlockHeader {
+            index: (index & !VERSION_FLAG) as usize,
+            previous_hash: input.str()?,
+            merkle_root: input.str()?,
+            state_root: input.str()?,
+            timestamp: input.i64()?,
+            difficulty: input.u64()?,
+            nonce: input.u64()?,
+            version: version
+        })
+    }
+}
+
+///
+/// sender, recipient, amount, then expires_at only if there is one. Transactions
+/// without an expiry hash the same as they did before expiries existed.
+/// 
+impl Encode for Transaction {
+    fn encode(&self, out: &mut Vec<u8>) {
+        encode_fields(self, out);
+        if let Some(expires_at) = self.expires_at {
+            encode_u64(expires_at as u64, out);
+        }
+    }
+}
+
+fn encode_fields(txn: &Transaction, out: &mut Vec<u8>) {
+    encode_str(&txn.sender, out);
+    encode_str(&txn.recipient, out);
+    encode_i64(txn.amount, out);
+}
+
+fn decode_fields(input: &mut Decoder) -> Result<Transaction, String> {
+    let sender = input.str()?;
+    let recipient = input.str()?;
+    let amount = input.i64()?;
+    Ok(Transaction::new(sender, recipient, amount))
+}
+
+///
+/// A transaction decoded on its own, so anything after the amount is the expiry
+/// 
+impl Decode for Transaction {
+    fn decode(input: &mut Decoder) -> Result<Transaction, String> {
+        let mut txn = decode_fields(input)?;
+        if !input.is_empty() {
+            txn.expires_at = Some(input.u64()? as usize);
+        }
+        Ok(txn)
+    }
+}
+
+///
+/// Set in a block's transaction count when each transaction is followed by its
+/// expiry. Blocks without expiring transactions are written as they were before
+/// 
+const EXPIRY_FLAG: u64 = 1 << 63;
+
+///
+/// header, number of transactions, transactions (in order). With `EXPIRY_FLAG`
+/// set, each transaction is followed by 0 (no expiry) or 1 and its expiry
+/// 
+impl Encode for Block {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.header.encode(out);
+        let expiring = self.transactions.iter().any(|txn| txn.expires_at.is_some());
+        let count = self.transactions.len() as u64;
+        encode_u64(if expiring { count | EXPIRY_FLAG } else { count }, out);
+        for txn in self.transactions.iter() {
+            encode_fields(txn, out);
+            if expiring {
+                match txn.expires_at {
+                    Some(expires_at) => {
+                        encode_u64(1, out);
+                        encode_u64(expires_at as u64, out);
+                    },
+                    None => encode_u64(0, out)
+                }
+            }
+        }
+    }
+}
+
+impl Decode for Block {
+    fn decode(input: &mut Decoder) -> Result<Block, String> {
+        let header = BlockHeader::decode(input)?;
+        let count = input.u64()?;
+        let expiring = count & EXPIRY_FLAG != 0;
+        let mut transactions = BTreeSet::new();
+        for _ in 0..(count & !EXPIRY_FLAG) {
+            let mut txn = decode_fields(input)?;
+            if expiring {
+                txn.expires_at = match input.u64()? {
+                    0 => None,
+                    1 => Some(input.u64()? as usize),
+                    flag => return Err(format!("invalid expiry flag {}", flag))
+                };
+            }
+            transactions.insert(txn);
+        }
+        Ok(Block { header: header, transactions: transactions })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lib::blockchain::{Block, BlockHeader};
+    use lib::encoding::*;
+    use lib::hasher::*;
+    use lib::random::xorshift;
+    use lib::testvectors;
+    use lib::transaction::Transaction;
+    use lib::versions::FIRST_VERSION;
+
+    fn header() -> BlockHeader {
+        BlockHeader {
+            index: 2,
+            previous_hash: "prev".into(),
+            merkle_root: "root".into(),
+            state_root: "state".into(),
+            timestamp: 1508000000,
+            difficulty: 3,
+            nonce: 258,
+            version: FIRST_VERSION
+        }
+    }
+
+    #[test]
+    fn integers() {
+        let mut out = Vec::new();
+        encode_u64(258, &mut out);
+        encode_i64(-1, &mut out);
+        assert_eq!(out, vec![0, 0, 0, 0, 0, 0, 1, 2, 255, 255, 255, 255, 255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn strings_are_length_prefixed() {
+        let mut out = Vec::new();
+        encode_str("ab", &mut out);
+        assert_eq!(out, vec![0, 0, 0, 0, 0, 0, 0, 2, b'a', b'b']);
+
+        //without the prefix these would encode the same
+        let a = encode(&Transaction::new("ab".into(), "c".into(), 1));
+        let b = encode(&Transaction::new("a".into(), "bc".into(), 1));
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn transaction() {
+        let txn = Transaction::new("a".into(), "b".into(), 5);
+        assert_eq!(encode(&txn), vec![0, 0, 0, 0, 0, 0, 0, 1, b'a',
+                                      0, 0, 0, 0, 0, 0, 0, 1, b'b',
+                                      0, 0, 0, 0, 0, 0, 0, 5]);
+        assert_eq!(HashAlgorithm::Sha256.hash(Domain::Transaction, &txn), "A2Phba5TTAhoNjvumo09eLFDg6Zml7oCj+8o9SjofjE=");
+    }
+
+    #[test]
+    fn block_header() {
+        assert_eq!(encode(&header()).len(), 8 + 8 + 4 + 8 + 4 + 8 + 5 + 8 + 8 + 8);
+        assert_eq!(HashAlgorithm::Sha256.hash(Domain::Block, &header()), "f1UkdIVxqdS3uMvHALB5hvz/JR+c+jkYeOX/nAinFq8=");
+    }
+
+    #[test]
+    fn block_header_version() {
+        let mut upgraded = header();
+        upgraded.version = 2;
+        let bytes = encode(&upgraded);
+        assert_eq!(bytes.len(), encode(&header()).len() + 8);
+        assert_eq!(decode::<BlockHeader>(&bytes), Ok(upgraded.clone()));
+        assert_ne!(HashAlgorithm::Sha256.hash(Domain::Block, &upgraded), HashAlgorithm::Sha256.hash(Domain::Block, &header()));
+
+        let mut padded = bytes.clone();
+        padded[8..16].copy_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1]);
+        assert!(decode::<BlockHeader>(&padded).is_err(), "the first version is never written");
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut block = Block { header: header(), transactions: Default::default() };
+        block.transactions.insert(Transaction::new("a".into(), "b".into(), 5));
+        block.transactions.insert(Transaction::reward("miner".into()));
+        let bytes = encode(&block);
+        assert_eq!(decode::<Block>(&bytes), Ok(block.clone()));
+
+        block.transactions.insert(Transaction::new("c".into(), "d".into(), 1).expiring(9));
+        let bytes = encode(&block);
+        assert_eq!(decode::<Block>(&bytes), Ok(block));
+    }
+
+    #[test]
+    fn transaction_expiry() {
+        let txn = Transaction::new("a".into(), "b".into(), 5).expiring(3);
+        let bytes = encode(&txn);
+        assert_eq!(&bytes[bytes.len() - 8..], &[0, 0, 0, 0, 0, 0, 0, 3]);
+        assert_eq!(decode::<Transaction>(&bytes), Ok(txn.clone()));
+        assert_ne!(HashAlgorithm::Sha256.hash(Domain::Transaction, &txn),
+                   HashAlgorithm::Sha256.hash(Domain::Transaction, &Transaction::new("a".into(), "b".into(), 5)));
+    }
+
+    ///
+    /// Arbitrary bytes (say, a damaged block file) decode to an error, never a
+    /// panic or a huge allocation
+    /// 
+    #[test]
+    fn decode_garbage() {
+        let mut block = Block { header: header(), transactions: Default::default() };
+        block.transactions.insert(Transaction::new("a".into(), "b".into(), 5));
+        let valid = encode(&block);
+
+        for bytes in testvectors::mutations(&valid, 1000) {
+            let _ = decode::<Block>(&bytes);
+        }
+        let mut rng: u64 = 0x9E3779B97F4A7C15;
+        for _ in 0..1000 {
+            let len = xorshift(&mut rng) % 64;
+            let noise: Vec<u8> = (0..len).map(|_| xorshift(&mut rng) as u8).collect();
+            let _ = decode::<Block>(&noise);
+        }
+    }
+
+    #[test]
+    fn decode_truncated() {
+        let bytes = encode(&header());
+        assert!(decode::<BlockHeader>(&bytes[..bytes.len() - 1]).is_err());
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(decode::<BlockHeader>(&trailing).is_err());
+    }
+}
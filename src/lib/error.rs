@@ -0,0 +1,88 @@
+use reqwest;
+use serde_json;
+use std::error;
+use std::fmt;
+use std::io;
+use std::result;
+
+///
+/// Everything that can go wrong in lib. The web layer maps each variant to an
+/// http status
+///
+/// Storage: Reading or writing the block store
+/// Validation: A request or block that breaks the rules
+/// Network: Talking to peers
+/// Serialization: Json in or out
+/// Lock: Gave up waiting for the blockchain (or peers) lock
+/// Protocol: A peer on another network, or speaking another protocol version
+/// Limit: A client is over a limit, such as pending transactions per sender
+/// Cluster: This replica can't write, another one holds the lease
+///
+#[derive(Debug)]
+pub enum Error {
+    Storage(io::Error),
+    Validation(String),
+    Network(String),
+    Serialization(String),
+    Lock(String),
+    Protocol(String),
+    Limit(String),
+    Cluster(String)
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Storage(ref e) => write!(f, "Storage error. {}", e),
+            Error::Validation(ref reason) => write!(f, "{}", reason),
+            Error::Network(ref reason) => write!(f, "Network error. {}", reason),
+            Error::Serialization(ref reason) => write!(f, "Serialization error. {}", reason),
+            Error::Lock(ref reason) => write!(f, "{}", reason),
+            Error::Protocol(ref reason) => write!(f, "{}", reason),
+            Error::Limit(ref reason) => write!(f, "{}", reason),
+            Error::Cluster(ref reason) => write!(f, "{}", reason)
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::Storage(ref e) => e.description(),
+            Error::Validation(ref reason) |
+            Error::Network(ref reason) |
+            Error::Serialization(ref reason) |
+            Error::Lock(ref reason) |
+            Error::Protocol(ref reason) |
+            Error::Limit(ref reason) |
+            Error::Cluster(ref reason) => reason
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            Error::Storage(ref e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Storage(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Error {
+        Error::Serialization(e.to_string())
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Error {
+        Error::Network(e.to_string())
+    }
+}
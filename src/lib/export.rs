@@ -0,0 +1,207 @@
+use lib::blockchain::Blockchain;
+use lib::error::Result;
+use lib::transaction::Amount;
+use serde_json;
+use std::io::Write;
+
+///
+/// Flat file formats for loading chain history into other tools
+///
+/// Jsonl: One json object per line
+/// Csv: A header row, then one row per record
+/// Blocks: One whole block per line, as json. Not flat, but `chain import` reads it
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Jsonl,
+    Csv,
+    Blocks
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Option<Format> {
+        match name {
+            "jsonl" => Some(Format::Jsonl),
+            "csv" => Some(Format::Csv),
+            "blocks" => Some(Format::Blocks),
+            _ => None
+        }
+    }
+}
+
+///
+/// One row of the export. Every block gets a "block" record, followed by a
+/// "transaction" record for each of its transactions. Fields that don't apply to
+/// a record are empty (null in jsonl)
+///
+#[derive(Serialize)]
+struct Record<'a> {
+    record: &'static str,
+    block_index: usize,
+    block_hash: &'a str,
+    previous_hash: Option<&'a str>,
+    merkle_root: Option<&'a str>,
+    state_root: Option<&'a str>,
+    timestamp: Option<i64>,
+    difficulty: Option<u64>,
+    nonce: Option<u64>,
+    sender: Option<&'a str>,
+    recipient: Option<&'a str>,
+    amount: Option<Amount>,
+    expires_at: Option<usize>
+}
+
+const CSV_HEADER: &'static str = "record,block_index,block_hash,previous_hash,merkle_root,state_root,timestamp,difficulty,nonce,sender,recipient,amount,expires_at";
+
+impl<'a> Record<'a> {
+    fn csv(&self) -> String {
+        let fields = vec![
+            self.record.to_string(),
+            self.block_index.to_string(),
+            csv_field(self.block_hash),
+            self.previous_hash.map(csv_field).unwrap_or_default(),
+            self.merkle_root.map(csv_field).unwrap_or_default(),
+            self.state_root.map(csv_field).unwrap_or_default(),
+            self.timestamp.map(|t| t.to_string()).unwrap_or_default(),
+            self.difficulty.map(|d| d.to_string()).unwrap_or_default(),
+            self.nonce.map(|n| n.to_string()).unwrap_or_default(),
+            self.sender.map(csv_field).unwrap_or_default(),
+            self.recipient.map(csv_field).unwrap_or_default(),
+            self.amount.map(|a| a.to_string()).unwrap_or_default(),
+            self.expires_at.map(|e| e.to_string()).unwrap_or_default()
+        ];
+        fields.join(",")
+    }
+}
+
+///
+/// Quote a field if it contains a comma, quote or newline. Addresses are
+/// arbitrary strings
+///
+fn csv_field(value: &str) -> String {
+    if value.contains(|c: char| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+///
+/// Write every block and transaction in the chain to `out`
+///
+pub fn export<W>(blockchain: &Blockchain, format: Format, out: &mut W) -> Result<()> where W: Write {
+    if format == Format::Csv {
+        writeln!(out, "{}", CSV_HEADER)?;
+    }
+    if format == Format::Blocks {
+        for block in blockchain.chain() {
+            serde_json::to_writer(&mut *out, block)?;
+            out.write_all(b"\n")?;
+        }
+        return Ok(());
+    }
+    for (block, hash) in blockchain.chain().iter().zip(blockchain.hashes()) {
+        let header = &block.header;
+        write_record(&Record {
+            record: "block",
+            block_index: header.index,
+            block_hash: hash,
+            previous_hash: Some(header.previous_hash.as_str()),
+            merkle_root: Some(header.merkle_root.as_str()),
+            state_root: Some(header.state_root.as_str()),
+            timestamp: Some(header.timestamp),
+            difficulty: Some(header.difficulty),
+            nonce: Some(header.nonce),
+            sender: None,
+            recipient: None,
+            amount: None,
+            expires_at: None
+        }, format, out)?;
+        for txn in block.transactions.iter() {
+            write_record(&Record {
+                record: "transaction",
+                block_index: header.index,
+                block_hash: hash,
+                previous_hash: None,
+                merkle_root: None,
+                state_root: None,
+                timestamp: None,
+                difficulty: None,
+                nonce: None,
+                sender: Some(txn.sender.as_str()),
+                recipient: Some(txn.recipient.as_str()),
+                amount: Some(txn.amount),
+                expires_at: txn.expires_at
+            }, format, out)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_record<W>(record: &Record, format: Format, out: &mut W) -> Result<()> where W: Write {
+    match format {
+        Format::Jsonl => {
+            serde_json::to_writer(&mut *out, record)?;
+            out.write_all(b"\n")?;
+        },
+        Format::Csv => writeln!(out, "{}", record.csv())?,
+        Format::Blocks => unreachable!("blocks are written whole")
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use lib::blockchain::{Block, Blockchain};
+    use lib::export::*;
+    use lib::transaction::Transaction;
+    use serde_json::{self, Value};
+
+    fn blockchain() -> Blockchain {
+        let mut blockchain = Blockchain::new_with(1);
+        blockchain.new_transaction(Transaction::new("a, the sender".into(), "b".into(), 5));
+        blockchain.mine().expect("mined");
+        blockchain
+    }
+
+    fn export_string(blockchain: &Blockchain, format: Format) -> String {
+        let mut out = Vec::new();
+        export(blockchain, format, &mut out).expect("exported");
+        String::from_utf8(out).expect("utf-8")
+    }
+
+    #[test]
+    fn jsonl() {
+        let blockchain = blockchain();
+        let exported = export_string(&blockchain, Format::Jsonl);
+        let records: Vec<Value> = exported.lines().map(|line| serde_json::from_str(line).expect("json line")).collect();
+        //genesis, block 2, its transfer and its reward
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[0]["record"], "block");
+        assert_eq!(records[1]["block_index"], 2);
+        let transfer = records.iter().find(|record| record["sender"] == "a, the sender").expect("transfer exported");
+        assert_eq!(transfer["record"], "transaction");
+        assert_eq!(transfer["amount"], 5);
+        assert_eq!(transfer["block_hash"], records[1]["block_hash"]);
+    }
+
+    #[test]
+    fn csv() {
+        let blockchain = blockchain();
+        let exported = export_string(&blockchain, Format::Csv);
+        let lines: Vec<&str> = exported.lines().collect();
+        assert_eq!(lines.len(), 5, "header and 4 records");
+        assert!(lines[0].starts_with("record,block_index,"));
+        assert!(lines.iter().any(|line| line.contains(",\"a, the sender\",b,5")), "addresses with commas are quoted");
+        let columns = lines[0].split(',').count();
+        assert_eq!(lines[1].split(',').count(), columns, "genesis block row");
+    }
+
+    #[test]
+    fn blocks() {
+        let blockchain = blockchain();
+        let exported = export_string(&blockchain, Format::Blocks);
+        let blocks: Vec<Block> = exported.lines().map(|line| serde_json::from_str(line).expect("a block a line")).collect();
+        assert_eq!(blocks.iter().collect::<Vec<&Block>>(), blockchain.chain().iter().collect::<Vec<&Block>>());
+    }
+}
@@ -0,0 +1,255 @@
+use lib::blockchain::Blockchain;
+use lib::error::{Error, Result};
+use lib::hasher::HashAlgorithm;
+use lib::versions::{Policy, Rules, FIRST_VERSION, SIGNAL_SHIFT, rules_version};
+use serde_json;
+use std::fs::File;
+use std::path::Path;
+
+//Soft forks, version bits style. A deployment is a new version of the rules with a
+//signal bit, and miners that support it set the bit in the version of every block
+//they mine. Blocks are counted in windows of `window` blocks, from genesis. This
+//chain never retargets its difficulty, so the end of a window stands in for a
+//retarget boundary. Once `threshold` blocks of a window signal, the deployment is
+//locked in, and it activates at the end of the next window, which leaves nodes
+//that haven't upgraded a window to notice. A deployments file lists them:
+//
+//  [{"name": "sha512-headers", "bit": 0, "version": 2, "hasher": "sha512", "window": 10, "threshold": 8}]
+
+///
+/// Blocks counted together, unless a deployment says otherwise
+///
+pub const DEFAULT_WINDOW: usize = 10;
+
+///
+/// Blocks of a window that must signal, unless a deployment says otherwise
+///
+pub const DEFAULT_THRESHOLD: usize = 8;
+
+///
+/// The highest signal bit, there are 32 above the rules version
+///
+pub const MAX_BIT: u8 = 31;
+
+///
+/// A new version of the rules, activated once enough miners signal for it
+///
+/// bit: Which bit above the rules version miners set to signal
+/// version: The version blocks are once it's active
+/// window: How many blocks are counted together
+/// threshold: How many blocks of a window must signal for it to lock in
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Deployment {
+    pub name: String,
+    pub bit: u8,
+    pub version: u64,
+    pub rules: Rules,
+    pub window: usize,
+    pub threshold: usize
+}
+
+#[derive(Deserialize)]
+struct DeploymentEntry {
+    name: String,
+    bit: u8,
+    version: u64,
+    #[serde(default)]
+    hasher: Option<String>,
+    #[serde(default = "commits_state")]
+    state_root: bool,
+    #[serde(default = "default_window")]
+    window: usize,
+    #[serde(default = "default_threshold")]
+    threshold: usize
+}
+
+fn commits_state() -> bool {
+    true
+}
+
+fn default_window() -> usize {
+    DEFAULT_WINDOW
+}
+
+fn default_threshold() -> usize {
+    DEFAULT_THRESHOLD
+}
+
+impl Deployment {
+    ///
+    /// Counted in windows of `DEFAULT_WINDOW` blocks, locking in at `DEFAULT_THRESHOLD`
+    ///
+    pub fn new(name: String, bit: u8, version: u64, rules: Rules) -> Deployment {
+        Deployment { name: name, bit: bit, version: version, rules: rules, window: DEFAULT_WINDOW, threshold: DEFAULT_THRESHOLD }
+    }
+
+    ///
+    /// What a miner adds to a block's version to signal for it
+    ///
+    pub fn signal(&self) -> u64 {
+        1 << (SIGNAL_SHIFT + self.bit as u64)
+    }
+
+    ///
+    /// Whether a block of `version` signals for it
+    ///
+    pub fn signaled_by(&self, version: u64) -> bool {
+        version & self.signal() != 0
+    }
+
+    pub fn check(&self) -> Result<()> {
+        if self.bit > MAX_BIT {
+            return Err(Error::Validation(format!("Deployment {} bit must be at most {}, got {}", self.name, MAX_BIT, self.bit)));
+        }
+        if self.version <= FIRST_VERSION || rules_version(self.version) != self.version {
+            return Err(Error::Validation(format!("Deployment {} version must be above {} and below 2^{}, got {}", self.name, FIRST_VERSION, SIGNAL_SHIFT, self.version)));
+        }
+        if self.window == 0 || self.threshold == 0 || self.threshold > self.window {
+            return Err(Error::Validation(format!("Deployment {} threshold must be between 1 and its window of {}, got {}", self.name, self.window, self.threshold)));
+        }
+        Ok(())
+    }
+}
+
+///
+/// Read a deployments file, checking every deployment
+///
+pub fn load(path: &Path) -> Result<Vec<Deployment>> {
+    let entries: Vec<DeploymentEntry> = serde_json::from_reader(File::open(path)?)?;
+    let mut deployments = Vec::new();
+    for entry in entries {
+        let hasher = match entry.hasher {
+            Some(ref name) => Some(HashAlgorithm::from_name(name).ok_or_else(|| Error::Validation(format!("Deployment {} has an unknown hasher {}", entry.name, name)))?),
+            None => None
+        };
+        let deployment = Deployment {
+            name: entry.name,
+            bit: entry.bit,
+            version: entry.version,
+            rules: Rules { hasher: hasher, state_root: entry.state_root },
+            window: entry.window,
+            threshold: entry.threshold
+        };
+        deployment.check()?;
+        deployments.push(deployment);
+    }
+    Ok(deployments)
+}
+
+///
+/// After the block at height `versions.len()`, where `versions` are the versions
+/// of every block from genesis, schedule the deployments a window just ended with
+/// enough signals for
+///
+/// returns: The deployments locked in
+pub fn lock_in<'a>(deployments: &'a [Deployment], policy: &mut Policy, versions: &[u64]) -> Vec<&'a Deployment> {
+    let height = versions.len();
+    let mut locked_in = Vec::new();
+    for deployment in deployments {
+        if height == 0 || height % deployment.window != 0 || policy.activation(deployment.version).is_some() {
+            continue;
+        }
+        let signaled = versions[height - deployment.window..].iter().filter(|version| deployment.signaled_by(**version)).count();
+        if signaled < deployment.threshold {
+            continue;
+        }
+        match policy.schedule(deployment.version, height + deployment.window + 1) {
+            Ok(()) => locked_in.push(deployment),
+            Err(e) => debug!("Deployment {} had {} signals, but can't be scheduled. {}", deployment.name, signaled, e)
+        }
+    }
+    locked_in
+}
+
+///
+/// Where a deployment is at
+///
+/// state: signaling, locked_in or active
+/// window_start: The first block of the current window
+/// signaled: Blocks of the current window that signal, so far
+/// activates_at: The first block of its version, once it's locked in
+///
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DeploymentStatus {
+    pub name: String,
+    pub bit: u8,
+    pub version: u64,
+    pub state: &'static str,
+    pub window: usize,
+    pub threshold: usize,
+    pub window_start: usize,
+    pub signaled: usize,
+    pub activates_at: Option<usize>
+}
+
+///
+/// Where each of `blockchain`'s deployments is at, at its tip
+///
+pub fn status(blockchain: &Blockchain) -> Vec<DeploymentStatus> {
+    let height = blockchain.len();
+    blockchain.deployments().iter().map(|deployment| {
+        let activates_at = blockchain.policy().activation(deployment.version);
+        let state = match activates_at {
+            None => "signaling",
+            Some(activates_at) if height < activates_at => "locked_in",
+            Some(_) => "active"
+        };
+        let window_start = height - height % deployment.window + 1;
+        let signaled = blockchain.chain()
+                                 .iter()
+                                 .skip(window_start - 1)
+                                 .filter(|block| deployment.signaled_by(block.header.version))
+                                 .count();
+        DeploymentStatus {
+            name: deployment.name.clone(),
+            bit: deployment.bit,
+            version: deployment.version,
+            state: state,
+            window: deployment.window,
+            threshold: deployment.threshold,
+            window_start: window_start,
+            signaled: signaled,
+            activates_at: activates_at
+        }
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use lib::forks::*;
+    use lib::versions::{Policy, Rules, FIRST_VERSION};
+
+    #[test]
+    fn lock_in_at_threshold() {
+        let mut deployment = Deployment::new("test".into(), 3, 2, Rules::first());
+        deployment.window = 4;
+        deployment.threshold = 3;
+        let signaling = FIRST_VERSION | deployment.signal();
+        assert!(deployment.signaled_by(signaling));
+        assert!(!deployment.signaled_by(FIRST_VERSION));
+
+        let mut policy = Policy::new();
+        policy.define(2, Rules::first()).expect("defined");
+        let deployments = vec![deployment];
+        let mut versions = vec![FIRST_VERSION, signaling, FIRST_VERSION];
+        assert!(lock_in(&deployments, &mut policy, &versions).is_empty(), "mid window");
+        versions.push(signaling);
+        assert!(lock_in(&deployments, &mut policy, &versions).is_empty(), "2 of 4");
+
+        versions.extend(vec![signaling, signaling, FIRST_VERSION, signaling]);
+        assert_eq!(lock_in(&deployments, &mut policy, &versions).len(), 1, "3 of 4");
+        assert_eq!(policy.activation(2), Some(13), "a window later");
+        assert!(lock_in(&deployments, &mut policy, &versions).is_empty(), "only once");
+    }
+
+    #[test]
+    fn check() {
+        assert!(Deployment::new("ok".into(), MAX_BIT, 2, Rules::first()).check().is_ok());
+        assert!(Deployment::new("bit".into(), MAX_BIT + 1, 2, Rules::first()).check().is_err());
+        assert!(Deployment::new("version".into(), 0, FIRST_VERSION, Rules::first()).check().is_err());
+        let mut deployment = Deployment::new("threshold".into(), 0, 2, Rules::first());
+        deployment.threshold = deployment.window + 1;
+        assert!(deployment.check().is_err());
+    }
+}
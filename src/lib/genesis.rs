@@ -0,0 +1,91 @@
+use lib::error::{Error, Result};
+use lib::transaction::{Address, Amount, Transaction};
+use serde_json;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::Path;
+
+//A genesis file funds accounts from the start, so a class can send coins without
+//mining first:
+//
+//  {"allocations": {"alice": 100, "bob": 50}}
+//
+//Each allocation is a transaction from "0" in the genesis block, like a mining
+//reward, so it counts towards the rewards in /stats. Peers don't check each
+//other's genesis block, every node of a class should start from the same file.
+
+///
+/// What goes in the genesis block
+///
+/// allocations: Starting balances, by address
+///
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct Genesis {
+    #[serde(default)]
+    pub allocations: BTreeMap<Address, Amount>
+}
+
+impl Genesis {
+    ///
+    /// Read a genesis file, checking every allocation
+    ///
+    pub fn load(path: &Path) -> Result<Genesis> {
+        let genesis: Genesis = serde_json::from_reader(File::open(path)?)?;
+        genesis.check()?;
+        Ok(genesis)
+    }
+
+    fn check(&self) -> Result<()> {
+        for (address, amount) in self.allocations.iter() {
+            if address.trim().is_empty() || address == "0" {
+                return Err(Error::Validation(format!("Can't allocate to address \"{}\"", address)));
+            }
+            if *amount <= 0 {
+                return Err(Error::Validation(format!("Allocation to {} must be positive, got {}", address, amount)));
+            }
+        }
+        Ok(())
+    }
+
+    ///
+    /// The genesis block's transactions
+    ///
+    pub fn transactions(&self) -> Vec<Transaction> {
+        self.allocations
+            .iter()
+            .map(|(address, amount)| Transaction::new("0".into(), address.clone(), *amount))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lib::error::Error;
+    use lib::genesis::Genesis;
+    use std::env;
+    use std::fs::File;
+    use std::io::Write;
+
+    fn load(name: &str, json: &str) -> Result<Genesis, Error> {
+        let path = env::temp_dir().join(format!("learnnet-genesis-{}.json", name));
+        File::create(&path).and_then(|mut file| file.write_all(json.as_bytes())).expect("written");
+        Genesis::load(&path)
+    }
+
+    #[test]
+    fn load_allocations() {
+        let genesis = load("load_allocations", r#"{"allocations": {"alice": 100, "bob": 50}}"#).expect("loaded");
+        assert_eq!(genesis.allocations.get("alice"), Some(&100));
+        let transactions = genesis.transactions();
+        assert_eq!(transactions.len(), 2);
+        assert!(transactions.iter().all(|txn| txn.is_reward()));
+
+        assert_eq!(load("load_empty", "{}").expect("loaded"), Genesis::default());
+        match load("load_negative", r#"{"allocations": {"alice": -1}}"#) {
+            Err(Error::Validation(_)) => {},
+            other => panic!("expected a validation error, got {:?}", other)
+        }
+        assert!(load("load_reserved", r#"{"allocations": {"0": 10}}"#).is_err());
+        assert!(load("load_unreadable", r#"{"allocations": "#).is_err());
+    }
+}
@@ -1,21 +1,124 @@
-use serde;
-use serde_json;
 use base64;
-use std::fmt::Debug;
-use std::result::Result;
-use sha2::{Sha256, Digest};
+use lib::encoding::{self, Encode, encode_str};
+use sha2::{Sha256, Sha512, Digest};
 
+///
+/// What is being hashed. The domain's tag is hashed in front of the bytes, so a
+/// transaction can never hash to the same value as a block header or a Merkle
+/// node, even if their encodings happen to match.
+/// 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Domain {
+    Block,
+    Transaction,
+    MerkleNode,
+    State
+}
+
+impl Domain {
+    pub fn tag(&self) -> &'static str {
+        match *self {
+            Domain::Block => "learnnet/block",
+            Domain::Transaction => "learnnet/transaction",
+            Domain::MerkleNode => "learnnet/merkle",
+            Domain::State => "learnnet/state"
+        }
+    }
+}
+
+///
+/// A digest function. Everything that gets hashed goes through here
+/// 
+pub trait Hasher {
+    ///
+    /// The base64 encoded digest of `bytes`
+    /// 
+    fn digest(&self, bytes: &[u8]) -> String;
+
+    ///
+    /// The digest of `bytes`, tagged with their `Domain`
+    /// 
+    fn hash_bytes(&self, domain: Domain, bytes: &[u8]) -> String {
+        let mut tagged = Vec::with_capacity(bytes.len() + 32);
+        encode_str(domain.tag(), &mut tagged);
+        tagged.extend_from_slice(bytes);
+        self.digest(&tagged)
+    }
+
+    ///
+    /// The digest of the canonical encoding of `t`, tagged with its `Domain`
+    /// 
+    fn hash<T>(&self, domain: Domain, t: &T) -> String where T: Encode, Self: Sized {
+        self.hash_bytes(domain, &encoding::encode(t))
+    }
+}
+
+///
+/// The digests available to a network. Every node on a network must use the same one.
+/// 
+/// Sha256: The default
+/// Sha512: Faster than SHA-256 on 64-bit machines, for quicker demo mining
+/// 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    Sha256,
+    Sha512
+}
+
+impl HashAlgorithm {
+    pub fn from_name(name: &str) -> Option<HashAlgorithm> {
+        match name {
+            "sha256" => Some(HashAlgorithm::Sha256),
+            "sha512" => Some(HashAlgorithm::Sha512),
+            _ => None
+        }
+    }
+}
+
+impl Default for HashAlgorithm {
+    fn default() -> HashAlgorithm {
+        HashAlgorithm::Sha256
+    }
+}
 
-pub fn hash<T>(t: &T) -> Result<String, String> where T: serde::Serialize + Debug {
-    
-    let json = try!(serde_json::to_string(t).map_err(|e| e.to_string()));
-    Ok(hash_string(json))
+impl Hasher for HashAlgorithm {
+    fn digest(&self, bytes: &[u8]) -> String {
+        let base64_hash = match *self {
+            HashAlgorithm::Sha256 => {
+                let mut hasher = Sha256::default();
+                hasher.input(bytes);
+                base64::encode(hasher.result().as_slice())
+            },
+            HashAlgorithm::Sha512 => {
+                let mut hasher = Sha512::default();
+                hasher.input(bytes);
+                base64::encode(hasher.result().as_slice())
+            }
+        };
+        trace!("bytes {:?} -> {:?} hash: {:?}", bytes, self, base64_hash);
+        base64_hash
+    }
 }
 
-pub fn hash_string(string: String) -> String {
-    let mut hasher = Sha256::default();
-    hasher.input(string.as_bytes());
-    let base64_hash = base64::encode(hasher.result().as_slice());
-    trace!("struct {:?} -> hash: {:?}", string, base64_hash);
-    base64_hash
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use lib::hasher::*;
+
+    #[test]
+    fn domains_are_separated() {
+        let hasher = HashAlgorithm::Sha256;
+        let bytes = b"same bytes";
+        assert_ne!(hasher.hash_bytes(Domain::Block, bytes), hasher.hash_bytes(Domain::Transaction, bytes));
+        assert_ne!(hasher.hash_bytes(Domain::Block, bytes), hasher.hash_bytes(Domain::MerkleNode, bytes));
+        assert_ne!(hasher.hash_bytes(Domain::Block, bytes), hasher.hash_bytes(Domain::State, bytes));
+        assert_ne!(hasher.hash_bytes(Domain::Block, bytes), hasher.digest(bytes));
+    }
+
+    #[test]
+    fn algorithms() {
+        assert_eq!(HashAlgorithm::Sha256.digest(b""), "47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=");
+        assert_eq!(HashAlgorithm::Sha512.digest(b""), "z4PhNX7vuL3xVChQ1m2AB9Yg5AULVxXcg/SpIdNs6c5H0NE8XYXysP+DGNKHfuwvY7kxvUdBeoGlODJ6+SfaPg==");
+        assert_eq!(HashAlgorithm::from_name("sha512"), Some(HashAlgorithm::Sha512));
+        assert_eq!(HashAlgorithm::from_name("md5"), None);
+    }
+}
@@ -0,0 +1,231 @@
+use lib::blockchain::{Block, Blockchain};
+use lib::error::{Error, Result};
+use serde_json;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::iter;
+use std::path::Path;
+use std::time::Instant;
+
+//A chain is imported from a file of blocks, one json block a line, in height
+//order, as `chain export --format blocks` writes them. Blocks are read and checked
+//a batch at a time, so a long chain never has to fit in memory, and an invalid
+//block stops the import with everything before it kept. Blocks we already have
+//are skipped, so an interrupted import picks up where it stopped when it's run
+//again over the same file.
+
+///
+/// How many blocks are read and applied at once. The WRITE lock is taken once a
+/// batch
+///
+pub const IMPORT_BATCH: usize = 100;
+
+///
+/// Progress is logged at most this often
+///
+pub const PROGRESS_SECS: u64 = 5;
+
+///
+/// How far an import has got
+///
+/// state: running, done or failed
+/// imported: Blocks added to our chain
+/// skipped: Blocks we already had
+/// height: The length of our chain
+/// percent: How much of the file has been read
+/// blocks_per_sec: Blocks read a second, imported or skipped
+///
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Progress {
+    pub path: String,
+    pub state: &'static str,
+    pub imported: usize,
+    pub skipped: usize,
+    pub height: usize,
+    pub percent: f64,
+    pub blocks_per_sec: f64,
+    pub error: Option<String>
+}
+
+///
+/// An import in progress, reading blocks from `reader`
+///
+/// adopt_genesis: Start from the file's genesis block rather than ours, for an
+///                empty node
+///
+pub struct Import<R> {
+    reader: R,
+    path: String,
+    total_bytes: u64,
+    read_bytes: u64,
+    line: usize,
+    started: Instant,
+    imported: usize,
+    skipped: usize,
+    height: usize,
+    pub adopt_genesis: bool
+}
+
+impl Import<BufReader<File>> {
+    ///
+    /// Import the blocks in the file at `path`
+    ///
+    pub fn open(path: &Path) -> Result<Import<BufReader<File>>> {
+        let file = File::open(path)?;
+        let total_bytes = file.metadata()?.len();
+        Ok(Import::new(BufReader::new(file), path.display().to_string(), total_bytes))
+    }
+}
+
+impl<R> Import<R> where R: BufRead {
+    ///
+    /// Import the blocks `reader` holds, `total_bytes` of them, for progress
+    ///
+    pub fn new(reader: R, path: String, total_bytes: u64) -> Import<R> {
+        Import {
+            reader: reader,
+            path: path,
+            total_bytes: total_bytes,
+            read_bytes: 0,
+            line: 0,
+            started: Instant::now(),
+            imported: 0,
+            skipped: 0,
+            height: 0,
+            adopt_genesis: false
+        }
+    }
+
+    ///
+    /// Read up to `count` more blocks
+    ///
+    /// returns: No blocks at the end of the file
+    pub fn next_batch(&mut self, count: usize) -> Result<Vec<Block>> {
+        let mut blocks = Vec::with_capacity(count);
+        while blocks.len() < count {
+            let mut line = String::new();
+            let read = self.reader.read_line(&mut line)?;
+            if read == 0 {
+                break;
+            }
+            self.read_bytes += read as u64;
+            self.line += 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let block = serde_json::from_str(&line).map_err(|e| Error::Serialization(format!("line {}: {}", self.line, e)))?;
+            blocks.push(block);
+        }
+        Ok(blocks)
+    }
+
+    ///
+    /// Add `blocks` to `blockchain`, checking each like a block from a peer.
+    /// Blocks it already has are skipped, a block that's different from ours at
+    /// its height stops the import
+    ///
+    pub fn apply(&mut self, blockchain: &mut Blockchain, blocks: Vec<Block>) -> Result<()> {
+        for block in blocks {
+            //so it's right however far the batch gets
+            self.height = blockchain.len();
+            let index = block.header.index;
+            if index == 1 && self.adopt_genesis {
+                self.adopt_genesis = false;
+                if Some(blockchain.hash(&block.header).as_str()) != blockchain.hash_at(1) {
+                    blockchain.replace(iter::once(block).collect());
+                    self.imported += 1;
+                    continue;
+                }
+            }
+            if index <= blockchain.len() {
+                if blockchain.hash_at(index) != Some(blockchain.hash(&block.header).as_str()) {
+                    return Err(Error::Validation(format!("block {} is different from ours, the file is another chain", index)));
+                }
+                self.skipped += 1;
+                continue;
+            }
+            blockchain.append(block)?;
+            self.imported += 1;
+        }
+        self.height = blockchain.len();
+        Ok(())
+    }
+
+    pub fn progress(&self, state: &'static str, error: Option<String>) -> Progress {
+        let elapsed = self.started.elapsed();
+        let secs = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+        let read = (self.imported + self.skipped) as f64;
+        Progress {
+            path: self.path.clone(),
+            state: state,
+            imported: self.imported,
+            skipped: self.skipped,
+            height: self.height,
+            percent: if self.total_bytes == 0 { 100.0 } else { self.read_bytes as f64 * 100.0 / self.total_bytes as f64 },
+            blocks_per_sec: if secs > 0.0 { read / secs } else { 0.0 },
+            error: error
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lib::blockchain::Blockchain;
+    use lib::import::*;
+    use serde_json;
+    use std::io::Cursor;
+
+    fn file(blockchain: &Blockchain) -> String {
+        blockchain.chain().iter().map(|block| serde_json::to_string(block).expect("serialized") + "\n").collect()
+    }
+
+    #[test]
+    fn import_and_resume() {
+        let mut theirs = Blockchain::new_with(1);
+        for _ in 0..4 {
+            theirs.mine().expect("mined");
+        }
+        let contents = file(&theirs);
+
+        //interrupted after the first batch
+        let mut ours = Blockchain::new_with(1);
+        ours.replace(theirs.chain().iter().take(1).cloned().collect());
+        let mut import = Import::new(Cursor::new(contents.clone()), "test".into(), contents.len() as u64);
+        let batch = import.next_batch(3).expect("read");
+        import.apply(&mut ours, batch).expect("applied");
+        assert_eq!(ours.len(), 3);
+        let progress = import.progress("running", None);
+        assert_eq!((progress.imported, progress.skipped), (2, 1));
+        assert!(progress.percent > 0.0 && progress.percent < 100.0);
+
+        let mut resumed = Import::new(Cursor::new(contents.clone()), "test".into(), contents.len() as u64);
+        loop {
+            let batch = resumed.next_batch(2).expect("read");
+            if batch.is_empty() {
+                break;
+            }
+            resumed.apply(&mut ours, batch).expect("applied");
+        }
+        assert_eq!(ours.hash_last_block(), theirs.hash_last_block());
+        let progress = resumed.progress("done", None);
+        assert_eq!((progress.imported, progress.skipped, progress.height), (2, 3, 5));
+        assert_eq!(progress.percent, 100.0);
+    }
+
+    #[test]
+    fn stops_at_another_chain() {
+        let mut theirs = Blockchain::new_with(1);
+        theirs.mine().expect("mined");
+        let mut ours = Blockchain::new_with(1);
+        ours.replace(theirs.chain().iter().take(1).cloned().collect());
+        ours.mine().expect("mined");
+
+        let contents = file(&theirs);
+        let mut import = Import::new(Cursor::new(contents.clone()), "test".into(), contents.len() as u64);
+        let batch = import.next_batch(IMPORT_BATCH).expect("read");
+        assert!(import.apply(&mut ours, batch).is_err(), "block 2 is a fork");
+
+        let mut garbage = Import::new(Cursor::new(String::from("not a block\n")), "test".into(), 12);
+        assert!(garbage.next_batch(IMPORT_BATCH).is_err());
+    }
+}
@@ -0,0 +1,154 @@
+use lib::blockchain::{Block, Blockchain, Chain};
+use lib::error::{Error, Result};
+use lib::hasher::HashAlgorithm;
+use lib::transaction::Transaction;
+use serde_json;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+
+///
+/// Everything that changes a node's chain or pending transactions. Replaying
+/// them in order rebuilds the node exactly: mined blocks are recorded whole, so
+/// timestamps and nonces don't have to be found again.
+///
+/// Transaction: Accepted by POST /transaction/new
+/// Mined: A block this node mined, or imported
+/// Replaced: Our chain was replaced, during consensus or on startup. A journal
+///           starts with one, so replay starts from the same genesis block
+/// Cleared: Every pending transaction was dropped, by an operator
+///
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Event {
+    Transaction(Transaction),
+    Mined(Block),
+    Replaced(Chain),
+    Cleared
+}
+
+///
+/// A json lines file of `Event`s, appended to as they happen
+///
+#[derive(Debug)]
+pub struct Journal {
+    file: File
+}
+
+impl Journal {
+    ///
+    /// Open (or create) the journal at `path` for appending
+    ///
+    pub fn open(path: &Path) -> Result<Journal> {
+        let file = OpenOptions::new().append(true).create(true).open(path)?;
+        Ok(Journal { file: file })
+    }
+
+    pub fn record(&mut self, event: &Event) -> Result<()> {
+        let mut line = serde_json::to_vec(event)?;
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+        Ok(())
+    }
+}
+
+///
+/// Read every event from the journal at `path`
+///
+pub fn read(path: &Path) -> Result<Vec<Event>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut events = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            events.push(serde_json::from_str(&line)?);
+        }
+    }
+    Ok(events)
+}
+
+///
+/// Rebuild a node from its events. Chains and blocks are checked as they are
+/// applied, so a journal from a node with a consensus bug stops at the first
+/// event this build disagrees with.
+///
+pub fn replay(events: Vec<Event>, difficulty: u64, hasher: HashAlgorithm) -> Result<Blockchain> {
+    let mut blockchain = Blockchain::new_with_hasher(difficulty, hasher);
+    for (i, event) in events.into_iter().enumerate() {
+        let applied = match event {
+            Event::Transaction(txn) => {
+                blockchain.new_transaction(txn);
+                Ok(())
+            },
+            Event::Mined(block) => blockchain.append(block).map(|_| ()),
+            Event::Replaced(chain) => {
+                if blockchain.valid_chain(&chain) {
+                    blockchain.replace(chain);
+                    Ok(())
+                } else {
+                    Err(Error::Validation(String::from("replacement chain is invalid")))
+                }
+            },
+            Event::Cleared => {
+                blockchain.clear_transactions();
+                Ok(())
+            }
+        };
+        if let Err(e) = applied {
+            return Err(Error::Validation(format!("event {}: {}", i + 1, e)));
+        }
+    }
+    Ok(blockchain)
+}
+
+#[cfg(test)]
+mod tests {
+    use lib::blockchain::Blockchain;
+    use lib::hasher::HashAlgorithm;
+    use lib::journal::*;
+    use lib::transaction::Transaction;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn record_and_replay() {
+        let path = env::temp_dir().join("learnnet-journal-record_and_replay.jsonl");
+        let _ = fs::remove_file(&path);
+        let mut journal = Journal::open(&path).expect("opened");
+
+        let mut blockchain = Blockchain::new_with(1);
+        journal.record(&Event::Replaced(blockchain.chain().clone())).expect("recorded");
+        let txn = Transaction::new("a".into(), "b".into(), 1);
+        blockchain.new_transaction(txn.clone());
+        journal.record(&Event::Transaction(txn)).expect("recorded");
+        blockchain.mine().expect("mined");
+        journal.record(&Event::Mined(blockchain.last_block().clone())).expect("recorded");
+        let pending = Transaction::new("c".into(), "d".into(), 2);
+        blockchain.new_transaction(pending.clone());
+        journal.record(&Event::Transaction(pending)).expect("recorded");
+        blockchain.clear_transactions();
+        journal.record(&Event::Cleared).expect("recorded");
+        let kept = Transaction::new("e".into(), "f".into(), 3);
+        blockchain.new_transaction(kept.clone());
+        journal.record(&Event::Transaction(kept)).expect("recorded");
+
+        let replayed = replay(read(&path).expect("read"), 1, HashAlgorithm::default()).expect("replayed");
+        assert_eq!(replayed.chain(), blockchain.chain());
+        assert_eq!(replayed.state(), blockchain.state());
+        assert_eq!(replayed.current_transactions(), blockchain.current_transactions());
+    }
+
+    #[test]
+    fn replay_stops_at_bad_block() {
+        let mut blockchain = Blockchain::new_with(1);
+        blockchain.mine().expect("mined");
+        let mut bad = blockchain.last_block().clone();
+        bad.header.previous_hash = "bogus".into();
+
+        let events = vec![
+            Event::Replaced(blockchain.chain().iter().take(1).cloned().collect()),
+            Event::Mined(bad)
+        ];
+        let error = replay(events, 1, HashAlgorithm::default()).err().expect("expected an error");
+        assert!(error.to_string().starts_with("event 2:"), "{}", error);
+    }
+}
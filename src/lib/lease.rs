@@ -0,0 +1,173 @@
+use lib::error::Result;
+use serde_json;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+///
+/// How long a lease lasts without being renewed, unless configured otherwise
+///
+pub const DEFAULT_LEASE_SECS: i64 = 15;
+
+///
+/// The lease file. Who holds it, until when, and the term: bumped whenever the
+/// holder changes, so a holder can tell it lost the lease in between renewals
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Held {
+    holder: String,
+    term: u64,
+    expires: i64
+}
+
+///
+/// The outcome of claiming a lease
+///
+/// Ours: We hold it, for this term
+/// Theirs: Someone else holds it
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum Claim {
+    Ours(u64),
+    Theirs(String)
+}
+
+///
+/// A lease in a file that several processes share, such as replicas over one
+/// --data-dir. Whoever holds it until it expires gets to write.
+///
+/// Claims are serialized with a lock file next to the lease, created with
+/// `create_new`. One left behind by a process that died mid-claim is ignored
+/// once it's older than the lease itself.
+///
+#[derive(Debug)]
+pub struct Lease {
+    path: PathBuf,
+    holder: String,
+    ttl: i64
+}
+
+impl Lease {
+    pub fn new(path: &Path, holder: String, ttl: i64) -> Lease {
+        Lease { path: path.to_path_buf(), holder: holder, ttl: ttl }
+    }
+
+    ///
+    /// Who we claim as
+    ///
+    pub fn holder(&self) -> &str {
+        &self.holder
+    }
+
+    ///
+    /// Take the lease if it's free or expired, or renew it if it's ours, at
+    /// `now` (seconds since the epoch)
+    ///
+    pub fn claim(&self, now: i64) -> Result<Claim> {
+        let lock = self.path.with_extension("lock");
+        match OpenOptions::new().write(true).create_new(true).open(&lock) {
+            Ok(_) => {},
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                if self.stale(&lock) {
+                    warn!("Removing stale lease lock {}", lock.display());
+                    fs::remove_file(&lock)?;
+                    return self.claim(now);
+                }
+                //someone else is claiming, say how things stood before they started
+                return Ok(match self.read()? {
+                    Some(ref held) if held.holder == self.holder && held.expires > now => Claim::Ours(held.term),
+                    Some(held) => Claim::Theirs(held.holder),
+                    None => Claim::Theirs(String::from("unknown"))
+                });
+            },
+            Err(e) => return Err(e.into())
+        }
+        let claimed = self.claim_locked(now);
+        fs::remove_file(&lock)?;
+        claimed
+    }
+
+    fn claim_locked(&self, now: i64) -> Result<Claim> {
+        let current = self.read()?;
+        let term = match current {
+            Some(ref held) if held.holder == self.holder => held.term,
+            Some(ref held) if held.expires > now => return Ok(Claim::Theirs(held.holder.clone())),
+            Some(ref held) => {
+                info!("Taking over the lease from {}, it expired at {}", held.holder, held.expires);
+                held.term + 1
+            },
+            None => 1
+        };
+        self.write(&Held { holder: self.holder.clone(), term: term, expires: now + self.ttl })?;
+        Ok(Claim::Ours(term))
+    }
+
+    fn stale(&self, lock: &Path) -> bool {
+        fs::metadata(lock)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| modified.elapsed().ok())
+            .map(|age| age > Duration::from_secs(self.ttl as u64))
+            .unwrap_or(false)
+    }
+
+    fn read(&self) -> Result<Option<Held>> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into())
+        };
+        Ok(Some(serde_json::from_reader(BufReader::new(file))?))
+    }
+
+    //through a temporary file, so a reader never sees half a lease
+    fn write(&self, held: &Held) -> Result<()> {
+        let tmp = self.path.with_extension("tmp");
+        {
+            let mut file = File::create(&tmp)?;
+            serde_json::to_writer(&mut file, held)?;
+            file.write_all(b"\n")?;
+            file.sync_all()?;
+        }
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lib::lease::*;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn claim() {
+        let path = env::temp_dir().join("learnnet-lease-claim.lease");
+        let _ = fs::remove_file(&path);
+        let a = Lease::new(&path, "a".into(), 10);
+        let b = Lease::new(&path, "b".into(), 10);
+
+        assert_eq!(a.claim(100).expect("claimed"), Claim::Ours(1));
+        assert_eq!(b.claim(101).expect("claimed"), Claim::Theirs("a".into()));
+        assert_eq!(a.claim(105).expect("renewed"), Claim::Ours(1));
+        assert_eq!(b.claim(114).expect("claimed"), Claim::Theirs("a".into()), "renewed until 115");
+        assert_eq!(b.claim(116).expect("claimed"), Claim::Ours(2), "expired");
+        assert_eq!(a.claim(117).expect("claimed"), Claim::Theirs("b".into()));
+    }
+
+    #[test]
+    fn claim_while_locked() {
+        let path = env::temp_dir().join("learnnet-lease-claim_while_locked.lease");
+        let _ = fs::remove_file(&path);
+        let a = Lease::new(&path, "a".into(), 10);
+        let b = Lease::new(&path, "b".into(), 10);
+        assert_eq!(a.claim(100).expect("claimed"), Claim::Ours(1));
+
+        fs::File::create(path.with_extension("lock")).expect("locked");
+        assert_eq!(a.claim(101).expect("claimed"), Claim::Ours(1), "still ours while someone else claims");
+        assert_eq!(b.claim(120).expect("claimed"), Claim::Theirs("a".into()), "not taken over while locked");
+        fs::remove_file(path.with_extension("lock")).expect("unlocked");
+        assert_eq!(b.claim(120).expect("claimed"), Claim::Ours(2));
+    }
+}
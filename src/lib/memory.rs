@@ -0,0 +1,78 @@
+use lib::blockchain::Block;
+use lib::consensus::MAX_CHAIN_BYTES;
+use lib::encoding::encode;
+use lib::transaction::Transaction;
+
+//Rough memory accounting, so a small node can put a ceiling on what peers and
+//clients make it hold. Nothing here measures the allocator: a block or
+//transaction is counted as its canonical encoding plus a fixed overhead for the
+//structs, strings and collection nodes holding it. That's within a small factor
+//of the truth, which is all a cap needs. The chain itself is never evicted, only
+//counted; what a peer can make us download is capped instead.
+
+///
+/// Bytes counted for each transaction on top of its encoding, for its struct,
+/// its two address strings and its place in a set
+///
+pub const TRANSACTION_OVERHEAD: usize = 96;
+
+///
+/// Bytes counted for each block on top of its header's encoding and its
+/// transactions, for the header's strings and the block's remembered hash
+///
+pub const BLOCK_OVERHEAD: usize = 256;
+
+///
+/// The most the mempool and the orphan pool may hold, unless configured
+/// otherwise
+///
+pub const DEFAULT_MAX_MEMPOOL_BYTES: usize = 16 * 1024 * 1024;
+pub const DEFAULT_MAX_ORPHAN_BYTES: usize = 16 * 1024 * 1024;
+
+///
+/// How much memory a node may use for what peers and clients send it
+///
+/// mempool: Transactions beyond this are refused, and when blocks we abandon put
+///          more back, the excess is dropped
+/// orphans: Abandoned blocks beyond this are dropped, lowest first, as beyond
+///          `MAX_ORPHANS`
+/// peer_chain: The most json a peer's chain may be, bigger downloads are dropped
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Caps {
+    pub mempool: usize,
+    pub orphans: usize,
+    pub peer_chain: u64
+}
+
+impl Default for Caps {
+    fn default() -> Caps {
+        Caps { mempool: DEFAULT_MAX_MEMPOOL_BYTES, orphans: DEFAULT_MAX_ORPHAN_BYTES, peer_chain: MAX_CHAIN_BYTES }
+    }
+}
+
+///
+/// Approximately how many bytes each part of a node holds
+///
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Usage {
+    pub chain: usize,
+    pub mempool: usize,
+    pub orphans: usize
+}
+
+pub fn transaction_bytes(txn: &Transaction) -> usize {
+    txn.size() + TRANSACTION_OVERHEAD
+}
+
+pub fn transactions_bytes<'a, I>(txns: I) -> usize where I: IntoIterator<Item = &'a Transaction> {
+    txns.into_iter().map(transaction_bytes).sum()
+}
+
+pub fn block_bytes(block: &Block) -> usize {
+    encode(&block.header).len() + BLOCK_OVERHEAD + transactions_bytes(&block.transactions)
+}
+
+pub fn blocks_bytes<'a, I>(blocks: I) -> usize where I: IntoIterator<Item = &'a Block> {
+    blocks.into_iter().map(block_bytes).sum()
+}
@@ -0,0 +1,143 @@
+use lib::blockchain::Blockchain;
+use lib::consensus::{Candidate, Consensus};
+use lib::error::{Error, Result};
+use lib::transaction::Transaction;
+use reqwest::Client;
+use serde_json;
+use std::cmp;
+use std::collections::BTreeSet;
+use std::time::Duration;
+use url::Url;
+
+//Pending transactions only reach the node they were sent to. To spread them, nodes
+//ask each other for a digest of their mempool, the start of each pending
+//transaction's id, and then fetch only the transactions behind prefixes they
+//don't have. A transaction that missed a node while it was disconnected turns up
+//there on the next round, without anything being sent to everyone.
+
+///
+/// How many characters of a transaction id go in a digest. 48 bits of base64, so
+/// two pending transactions sharing one is unlikely on any network this runs
+///
+pub const PREFIX_LEN: usize = 8;
+
+///
+/// The most transactions asked for in one request
+///
+pub const MAX_FETCH: usize = 100;
+
+///
+/// Our pending transactions, compactly
+///
+/// prefixes: The first `PREFIX_LEN` characters of each id, sorted
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Digest {
+    pub prefixes: Vec<String>
+}
+
+#[derive(Deserialize)]
+struct PendingResponse {
+    transactions: Vec<Transaction>
+}
+
+fn prefix(id: &str) -> &str {
+    &id[..cmp::min(id.len(), PREFIX_LEN)]
+}
+
+///
+/// The digest of `blockchain`'s pending transactions
+///
+pub fn digest(blockchain: &Blockchain) -> Digest {
+    let prefixes: BTreeSet<String> = blockchain.current_transactions()
+                                               .iter()
+                                               .map(|txn| String::from(prefix(&blockchain.transaction_id(txn))))
+                                               .collect();
+    Digest { prefixes: prefixes.into_iter().collect() }
+}
+
+///
+/// The prefixes in `theirs` that aren't in `ours`, at most `MAX_FETCH`
+///
+pub fn missing(ours: &Digest, theirs: &Digest) -> Vec<String> {
+    let ours: BTreeSet<&String> = ours.prefixes.iter().collect();
+    theirs.prefixes.iter().filter(|prefix| !ours.contains(prefix)).take(MAX_FETCH).cloned().collect()
+}
+
+///
+/// Our pending transactions whose ids start with one of `prefixes`
+///
+pub fn matching<'a>(blockchain: &'a Blockchain, prefixes: &[String]) -> Vec<&'a Transaction> {
+    let prefixes: BTreeSet<&str> = prefixes.iter().map(|prefix| prefix.as_str()).collect();
+    blockchain.current_transactions()
+              .iter()
+              .filter(|txn| prefixes.contains(prefix(&blockchain.transaction_id(txn))))
+              .collect()
+}
+
+///
+/// Of the transactions a peer sent, the ones we should add: not rewards, which
+/// only a miner may create, and not already in our chain or mempool
+///
+pub fn acceptable(blockchain: &Blockchain, transactions: Vec<Transaction>) -> Vec<Transaction> {
+    let known: BTreeSet<&Transaction> = blockchain.chain()
+                                                  .iter()
+                                                  .flat_map(|block| block.transactions.iter())
+                                                  .chain(blockchain.current_transactions().iter())
+                                                  .collect();
+    transactions.into_iter().filter(|txn| !txn.is_reward() && !known.contains(txn)).collect()
+}
+
+///
+/// Download a node's mempool digest
+///
+/// returns: It, and the bytes it took
+pub fn get_digest(candidate: &Candidate, timeout: Duration, network: &str) -> Result<(Digest, u64)> {
+    let client = Client::builder().timeout(timeout).build()?;
+    let raw = Consensus::get_from(&client, &format!("{}/mempool/digest", candidate.node.trim_right_matches('/')), &candidate.addresses, network)?;
+    Ok((serde_json::from_str(&raw)?, raw.len() as u64))
+}
+
+///
+/// Download the pending transactions behind `prefixes` from a node
+///
+/// returns: Them, and the bytes they took
+pub fn get_transactions(candidate: &Candidate, prefixes: &[String], timeout: Duration, network: &str) -> Result<(Vec<Transaction>, u64)> {
+    let client = Client::builder().timeout(timeout).build()?;
+    let node = &candidate.node;
+    let mut url = Url::parse(&format!("{}/mempool", node.trim_right_matches('/'))).map_err(|e| Error::Validation(format!("Invalid node url {}. {}", node, e)))?;
+    url.query_pairs_mut().append_pair("prefixes", &prefixes.join(","));
+    let raw = Consensus::get_from(&client, url.as_str(), &candidate.addresses, network)?;
+    let pending: PendingResponse = serde_json::from_str(&raw)?;
+    Ok((pending.transactions, raw.len() as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use lib::blockchain::Blockchain;
+    use lib::mempool::*;
+    use lib::transaction::Transaction;
+
+    #[test]
+    fn reconcile() {
+        let mut ours = Blockchain::new_with(1);
+        let mut theirs = Blockchain::new_with(1);
+        let shared = Transaction::new("a".into(), "b".into(), 1);
+        let dropped = Transaction::new("c".into(), "d".into(), 2);
+        ours.new_transaction(shared.clone());
+        theirs.new_transaction(shared.clone());
+        theirs.new_transaction(dropped.clone());
+        theirs.new_transaction(Transaction::new("0".into(), "d".into(), 50));
+
+        let their_digest = digest(&theirs);
+        assert_eq!(their_digest.prefixes.len(), 3);
+        assert!(their_digest.prefixes.iter().all(|prefix| prefix.len() == PREFIX_LEN));
+        let missing = missing(&digest(&ours), &their_digest);
+        assert_eq!(missing.len(), 2, "the one we share isn't fetched");
+
+        let sent: Vec<Transaction> = matching(&theirs, &missing).into_iter().cloned().collect();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(acceptable(&ours, sent), vec![dropped], "rewards and known transactions are left out");
+        assert!(acceptable(&ours, vec![shared]).is_empty());
+    }
+}
@@ -0,0 +1,181 @@
+use lib::encoding::encode_str;
+use lib::hasher::{Hasher, Domain};
+use lib::transaction::Transaction;
+use std::collections::BTreeSet;
+
+///
+/// The Merkle root of a block's transactions.
+/// 
+/// The leaves are the transaction hashes (in the set's order). Each level hashes
+/// neighbouring pairs together, repeating the last hash when a level has an odd
+/// number of hashes, until one is left. A block with no transactions has the hash
+/// of no bytes as its root.
+/// 
+pub fn root<H>(hasher: &H, transactions: &BTreeSet<Transaction>) -> String where H: Hasher {
+    root_of_leaves(hasher, transactions.iter().map(|txn| hasher.hash(Domain::Transaction, txn)).collect())
+}
+
+///
+/// `root`, from the canonical encodings of the transactions instead, in the
+/// same order. They can be hashed somewhere the transactions aren't
+/// 
+pub fn root_of_encoded<H>(hasher: &H, encoded: &[Vec<u8>]) -> String where H: Hasher {
+    root_of_leaves(hasher, encoded.iter().map(|bytes| hasher.hash_bytes(Domain::Transaction, bytes)).collect())
+}
+
+fn root_of_leaves<H>(hasher: &H, mut level: Vec<String>) -> String where H: Hasher {
+    if level.is_empty() {
+        return hasher.hash_bytes(Domain::MerkleNode, &[]);
+    }
+    while level.len() > 1 {
+        level = next_level(hasher, &level);
+    }
+    level.pop().expect("invariant: one hash left")
+}
+
+fn next_level<H>(hasher: &H, level: &[String]) -> Vec<String> where H: Hasher {
+    level.chunks(2)
+         .map(|pair| {
+             let left = &pair[0];
+             let right = pair.get(1).unwrap_or(left);
+             node(hasher, left, right)
+         })
+         .collect()
+}
+
+///
+/// One step of a Merkle proof: the hash to combine with on the way up to the
+/// root, and which side of the pair it's on
+/// 
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProofStep {
+    pub hash: String,
+    pub side: &'static str
+}
+
+///
+/// The hashes that lead from `transaction` up to the root of `transactions`, the
+/// lowest first. None if it isn't one of them
+/// 
+pub fn proof<H>(hasher: &H, transactions: &BTreeSet<Transaction>, transaction: &Transaction) -> Option<Vec<ProofStep>> where H: Hasher {
+    let mut position = transactions.iter().position(|txn| txn == transaction)?;
+    let mut level: Vec<String> = transactions.iter().map(|txn| hasher.hash(Domain::Transaction, txn)).collect();
+    let mut steps = Vec::new();
+    while level.len() > 1 {
+        let step = if position % 2 == 0 {
+            //a last hash without a pair is paired with itself
+            ProofStep { hash: level.get(position + 1).unwrap_or(&level[position]).clone(), side: "right" }
+        } else {
+            ProofStep { hash: level[position - 1].clone(), side: "left" }
+        };
+        steps.push(step);
+        level = next_level(hasher, &level);
+        position /= 2;
+    }
+    Some(steps)
+}
+
+///
+/// Whether `steps` lead from the transaction hash `leaf` to `root`
+/// 
+pub fn verify<H>(hasher: &H, leaf: &str, steps: &[ProofStep], root: &str) -> bool where H: Hasher {
+    let hash = steps.iter().fold(leaf.to_string(), |hash, step| match step.side {
+        "left" => node(hasher, &step.hash, &hash),
+        _ => node(hasher, &hash, &step.hash)
+    });
+    hash == root
+}
+
+///
+/// The hash of two child hashes
+/// 
+fn node<H>(hasher: &H, left: &str, right: &str) -> String where H: Hasher {
+    let mut bytes = Vec::new();
+    encode_str(left, &mut bytes);
+    encode_str(right, &mut bytes);
+    hasher.hash_bytes(Domain::MerkleNode, &bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use lib::encoding;
+    use lib::hasher::*;
+    use lib::merkle;
+    use lib::transaction::Transaction;
+    use std::collections::BTreeSet;
+
+    fn transactions(count: i64) -> BTreeSet<Transaction> {
+        (0..count).map(|i| Transaction::new("a".into(), "b".into(), i)).collect()
+    }
+
+    const SHA256: HashAlgorithm = HashAlgorithm::Sha256;
+
+    #[test]
+    fn empty() {
+        assert_eq!(merkle::root(&SHA256, &BTreeSet::new()), "xm1RA0PH2xUtbJJZtuk5NlyNW7wl990oGmVXxSH0rJY=");
+    }
+
+    #[test]
+    fn single() {
+        let txns = transactions(1);
+        let leaf = SHA256.hash(Domain::Transaction, txns.iter().next().unwrap());
+        assert_eq!(merkle::root(&SHA256, &txns), leaf, "one transaction is its own root");
+    }
+
+    #[test]
+    fn pair() {
+        assert_eq!(merkle::root(&SHA256, &transactions(2)), "DospTqbgAbVL1VfTDZZePmA84TBw3a0iRUG/Z3gQUok=");
+    }
+
+    #[test]
+    fn root_of_encoded() {
+        for count in 0..6 {
+            let txns = transactions(count);
+            let encoded: Vec<Vec<u8>> = txns.iter().map(encoding::encode).collect();
+            assert_eq!(merkle::root_of_encoded(&SHA256, &encoded), merkle::root(&SHA256, &txns), "{} transactions", count);
+        }
+    }
+
+    #[test]
+    fn odd_level_repeats_last() {
+        let txns = transactions(3);
+        let leaves: Vec<String> = txns.iter().map(|txn| SHA256.hash(Domain::Transaction, txn)).collect();
+        let left = merkle::node(&SHA256, &leaves[0], &leaves[1]);
+        let right = merkle::node(&SHA256, &leaves[2], &leaves[2]);
+        assert_eq!(merkle::root(&SHA256, &txns), merkle::node(&SHA256, &left, &right));
+    }
+
+    #[test]
+    fn commits_to_every_transaction() {
+        let txns = transactions(4);
+        let mut changed = transactions(4);
+        changed.insert(Transaction::new("c".into(), "d".into(), 1));
+        assert_eq!(merkle::root(&SHA256, &txns), merkle::root(&SHA256, &transactions(4)));
+        assert_ne!(merkle::root(&SHA256, &txns), merkle::root(&SHA256, &changed));
+    }
+
+    #[test]
+    fn proofs() {
+        for count in 1..6 {
+            let txns = transactions(count);
+            let root = merkle::root(&SHA256, &txns);
+            for txn in txns.iter() {
+                let steps = merkle::proof(&SHA256, &txns, txn).expect("a proof");
+                let leaf = SHA256.hash(Domain::Transaction, txn);
+                assert!(merkle::verify(&SHA256, &leaf, &steps, &root), "{} of {}", txn.amount, count);
+                assert!(!merkle::verify(&SHA256, &leaf, &steps, "bogus"));
+            }
+        }
+        let txns = transactions(4);
+        let other = Transaction::new("c".into(), "d".into(), 1);
+        assert_eq!(merkle::proof(&SHA256, &txns, &other), None);
+        let steps = merkle::proof(&SHA256, &txns, txns.iter().next().unwrap()).unwrap();
+        assert!(!merkle::verify(&SHA256, &SHA256.hash(Domain::Transaction, &other), &steps, &merkle::root(&SHA256, &txns)), "only for its own transaction");
+    }
+
+    #[test]
+    fn algorithm_changes_root() {
+        let txns = transactions(2);
+        assert_ne!(merkle::root(&SHA256, &txns), merkle::root(&HashAlgorithm::Sha512, &txns));
+    }
+}
@@ -1,6 +1,37 @@
 //this part could go to a crate and be used by web
 pub mod blockchain;
+pub mod chain;
+pub mod error;
 pub mod transaction;
 pub mod hasher;
+pub mod encoding;
 pub mod consensus;
-
+pub mod peers;
+pub mod mempool;
+pub mod memory;
+pub mod merkle;
+pub mod selection;
+pub mod state;
+pub mod validation;
+pub mod standard;
+pub mod store;
+pub mod export;
+pub mod import;
+pub mod compare;
+pub mod bootstrap;
+pub mod genesis;
+pub mod forks;
+pub mod dot;
+pub mod journal;
+pub mod lease;
+pub mod protocol;
+pub mod qr;
+pub mod rounds;
+pub mod rejections;
+pub mod versions;
+pub mod upnp;
+pub mod random;
+#[cfg(test)]
+pub mod sim;
+#[cfg(test)]
+pub mod testvectors;
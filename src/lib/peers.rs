@@ -0,0 +1,635 @@
+use lib::error::{Error, Result};
+use lib::random::xorshift;
+use serde_json;
+use std::collections::{BTreeMap, HashMap};
+use std::collections::hash_map::Keys;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Write};
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::Path;
+use url::{Host, Url};
+
+///
+/// A peer that failed this many consensus rounds in a row stops counting as good
+/// 
+pub const MAX_FAILURES: u32 = 3;
+
+///
+/// How many peers a consensus round talks to, unless configured otherwise
+/// 
+pub const DEFAULT_PEERS_PER_ROUND: usize = 8;
+
+///
+/// How long a peer's resolved addresses are used before it's looked up again
+/// 
+pub const RESOLVE_TTL_SECS: i64 = 300;
+
+///
+/// A peer is banned once it has sent at least this many payloads we rejected...
+/// 
+pub const BAN_MIN_INVALID: u64 = 5;
+
+///
+/// ...and they're at least this share of what we've asked it for
+/// 
+pub const BAN_INVALID_PERCENT: u64 = 20;
+
+///
+/// How long a ban lasts. A peer still over the limits when it's lifted is banned
+/// again at its next bad payload
+/// 
+pub const BAN_SECS: i64 = 3600;
+
+///
+/// What we know about a peer
+/// 
+/// last_seen: When it last answered, in seconds since the epoch. None if never
+/// failures: Consensus rounds it has failed in a row
+/// latency_ms: How long it takes to send its chain, smoothed over rounds. None if never
+/// height: The length of the chain it last sent. None if never
+/// work: The work that chain proves, see `chain_work`. None if never
+/// stats: What we've asked of it, and how much of what it sent we rejected
+/// 
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PeerInfo {
+    pub last_seen: Option<i64>,
+    pub failures: u32,
+    pub latency_ms: Option<u64>,
+    pub height: Option<usize>,
+    pub work: Option<u64>,
+    pub stats: PeerStats
+}
+
+///
+/// A peer's traffic with us, since it was registered. Only requests we make are
+/// counted, requests peers make of us don't say which peer they're from
+/// 
+/// requests: Requests we've sent it
+/// invalid: Answers we rejected for what was in them: a chain that breaks the
+///          rules or is too big, another network, json that isn't what we
+///          asked for. Not answering isn't counted, that's `failures`
+/// bytes_received: Of the bodies it answered with
+/// bytes_sent: Of the bodies we sent it
+/// bans: How many times it's been banned
+/// banned_until: Seconds since the epoch. Not asked anything until then
+/// 
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerStats {
+    pub requests: u64,
+    pub invalid: u64,
+    pub bytes_received: u64,
+    pub bytes_sent: u64,
+    pub bans: u32,
+    pub banned_until: Option<i64>
+}
+
+impl PeerInfo {
+    pub fn is_good(&self) -> bool {
+        self.failures < MAX_FAILURES
+    }
+
+    pub fn is_banned(&self, now: i64) -> bool {
+        self.stats.banned_until.map(|until| until > now).unwrap_or(false)
+    }
+}
+
+///
+/// The other nodes in the network.
+/// 
+/// Kept apart from the `Blockchain` (and behind its own lock) since registering
+/// a peer has nothing to do with chain data.
+/// 
+#[derive(Debug, Default)]
+pub struct Peers {
+    nodes: HashMap<Url, PeerInfo>,
+    resolved: HashMap<Url, Resolved>
+}
+
+//not saved, addresses are looked up again after a restart
+#[derive(Debug)]
+struct Resolved {
+    at: i64,
+    addresses: Vec<SocketAddr>
+}
+
+///
+/// One line of the peers file
+/// 
+#[derive(Serialize, Deserialize)]
+struct SavedPeer {
+    url: String,
+    last_seen: Option<i64>,
+    failures: u32,
+    #[serde(default)]
+    latency_ms: Option<u64>,
+    #[serde(default)]
+    height: Option<usize>,
+    #[serde(default)]
+    work: Option<u64>,
+    #[serde(default)]
+    stats: PeerStats
+}
+
+impl Peers {
+
+    pub fn new() -> Peers {
+        Peers { nodes: HashMap::new(), resolved: HashMap::new() }
+    }
+
+    ///
+    /// Add a new node
+    /// 
+    /// returns: false if it was already registered
+    pub fn register(&mut self, address: Url) -> bool {
+        if self.nodes.contains_key(&address) {
+            return false;
+        }
+        self.nodes.insert(address, PeerInfo::default());
+        true
+    }
+
+    ///
+    /// Returns the other nodes in the network
+    /// 
+    pub fn nodes(&self) -> Keys<Url, PeerInfo> {
+        self.nodes.keys()
+    }
+
+    pub fn info(&self, address: &Url) -> Option<&PeerInfo> {
+        self.nodes.get(address)
+    }
+
+    ///
+    /// `address` answered at `timestamp`
+    /// 
+    pub fn seen(&mut self, address: &Url, timestamp: i64) {
+        if let Some(info) = self.nodes.get_mut(address) {
+            info.last_seen = Some(timestamp);
+            info.failures = 0;
+        }
+    }
+
+    ///
+    /// `address` sent a chain of `height` blocks proving `work` in `latency_ms`.
+    /// The latency is smoothed, so one slow round doesn't send a peer to the back
+    /// 
+    pub fn measured(&mut self, address: &Url, latency_ms: u64, height: usize, work: u64) {
+        if let Some(info) = self.nodes.get_mut(address) {
+            info.latency_ms = Some(match info.latency_ms {
+                Some(previous) => (previous * 3 + latency_ms) / 4,
+                None => latency_ms
+            });
+            info.height = Some(height);
+            info.work = Some(work);
+        }
+    }
+
+    ///
+    /// `address` didn't answer, or answered with something we couldn't use
+    /// 
+    pub fn failed(&mut self, address: &Url) {
+        if let Some(info) = self.nodes.get_mut(address) {
+            info.failures = info.failures.saturating_add(1);
+        }
+    }
+
+    ///
+    /// We sent `address` a request with a body of `sent` bytes, and it answered
+    /// with `received`
+    /// 
+    pub fn exchanged(&mut self, address: &Url, sent: u64, received: u64) {
+        if let Some(info) = self.nodes.get_mut(address) {
+            info.stats.requests += 1;
+            info.stats.bytes_sent += sent;
+            info.stats.bytes_received += received;
+        }
+    }
+
+    ///
+    /// `address` answered at `now` with something we rejected for what was in it.
+    /// Bans it for `BAN_SECS` if that's one too many
+    /// 
+    /// returns: Whether it's now banned
+    pub fn misbehaved(&mut self, address: &Url, now: i64) -> bool {
+        let info = match self.nodes.get_mut(address) {
+            Some(info) => info,
+            None => return false
+        };
+        info.stats.invalid += 1;
+        let stats = &mut info.stats;
+        if stats.invalid >= BAN_MIN_INVALID && stats.invalid * 100 >= stats.requests * BAN_INVALID_PERCENT {
+            if stats.banned_until.map(|until| until <= now).unwrap_or(true) {
+                stats.bans += 1;
+                warn!("Banning {} for {}s, {} of {} answers were rejected", address, BAN_SECS, stats.invalid, stats.requests);
+            }
+            stats.banned_until = Some(now + BAN_SECS);
+            return true;
+        }
+        false
+    }
+
+    ///
+    /// The addresses `address` last resolved to, however long ago. Empty if it
+    /// never has
+    /// 
+    pub fn addresses(&self, address: &Url) -> Vec<SocketAddr> {
+        self.resolved.get(address).map(|resolved| resolved.addresses.clone()).unwrap_or_default()
+    }
+
+    ///
+    /// Whether `address` needs looking up at `now`: it's never been, or not in
+    /// the last `RESOLVE_TTL_SECS`
+    /// 
+    pub fn needs_resolving(&self, address: &Url, now: i64) -> bool {
+        self.resolved.get(address).map(|resolved| resolved.at + RESOLVE_TTL_SECS <= now).unwrap_or(true)
+    }
+
+    ///
+    /// `address` resolved to `addresses` at `now`. A lookup that found nothing
+    /// keeps the addresses from before, a peer whose DNS is down may still be up
+    /// 
+    pub fn resolved(&mut self, address: &Url, addresses: Vec<SocketAddr>, now: i64) {
+        if !self.nodes.contains_key(address) {
+            return;
+        }
+        let entry = self.resolved.entry(address.clone()).or_insert_with(|| Resolved { at: now, addresses: Vec::new() });
+        entry.at = now;
+        if !addresses.is_empty() {
+            entry.addresses = addresses;
+        }
+    }
+
+    ///
+    /// Number of registered nodes
+    /// 
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    ///
+    /// Number of nodes that haven't failed `MAX_FAILURES` rounds in a row
+    /// 
+    pub fn good(&self) -> usize {
+        self.nodes.values().filter(|info| info.is_good()).count()
+    }
+
+    ///
+    /// At most `count` nodes to talk to this round. Good nodes come first, picked
+    /// at random (from `seed`) one subnet at a time, so a crowd of nodes on one
+    /// network can't take every place. Failing nodes only fill places that are
+    /// left, so they still get a chance to recover. Nodes banned at `now` get none
+    /// 
+    pub fn sample(&self, count: usize, seed: u64, now: i64) -> Vec<Url> {
+        let mut rng = seed | 1;
+        let mut next = move || xorshift(&mut rng);
+        let mut nodes: Vec<(&Url, &PeerInfo)> = self.nodes.iter().filter(|&(_, info)| !info.is_banned(now)).collect();
+        //HashMap order isn't even stable between runs, so sort before shuffling
+        nodes.sort_by(|a, b| a.0.cmp(b.0));
+        for i in (1..nodes.len()).rev() {
+            let j = (next() % (i as u64 + 1)) as usize;
+            nodes.swap(i, j);
+        }
+        let (good, failing): (Vec<(&Url, &PeerInfo)>, Vec<(&Url, &PeerInfo)>) = nodes.into_iter().partition(|&(_, info)| info.is_good());
+
+        let mut subnets: BTreeMap<String, Vec<&Url>> = BTreeMap::new();
+        let mut order = Vec::new();
+        for (url, _) in good {
+            let subnet = subnet(url);
+            if !subnets.contains_key(&subnet) {
+                order.push(subnet.clone());
+            }
+            subnets.entry(subnet).or_insert_with(Vec::new).push(url);
+        }
+        let mut picked = Vec::with_capacity(count);
+        let mut round = 0;
+        while picked.len() < count && order.iter().any(|subnet| subnets[subnet].len() > round) {
+            for subnet in order.iter() {
+                if picked.len() < count {
+                    if let Some(url) = subnets[subnet].get(round) {
+                        picked.push((*url).clone());
+                    }
+                }
+            }
+            round += 1;
+        }
+        for (url, _) in failing {
+            if picked.len() < count {
+                picked.push(url.clone());
+            }
+        }
+        picked
+    }
+
+    ///
+    /// Sort `nodes` into the order to ask them for their chains: the most work
+    /// first, so the best chain tends to arrive early, and the fastest first
+    /// among equals. Nodes we've never heard from could have the most of all
+    /// and go first
+    /// 
+    pub fn prioritize(&self, nodes: &mut Vec<Url>) {
+        let default = PeerInfo::default();
+        nodes.sort_by(|a, b| {
+            let a = self.nodes.get(a).unwrap_or(&default);
+            let b = self.nodes.get(b).unwrap_or(&default);
+            let work = |info: &PeerInfo| info.work.unwrap_or(u64::max_value());
+            let latency = |info: &PeerInfo| info.latency_ms.unwrap_or(u64::max_value());
+            work(b).cmp(&work(a)).then(latency(a).cmp(&latency(b)))
+        });
+    }
+
+    ///
+    /// Write every node, and what we know about it, to `path` as json. Written
+    /// to a temporary file first, so a crash never leaves half a peers file
+    /// 
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut saved: Vec<SavedPeer> = self.nodes.iter().map(|(url, info)| SavedPeer {
+            url: url.as_str().to_string(),
+            last_seen: info.last_seen,
+            failures: info.failures,
+            latency_ms: info.latency_ms,
+            height: info.height,
+            work: info.work,
+            stats: info.stats.clone()
+        }).collect();
+        saved.sort_by(|a, b| a.url.cmp(&b.url));
+        let tmp = path.with_extension("tmp");
+        {
+            let mut file = File::create(&tmp)?;
+            serde_json::to_writer_pretty(&mut file, &saved)?;
+            file.write_all(b"\n")?;
+            file.sync_all()?;
+        }
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    ///
+    /// Read the peers saved at `path`. No file is no peers
+    /// 
+    pub fn load(path: &Path) -> Result<Peers> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Peers::new()),
+            Err(e) => return Err(Error::from(e))
+        };
+        let saved: Vec<SavedPeer> = serde_json::from_reader(BufReader::new(file))?;
+        let mut peers = Peers::new();
+        for peer in saved {
+            let url = Url::parse(&peer.url).map_err(|e| Error::Validation(format!("Bad peer {} in {}. {}", peer.url, path.display(), e)))?;
+            peers.nodes.insert(url, PeerInfo {
+                last_seen: peer.last_seen,
+                failures: peer.failures,
+                latency_ms: peer.latency_ms,
+                height: peer.height,
+                work: peer.work,
+                stats: peer.stats
+            });
+        }
+        Ok(peers)
+    }
+}
+
+///
+/// A node's url from what was registered: a url, or just a host and port like
+/// `192.168.0.5:5000`, `[::1]:8000` or `node.example.com:8000`, which are http.
+/// A host alone, like `10.0.0.5`, is on `default_port`. A url is taken as
+/// written, so `http://10.0.0.5` is on 80
+/// 
+pub fn parse_node(node: &str, default_port: u16) -> Result<Url> {
+    let node = node.trim();
+    let url = if node.contains("://") {
+        Url::parse(node)
+    } else {
+        Url::parse(&format!("http://{}", node)).map(|mut url| {
+            if !has_port(node) {
+                //only fails for urls that can't have a port, and http ones can
+                let _ = url.set_port(Some(default_port));
+            }
+            url
+        })
+    };
+    let url = url.map_err(|e| Error::Validation(format!("Invalid node {}. {}", node, e)))?;
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err(Error::Validation(format!("Invalid node {}. Nodes are http or https, not {}", node, url.scheme())));
+    }
+    if url.host().is_none() {
+        return Err(Error::Validation(format!("Invalid node {}. It has no host", node)));
+    }
+    Ok(url)
+}
+
+//whether the host and port `node`, with no scheme, gives a port. Url drops
+//the scheme's own, so it can't tell `host:80` from `host`
+fn has_port(node: &str) -> bool {
+    let authority = node.split('/').next().unwrap_or("");
+    let port_from = authority.rfind(']').map(|end| end + 1).unwrap_or(0);
+    authority[port_from..].contains(':')
+}
+
+///
+/// Look up the addresses `url` could be reached at. A host name can have more
+/// than one, IP addresses are only themselves
+/// 
+pub fn resolve(url: &Url) -> io::Result<Vec<SocketAddr>> {
+    let port = url.port_or_known_default().unwrap_or(80);
+    let mut addresses: Vec<SocketAddr> = match url.host() {
+        Some(Host::Ipv4(ip)) => (ip, port).to_socket_addrs()?.collect(),
+        Some(Host::Ipv6(ip)) => (ip, port).to_socket_addrs()?.collect(),
+        Some(Host::Domain(domain)) => (domain, port).to_socket_addrs()?.collect(),
+        None => Vec::new()
+    };
+    addresses.dedup();
+    Ok(addresses)
+}
+
+///
+/// Nodes are considered to be on the same network if they share a /16 (IPv4),
+/// a /32 (IPv6) or a host name
+/// 
+fn subnet(url: &Url) -> String {
+    match url.host() {
+        Some(Host::Ipv4(ip)) => {
+            let octets = ip.octets();
+            format!("{}.{}", octets[0], octets[1])
+        },
+        Some(Host::Ipv6(ip)) => {
+            let segments = ip.segments();
+            format!("{:x}:{:x}", segments[0], segments[1])
+        },
+        Some(Host::Domain(domain)) => domain.to_string(),
+        None => String::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lib::peers::*;
+    use std::env;
+    use std::fs;
+    use std::net::SocketAddr;
+    use url::Url;
+
+    #[test]
+    fn register() {
+        let mut peers = Peers::new();
+        let test_local_url = Url::parse("http://localhost:9000").expect("valid url");
+        assert!(peers.register(test_local_url.clone()));
+        assert_eq!(peers.len(),  1, "Expected 1 node");
+        assert!(!peers.register(test_local_url));
+        assert_eq!(peers.len(),  1, "Expected 1 node after dupe add (idempotent)");
+    }
+
+    #[test]
+    fn reputation() {
+        let mut peers = Peers::new();
+        let url = Url::parse("http://localhost:9000").expect("valid url");
+        peers.register(url.clone());
+        for _ in 0..MAX_FAILURES {
+            peers.failed(&url);
+        }
+        assert_eq!(peers.good(), 0);
+        peers.seen(&url, 42);
+        assert_eq!(peers.info(&url), Some(&PeerInfo { last_seen: Some(42), failures: 0, latency_ms: None, height: None, work: None, stats: PeerStats::default() }));
+        assert_eq!(peers.good(), 1);
+        assert!(!peers.register(url.clone()));
+        assert_eq!(peers.info(&url).map(|info| info.last_seen), Some(Some(42)), "registering again keeps what we know");
+    }
+
+    #[test]
+    fn sample() {
+        let mut peers = Peers::new();
+        for i in 0..10 {
+            peers.register(Url::parse(&format!("http://10.0.0.{}:8000", i)).expect("valid url"));
+        }
+        let other = Url::parse("http://192.168.1.1:8000").expect("valid url");
+        let failing = Url::parse("http://172.16.0.1:8000").expect("valid url");
+        peers.register(other.clone());
+        peers.register(failing.clone());
+        for _ in 0..MAX_FAILURES {
+            peers.failed(&failing);
+        }
+
+        for seed in 0..20 {
+            let sample = peers.sample(3, seed, 0);
+            assert_eq!(sample.len(), 3);
+            assert!(sample.contains(&other), "the only node on its subnet always gets a place");
+            assert!(!sample.contains(&failing), "good nodes come first");
+        }
+        assert_eq!(peers.sample(3, 7, 0), peers.sample(3, 7, 0));
+        assert_eq!(peers.sample(100, 7, 0).len(), 12);
+        assert_eq!(peers.sample(100, 7, 0).last(), Some(&failing), "failing nodes fill what's left");
+    }
+
+    #[test]
+    fn bans() {
+        let mut peers = Peers::new();
+        let url = Url::parse("http://localhost:9000").expect("valid url");
+        peers.register(url.clone());
+        for _ in 0..100 {
+            peers.exchanged(&url, 0, 1000);
+        }
+        for _ in 0..BAN_MIN_INVALID - 1 {
+            assert!(!peers.misbehaved(&url, 42), "too few to ban");
+        }
+        //of 100 requests, one short of the share
+        for _ in 0..BAN_INVALID_PERCENT - BAN_MIN_INVALID {
+            assert!(!peers.misbehaved(&url, 42), "a small share of what it sent");
+        }
+        assert!(peers.misbehaved(&url, 42));
+        let stats = peers.info(&url).map(|info| info.stats.clone()).expect("registered");
+        assert_eq!((stats.requests, stats.bytes_received, stats.bans, stats.banned_until), (100, 100_000, 1, Some(42 + BAN_SECS)));
+        assert!(peers.sample(10, 7, 42).is_empty(), "banned");
+        assert_eq!(peers.sample(10, 7, 42 + BAN_SECS), vec![url.clone()], "lifted");
+        assert!(peers.misbehaved(&url, 42 + BAN_SECS), "still over the limits");
+        assert_eq!(peers.info(&url).map(|info| info.stats.bans), Some(2));
+    }
+
+    #[test]
+    fn prioritize() {
+        let mut peers = Peers::new();
+        let fast = Url::parse("http://localhost:9000").expect("valid url");
+        let slow = Url::parse("http://localhost:9001").expect("valid url");
+        let short = Url::parse("http://localhost:9002").expect("valid url");
+        let new = Url::parse("http://localhost:9003").expect("valid url");
+        for url in vec![&fast, &slow, &short, &new] {
+            peers.register(url.clone());
+        }
+        peers.measured(&fast, 10, 5, 320);
+        peers.measured(&slow, 500, 5, 320);
+        peers.measured(&short, 1, 2, 128);
+
+        let mut nodes = vec![short.clone(), slow.clone(), fast.clone(), new.clone()];
+        peers.prioritize(&mut nodes);
+        assert_eq!(nodes, vec![new, fast.clone(), slow, short]);
+
+        peers.measured(&fast, 50, 5, 320);
+        assert_eq!(peers.info(&fast).and_then(|info| info.latency_ms), Some(20), "smoothed");
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(parse_node("http://[::1]:8000", 9000).map(|url| url.host_str().map(String::from)).ok(), Some(Some(String::from("[::1]"))));
+        assert_eq!(parse_node("[::1]:8000", 9000).map(|url| url.port()).ok(), Some(Some(8000)), "scheme defaults to http");
+        assert_eq!(parse_node(" 192.168.0.5:5000 ", 9000).map(|url| url.into_string()).ok(), Some(String::from("http://192.168.0.5:5000/")));
+        assert!(parse_node("https://node.example.com", 9000).is_ok());
+        assert!(parse_node("ftp://node.example.com", 9000).is_err());
+        assert!(parse_node("::1:8000", 9000).is_err(), "IPv6 needs brackets");
+        assert!(parse_node("http://", 9000).is_err());
+    }
+
+    #[test]
+    fn parse_shorthand() {
+        let parsed = |node: &str| parse_node(node, 8000).map(|url| url.into_string()).ok();
+        assert_eq!(parsed("10.0.0.5"), Some(String::from("http://10.0.0.5:8000/")));
+        assert_eq!(parsed("[::1]"), Some(String::from("http://[::1]:8000/")));
+        assert_eq!(parsed("node.example.com/"), Some(String::from("http://node.example.com:8000/")));
+        //every way of writing the same node registers it once
+        assert_eq!(parsed("10.0.0.5"), parsed("http://10.0.0.5:8000"));
+        assert_eq!(parsed("10.0.0.5:8000"), parsed("http://10.0.0.5:8000/"));
+        assert_eq!(parsed("10.0.0.5:80"), Some(String::from("http://10.0.0.5/")), "a port given is kept, even the scheme's");
+        assert_eq!(parsed("http://10.0.0.5"), Some(String::from("http://10.0.0.5/")), "urls are taken as written");
+    }
+
+    #[test]
+    fn resolution_cache() {
+        let mut peers = Peers::new();
+        let url = Url::parse("http://[::1]:9000").expect("valid url");
+        let local: SocketAddr = "[::1]:9000".parse().expect("valid address");
+        let addresses = resolve(&url).expect("an IP resolves to itself");
+        assert_eq!(addresses, vec![local]);
+
+        peers.resolved(&url, addresses.clone(), 100);
+        assert!(peers.addresses(&url).is_empty(), "only registered nodes are cached");
+        peers.register(url.clone());
+        assert!(peers.needs_resolving(&url, 100));
+        peers.resolved(&url, addresses.clone(), 100);
+        assert!(!peers.needs_resolving(&url, 100 + RESOLVE_TTL_SECS - 1));
+        assert!(peers.needs_resolving(&url, 100 + RESOLVE_TTL_SECS));
+
+        peers.resolved(&url, Vec::new(), 100 + RESOLVE_TTL_SECS);
+        assert_eq!(peers.addresses(&url), addresses, "a failed lookup keeps what we had");
+        assert!(!peers.needs_resolving(&url, 100 + RESOLVE_TTL_SECS), "and isn't retried right away");
+    }
+
+    #[test]
+    fn save_and_load() {
+        let path = env::temp_dir().join("learnnet-peers-save_and_load.json");
+        let _ = fs::remove_file(&path);
+        assert_eq!(Peers::load(&path).expect("no file is no peers").len(), 0);
+
+        let mut peers = Peers::new();
+        let seen = Url::parse("http://localhost:9000").expect("valid url");
+        let down = Url::parse("http://localhost:9001").expect("valid url");
+        peers.register(seen.clone());
+        peers.register(down.clone());
+        peers.seen(&seen, 42);
+        peers.measured(&seen, 10, 3, 192);
+        peers.failed(&down);
+        peers.save(&path).expect("saved");
+
+        let loaded = Peers::load(&path).expect("loaded");
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded.info(&seen), peers.info(&seen));
+        assert_eq!(loaded.info(&down), peers.info(&down));
+    }
+}
@@ -0,0 +1,175 @@
+use lib::error::{Error, Result};
+use std::cmp;
+use std::io::{self, Read};
+
+///
+/// Headers every node sends on peer requests and responses, so nodes on
+/// different networks, or speaking different versions, notice before syncing
+///
+pub const NETWORK_HEADER: &'static str = "X-Learnnet-Network";
+pub const VERSION_HEADER: &'static str = "X-Learnnet-Version";
+
+///
+/// Bumped whenever blocks, hashes or peer endpoints change incompatibly
+///
+/// 2: Transactions can expire
+///
+pub const PROTOCOL_VERSION: u32 = 2;
+
+///
+/// The network a node joins unless told otherwise
+///
+pub const DEFAULT_NETWORK: &'static str = "learnnet";
+
+///
+/// The port a peer registered by host alone is taken to listen on, unless the
+/// node is told another. Rocket's default, so a classroom of nodes started
+/// without a port can register each other as just `10.0.0.5`
+///
+pub const DEFAULT_PORT: u16 = 8000;
+
+///
+/// Check a peer's network and version headers against ours. Either header
+/// missing is an error; callers that talk to clients as well as peers only
+/// check when the headers were sent
+///
+pub fn check(network: &str, peer_network: Option<&str>, peer_version: Option<&str>) -> Result<()> {
+    let (peer_network, peer_version) = match (peer_network, peer_version) {
+        (Some(peer_network), Some(peer_version)) => (peer_network, peer_version),
+        _ => return Err(Error::Protocol(format!("Missing {} or {}. The peer is too old for protocol version {}", NETWORK_HEADER, VERSION_HEADER, PROTOCOL_VERSION)))
+    };
+    if peer_network != network {
+        return Err(Error::Protocol(format!("Network mismatch. This node is on {}, the peer is on {}", network, peer_network)));
+    }
+    match peer_version.trim().parse::<u32>() {
+        Ok(version) if version == PROTOCOL_VERSION => Ok(()),
+        _ => Err(Error::Protocol(format!("Protocol version mismatch. This node speaks {}, the peer speaks {}", PROTOCOL_VERSION, peer_version)))
+    }
+}
+
+///
+/// The deepest arrays and objects may nest in json from a peer. A chain's
+/// transactions nest 5 deep, parsing anything much deeper could overflow the stack
+///
+pub const MAX_JSON_DEPTH: usize = 32;
+
+///
+/// The largest request body read from a peer or client
+///
+pub const MAX_BODY_BYTES: u64 = 1024 * 1024;
+
+///
+/// Reads json from a peer, failing once more than `limit` bytes have been read,
+/// or once it nests deeper than `MAX_JSON_DEPTH`, before a parser sees either.
+/// Brackets in strings don't count
+///
+pub struct Limited<R> {
+    inner: R,
+    limit: u64,
+    remaining: u64,
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+    exceeded: Option<String>
+}
+
+impl<R> Limited<R> where R: Read {
+    pub fn new(inner: R, limit: u64) -> Limited<R> {
+        Limited { inner: inner, limit: limit, remaining: limit, depth: 0, in_string: false, escaped: false, exceeded: None }
+    }
+
+    ///
+    /// Which limit was exceeded, once reading has failed because of one
+    ///
+    pub fn exceeded(&self) -> Option<&str> {
+        self.exceeded.as_ref().map(|exceeded| exceeded.as_str())
+    }
+
+    fn exceed(&mut self, exceeded: String) -> io::Error {
+        self.exceeded = Some(exceeded.clone());
+        io::Error::new(io::ErrorKind::InvalidData, exceeded)
+    }
+}
+
+impl<R> Read for Limited<R> where R: Read {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(ref exceeded) = self.exceeded {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, exceeded.clone()));
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.remaining == 0 {
+            //exactly `limit` bytes is fine, one more isn't
+            return match self.inner.read(&mut [0; 1])? {
+                0 => Ok(0),
+                _ => {
+                    let limit = self.limit;
+                    Err(self.exceed(format!("more than {} bytes", limit)))
+                }
+            };
+        }
+        let max = cmp::min(buf.len() as u64, self.remaining) as usize;
+        let read = self.inner.read(&mut buf[..max])?;
+        self.remaining -= read as u64;
+        for &byte in &buf[..read] {
+            if self.in_string {
+                if self.escaped {
+                    self.escaped = false;
+                } else if byte == b'\\' {
+                    self.escaped = true;
+                } else if byte == b'"' {
+                    self.in_string = false;
+                }
+                continue;
+            }
+            match byte {
+                b'"' => self.in_string = true,
+                b'[' | b'{' => self.depth += 1,
+                b']' | b'}' => self.depth = self.depth.saturating_sub(1),
+                _ => {}
+            }
+            if self.depth > MAX_JSON_DEPTH {
+                return Err(self.exceed(format!("nested deeper than {}", MAX_JSON_DEPTH)));
+            }
+        }
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lib::protocol::*;
+    use serde_json::{self, Value};
+    use std::iter;
+
+    #[test]
+    fn check_headers() {
+        let version = PROTOCOL_VERSION.to_string();
+        assert!(check("learnnet", Some("learnnet"), Some(&version)).is_ok());
+        assert!(check("learnnet", Some("classroom"), Some(&version)).is_err());
+        assert!(check("learnnet", Some("learnnet"), Some("0")).is_err());
+        assert!(check("learnnet", Some("learnnet"), Some("one")).is_err());
+        assert!(check("learnnet", None, Some(&version)).is_err());
+        assert!(check("learnnet", Some("learnnet"), None).is_err());
+    }
+
+    #[test]
+    fn limited() {
+        let parse = |json: &str, limit: u64| {
+            let mut limited = Limited::new(json.as_bytes(), limit);
+            let parsed: serde_json::Result<Value> = serde_json::from_reader(&mut limited);
+            (parsed.is_ok(), limited.exceeded().map(String::from))
+        };
+        let nested = |depth: usize| -> String { iter::repeat('[').take(depth).chain(iter::repeat(']').take(depth)).collect() };
+
+        assert_eq!(parse(&nested(MAX_JSON_DEPTH), 1024), (true, None));
+        assert_eq!(parse(&nested(100000), 1024 * 1024), (false, Some(format!("nested deeper than {}", MAX_JSON_DEPTH))));
+        let brackets = format!("[\"{}\", \"\\\"]\"]", nested(100));
+        assert_eq!(parse(&brackets, 1024), (true, None), "brackets in strings, and escaped quotes");
+
+        let exact = "[1, 2, 3]";
+        assert_eq!(parse(exact, exact.len() as u64), (true, None));
+        assert_eq!(parse(exact, exact.len() as u64 - 1), (false, Some(format!("more than {} bytes", exact.len() - 1))));
+    }
+}
@@ -0,0 +1,530 @@
+use lib::error::{Error, Result};
+use std::cmp;
+
+//Just enough QR to put a payment uri on a screen: byte mode at error correction
+//level M, any version. Follows ISO/IEC 18004, laid out the way most small
+//encoders are (function patterns first, then data zigzagged around them).
+
+///
+/// Error correction codewords per block, and blocks, for each version at level M.
+/// Index 0 is unused
+///
+const ECC_CODEWORDS_PER_BLOCK: [usize; 41] = [
+    0, 10, 16, 26, 18, 24, 16, 18, 22, 22, 26, 30, 22, 22, 24, 24, 28, 28, 26, 26, 26,
+    26, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28
+];
+const ECC_BLOCKS: [usize; 41] = [
+    0, 1, 1, 1, 2, 2, 4, 4, 4, 5, 5, 5, 8, 9, 9, 10, 10, 11, 13, 14, 16,
+    17, 17, 18, 20, 21, 23, 25, 26, 28, 29, 31, 33, 35, 37, 38, 40, 43, 45, 47, 49
+];
+
+///
+/// Level M in the format bits
+///
+const FORMAT_LEVEL_M: u32 = 0;
+
+///
+/// Every mask is valid and scanners read which one from the format bits.
+/// Scoring all eight only helps codes that happen to look odd, so we always use
+/// the checkerboard
+///
+const MASK: u32 = 0;
+
+///
+/// Light modules around the code, which scanners need to find it
+///
+const QUIET_ZONE: usize = 4;
+
+///
+/// A QR code, as a square of dark and light modules
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct QrCode {
+    size: usize,
+    modules: Vec<bool>,
+    //function patterns (finders, timing, format..) that data and the mask skip
+    function: Vec<bool>
+}
+
+impl QrCode {
+    ///
+    /// Encode `data` in the smallest version it fits
+    ///
+    pub fn encode(data: &[u8]) -> Result<QrCode> {
+        let version = match (1..41).find(|version| data_bits(*version, data.len()) <= data_codewords(*version) * 8) {
+            Some(version) => version,
+            None => return Err(Error::Validation(format!("{} bytes is too long for a QR code", data.len())))
+        };
+        let size = version * 4 + 17;
+        let mut qr = QrCode {
+            size: size,
+            modules: vec![false; size * size],
+            function: vec![false; size * size]
+        };
+        qr.draw_function_patterns(version);
+        let codewords = interleave(version, &pad(version, data));
+        qr.draw_codewords(&codewords);
+        qr.apply_mask();
+        Ok(qr)
+    }
+
+    ///
+    /// Modules per side, not counting the quiet zone
+    ///
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn dark(&self, x: usize, y: usize) -> bool {
+        self.modules[y * self.size + x]
+    }
+
+    ///
+    /// A greyscale PNG, `scale` pixels per module, with the quiet zone
+    ///
+    pub fn png(&self, scale: usize) -> Vec<u8> {
+        let side = (self.size + QUIET_ZONE * 2) * scale;
+        let mut pixels = Vec::with_capacity((side + 1) * side);
+        for py in 0..side {
+            //filter type none
+            pixels.push(0);
+            for px in 0..side {
+                let (x, y) = (px / scale, py / scale);
+                let dark = x >= QUIET_ZONE && y >= QUIET_ZONE && x < self.size + QUIET_ZONE && y < self.size + QUIET_ZONE &&
+                           self.dark(x - QUIET_ZONE, y - QUIET_ZONE);
+                pixels.push(if dark { 0 } else { 255 });
+            }
+        }
+        png(side as u32, &pixels)
+    }
+
+    fn set_function(&mut self, x: usize, y: usize, dark: bool) {
+        self.modules[y * self.size + x] = dark;
+        self.function[y * self.size + x] = true;
+    }
+
+    fn draw_function_patterns(&mut self, version: usize) {
+        let size = self.size;
+        for i in 0..size {
+            self.set_function(6, i, i % 2 == 0);
+            self.set_function(i, 6, i % 2 == 0);
+        }
+        self.draw_finder(3, 3);
+        self.draw_finder(size - 4, 3);
+        self.draw_finder(3, size - 4);
+
+        let positions = alignment_positions(version);
+        let last = positions.len().saturating_sub(1);
+        for i in 0..positions.len() {
+            for j in 0..positions.len() {
+                //the corners with finders don't get one
+                if (i == 0 && j == 0) || (i == 0 && j == last) || (i == last && j == 0) {
+                    continue;
+                }
+                self.draw_alignment(positions[i], positions[j]);
+            }
+        }
+        self.draw_format();
+        self.draw_version(version);
+    }
+
+    //a finder and the light separator around it, clipped to the code
+    fn draw_finder(&mut self, x: usize, y: usize) {
+        for dy in -4i32..5 {
+            for dx in -4i32..5 {
+                let (xx, yy) = (x as i32 + dx, y as i32 + dy);
+                if xx >= 0 && yy >= 0 && (xx as usize) < self.size && (yy as usize) < self.size {
+                    let distance = cmp::max(dx.abs(), dy.abs());
+                    self.set_function(xx as usize, yy as usize, distance != 2 && distance != 4);
+                }
+            }
+        }
+    }
+
+    fn draw_alignment(&mut self, x: usize, y: usize) {
+        for dy in -2i32..3 {
+            for dx in -2i32..3 {
+                let distance = cmp::max(dx.abs(), dy.abs());
+                self.set_function((x as i32 + dx) as usize, (y as i32 + dy) as usize, distance != 1);
+            }
+        }
+    }
+
+    fn draw_format(&mut self) {
+        let bits = format_bits(FORMAT_LEVEL_M, MASK);
+        let size = self.size;
+        //around the top left finder
+        for i in 0..6 {
+            self.set_function(8, i, bit(bits, i));
+        }
+        self.set_function(8, 7, bit(bits, 6));
+        self.set_function(8, 8, bit(bits, 7));
+        self.set_function(7, 8, bit(bits, 8));
+        for i in 9..15 {
+            self.set_function(14 - i, 8, bit(bits, i));
+        }
+        //split between the other two
+        for i in 0..8 {
+            self.set_function(size - 1 - i, 8, bit(bits, i));
+        }
+        for i in 8..15 {
+            self.set_function(8, size - 15 + i, bit(bits, i));
+        }
+        self.set_function(8, size - 8, true);
+    }
+
+    fn draw_version(&mut self, version: usize) {
+        if version < 7 {
+            return;
+        }
+        let bits = version_bits(version as u32);
+        for i in 0..18 {
+            let (a, b) = (self.size - 11 + i % 3, i / 3);
+            self.set_function(a, b, bit(bits, i));
+            self.set_function(b, a, bit(bits, i));
+        }
+    }
+
+    //two columns at a time, right to left, alternately up and down, skipping the
+    //vertical timing pattern
+    fn draw_codewords(&mut self, codewords: &[u8]) {
+        let size = self.size;
+        let mut i = 0;
+        let mut right = size as i32 - 1;
+        while right >= 1 {
+            if right == 6 {
+                right = 5;
+            }
+            let upward = (right + 1) & 2 == 0;
+            for vertical in 0..size {
+                for j in 0..2 {
+                    let x = right as usize - j;
+                    let y = if upward { size - 1 - vertical } else { vertical };
+                    if !self.function[y * size + x] && i < codewords.len() * 8 {
+                        self.modules[y * size + x] = bit(codewords[i >> 3] as u32, 7 - (i & 7));
+                        i += 1;
+                    }
+                }
+            }
+            right -= 2;
+        }
+    }
+
+    fn apply_mask(&mut self) {
+        for y in 0..self.size {
+            for x in 0..self.size {
+                let index = y * self.size + x;
+                if !self.function[index] && (x + y) % 2 == 0 {
+                    self.modules[index] = !self.modules[index];
+                }
+            }
+        }
+    }
+}
+
+fn bit(bits: u32, i: usize) -> bool {
+    (bits >> i) & 1 != 0
+}
+
+///
+/// Error correction level and mask, with a BCH code, xored so it's never all light
+///
+fn format_bits(level: u32, mask: u32) -> u32 {
+    let data = level << 3 | mask;
+    let mut remainder = data;
+    for _ in 0..10 {
+        remainder = (remainder << 1) ^ ((remainder >> 9) * 0x537);
+    }
+    (data << 10 | remainder) ^ 0x5412
+}
+
+///
+/// Versions from 7 up spell out their version, with a Golay code
+///
+fn version_bits(version: u32) -> u32 {
+    let mut remainder = version;
+    for _ in 0..12 {
+        remainder = (remainder << 1) ^ ((remainder >> 11) * 0x1F25);
+    }
+    version << 12 | remainder
+}
+
+///
+/// Centres of the alignment patterns along either axis
+///
+fn alignment_positions(version: usize) -> Vec<usize> {
+    if version == 1 {
+        return Vec::new();
+    }
+    let count = version / 7 + 2;
+    let step = if version == 32 { 26 } else { (version * 4 + count * 2 + 1) / (count * 2 - 2) * 2 };
+    let mut positions = vec![6];
+    let mut position = version * 4 + 17 - 7;
+    for _ in 0..count - 1 {
+        positions.insert(1, position);
+        position -= step;
+    }
+    positions
+}
+
+///
+/// Modules left for data and error correction once the function patterns are drawn
+///
+fn raw_modules(version: usize) -> usize {
+    let mut modules = (16 * version + 128) * version + 64;
+    if version >= 2 {
+        let count = version / 7 + 2;
+        modules -= (25 * count - 10) * count - 55;
+        if version >= 7 {
+            modules -= 36;
+        }
+    }
+    modules
+}
+
+fn data_codewords(version: usize) -> usize {
+    raw_modules(version) / 8 - ECC_CODEWORDS_PER_BLOCK[version] * ECC_BLOCKS[version]
+}
+
+fn count_bits(version: usize) -> usize {
+    if version < 10 { 8 } else { 16 }
+}
+
+//mode, length and the bytes themselves
+fn data_bits(version: usize, len: usize) -> usize {
+    4 + count_bits(version) + len * 8
+}
+
+///
+/// Byte mode segment, terminated and padded to the version's data codewords
+///
+fn pad(version: usize, data: &[u8]) -> Vec<u8> {
+    let capacity = data_codewords(version) * 8;
+    let mut bits = Vec::with_capacity(capacity);
+    push_bits(&mut bits, 0b0100, 4);
+    push_bits(&mut bits, data.len() as u32, count_bits(version));
+    for byte in data {
+        push_bits(&mut bits, *byte as u32, 8);
+    }
+    let terminator = cmp::min(4, capacity - bits.len());
+    push_bits(&mut bits, 0, terminator);
+    let to_byte = (8 - bits.len() % 8) % 8;
+    push_bits(&mut bits, 0, to_byte);
+
+    let mut codewords: Vec<u8> = bits.chunks(8)
+                                     .map(|chunk| chunk.iter().fold(0u8, |byte, bit| byte << 1 | *bit as u8))
+                                     .collect();
+    let mut filler = [0xEC, 0x11].iter().cycle();
+    while codewords.len() < data_codewords(version) {
+        codewords.push(*filler.next().expect("cycles forever"));
+    }
+    codewords
+}
+
+fn push_bits(bits: &mut Vec<bool>, value: u32, count: usize) {
+    for i in (0..count).rev() {
+        bits.push(bit(value, i));
+    }
+}
+
+///
+/// Split the data into blocks, add each block's error correction, and interleave
+/// the blocks codeword by codeword
+///
+fn interleave(version: usize, data: &[u8]) -> Vec<u8> {
+    let blocks_count = ECC_BLOCKS[version];
+    let ecc_len = ECC_CODEWORDS_PER_BLOCK[version];
+    let raw_codewords = raw_modules(version) / 8;
+    //the last few blocks get one more data codeword
+    let short_blocks = blocks_count - raw_codewords % blocks_count;
+    let short_len = raw_codewords / blocks_count;
+
+    let divisor = rs_divisor(ecc_len);
+    let mut blocks = Vec::with_capacity(blocks_count);
+    let mut start = 0;
+    for i in 0..blocks_count {
+        let len = short_len - ecc_len + if i < short_blocks { 0 } else { 1 };
+        let mut block = data[start..start + len].to_vec();
+        start += len;
+        let ecc = rs_remainder(&block, &divisor);
+        if i < short_blocks {
+            //a placeholder so every block lines up, skipped below
+            block.push(0);
+        }
+        block.extend(ecc);
+        blocks.push(block);
+    }
+
+    let mut result = Vec::with_capacity(raw_codewords);
+    for i in 0..short_len + 1 {
+        for (j, block) in blocks.iter().enumerate() {
+            if i != short_len - ecc_len || j >= short_blocks {
+                result.push(block[i]);
+            }
+        }
+    }
+    result
+}
+
+///
+/// Multiply in GF(2^8), modulo x^8 + x^4 + x^3 + x^2 + 1
+///
+fn gf_multiply(x: u8, y: u8) -> u8 {
+    let mut z: u32 = 0;
+    for i in (0..8).rev() {
+        z = (z << 1) ^ ((z >> 7) * 0x11D);
+        z ^= ((y as u32 >> i) & 1) * x as u32;
+    }
+    z as u8
+}
+
+///
+/// The Reed-Solomon generator polynomial of `degree`, highest term first and
+/// without its leading 1
+///
+fn rs_divisor(degree: usize) -> Vec<u8> {
+    let mut result = vec![0u8; degree];
+    result[degree - 1] = 1;
+    let mut root = 1u8;
+    for _ in 0..degree {
+        for j in 0..degree {
+            result[j] = gf_multiply(result[j], root);
+            if j + 1 < degree {
+                result[j] ^= result[j + 1];
+            }
+        }
+        root = gf_multiply(root, 0x02);
+    }
+    result
+}
+
+fn rs_remainder(data: &[u8], divisor: &[u8]) -> Vec<u8> {
+    let mut result = vec![0u8; divisor.len()];
+    for byte in data {
+        let factor = *byte ^ result.remove(0);
+        result.push(0);
+        for (r, d) in result.iter_mut().zip(divisor.iter()) {
+            *r ^= gf_multiply(*d, factor);
+        }
+    }
+    result
+}
+
+///
+/// An 8 bit greyscale PNG of `side` x `side` pixels. `rows` already has the
+/// filter byte in front of each row. Compressed with stored deflate blocks, the
+/// checkerboard of a QR code hardly compresses anyway
+///
+fn png(side: u32, rows: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+    let mut header = Vec::with_capacity(13);
+    push_u32(&mut header, side);
+    push_u32(&mut header, side);
+    //8 bits, greyscale, deflate, no filtering beyond the per row byte, not interlaced
+    header.extend_from_slice(&[8, 0, 0, 0, 0]);
+    chunk(&mut out, b"IHDR", &header);
+    chunk(&mut out, b"IDAT", &zlib_stored(rows));
+    chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+fn push_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&[(value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8, value as u8]);
+}
+
+fn chunk(out: &mut Vec<u8>, kind: &[u8], data: &[u8]) {
+    push_u32(out, data.len() as u32);
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = crc32(&out[start..]);
+    push_u32(out, crc);
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let blocks: Vec<&[u8]> = data.chunks(0xFFFF).collect();
+    for (i, block) in blocks.iter().enumerate() {
+        let last = i + 1 == blocks.len();
+        let len = block.len() as u16;
+        out.push(if last { 1 } else { 0 });
+        out.extend_from_slice(&[len as u8, (len >> 8) as u8, !len as u8, (!len >> 8) as u8]);
+        out.extend_from_slice(block);
+    }
+    if blocks.is_empty() {
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    push_u32(&mut out, adler32(data));
+    out
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
+
+#[cfg(test)]
+mod tests {
+    use lib::qr::*;
+
+    #[test]
+    fn reed_solomon() {
+        //version 1-M "HELLO WORLD"
+        let data = [32, 91, 11, 120, 209, 114, 220, 77, 67, 64, 236, 17, 236, 17, 236, 17];
+        assert_eq!(rs_remainder(&data, &rs_divisor(10)), vec![196, 35, 39, 119, 235, 215, 231, 226, 93, 23]);
+    }
+
+    #[test]
+    fn format_and_version() {
+        assert_eq!(format_bits(0, 0), 0b101010000010010);
+        assert_eq!(format_bits(1, 4), 0b110011000101111);
+        assert_eq!(version_bits(7), 0b000111110010010100);
+        assert_eq!(data_codewords(1), 16);
+        assert_eq!(data_codewords(10), 216);
+        assert_eq!(data_codewords(40), 2334);
+    }
+
+    #[test]
+    fn encode() {
+        let qr = QrCode::encode(b"learnnet:shop?amount=5").expect("encoded");
+        assert_eq!(qr.size(), 25, "22 bytes doesn't fit version 1-M");
+        //finder centres and their light separators
+        for &(x, y) in [(3, 3), (21, 3), (3, 21)].iter() {
+            assert!(qr.dark(x, y));
+            assert!(!qr.dark(x + 2, y));
+        }
+        assert!(qr.dark(8, 25 - 8), "the dark module");
+        assert!(qr.dark(18, 18), "alignment centre");
+
+        assert_eq!(QrCode::encode(&[b'a'; 14]).expect("encoded").size(), 21);
+        assert_eq!(QrCode::encode(&[b'a'; 2331]).expect("encoded").size(), 177);
+        assert!(QrCode::encode(&[b'a'; 2332]).is_err());
+    }
+
+    #[test]
+    fn png() {
+        let png = QrCode::encode(b"learnnet").expect("encoded").png(2);
+        assert_eq!(&png[..8], &[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A]);
+        assert_eq!(&png[12..16], b"IHDR");
+        //(21 + 8) * 2 pixels
+        assert_eq!(&png[16..24], &[0, 0, 0, 58, 0, 0, 0, 58]);
+        assert_eq!(&png[png.len() - 8..png.len() - 4], b"IEND");
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+}
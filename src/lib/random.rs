@@ -0,0 +1,14 @@
+//Randomness for what doesn't need to be unpredictable, like the order peers are
+//asked in or transactions are shuffled into, and garbage for tests. The same
+//seed gives the same numbers on every node and every run.
+
+///
+/// The next number from xorshift64, which also becomes the new `state`. A state
+/// of 0 only ever gives 0, so make seeds odd
+///
+pub fn xorshift(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
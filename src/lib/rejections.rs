@@ -0,0 +1,168 @@
+use lib::error::Result;
+use serde_json;
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+//What we refused, so a student whose transaction "disappeared" can find out why.
+//Transactions are logged when a client's submission or a peer's mempool offers
+//one we won't take, chains when a peer sends one we won't adopt. The log is a
+//json lines file that's appended to, and rewritten with just the latest entries
+//once it holds twice as many as are kept, so it never grows without bound.
+
+///
+/// How many rejections are kept, in memory and in the file
+///
+pub const MAX_REJECTIONS: usize = 1000;
+
+///
+/// What was rejected
+///
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    Transaction,
+    Chain
+}
+
+///
+/// Something we refused
+///
+/// at: Seconds since the epoch
+/// hash: The transaction's id. None for chains, and bodies that didn't parse
+/// source: The client's address, or the peer's url. None if it isn't known
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Rejection {
+    pub at: i64,
+    pub kind: Kind,
+    pub hash: Option<String>,
+    pub reason: String,
+    pub source: Option<String>
+}
+
+///
+/// The last `MAX_REJECTIONS` rejections, and the file they're written to if
+/// there is one
+///
+#[derive(Debug)]
+pub struct RejectionLog {
+    path: Option<PathBuf>,
+    file: Option<File>,
+    lines: usize,
+    recent: VecDeque<Rejection>
+}
+
+impl RejectionLog {
+    pub fn in_memory() -> RejectionLog {
+        RejectionLog { path: None, file: None, lines: 0, recent: VecDeque::new() }
+    }
+
+    ///
+    /// Open (or create) the log at `path`, reading back the last rejections in it
+    ///
+    pub fn open(path: &Path) -> Result<RejectionLog> {
+        let mut recent = VecDeque::new();
+        let mut lines = 0;
+        match File::open(path) {
+            Ok(file) => {
+                for line in BufReader::new(file).lines() {
+                    let line = line?;
+                    lines += 1;
+                    //one cut off by a crash is only a lost record
+                    match serde_json::from_str(&line) {
+                        Ok(rejection) => push(&mut recent, rejection),
+                        Err(e) => warn!("Skipping unreadable rejection in {}. {}", path.display(), e)
+                    }
+                }
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {},
+            Err(e) => return Err(e.into())
+        }
+        let file = OpenOptions::new().append(true).create(true).open(path)?;
+        Ok(RejectionLog { path: Some(path.to_path_buf()), file: Some(file), lines: lines, recent: recent })
+    }
+
+    pub fn record(&mut self, rejection: Rejection) -> Result<()> {
+        if let Some(ref mut file) = self.file {
+            let mut line = serde_json::to_vec(&rejection)?;
+            line.push(b'\n');
+            file.write_all(&line)?;
+            self.lines += 1;
+        }
+        push(&mut self.recent, rejection);
+        if self.lines >= 2 * MAX_REJECTIONS {
+            self.compact()?;
+        }
+        Ok(())
+    }
+
+    ///
+    /// Up to `count` of the latest rejections, newest first
+    ///
+    pub fn last(&self, count: usize) -> Vec<&Rejection> {
+        self.recent.iter().rev().take(count).collect()
+    }
+
+    //rewrites the file with only what's kept, swapped in whole so a crash
+    //leaves one or the other
+    fn compact(&mut self) -> Result<()> {
+        let path = match self.path {
+            Some(ref path) => path.clone(),
+            None => return Ok(())
+        };
+        let tmp = path.with_extension("tmp");
+        {
+            let mut file = File::create(&tmp)?;
+            for rejection in self.recent.iter() {
+                let mut line = serde_json::to_vec(rejection)?;
+                line.push(b'\n');
+                file.write_all(&line)?;
+            }
+            file.sync_all()?;
+        }
+        fs::rename(&tmp, &path)?;
+        self.file = Some(OpenOptions::new().append(true).open(&path)?);
+        self.lines = self.recent.len();
+        Ok(())
+    }
+}
+
+fn push(recent: &mut VecDeque<Rejection>, rejection: Rejection) {
+    recent.push_back(rejection);
+    while recent.len() > MAX_REJECTIONS {
+        recent.pop_front();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lib::rejections::*;
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::{BufRead, BufReader};
+
+    fn rejection(at: i64) -> Rejection {
+        Rejection { at: at, kind: Kind::Transaction, hash: Some(format!("{:064}", at)), reason: "too many pending".into(), source: Some("127.0.0.1".into()) }
+    }
+
+    #[test]
+    fn record_and_cap() {
+        let path = env::temp_dir().join("learnnet-rejections-record_and_cap.jsonl");
+        let _ = fs::remove_file(&path);
+        {
+            let mut log = RejectionLog::open(&path).expect("opened");
+            for at in 0..2 * MAX_REJECTIONS as i64 + 5 {
+                log.record(rejection(at)).expect("recorded");
+            }
+            assert_eq!(log.last(2), vec![&rejection(2 * MAX_REJECTIONS as i64 + 4), &rejection(2 * MAX_REJECTIONS as i64 + 3)]);
+        }
+        let lines = BufReader::new(File::open(&path).expect("file")).lines().count();
+        assert!(lines < 2 * MAX_REJECTIONS, "compacted, {} lines", lines);
+
+        let log = RejectionLog::open(&path).expect("reopened");
+        assert_eq!(log.last(usize::max_value()).len(), MAX_REJECTIONS);
+        assert_eq!(log.last(1)[0].at, 2 * MAX_REJECTIONS as i64 + 4);
+    }
+}
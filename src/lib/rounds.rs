@@ -0,0 +1,184 @@
+use lib::blockchain::chain_work;
+use lib::consensus::{Fetched, Outcome};
+use lib::error::Result;
+use serde_json;
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+///
+/// How many rounds are kept in memory, and read back on startup
+///
+pub const MAX_ROUNDS: usize = 100;
+
+///
+/// How a consensus round went, for working out why a node won't settle on a chain
+///
+/// started: Seconds since the epoch
+/// height: Our chain's length before the round
+/// new_height: And after
+/// error: Why the round stopped early, if it did
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Round {
+    pub started: i64,
+    pub duration_ms: u64,
+    pub height: usize,
+    pub new_height: usize,
+    pub replaced: bool,
+    pub peers: Vec<PeerReport>,
+    pub error: Option<String>
+}
+
+///
+/// What one peer sent in a round
+///
+/// height: The length of the chain it sent. None if it didn't send one
+/// work: The work its chain claims, see `chain_work`. None if it didn't send one,
+///       or in rounds recorded before work was
+/// outcome: How asking it went. None in rounds recorded before outcomes were
+/// validated: Its chain was checked against our rules in full, and passed. Only
+///            the chain adopted is, consensus stops at the first valid one
+/// adopted: Its chain replaced ours. False for all of them in rounds recorded
+///          before, see the round's `replaced`
+/// error: Why we couldn't get its chain, or why we didn't take it
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PeerReport {
+    pub node: String,
+    pub height: Option<usize>,
+    #[serde(default)]
+    pub work: Option<u64>,
+    pub latency_ms: u64,
+    pub outcome: Option<Outcome>,
+    #[serde(default)]
+    pub validated: bool,
+    #[serde(default)]
+    pub adopted: bool,
+    pub error: Option<String>
+}
+
+impl<'a> From<&'a Fetched> for PeerReport {
+    fn from(fetched: &'a Fetched) -> PeerReport {
+        PeerReport {
+            node: fetched.node.clone(),
+            height: fetched.result.as_ref().ok().map(|chain| chain.len()),
+            work: fetched.result.as_ref().ok().map(|chain| chain_work(chain)),
+            latency_ms: fetched.latency.as_secs() * 1000 + (fetched.latency.subsec_nanos() / 1_000_000) as u64,
+            outcome: Some(fetched.outcome),
+            validated: false,
+            adopted: false,
+            error: fetched.result.as_ref().err().map(|e| e.to_string())
+        }
+    }
+}
+
+///
+/// The last `MAX_ROUNDS` rounds, and a json lines file every round is appended to
+/// if there is one
+///
+#[derive(Debug)]
+pub struct RoundLog {
+    file: Option<File>,
+    recent: VecDeque<Round>
+}
+
+impl RoundLog {
+    pub fn in_memory() -> RoundLog {
+        RoundLog { file: None, recent: VecDeque::new() }
+    }
+
+    ///
+    /// Open (or create) the log at `path`, reading back the last rounds in it
+    ///
+    pub fn open(path: &Path) -> Result<RoundLog> {
+        let mut recent = VecDeque::new();
+        match File::open(path) {
+            Ok(file) => {
+                for line in BufReader::new(file).lines() {
+                    let line = line?;
+                    //a round cut off by a crash is only a lost record
+                    match serde_json::from_str(&line) {
+                        Ok(round) => push(&mut recent, round),
+                        Err(e) => warn!("Skipping unreadable round in {}. {}", path.display(), e)
+                    }
+                }
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {},
+            Err(e) => return Err(e.into())
+        }
+        let file = OpenOptions::new().append(true).create(true).open(path)?;
+        Ok(RoundLog { file: Some(file), recent: recent })
+    }
+
+    pub fn record(&mut self, round: Round) -> Result<()> {
+        if let Some(ref mut file) = self.file {
+            let mut line = serde_json::to_vec(&round)?;
+            line.push(b'\n');
+            file.write_all(&line)?;
+        }
+        push(&mut self.recent, round);
+        Ok(())
+    }
+
+    ///
+    /// Up to `count` of the latest rounds, newest first
+    ///
+    pub fn last(&self, count: usize) -> Vec<&Round> {
+        self.recent.iter().rev().take(count).collect()
+    }
+}
+
+fn push(recent: &mut VecDeque<Round>, round: Round) {
+    recent.push_back(round);
+    while recent.len() > MAX_ROUNDS {
+        recent.pop_front();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lib::consensus::Outcome;
+    use lib::rounds::*;
+    use serde_json;
+    use std::env;
+    use std::fs;
+
+    fn round(started: i64) -> Round {
+        Round {
+            started: started,
+            duration_ms: 10,
+            height: 1,
+            new_height: 1,
+            replaced: false,
+            peers: vec![PeerReport { node: "http://localhost:1".into(), height: None, work: None, latency_ms: 5, outcome: Some(Outcome::Unreachable),
+                                     validated: false, adopted: false, error: Some("down".into()) }],
+            error: None
+        }
+    }
+
+    #[test]
+    fn record_and_reopen() {
+        let path = env::temp_dir().join("learnnet-rounds-record_and_reopen.jsonl");
+        let _ = fs::remove_file(&path);
+        {
+            let mut log = RoundLog::open(&path).expect("opened");
+            for started in 0..MAX_ROUNDS as i64 + 5 {
+                log.record(round(started)).expect("recorded");
+            }
+            assert_eq!(log.last(2), vec![&round(MAX_ROUNDS as i64 + 4), &round(MAX_ROUNDS as i64 + 3)]);
+        }
+        let log = RoundLog::open(&path).expect("reopened");
+        assert_eq!(log.last(1000).len(), MAX_ROUNDS);
+        assert_eq!(log.last(1)[0].started, MAX_ROUNDS as i64 + 4);
+    }
+
+    #[test]
+    fn outcomes() {
+        let report = round(1).peers[0].clone();
+        assert!(serde_json::to_string(&report).expect("serialized").contains(r#""outcome":"unreachable""#));
+        let old: PeerReport = serde_json::from_str(r#"{"node": "http://localhost:1", "height": 2, "latency_ms": 5, "error": null}"#).expect("parsed");
+        assert_eq!(old.outcome, None, "recorded before outcomes were");
+    }
+}
@@ -0,0 +1,130 @@
+use lib::random::xorshift;
+use lib::transaction::Transaction;
+use std::collections::BTreeMap;
+use std::fmt;
+
+//Which pending transactions go in the block a node mines is up to the node, like
+//standardness: any selection makes a valid block. It only matters once more are
+//waiting than a block may hold (see `Blockchain::set_max_block_transactions`),
+//otherwise every one goes in. A selector is handed the pending transactions
+//oldest first and picks some; write one and hand it to `Blockchain::set_selector`
+//to try a policy of your own. Transactions pay no fees, so there's no ranking
+//them by fee.
+
+///
+/// Picks what goes in the next block mined
+///
+pub trait TransactionSelector: fmt::Debug + Send + Sync {
+    ///
+    /// Up to `limit` of `pending`, which come in the order they arrived, oldest
+    /// first. `seed` is the same for every selection for the same block, and
+    /// changes from block to block
+    ///
+    fn select<'a>(&self, pending: &[&'a Transaction], limit: usize, seed: u64) -> Vec<&'a Transaction>;
+}
+
+///
+/// The selectors built in
+///
+/// Fifo: Oldest first
+/// Random: Any, shuffled by the seed
+/// PerSender: Oldest first, taking turns between senders, so one busy sender
+///            can't fill blocks while others wait
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Strategy {
+    Fifo,
+    Random,
+    PerSender
+}
+
+impl Default for Strategy {
+    fn default() -> Strategy {
+        Strategy::Fifo
+    }
+}
+
+impl Strategy {
+    pub fn from_name(name: &str) -> Option<Strategy> {
+        match name {
+            "fifo" => Some(Strategy::Fifo),
+            "random" => Some(Strategy::Random),
+            "per-sender" => Some(Strategy::PerSender),
+            _ => None
+        }
+    }
+}
+
+impl TransactionSelector for Strategy {
+    fn select<'a>(&self, pending: &[&'a Transaction], limit: usize, seed: u64) -> Vec<&'a Transaction> {
+        match *self {
+            Strategy::Fifo => pending.iter().take(limit).cloned().collect(),
+            Strategy::Random => {
+                let mut rng = seed | 1;
+                let mut shuffled = pending.to_vec();
+                for i in (1..shuffled.len()).rev() {
+                    let j = (xorshift(&mut rng) % (i as u64 + 1)) as usize;
+                    shuffled.swap(i, j);
+                }
+                shuffled.truncate(limit);
+                shuffled
+            },
+            Strategy::PerSender => {
+                let mut senders: BTreeMap<&str, Vec<&'a Transaction>> = BTreeMap::new();
+                let mut order = Vec::new();
+                for &txn in pending {
+                    if !senders.contains_key(txn.sender.as_str()) {
+                        order.push(txn.sender.as_str());
+                    }
+                    senders.entry(txn.sender.as_str()).or_insert_with(Vec::new).push(txn);
+                }
+                let mut picked = Vec::with_capacity(limit);
+                let mut round = 0;
+                while picked.len() < limit && picked.len() < pending.len() {
+                    for sender in order.iter() {
+                        if let Some(&txn) = senders[sender].get(round) {
+                            if picked.len() < limit {
+                                picked.push(txn);
+                            }
+                        }
+                    }
+                    round += 1;
+                }
+                picked
+            }
+        }
+    }
+}
+
+///
+/// A seed for selecting the block on top of `previous_hash`
+///
+pub fn seed(previous_hash: &str) -> u64 {
+    //FNV-1a
+    previous_hash.bytes().fold(0xcbf29ce484222325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+}
+
+#[cfg(test)]
+mod tests {
+    use lib::selection::*;
+    use lib::transaction::Transaction;
+
+    #[test]
+    fn strategies() {
+        let txns: Vec<Transaction> = vec![("a", 1), ("a", 2), ("a", 3), ("b", 4), ("c", 5)].into_iter()
+                                         .map(|(sender, amount)| Transaction::new(sender.into(), "z".into(), amount))
+                                         .collect();
+        let pending: Vec<&Transaction> = txns.iter().collect();
+        let amounts = |picked: Vec<&Transaction>| picked.iter().map(|txn| txn.amount).collect::<Vec<_>>();
+
+        assert_eq!(amounts(Strategy::Fifo.select(&pending, 3, 0)), vec![1, 2, 3]);
+        assert_eq!(amounts(Strategy::PerSender.select(&pending, 3, 0)), vec![1, 4, 5], "a turn each");
+        assert_eq!(amounts(Strategy::PerSender.select(&pending, 10, 0)), vec![1, 4, 5, 2, 3]);
+
+        let random = Strategy::Random.select(&pending, 3, seed("prev"));
+        assert_eq!(random.len(), 3);
+        assert_eq!(random, Strategy::Random.select(&pending, 3, seed("prev")), "the same for the same block");
+        assert_eq!(Strategy::from_name("per-sender"), Some(Strategy::PerSender));
+        assert_eq!(Strategy::from_name("fee"), None);
+    }
+}
@@ -0,0 +1,225 @@
+use lib::blockchain::{Chain, Blockchain};
+use lib::consensus::Consensus;
+use lib::transaction::Transaction;
+
+///
+/// An in-process network of nodes for tests. No http, no docker.
+///
+/// nodes: The nodes, addressed by their position
+/// partitions: Which partition each node is in. Nodes only see the chains of
+///             nodes in the same partition during consensus
+///
+pub struct Network {
+    nodes: Vec<Blockchain>,
+    partitions: Vec<usize>
+}
+
+impl Network {
+
+    pub fn new_with(size: usize, difficulty: u64) -> Network {
+        Network {
+            nodes: (0..size).map(|_| Blockchain::new_with(difficulty)).collect(),
+            partitions: vec![0; size]
+        }
+    }
+
+    pub fn node(&self, node: usize) -> &Blockchain {
+        &self.nodes[node]
+    }
+
+    ///
+    /// Split the network. Each group becomes its own partition
+    ///
+    pub fn partition(&mut self, groups: &[&[usize]]) {
+        for (partition, group) in groups.iter().enumerate() {
+            for node in group.iter() {
+                self.partitions[*node] = partition;
+            }
+        }
+    }
+
+    ///
+    /// Rejoin all partitions
+    ///
+    pub fn heal(&mut self) {
+        for partition in self.partitions.iter_mut() {
+            *partition = 0;
+        }
+    }
+
+    pub fn transact(&mut self, node: usize, sender: &str, recipient: &str, amount: i64) {
+        self.nodes[node].new_transaction(Transaction::new(sender.into(), recipient.into(), amount));
+    }
+
+    ///
+    /// Have `node` mine at `difficulty` from now on
+    ///
+    pub fn set_difficulty(&mut self, node: usize, difficulty: u64) {
+        self.nodes[node].set_difficulty(difficulty).expect("invalid difficulty");
+    }
+
+    pub fn mine(&mut self, node: usize) {
+        self.nodes[node].mine().expect("mining failed");
+    }
+
+    ///
+    /// Run consensus on every node until no chain changes
+    ///
+    pub fn resolve(&mut self) {
+        loop {
+            let mut any_replaced = false;
+            for node in 0..self.nodes.len() {
+                let chains = self.reachable_chains(node);
+                if Consensus::take_authoritive(&mut self.nodes[node], chains) {
+                    any_replaced = true;
+                }
+            }
+            if !any_replaced {
+                break;
+            }
+        }
+    }
+
+    ///
+    /// Copies of the chains a node could download right now
+    ///
+    fn reachable_chains(&self, node: usize) -> Vec<Chain> {
+        (0..self.nodes.len())
+            .filter(|other| *other != node && self.partitions[*other] == self.partitions[node])
+            .map(|other| self.nodes[other].chain().clone())
+            .collect()
+    }
+
+    ///
+    /// Every non-reward transaction in a node's chain
+    ///
+    pub fn confirmed(&self, node: usize) -> Vec<Transaction> {
+        self.nodes[node]
+            .chain()
+            .iter()
+            .flat_map(|block| block.transactions.iter())
+            .filter(|txn| !txn.is_reward())
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lib::sim::Network;
+
+    fn assert_converged(network: &Network, size: usize) {
+        for node in 1..size {
+            assert_eq!(network.node(node).chain(), network.node(0).chain(), "node {} diverged from node 0", node);
+        }
+    }
+
+    #[test]
+    fn partition_and_heal() {
+        let mut network = Network::new_with(3, 1);
+
+        network.partition(&[&[0, 1], &[2]]);
+        //majority side does more work
+        network.transact(0, "a", "b", 1);
+        network.mine(0);
+        network.resolve();
+        network.transact(1, "c", "d", 2);
+        network.mine(1);
+        //minority side confirms a transaction the majority never saw
+        network.transact(2, "e", "f", 3);
+        network.mine(2);
+        network.resolve();
+
+        assert_eq!(network.node(0).len(), 3, "majority partition: genesis + 2 mined");
+        assert_eq!(network.node(1).chain(), network.node(0).chain());
+        assert_eq!(network.node(2).len(), 2, "minority partition: genesis + 1 mined");
+
+        network.heal();
+        network.resolve();
+        assert_converged(&network, 3);
+        assert_eq!(network.node(2).len(), 3, "most work wins");
+
+        //e->f was only confirmed on the abandoned fork. It's pending again on node 2,
+        //so the next block node 2 mines confirms it
+        network.mine(2);
+        network.resolve();
+        assert_converged(&network, 3);
+
+        let confirmed = network.confirmed(0);
+        for &(sender, amount) in [("a", 1), ("c", 2), ("e", 3)].iter() {
+            assert!(confirmed.iter().any(|txn| txn.sender == sender && txn.amount == amount),
+                    "transaction from {} was lost", sender);
+        }
+    }
+
+    #[test]
+    fn repeated_partitions() {
+        let mut network = Network::new_with(4, 1);
+
+        network.partition(&[&[0, 1], &[2, 3]]);
+        network.transact(0, "a", "b", 1);
+        network.mine(0);
+        network.transact(2, "c", "d", 2);
+        network.mine(2);
+        network.mine(3);
+        network.resolve();
+
+        network.partition(&[&[0, 2], &[1, 3]]);
+        network.mine(1);
+        network.resolve();
+
+        network.heal();
+        network.resolve();
+        assert_converged(&network, 4);
+
+        //whoever lost their fork mines the requeued transactions back in
+        for node in 0..4 {
+            network.mine(node);
+            network.resolve();
+        }
+        assert_converged(&network, 4);
+
+        let confirmed = network.confirmed(0);
+        for &(sender, amount) in [("a", 1), ("c", 2)].iter() {
+            assert!(confirmed.iter().any(|txn| txn.sender == sender && txn.amount == amount),
+                    "transaction from {} was lost", sender);
+        }
+    }
+
+    #[test]
+    fn harder_partition_wins() {
+        let mut network = Network::new_with(3, 1);
+
+        network.partition(&[&[0, 1], &[2]]);
+        //the majority mines more blocks, and the minority fewer but harder ones
+        network.transact(0, "a", "b", 1);
+        for _ in 0..3 {
+            network.mine(0);
+        }
+        network.resolve();
+        network.set_difficulty(2, 2);
+        network.transact(2, "c", "d", 2);
+        network.mine(2);
+        network.resolve();
+        assert!(network.node(0).len() > network.node(2).len());
+        assert!(network.node(2).work() > network.node(0).work());
+
+        network.heal();
+        network.resolve();
+        assert_converged(&network, 3);
+        assert_eq!(network.node(0).len(), 2, "the shorter chain proves more work");
+
+        //a->b was only confirmed on the abandoned fork, node 0 mines it back in at
+        //the difficulty the network now mines at
+        network.set_difficulty(0, 2);
+        network.mine(0);
+        network.resolve();
+        assert_converged(&network, 3);
+
+        let confirmed = network.confirmed(0);
+        for &(sender, amount) in [("a", 1), ("c", 2)].iter() {
+            assert!(confirmed.iter().any(|txn| txn.sender == sender && txn.amount == amount),
+                    "transaction from {} was lost", sender);
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use lib::error::{Error, Result};
+use lib::transaction::{Amount, Transaction};
+use lib::validation::Check;
+
+//Consensus rules say which blocks are valid, every node must agree on them. Policy
+//is what a node is willing to relay and mine, and each node picks its own. A
+//transaction our policy turns away from the mempool is still valid in a block
+//someone else mined, so none of this is checked in blocks. Transactions have no
+//scripts or signatures yet, so there's nothing to limit but amounts and sizes.
+
+///
+/// Amounts below this are dust, unless configured otherwise
+///
+pub const DEFAULT_DUST: Amount = 5;
+
+///
+/// The largest transaction accepted, encoded, unless configured otherwise
+///
+pub const DEFAULT_MAX_TRANSACTION_SIZE: usize = 256;
+
+///
+/// A node's policy for which transactions it admits to its mempool
+///
+/// dust: The smallest amount accepted
+/// max_size: The most bytes a transaction may take, encoded. Long addresses make
+///           big transactions
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Standardness {
+    pub dust: Amount,
+    pub max_size: usize
+}
+
+impl Default for Standardness {
+    fn default() -> Standardness {
+        Standardness { dust: DEFAULT_DUST, max_size: DEFAULT_MAX_TRANSACTION_SIZE }
+    }
+}
+
+impl Standardness {
+    ///
+    /// Whether `txn` is standard, as a validation check
+    ///
+    pub fn check(&self, txn: &Transaction) -> Check {
+        let size = txn.size();
+        let (passed, reason) = if txn.amount < self.dust {
+            (false, format!("amount {} is dust, this node admits at least {}", txn.amount, self.dust))
+        } else if size > self.max_size {
+            (false, format!("{} bytes, this node admits at most {}", size, self.max_size))
+        } else {
+            (true, String::from("ok"))
+        };
+        Check { name: "standard", passed: passed, reason: reason }
+    }
+
+    ///
+    /// `check` as an error, for admission
+    ///
+    pub fn admit(&self, txn: &Transaction) -> Result<()> {
+        let check = self.check(txn);
+        if !check.passed {
+            return Err(Error::Validation(format!("Transaction isn't standard, {}", check.reason)));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lib::standard::*;
+    use lib::transaction::Transaction;
+    use std::iter;
+
+    #[test]
+    fn admit() {
+        let standardness = Standardness::default();
+        assert!(standardness.admit(&Transaction::new("a".into(), "b".into(), DEFAULT_DUST)).is_ok());
+        assert!(standardness.admit(&Transaction::new("a".into(), "b".into(), DEFAULT_DUST - 1)).is_err(), "dust");
+
+        let long: String = iter::repeat('x').take(DEFAULT_MAX_TRANSACTION_SIZE).collect();
+        let check = standardness.check(&Transaction::new("a".into(), long, DEFAULT_DUST));
+        assert!(!check.passed, "too big");
+        assert_eq!(check.name, "standard");
+    }
+}
@@ -0,0 +1,116 @@
+use lib::blockchain::{Block, Chain};
+use lib::encoding::{Encode, encode_u64, encode_str, encode_i64};
+use lib::hasher::{Hasher, Domain};
+use lib::transaction::{Transaction, Address, Amount};
+use std::collections::BTreeMap;
+
+///
+/// Account balances after applying every transaction in a chain, in order.
+/// 
+/// Each block header commits to the hash of the state after its transactions
+/// (the state root), so two nodes that disagree about a balance find out at the
+/// first block where they diverge.
+/// 
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct State {
+    balances: BTreeMap<Address, Amount>
+}
+
+impl State {
+
+    pub fn new() -> State {
+        State { balances: BTreeMap::new() }
+    }
+
+    ///
+    /// The state at the tip of `chain`
+    /// 
+    pub fn from_chain(chain: &Chain) -> State {
+        let mut state = State::new();
+        for block in chain {
+            state.apply_block(block);
+        }
+        state
+    }
+
+    ///
+    /// Move `amount` from sender to recipient. Rewards only credit the recipient
+    /// 
+    /// Wraps on overflow, like a release build would, so a peer's chain with
+    /// absurd amounts gets a (bad) state root check instead of a panic
+    /// 
+    pub fn apply(&mut self, txn: &Transaction) {
+        if !txn.is_reward() {
+            let sender = self.balances.entry(txn.sender.clone()).or_insert(0);
+            *sender = sender.wrapping_sub(txn.amount);
+        }
+        let recipient = self.balances.entry(txn.recipient.clone()).or_insert(0);
+        *recipient = recipient.wrapping_add(txn.amount);
+    }
+
+    pub fn apply_block(&mut self, block: &Block) {
+        for txn in block.transactions.iter() {
+            self.apply(txn);
+        }
+    }
+
+    ///
+    /// Balance of an address. Addresses never seen have 0
+    /// 
+    pub fn balance(&self, address: &str) -> Amount {
+        self.balances.get(address).cloned().unwrap_or(0)
+    }
+
+    pub fn balances(&self) -> &BTreeMap<Address, Amount> {
+        &self.balances
+    }
+
+    ///
+    /// Hash committed to in block headers
+    /// 
+    pub fn root<H>(&self, hasher: &H) -> String where H: Hasher {
+        hasher.hash(Domain::State, self)
+    }
+}
+
+///
+/// number of accounts, then each address and balance in address order
+/// 
+impl Encode for State {
+    fn encode(&self, out: &mut Vec<u8>) {
+        encode_u64(self.balances.len() as u64, out);
+        for (address, amount) in self.balances.iter() {
+            encode_str(address, out);
+            encode_i64(*amount, out);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lib::hasher::HashAlgorithm;
+    use lib::state::State;
+    use lib::transaction::Transaction;
+
+    #[test]
+    fn apply() {
+        let mut state = State::new();
+        state.apply(&Transaction::reward("miner".into()));
+        state.apply(&Transaction::new("miner".into(), "b".into(), 1));
+        state.apply(&Transaction::new("a".into(), "b".into(), 5));
+        assert_eq!(state.balance("miner"), 0);
+        assert_eq!(state.balance("a"), -5);
+        assert_eq!(state.balance("b"), 6);
+        assert_eq!(state.balance("nobody"), 0);
+    }
+
+    #[test]
+    fn root() {
+        let sha256 = HashAlgorithm::Sha256;
+        assert_eq!(State::new().root(&sha256), "LM++gpoN+jaMHV8FwBANda4lqFze+z1umkIPeJJ5ow0=");
+
+        let mut state = State::new();
+        state.apply(&Transaction::new("a".into(), "b".into(), 5));
+        assert_eq!(state.root(&sha256), "H4eHUomdhiabQS1KeOWd9ljq+mDaFSyQME+4ssbnFfk=");
+    }
+}
@@ -0,0 +1,595 @@
+use lib::blockchain::{Block, Blockchain, Chain, Verification};
+use lib::encoding::{self, Decoder, encode_u64, encode_str};
+use lib::error;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+///
+/// Somewhere to keep the chain between restarts. Heights start at 1 for the
+/// genesis block, like `BlockHeader::index`
+///
+pub trait ChainStore {
+    ///
+    /// Number of blocks stored
+    ///
+    fn len(&self) -> usize;
+
+    ///
+    /// Hash of the block at `height`
+    ///
+    fn hash(&self, height: usize) -> Option<&str>;
+
+    ///
+    /// Height of the block with `hash`
+    ///
+    fn height(&self, hash: &str) -> Option<usize>;
+
+    ///
+    /// Read the block at `height`
+    ///
+    fn get(&self, height: usize) -> io::Result<Option<Block>>;
+
+    ///
+    /// Add a block on top of the stored ones
+    ///
+    fn append(&mut self, block: &Block, hash: &str) -> io::Result<()>;
+
+    ///
+    /// Drop every block above `len`
+    ///
+    fn truncate(&mut self, len: usize) -> io::Result<()>;
+
+    ///
+    /// Pick up whatever another process sharing the store wrote since we last
+    /// looked. With `repair`, records it left half written are cut off, so only
+    /// repair once nobody else writes
+    ///
+    fn reload(&mut self, _repair: bool) -> io::Result<()> {
+        Ok(())
+    }
+
+    ///
+    /// Read every stored block, in order
+    ///
+    fn load(&self) -> io::Result<Vec<Block>> {
+        let mut blocks = Vec::with_capacity(self.len());
+        for height in 1..self.len() + 1 {
+            match self.get(height)? {
+                Some(block) => blocks.push(block),
+                None => return Err(invalid_data(format!("block {} missing", height)))
+            }
+        }
+        Ok(blocks)
+    }
+}
+
+///
+/// Make the store hold exactly the blocks in `blockchain`. Only the blocks above
+/// the last one both agree on are rewritten, so after mining that's one append,
+/// and after consensus it's the replaced fork.
+///
+pub fn sync(store: &mut ChainStore, blockchain: &Blockchain) -> error::Result<()> {
+    let hashes = blockchain.hashes();
+    let mut common = store.len().min(hashes.len());
+    while common > 0 && store.hash(common) != Some(hashes[common - 1].as_str()) {
+        common -= 1;
+    }
+    if common < store.len() {
+        warn!("Rewriting stored blocks above {} (had {})", common, store.len());
+        store.truncate(common)?;
+    }
+    for (block, hash) in blockchain.chain().iter().zip(hashes.iter()).skip(common) {
+        store.append(block, hash)?;
+    }
+    Ok(())
+}
+
+///
+/// Start `blockchain` from the stored chain. An empty store gets `blockchain`'s
+/// genesis block instead.
+///
+/// A damaged store (unreadable blocks, broken hash links, bad proofs) is cut back
+/// to its last good block rather than refusing to start. The blocks dropped that
+/// way come back from peers the next time consensus runs.
+///
+/// returns: how many stored blocks were dropped
+pub fn restore(store: &mut ChainStore, blockchain: &mut Blockchain) -> error::Result<usize> {
+    let stored = store.len();
+    let (mut blocks, unreadable) = read_blocks(store);
+    if let Some(reason) = unreadable {
+        warn!("Stored {}", reason);
+    }
+
+    let valid = blockchain.valid_prefix(&blocks);
+    if valid < stored {
+        warn!("Stored chain is damaged after block {} of {}. Truncating, the rest will be resynced from peers", valid, stored);
+        store.truncate(valid)?;
+        blocks.truncate(valid);
+    }
+    if valid > 0 {
+        let chain: Chain = blocks.into_iter().collect();
+        blockchain.replace(chain);
+        info!("Restored {} blocks", blockchain.len());
+    }
+    sync(store, blockchain)?;
+    Ok(stored - valid)
+}
+
+///
+/// Catch `blockchain` up with a store another process writes, after `reload`ing
+/// it. Nothing is written; blocks after the first invalid one are ignored
+///
+/// returns: true if our chain was replaced
+pub fn follow(store: &ChainStore, blockchain: &mut Blockchain) -> error::Result<bool> {
+    if store.len() == blockchain.len() && store.hash(store.len()) == Some(blockchain.hash_last_block().as_str()) {
+        return Ok(false);
+    }
+    let (blocks, unreadable) = read_blocks(store);
+    if let Some(reason) = unreadable {
+        warn!("Stored {}", reason);
+    }
+    let valid = blockchain.valid_prefix(&blocks);
+    if valid == 0 {
+        return Ok(false);
+    }
+    let chain: Chain = blocks.into_iter().take(valid).collect();
+    blockchain.replace(chain);
+    Ok(true)
+}
+
+///
+/// Check the stored chain from genesis without changing anything, e.g. after a
+/// crash. Unreadable blocks count as invalid
+/// 
+pub fn verify(store: &ChainStore, blockchain: &Blockchain) -> Verification {
+    let (blocks, unreadable) = read_blocks(store);
+    let mut verification = blockchain.verify(&blocks);
+    verification.length = store.len();
+    if verification.error.is_none() {
+        verification.error = unreadable;
+    }
+    verification
+}
+
+///
+/// Read stored blocks from genesis up to the first one that can't be read or is
+/// at the wrong height
+/// 
+/// returns: The blocks read, and what stopped the reading early
+fn read_blocks(store: &ChainStore) -> (Vec<Block>, Option<String>) {
+    let mut blocks = Vec::with_capacity(store.len());
+    for height in 1..store.len() + 1 {
+        match store.get(height) {
+            Ok(Some(block)) => {
+                if block.header.index != height {
+                    return (blocks, Some(format!("block {}: claims to be block {}", height, block.header.index)));
+                }
+                blocks.push(block);
+            },
+            Ok(None) => return (blocks, Some(format!("block {}: missing", height))),
+            Err(e) => return (blocks, Some(format!("block {}: unreadable. {}", height, e)))
+        }
+    }
+    (blocks, None)
+}
+
+///
+/// Where a block lives in blocks.dat
+///
+/// offset: Start of the record (its length prefix)
+/// length: Length of the encoded block, without the prefix
+/// hash: Hash of the block's header
+///
+#[derive(Debug)]
+struct Entry {
+    offset: u64,
+    length: u64,
+    hash: String
+}
+
+impl Entry {
+    ///
+    /// Where the next record in blocks.dat starts. None if a damaged index puts
+    /// it past the largest file there could be
+    ///
+    fn end(&self) -> Option<u64> {
+        self.offset.checked_add(8).and_then(|end| end.checked_add(self.length))
+    }
+
+    ///
+    /// Bytes this entry takes up in blocks.idx
+    ///
+    fn index_size(&self) -> u64 {
+        8 + 8 + 8 + self.hash.len() as u64
+    }
+}
+
+///
+/// An append-only block file plus an index.
+///
+/// blocks.dat: The blocks, each as a length-prefixed `Encode`ing
+/// blocks.idx: offset, length and hash of each block, in height order
+///
+/// The index is small and read whole on open, so opening doesn't depend on how
+/// long the chain is, and any block can be read with a single seek.
+///
+#[derive(Debug)]
+pub struct BlockFile {
+    blocks: File,
+    index: File,
+    entries: Vec<Entry>,
+    heights: HashMap<String, usize>
+}
+
+const BLOCKS_FILE: &'static str = "blocks.dat";
+const INDEX_FILE: &'static str = "blocks.idx";
+
+impl BlockFile {
+    ///
+    /// Open (or create) the block file in `dir`
+    ///
+    /// A crash while appending can leave half a record at the end of either file.
+    /// Anything after the last complete block is cut off
+    ///
+    pub fn open(dir: &Path) -> io::Result<BlockFile> {
+        Self::open_with(dir, true)
+    }
+
+    ///
+    /// Open the block file in `dir` alongside other processes, without cutting
+    /// anything off: the end might be a record another process is writing
+    ///
+    pub fn open_shared(dir: &Path) -> io::Result<BlockFile> {
+        Self::open_with(dir, false)
+    }
+
+    fn open_with(dir: &Path, repair: bool) -> io::Result<BlockFile> {
+        fs::create_dir_all(dir)?;
+        let options = {
+            let mut options = OpenOptions::new();
+            options.read(true).append(true).create(true);
+            options
+        };
+        let mut block_file = BlockFile {
+            blocks: options.open(dir.join(BLOCKS_FILE))?,
+            index: options.open(dir.join(INDEX_FILE))?,
+            entries: Vec::new(),
+            heights: HashMap::new()
+        };
+        block_file.reload(repair)?;
+        info!("Opened {} with {} blocks", dir.display(), block_file.len());
+        Ok(block_file)
+    }
+
+    ///
+    /// The complete records in the index
+    ///
+    fn read_index(bytes: &[u8]) -> Vec<Entry> {
+        let mut input = Decoder::new(bytes);
+        let mut entries = Vec::new();
+        while !input.is_empty() {
+            match Self::read_entry(&mut input) {
+                Ok(entry) => entries.push(entry),
+                Err(e) => {
+                    warn!("Block index is damaged after {} blocks. {}", entries.len(), e);
+                    break;
+                }
+            }
+        }
+        entries
+    }
+
+    fn read_entry(input: &mut Decoder) -> Result<Entry, String> {
+        Ok(Entry {
+            offset: input.u64()?,
+            length: input.u64()?,
+            hash: input.str()?
+        })
+    }
+
+    fn blocks_len(&self) -> u64 {
+        self.entries.last().and_then(Entry::end).unwrap_or(0)
+    }
+
+    fn index_len(&self) -> u64 {
+        self.entries.iter().map(Entry::index_size).sum()
+    }
+
+    ///
+    /// Cut both files back to the end of the last entry
+    ///
+    fn trim(&self) -> io::Result<()> {
+        self.index.set_len(self.index_len())?;
+        self.blocks.set_len(self.blocks_len())
+    }
+}
+
+impl ChainStore for BlockFile {
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn hash(&self, height: usize) -> Option<&str> {
+        self.entry(height).map(|entry| entry.hash.as_str())
+    }
+
+    fn height(&self, hash: &str) -> Option<usize> {
+        self.heights.get(hash).cloned()
+    }
+
+    fn get(&self, height: usize) -> io::Result<Option<Block>> {
+        let entry = match self.entry(height) {
+            Some(entry) => entry,
+            None => return Ok(None)
+        };
+        let mut file = &self.blocks;
+        file.seek(SeekFrom::Start(entry.offset))?;
+        let mut record = vec![0; 8 + entry.length as usize];
+        file.read_exact(&mut record)?;
+
+        let (prefix, bytes) = record.split_at(8);
+        let length = Decoder::new(prefix).u64().map_err(invalid_data)?;
+        if length != entry.length {
+            return Err(invalid_data(format!("block {} is {} bytes, index says {}", height, length, entry.length)));
+        }
+        encoding::decode(bytes).map(Some).map_err(invalid_data)
+    }
+
+    fn append(&mut self, block: &Block, hash: &str) -> io::Result<()> {
+        let bytes = encoding::encode(block);
+        let offset = self.blocks.metadata()?.len();
+        let mut record = Vec::with_capacity(8 + bytes.len());
+        encode_u64(bytes.len() as u64, &mut record);
+        record.extend_from_slice(&bytes);
+        self.blocks.write_all(&record)?;
+        self.blocks.sync_data()?;
+
+        //the block is safely written before the index points at it
+        let entry = Entry { offset: offset, length: bytes.len() as u64, hash: hash.into() };
+        let mut record = Vec::new();
+        encode_u64(entry.offset, &mut record);
+        encode_u64(entry.length, &mut record);
+        encode_str(&entry.hash, &mut record);
+        self.index.write_all(&record)?;
+        self.index.sync_data()?;
+
+        self.heights.insert(entry.hash.clone(), self.entries.len() + 1);
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    fn truncate(&mut self, len: usize) -> io::Result<()> {
+        if len >= self.entries.len() {
+            return Ok(());
+        }
+        for entry in self.entries.drain(len..) {
+            self.heights.remove(&entry.hash);
+        }
+        self.trim()
+    }
+
+    fn reload(&mut self, repair: bool) -> io::Result<()> {
+        let mut bytes = Vec::new();
+        {
+            let mut index = &self.index;
+            index.seek(SeekFrom::Start(0))?;
+            index.read_to_end(&mut bytes)?;
+        }
+        let mut entries = Self::read_index(&bytes);
+        let blocks_len = self.blocks.metadata()?.len();
+        let complete = entries.iter().take_while(|entry| entry.end().map(|end| end <= blocks_len).unwrap_or(false)).count();
+        entries.truncate(complete);
+        self.heights = entries.iter().enumerate().map(|(i, entry)| (entry.hash.clone(), i + 1)).collect();
+        self.entries = entries;
+        if repair && (self.blocks_len() != blocks_len || self.index_len() != bytes.len() as u64) {
+            warn!("Trimming incomplete records from the block file");
+            self.trim()?;
+        }
+        Ok(())
+    }
+}
+
+impl BlockFile {
+    fn entry(&self, height: usize) -> Option<&Entry> {
+        if height == 0 {
+            return None;
+        }
+        self.entries.get(height - 1)
+    }
+}
+
+fn invalid_data<E>(e: E) -> io::Error where E: Into<Box<::std::error::Error + Send + Sync>> {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+#[cfg(test)]
+mod tests {
+    use lib::blockchain::{Blockchain, Verification};
+    use lib::encoding::{encode_u64, encode_str};
+    use lib::store::*;
+    use std::env;
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("learnnet-store-{}", name));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn append_and_reopen() {
+        let dir = temp_dir("append_and_reopen");
+        let mut blockchain = Blockchain::new_with(1);
+        blockchain.mine().expect("mined");
+        blockchain.mine().expect("mined");
+        {
+            let mut store = BlockFile::open(&dir).expect("opened");
+            sync(&mut store, &blockchain).expect("synced");
+            assert_eq!(store.len(), 3);
+        }
+
+        let store = BlockFile::open(&dir).expect("reopened");
+        assert_eq!(store.len(), 3);
+        let tip = blockchain.hash_last_block();
+        assert_eq!(store.hash(3), Some(tip.as_str()));
+        assert_eq!(store.height(&tip), Some(3));
+        assert_eq!(store.get(2).expect("read").as_ref(), blockchain.chain().iter().nth(1));
+        assert_eq!(store.get(4).expect("read"), None);
+
+        let loaded: Vec<_> = store.load().expect("loaded");
+        assert_eq!(loaded, blockchain.chain().iter().cloned().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn sync_rewrites_replaced_fork() {
+        let dir = temp_dir("sync_rewrites_replaced_fork");
+        let mut blockchain = Blockchain::new_with(1);
+        blockchain.mine().expect("mined");
+        let mut store = BlockFile::open(&dir).expect("opened");
+        sync(&mut store, &blockchain).expect("synced");
+
+        //a longer fork from the same genesis
+        let mut fork = Blockchain::new_with(1);
+        fork.replace(blockchain.chain().iter().take(1).cloned().collect());
+        fork.mine().expect("mined");
+        fork.mine().expect("mined");
+        blockchain.replace(fork.chain().clone());
+        sync(&mut store, &blockchain).expect("synced");
+
+        assert_eq!(store.len(), 3);
+        assert_eq!(store.hash(3), Some(blockchain.hash_last_block().as_str()));
+        let reopened = BlockFile::open(&dir).expect("reopened");
+        assert_eq!(reopened.load().expect("loaded"), blockchain.chain().iter().cloned().collect::<Vec<_>>());
+    }
+
+    fn stored(name: &str, blocks: usize) -> (PathBuf, Blockchain) {
+        let dir = temp_dir(name);
+        let mut blockchain = Blockchain::new_with(1);
+        for _ in 1..blocks {
+            blockchain.mine().expect("mined");
+        }
+        let mut store = BlockFile::open(&dir).expect("opened");
+        sync(&mut store, &blockchain).expect("synced");
+        (dir, blockchain)
+    }
+
+    #[test]
+    fn repair_truncated_block_file() {
+        let (dir, blockchain) = stored("repair_truncated_block_file", 3);
+        let blocks = OpenOptions::new().write(true).open(dir.join(BLOCKS_FILE)).expect("opened");
+        let len = blocks.metadata().expect("metadata").len();
+        blocks.set_len(len - 3).expect("truncated");
+
+        let mut store = BlockFile::open(&dir).expect("reopened");
+        assert_eq!(store.len(), 2, "half written block dropped");
+        let mut restarted = Blockchain::new_with(1);
+        assert_eq!(restore(&mut store, &mut restarted).expect("restored"), 0);
+        assert_eq!(restarted.len(), 2);
+        assert_eq!(restarted.chain().iter().collect::<Vec<_>>(), blockchain.chain().iter().take(2).collect::<Vec<_>>());
+
+        //the repaired store takes new blocks
+        restarted.mine().expect("mined");
+        sync(&mut store, &restarted).expect("synced");
+        assert_eq!(BlockFile::open(&dir).expect("reopened").load().expect("loaded").len(), 3);
+    }
+
+    #[test]
+    fn repair_huge_index_entry() {
+        let (dir, blockchain) = stored("repair_huge_index_entry", 3);
+        let mut record = Vec::new();
+        encode_u64(8, &mut record);
+        encode_u64(u64::max_value() - 8, &mut record);
+        encode_str("garbage", &mut record);
+        let mut index = OpenOptions::new().append(true).open(dir.join(INDEX_FILE)).expect("opened");
+        index.write_all(&record).expect("written");
+
+        let mut store = BlockFile::open(&dir).expect("reopened");
+        assert_eq!(store.len(), 3, "entry past the end dropped");
+        assert_eq!(store.load().expect("loaded"), blockchain.chain().iter().cloned().collect::<Vec<_>>());
+        let mut restarted = Blockchain::new_with(1);
+        assert_eq!(restore(&mut store, &mut restarted).expect("restored"), 0);
+        restarted.mine().expect("mined");
+        sync(&mut store, &restarted).expect("synced");
+        assert_eq!(BlockFile::open(&dir).expect("reopened").len(), 4);
+    }
+
+    #[test]
+    fn repair_broken_link() {
+        let (dir, blockchain) = stored("repair_broken_link", 3);
+        let mut store = BlockFile::open(&dir).expect("reopened");
+        let mut bogus = blockchain.last_block().clone();
+        bogus.header.index = 4;
+        bogus.header.previous_hash = "bogus".into();
+        store.append(&bogus, "bogus").expect("appended");
+
+        let mut restarted = Blockchain::new_with(1);
+        assert_eq!(restore(&mut store, &mut restarted).expect("restored"), 1);
+        assert_eq!(restarted.chain(), blockchain.chain());
+        assert_eq!(store.len(), 3);
+        assert_eq!(store.height("bogus"), None);
+    }
+
+    #[test]
+    fn verify_stored() {
+        let (dir, blockchain) = stored("verify_stored", 3);
+        let mut store = BlockFile::open(&dir).expect("reopened");
+        assert_eq!(verify(&store, &blockchain), Verification { length: 3, valid: 3, error: None });
+
+        let mut bogus = blockchain.last_block().clone();
+        bogus.header.index = 4;
+        store.append(&bogus, "bogus").expect("appended");
+        let verification = verify(&store, &blockchain);
+        assert_eq!((verification.length, verification.valid), (4, 3));
+        assert!(verification.error.as_ref().expect("an error").starts_with("block 4: "), "{:?}", verification.error);
+        assert_eq!(store.len(), 4, "verify changes nothing");
+    }
+
+    #[test]
+    fn follow_another_writer() {
+        let dir = temp_dir("follow_another_writer");
+        let mut blockchain = Blockchain::new_with(1);
+        let mut leader = BlockFile::open(&dir).expect("opened");
+        restore(&mut leader, &mut blockchain).expect("restored");
+        let mut follower = BlockFile::open_shared(&dir).expect("opened");
+        let mut following = Blockchain::new_with(1);
+        assert!(follow(&follower, &mut following).expect("followed"));
+        assert!(!follow(&follower, &mut following).expect("followed"), "already caught up");
+
+        blockchain.mine().expect("mined");
+        sync(&mut leader, &blockchain).expect("synced");
+        follower.reload(false).expect("reloaded");
+        assert!(follow(&follower, &mut following).expect("followed"));
+        assert_eq!(following.chain(), blockchain.chain());
+
+        //half an index record, as if the leader died writing it
+        let index_len = fs::metadata(dir.join(INDEX_FILE)).expect("index").len();
+        OpenOptions::new().append(true).open(dir.join(INDEX_FILE)).expect("opened").write_all(&[1, 2, 3]).expect("written");
+        follower.reload(false).expect("reloaded");
+        assert_eq!(follower.len(), 2);
+        assert_eq!(fs::metadata(dir.join(INDEX_FILE)).expect("index").len(), index_len + 3, "followers don't repair");
+        follower.reload(true).expect("repaired");
+        assert_eq!(fs::metadata(dir.join(INDEX_FILE)).expect("index").len(), index_len);
+    }
+
+    #[test]
+    fn restore_after_restart() {
+        let dir = temp_dir("restore_after_restart");
+        let mut blockchain = Blockchain::new_with(1);
+        let mut store = BlockFile::open(&dir).expect("opened");
+        restore(&mut store, &mut blockchain).expect("restored");
+        assert_eq!(store.len(), 1, "genesis written to an empty store");
+        blockchain.mine().expect("mined");
+        sync(&mut store, &blockchain).expect("synced");
+        drop(store);
+
+        let mut restarted = Blockchain::new_with(1);
+        let mut store = BlockFile::open(&dir).expect("reopened");
+        restore(&mut store, &mut restarted).expect("restored");
+        assert_eq!(restarted.chain(), blockchain.chain());
+        assert_eq!(restarted.state(), blockchain.state());
+    }
+}
@@ -0,0 +1,163 @@
+use lib::blockchain::{Block, BlockHeader, GENESIS_TIMESTAMP};
+use lib::genesis::Genesis;
+use lib::random::xorshift;
+use lib::transaction::Transaction;
+use lib::versions::FIRST_VERSION;
+
+//Known values and what they encode and hash to, pinned. Every node of a network
+//must encode and hash blocks byte for byte the same, or they'd disagree on which
+//blocks are valid and the network would split, so a change that moves any of
+//these breaks the protocol: it needs a new rules version (see `lib::versions`),
+//not new vectors. Another implementation can check itself against them too.
+//Encodings are hex, hashes are base64 as everywhere else.
+
+///
+/// A plain transaction, and one that expires
+///
+pub fn transaction() -> Transaction {
+    Transaction::new("a".into(), "b".into(), 5)
+}
+
+pub const TRANSACTION_ENCODING: &'static str = "0000000000000001610000000000000001620000000000000005";
+pub const TRANSACTION_SHA256: &'static str = "A2Phba5TTAhoNjvumo09eLFDg6Zml7oCj+8o9SjofjE=";
+
+pub fn expiring_transaction() -> Transaction {
+    transaction().expiring(3)
+}
+
+pub const EXPIRING_TRANSACTION_ENCODING: &'static str = "00000000000000016100000000000000016200000000000000050000000000000003";
+pub const EXPIRING_TRANSACTION_SHA256: &'static str = "Q9vhUlFrAupHvQCxbEhx/DjrERvk2eF//6UbwOLWhD4=";
+
+///
+/// A block holding `transaction()` and a reward, whose header commits to made up
+/// roots. The header's hash doesn't depend on its body
+///
+pub fn block() -> Block {
+    let header = BlockHeader {
+        index: 2,
+        previous_hash: "prev".into(),
+        merkle_root: "root".into(),
+        state_root: "state".into(),
+        timestamp: GENESIS_TIMESTAMP,
+        difficulty: 3,
+        nonce: 258,
+        version: FIRST_VERSION
+    };
+    let mut block = Block { header: header, transactions: Default::default() };
+    block.transactions.insert(transaction());
+    block.transactions.insert(Transaction::reward("miner".into()));
+    block
+}
+
+pub const BLOCK_ENCODING: &'static str = "00000000000000020000000000000004707265760000000000000004726f6f74000000000000000573746174650000000059e2410000000000000000030000000000000102000000000000000200000000000000013000000000000000056d696e657200000000000000010000000000000001610000000000000001620000000000000005";
+
+///
+/// `block()`'s header at version 2, which writes the version out
+///
+pub const VERSION_2_HEADER_ENCODING: &'static str = "800000000000000200000000000000020000000000000004707265760000000000000004726f6f74000000000000000573746174650000000059e2410000000000000000030000000000000102";
+pub const VERSION_2_HEADER_SHA256: &'static str = "T7yGHApy8D1lRReh/ZtnPZ7EYFrsAp5mOlNBYH5eAQc=";
+
+///
+/// The Merkle root of `block()`'s transactions and one more, an odd number
+///
+pub const MERKLE_ROOT_OF_THREE_SHA256: &'static str = "GtZmv8LgXhHD2+WuosxfC6rf4GNICYAgltTZHqPTx2o=";
+
+///
+/// The genesis block of a chain at difficulty 1, without and with a genesis file
+///
+pub const GENESIS_SHA256: &'static str = "8uFFCp+1krTT7W2HTaxBoOdUg1aQa5gRDHN/AO4GZQ0=";
+pub const GENESIS_SHA512: &'static str = "Of3k+pdtKMw/8jLj863tFyPAFkGuGAolhGELzhivAp5nvfQO1c50R1xfMwgcpGy4ScASTqrecl2d5ZwZJi9lSw==";
+
+pub fn genesis() -> Genesis {
+    let mut genesis = Genesis::default();
+    genesis.allocations.insert("alice".into(), 100);
+    genesis.allocations.insert("bob".into(), 50);
+    genesis
+}
+
+pub const ALLOCATED_GENESIS_SHA256: &'static str = "Ukq0+lO6RVHqJg7Tz++t6cd0iBCRe3dDMepFEt3r/30=";
+pub const ALLOCATED_GENESIS_MERKLE_ROOT: &'static str = "Fqckd4s+G8u37NZMimk0HVakMWNFjiKjwOaZRw5GbRs=";
+pub const ALLOCATED_GENESIS_STATE_ROOT: &'static str = "dmxxHIg/pNobHX5yJWAWki9vqU0a7Nk2PzaKcq8DovA=";
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+//bytes json is made of, so a parser gets further into a garbled body than the
+//first byte. Anything else is as likely
+const JSON_BYTES: &'static [u8] = b"{}[]\":,-+.0123456789eEntrufals \\";
+
+///
+/// `rounds` garbled copies of `valid` for fuzzing a decoder: each has up to four
+/// bytes changed, added or removed, or is cut short. The same every run
+///
+pub fn mutations(valid: &[u8], rounds: usize) -> Vec<Vec<u8>> {
+    let mut rng: u64 = 0x2545F4914F6CDD1D;
+    (0..rounds).map(|_| {
+        let mut bytes = valid.to_vec();
+        for _ in 0..xorshift(&mut rng) % 4 + 1 {
+            let byte = match xorshift(&mut rng) % 2 {
+                0 => JSON_BYTES[(xorshift(&mut rng) % JSON_BYTES.len() as u64) as usize],
+                _ => xorshift(&mut rng) as u8
+            };
+            let at = (xorshift(&mut rng) % (bytes.len() as u64 + 1)) as usize;
+            match xorshift(&mut rng) % 4 {
+                0 if at < bytes.len() => bytes[at] = byte,
+                1 if at < bytes.len() => { bytes.remove(at); },
+                2 => bytes.truncate(at),
+                _ => bytes.insert(at, byte)
+            }
+        }
+        bytes
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use lib::blockchain::{Block, Blockchain};
+    use lib::encoding::{decode, encode};
+    use lib::hasher::{Domain, HashAlgorithm, Hasher};
+    use lib::merkle;
+    use lib::testvectors::*;
+    use lib::transaction::Transaction;
+
+    const SHA256: HashAlgorithm = HashAlgorithm::Sha256;
+
+    #[test]
+    fn transactions() {
+        assert_eq!(hex(&encode(&transaction())), TRANSACTION_ENCODING);
+        assert_eq!(SHA256.hash(Domain::Transaction, &transaction()), TRANSACTION_SHA256);
+        assert_eq!(hex(&encode(&expiring_transaction())), EXPIRING_TRANSACTION_ENCODING);
+        assert_eq!(SHA256.hash(Domain::Transaction, &expiring_transaction()), EXPIRING_TRANSACTION_SHA256);
+    }
+
+    #[test]
+    fn blocks() {
+        let block = block();
+        let bytes = encode(&block);
+        assert_eq!(hex(&bytes), BLOCK_ENCODING);
+        assert_eq!(decode::<Block>(&bytes), Ok(block.clone()));
+
+        let mut upgraded = block.header.clone();
+        upgraded.version = 2;
+        assert_eq!(hex(&encode(&upgraded)), VERSION_2_HEADER_ENCODING);
+        assert_eq!(SHA256.hash(Domain::Block, &upgraded), VERSION_2_HEADER_SHA256);
+
+        let mut three = block.transactions.clone();
+        three.insert(Transaction::new("c".into(), "d".into(), 7));
+        assert_eq!(merkle::root(&SHA256, &three), MERKLE_ROOT_OF_THREE_SHA256);
+    }
+
+    #[test]
+    fn genesis_blocks() {
+        let sha256 = Blockchain::new_with_hasher(1, HashAlgorithm::Sha256);
+        assert_eq!(sha256.hash_last_block(), GENESIS_SHA256, "the same on every node");
+        assert_eq!(Blockchain::new_with_hasher(1, HashAlgorithm::Sha512).hash_last_block(), GENESIS_SHA512);
+
+        let allocated = Blockchain::new_with_genesis(1, HashAlgorithm::Sha256, &genesis());
+        let header = &allocated.last_block().header;
+        assert_eq!(header.merkle_root, ALLOCATED_GENESIS_MERKLE_ROOT);
+        assert_eq!(header.state_root, ALLOCATED_GENESIS_STATE_ROOT);
+        assert_eq!(allocated.hash_last_block(), ALLOCATED_GENESIS_SHA256);
+    }
+}
@@ -1,7 +1,13 @@
+use lib::encoding;
 
 //Alias ensure we don't confuse these types with other strings or numbers
-type Address = String;
-type Amount = i64;
+pub type Address = String;
+pub type Amount = i64;
+
+///
+/// What mining a block pays, the one reward a block may hold
+/// 
+pub const REWARD: Amount = 1;
 
 #[derive(Debug)]
 #[derive(Clone)]
@@ -10,7 +16,10 @@ type Amount = i64;
 pub struct Transaction {
     pub sender: Address,
     pub recipient: Address,
-    pub amount: Amount
+    pub amount: Amount,
+    //the last block height it can be confirmed in. Left out of json when there's none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<usize>
 }
 
 impl Transaction {
@@ -18,7 +27,95 @@ impl Transaction {
         Transaction {
             sender: sender,
             recipient: recipient,
-            amount: amount
+            amount: amount,
+            expires_at: None
+        }
+    }
+
+    ///
+    /// The same transaction, but only valid in blocks up to `height`
+    /// 
+    pub fn expiring(mut self, height: usize) -> Transaction {
+        self.expires_at = Some(height);
+        self
+    }
+
+    ///
+    /// Too late to go in the block at `height`
+    /// 
+    pub fn is_expired(&self, height: usize) -> bool {
+        self.expires_at.map(|expires_at| expires_at < height).unwrap_or(false)
+    }
+
+    ///
+    /// The reward for mining a block. The sender is "0" to signify a newly mined coin
+    /// 
+    pub fn reward(recipient: Address) -> Transaction {
+        Transaction::new("0".into(), recipient, REWARD)
+    }
+
+    ///
+    /// Mining rewards are sent from "0"
+    /// 
+    pub fn is_reward(&self) -> bool {
+        self.sender == "0"
+    }
+
+    ///
+    /// Bytes in its canonical encoding, the one its id is the hash of
+    /// 
+    pub fn size(&self) -> usize {
+        encoding::encode(self).len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lib::testvectors;
+    use lib::transaction::Transaction;
+    use serde_json;
+
+    #[test]
+    fn expiry() {
+        let txn = Transaction::new("a".into(), "b".into(), 1);
+        assert!(!txn.is_expired(1000));
+        let expiring = txn.clone().expiring(3);
+        assert!(!expiring.is_expired(3));
+        assert!(expiring.is_expired(4));
+
+        assert!(!serde_json::to_string(&txn).unwrap().contains("expires_at"), "unchanged json without an expiry");
+        let parsed: Transaction = serde_json::from_str(r#"{"sender": "a", "recipient": "b", "amount": 1, "expires_at": 3}"#).unwrap();
+        assert_eq!(parsed, expiring);
+    }
+
+    #[test]
+    fn deserialize_garbage() {
+        let valid = r#"{"sender": "a", "recipient": "b", "amount": 5, "expires_at": 3}"#;
+        for json in testvectors::mutations(valid.as_bytes(), 2000) {
+            if let Ok(txn) = serde_json::from_slice::<Transaction>(&json) {
+                let again = serde_json::from_str::<Transaction>(&serde_json::to_string(&txn).expect("serialized"));
+                assert_eq!(again.ok(), Some(txn), "{} round trips", String::from_utf8_lossy(&json));
+            }
+        }
+        let rejected = [
+            "", "null", "[]", "{}", "5", r#"{"sender": "a", "recipient": "b"}"#,
+            r#"{"sender": "a", "recipient": "b", "amount": "5"}"#,
+            r#"{"sender": "a", "recipient": "b", "amount": 1.5}"#,
+            r#"{"sender": "a", "recipient": "b", "amount": 9223372036854775808}"#,
+            r#"{"sender": 1, "recipient": "b", "amount": 5}"#,
+            r#"{"sender": "a", "recipient": "b", "amount": 5, "expires_at": -1}"#,
+            r#"{"sender": "a", "recipient": "b", "amount": 5"#
+        ];
+        for json in rejected.iter() {
+            assert!(serde_json::from_str::<Transaction>(json).is_err(), "accepted {}", json);
         }
     }
+
+    #[test]
+    fn size() {
+        let txn = Transaction::new("a".into(), "b".into(), 1);
+        assert!(txn.size() > 0);
+        assert_eq!(Transaction::new("abc".into(), "b".into(), 1).size(), txn.size() + 2, "grows with the addresses");
+        assert!(txn.clone().expiring(3).size() > txn.size());
+    }
 }
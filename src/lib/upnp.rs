@@ -0,0 +1,221 @@
+use lib::error::{Error, Result};
+use reqwest::Client;
+use reqwest::header::Headers;
+use std::io::{self, Read};
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::str;
+use std::time::{Duration, Instant};
+use url::Url;
+
+//Just enough of UPnP's Internet Gateway Device protocol to forward a port on a
+//home router. The router is found by an SSDP search multicast on the LAN, and
+//answers with where its description is. The description says where to send
+//SOAP requests for its WAN connection, which map a port to us and tell us the
+//router's external address. Replies are picked apart as text rather than parsed
+//as xml, the few elements we need are unambiguous.
+
+///
+/// Where SSDP searches are multicast
+///
+pub const SSDP_ADDRESS: &'static str = "239.255.255.250:1900";
+
+///
+/// How long a mapping lasts unless renewed. Gateways that only take permanent
+/// mappings are asked for one instead
+///
+pub const DEFAULT_LEASE_SECS: u32 = 3600;
+
+///
+/// The most of a description or SOAP reply read
+///
+const MAX_REPLY_BYTES: u64 = 64 * 1024;
+
+///
+/// The services a gateway maps ports with, in order of preference
+///
+const SERVICES: [&'static str; 2] = ["urn:schemas-upnp-org:service:WANIPConnection:1",
+                                     "urn:schemas-upnp-org:service:WANPPPConnection:1"];
+
+const SEARCH_TARGET: &'static str = "urn:schemas-upnp-org:device:InternetGatewayDevice:1";
+
+///
+/// A router that can map ports
+///
+/// control_url: Where its SOAP requests go
+/// service: Which of `SERVICES` it offers
+/// local_ip: Our address on its LAN, which mappings point at
+///
+#[derive(Clone)]
+pub struct Gateway {
+    pub control_url: Url,
+    pub service: String,
+    pub local_ip: IpAddr,
+    client: Client
+}
+
+///
+/// Find a gateway on the LAN, waiting at most `timeout` for each reply
+///
+pub fn discover(timeout: Duration) -> Result<Gateway> {
+    let socket = UdpSocket::bind("0.0.0.0:0").map_err(network)?;
+    socket.set_read_timeout(Some(timeout)).map_err(network)?;
+    let search = format!("M-SEARCH * HTTP/1.1\r\nHOST: {}\r\nST: {}\r\nMAN: \"ssdp:discover\"\r\nMX: 2\r\n\r\n", SSDP_ADDRESS, SEARCH_TARGET);
+    socket.send_to(search.as_bytes(), SSDP_ADDRESS).map_err(network)?;
+    let client = Client::builder().timeout(timeout).build()?;
+    let started = Instant::now();
+    let mut buf = [0; 2048];
+    while started.elapsed() < timeout {
+        //a read that times out means nobody else is answering
+        let read = match socket.recv_from(&mut buf) {
+            Ok((read, _)) => read,
+            Err(_) => break
+        };
+        let location = match str::from_utf8(&buf[..read]).ok().and_then(location) {
+            Some(location) => location,
+            None => continue
+        };
+        match gateway(&client, &location) {
+            Ok(gateway) => return Ok(gateway),
+            Err(e) => debug!("{} isn't a gateway we can use. {}", location, e)
+        }
+    }
+    Err(Error::Network(String::from("No UPnP gateway answered")))
+}
+
+impl Gateway {
+    ///
+    /// Forward TCP `port` on the router to the same port here, for `lease_secs`
+    ///
+    pub fn map_port(&self, port: u16, lease_secs: u32) -> Result<()> {
+        let args = |lease: u32| format!("<NewRemoteHost></NewRemoteHost><NewExternalPort>{port}</NewExternalPort><NewProtocol>TCP</NewProtocol>\
+                                         <NewInternalPort>{port}</NewInternalPort><NewInternalClient>{client}</NewInternalClient>\
+                                         <NewEnabled>1</NewEnabled><NewPortMappingDescription>learnnet</NewPortMappingDescription>\
+                                         <NewLeaseDuration>{lease}</NewLeaseDuration>",
+                                        port = port, client = self.local_ip, lease = lease);
+        match self.soap("AddPortMapping", &args(lease_secs)) {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                debug!("{} refused a {}s mapping, asking for a permanent one. {}", self.control_url, lease_secs, e);
+                self.soap("AddPortMapping", &args(0)).map(|_| ())
+            }
+        }
+    }
+
+    ///
+    /// The router's address on the internet
+    ///
+    pub fn external_ip(&self) -> Result<IpAddr> {
+        let reply = self.soap("GetExternalIPAddress", "")?;
+        let ip = element(&reply, "NewExternalIPAddress").unwrap_or("");
+        ip.trim().parse().map_err(|_| Error::Network(format!("{} has no usable external address, it said {:?}", self.control_url, ip)))
+    }
+
+    fn soap(&self, action: &str, args: &str) -> Result<String> {
+        let body = format!("<?xml version=\"1.0\"?>\
+                            <s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\">\
+                            <s:Body><u:{action} xmlns:u=\"{service}\">{args}</u:{action}></s:Body></s:Envelope>",
+                           action = action, service = self.service, args = args);
+        let mut headers = Headers::new();
+        headers.set_raw("SOAPAction", format!("\"{}#{}\"", self.service, action));
+        headers.set_raw("Content-Type", String::from("text/xml; charset=\"utf-8\""));
+        let mut res = self.client.post(self.control_url.as_str()).headers(headers).body(body).send()?;
+        let mut reply = String::new();
+        (&mut res).take(MAX_REPLY_BYTES).read_to_string(&mut reply).map_err(network)?;
+        if !res.status().is_success() {
+            let reason = element(&reply, "errorDescription").or_else(|| element(&reply, "errorCode")).unwrap_or("no reason given");
+            return Err(Error::Network(format!("{} refused {}, {}. {}", self.control_url, action, res.status(), reason)));
+        }
+        Ok(reply)
+    }
+}
+
+///
+/// Find a gateway, forward `port` to us and return the url peers can reach us at
+///
+pub fn open(port: u16, lease_secs: u32, timeout: Duration) -> Result<(Gateway, Url)> {
+    let gateway = discover(timeout)?;
+    gateway.map_port(port, lease_secs)?;
+    let ip = gateway.external_ip()?;
+    let url = Url::parse(&format!("http://{}", SocketAddr::new(ip, port))).map_err(|e| Error::Network(format!("Invalid external address {}. {}", ip, e)))?;
+    Ok((gateway, url))
+}
+
+//the socket is on the network, not storage
+fn network(e: io::Error) -> Error {
+    Error::Network(format!("UPnP discovery failed. {}", e))
+}
+
+//the LOCATION header of an SSDP reply
+fn location(reply: &str) -> Option<Url> {
+    reply.lines()
+         .filter_map(|line| {
+             let colon = line.find(':')?;
+             let (name, value) = line.split_at(colon);
+             if name.trim().to_lowercase() == "location" { Url::parse(value[1..].trim()).ok() } else { None }
+         })
+         .next()
+}
+
+fn gateway(client: &Client, location: &Url) -> Result<Gateway> {
+    let mut res = client.get(location.as_str()).send()?;
+    let mut description = String::new();
+    (&mut res).take(MAX_REPLY_BYTES).read_to_string(&mut description).map_err(network)?;
+    let (service, control_url) = control_url(&description, location)
+        .ok_or_else(|| Error::Network(String::from("it offers no WAN connection")))?;
+    //our address on the interface that reaches it
+    let port = location.port_or_known_default().unwrap_or(80);
+    let host = location.host_str().unwrap_or("");
+    let probe = UdpSocket::bind("0.0.0.0:0").map_err(network)?;
+    probe.connect((host.trim_matches(|c| c == '[' || c == ']'), port)).map_err(network)?;
+    Ok(Gateway {
+        control_url: control_url,
+        service: String::from(service),
+        local_ip: probe.local_addr().map_err(network)?.ip(),
+        client: client.clone()
+    })
+}
+
+//the first of `SERVICES` described, and its control url, relative to `location`
+fn control_url(description: &str, location: &Url) -> Option<(&'static str, Url)> {
+    SERVICES.iter()
+            .filter_map(|&service| {
+                let at = description.find(&format!("<serviceType>{}</serviceType>", service))?;
+                let path = element(&description[at..], "controlURL")?;
+                location.join(path.trim()).ok().map(|url| (service, url))
+            })
+            .next()
+}
+
+//the text of the first <name> element
+fn element<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let open = format!("<{}>", name);
+    let start = xml.find(&open)? + open.len();
+    let end = xml[start..].find(&format!("</{}>", name))?;
+    Some(&xml[start..start + end])
+}
+
+#[cfg(test)]
+mod tests {
+    use lib::upnp::{control_url, element, location};
+    use url::Url;
+
+    #[test]
+    fn parse_replies() {
+        let reply = "HTTP/1.1 200 OK\r\nCACHE-CONTROL: max-age=120\r\nLocation: http://192.168.1.1:5000/rootDesc.xml\r\nST: x\r\n\r\n";
+        let at = location(reply).expect("a location");
+        assert_eq!(at.as_str(), "http://192.168.1.1:5000/rootDesc.xml");
+        assert_eq!(location("HTTP/1.1 200 OK\r\n\r\n"), None);
+
+        let description = "<root><service><serviceType>urn:schemas-upnp-org:service:Layer3Forwarding:1</serviceType><controlURL>/l3f</controlURL></service>\
+                           <service><serviceType>urn:schemas-upnp-org:service:WANPPPConnection:1</serviceType><controlURL>/ppp</controlURL></service>\
+                           <service><serviceType>urn:schemas-upnp-org:service:WANIPConnection:1</serviceType><controlURL> /ctl/IPConn </controlURL></service></root>";
+        let (service, url) = control_url(description, &at).expect("a wan connection");
+        assert_eq!(service, "urn:schemas-upnp-org:service:WANIPConnection:1", "preferred");
+        assert_eq!(url, Url::parse("http://192.168.1.1:5000/ctl/IPConn").expect("valid url"));
+        assert_eq!(control_url("<root></root>", &at), None);
+
+        let soap = "<s:Envelope><s:Body><u:GetExternalIPAddressResponse><NewExternalIPAddress>203.0.113.7</NewExternalIPAddress></u:GetExternalIPAddressResponse></s:Body></s:Envelope>";
+        assert_eq!(element(soap, "NewExternalIPAddress"), Some("203.0.113.7"));
+        assert_eq!(element(soap, "errorCode"), None);
+    }
+}
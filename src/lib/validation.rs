@@ -0,0 +1,174 @@
+use lib::blockchain::Blockchain;
+use lib::transaction::{Transaction, Amount};
+
+///
+/// The outcome of one validation rule
+/// 
+/// name: The rule that was checked
+/// passed: Whether the transaction satisfied it
+/// reason: Why it passed or failed, for humans
+/// 
+#[derive(Debug, Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub passed: bool,
+    pub reason: String
+}
+
+impl Check {
+    fn new(name: &'static str, passed: bool, reason: String) -> Check {
+        Check {
+            name: name,
+            passed: passed,
+            reason: reason
+        }
+    }
+}
+
+///
+/// The rules a transaction must pass for a node to add it to its pool, run
+/// against `blockchain` as it is: who it's from and to, the amount, and that it
+/// hasn't expired by the next block. Sender "0" is how a block pays its miner,
+/// anyone could mint coins if new transactions could use it
+/// 
+pub fn admission(txn: &Transaction, blockchain: &Blockchain) -> Vec<Check> {
+    let mut checks = Vec::new();
+
+    checks.push(if txn.sender.trim().is_empty() {
+        Check::new("sender", false, String::from("sender is empty"))
+    } else if txn.is_reward() {
+        Check::new("sender", false, String::from("sender \"0\" is reserved for mining rewards"))
+    } else {
+        Check::new("sender", true, String::from("ok"))
+    });
+
+    checks.push(if txn.recipient.trim().is_empty() {
+        Check::new("recipient", false, String::from("recipient is empty"))
+    } else if txn.recipient == txn.sender {
+        Check::new("recipient", false, String::from("recipient is the sender"))
+    } else {
+        Check::new("recipient", true, String::from("ok"))
+    });
+
+    checks.push(if txn.amount <= 0 {
+        Check::new("amount", false, format!("amount {} must be positive", txn.amount))
+    } else {
+        Check::new("amount", true, String::from("ok"))
+    });
+
+    let next = blockchain.len() + 1;
+    checks.push(match txn.expires_at {
+        Some(expires_at) if txn.is_expired(next) => Check::new("expiry", false, format!("expired at block {}, the next block is {}", expires_at, next)),
+        Some(expires_at) => Check::new("expiry", true, format!("valid until block {}", expires_at)),
+        None => Check::new("expiry", true, String::from("never expires"))
+    });
+
+    checks
+}
+
+///
+/// What a wallet would want to know that a node doesn't refuse a transaction
+/// for. Balances aren't enforced, a sender may overdraw, and resubmitting a
+/// transaction that's already pending changes nothing.
+/// 
+/// The balance rule counts what the sender already has pending, so two transactions
+/// that each fit the balance but not together are caught.
+/// 
+pub fn advice(txn: &Transaction, blockchain: &Blockchain) -> Vec<Check> {
+    let mut checks = Vec::new();
+
+    let available = available_balance(&txn.sender, blockchain);
+    checks.push(if available < txn.amount {
+        Check::new("balance", false, format!("sender has {} available (after pending transactions) but is sending {}", available, txn.amount))
+    } else {
+        Check::new("balance", true, format!("sender has {} available", available))
+    });
+
+    checks.push(if blockchain.current_transactions().contains(txn) {
+        Check::new("duplicate", false, String::from("an identical transaction is already pending"))
+    } else {
+        Check::new("duplicate", true, String::from("ok"))
+    });
+
+    checks
+}
+
+///
+/// Why the checks that failed did, for an error message
+/// 
+pub fn reasons(checks: &[Check]) -> String {
+    checks.iter().filter(|check| !check.passed).map(|check| check.reason.as_str()).collect::<Vec<_>>().join(", ")
+}
+
+///
+/// True if every check passed
+/// 
+pub fn passed(checks: &[Check]) -> bool {
+    checks.iter().all(|check| check.passed)
+}
+
+///
+/// Confirmed balance less whatever the sender already has pending
+/// 
+fn available_balance(sender: &str, blockchain: &Blockchain) -> Amount {
+    let pending: Amount = blockchain.current_transactions()
+                                    .iter()
+                                    .filter(|txn| txn.sender == sender)
+                                    .map(|txn| txn.amount)
+                                    .sum();
+    blockchain.state().balance(sender) - pending
+}
+
+#[cfg(test)]
+mod tests {
+    use lib::blockchain::Blockchain;
+    use lib::transaction::Transaction;
+    use lib::validation::*;
+
+    fn failed(checks: &[Check]) -> Vec<&'static str> {
+        checks.iter().filter(|check| !check.passed).map(|check| check.name).collect()
+    }
+
+    #[test]
+    fn valid() {
+        let mut blockchain = Blockchain::new_with(1);
+        blockchain.set_miner_address("a".into());
+        blockchain.mine().unwrap();
+        let txn = Transaction::new("a".into(), "b".into(), 1);
+        let checks = admission(&txn, &blockchain);
+        assert!(passed(&checks), "failed {:?}", failed(&checks));
+        let checks = advice(&txn, &blockchain);
+        assert!(passed(&checks), "failed {:?}", failed(&checks));
+    }
+
+    #[test]
+    fn invalid() {
+        let blockchain = Blockchain::new_with(1);
+        let checks = admission(&Transaction::new("0".into(), "".into(), -1), &blockchain);
+        assert!(!passed(&checks));
+        assert_eq!(failed(&checks), vec!["sender", "recipient", "amount"], "a negative amount is left to the amount check");
+    }
+
+    #[test]
+    fn expired() {
+        let mut blockchain = Blockchain::new_with(1);
+        blockchain.set_miner_address("a".into());
+        blockchain.mine().unwrap();
+        let txn = Transaction::new("a".into(), "b".into(), 1);
+        assert!(passed(&admission(&txn.clone().expiring(3), &blockchain)), "block 3 is next");
+        assert_eq!(failed(&admission(&txn.expiring(2), &blockchain)), vec!["expiry"]);
+    }
+
+    #[test]
+    fn pending_counts_against_balance() {
+        let mut blockchain = Blockchain::new_with(1);
+        blockchain.set_miner_address("a".into());
+        blockchain.mine().unwrap();
+        let txn = Transaction::new("a".into(), "b".into(), 1);
+        blockchain.new_transaction(txn.clone());
+        let checks = advice(&Transaction::new("a".into(), "c".into(), 1), &blockchain);
+        assert_eq!(failed(&checks), vec!["balance"]);
+        let checks = advice(&txn, &blockchain);
+        assert_eq!(failed(&checks), vec!["balance", "duplicate"]);
+    }
+}
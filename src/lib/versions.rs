@@ -0,0 +1,206 @@
+use lib::error::{Error, Result};
+use lib::hasher::HashAlgorithm;
+use std::collections::BTreeMap;
+
+//Every block header says which version of the rules it follows. A change to the
+//rules is a new version, activated at a height: blocks from that height on must
+//be the new version, and blocks below it are still checked by the rules they were
+//mined under, so a chain that crosses an upgrade stays valid. Every node of a
+//network needs the same policy, like the same hasher. An upgrade's height is either
+//configured up front or set once miners signal for it, see lib::forks.
+
+///
+/// The version of every block before any upgrade, including the genesis block
+///
+pub const FIRST_VERSION: u64 = 1;
+
+///
+/// The low 32 bits of a header's version are the version of the rules it follows.
+/// Miners set bits above them to signal for deployments
+///
+pub const SIGNAL_SHIFT: u64 = 32;
+
+///
+/// The version of the rules a header's `version` follows, without its signals
+///
+pub fn rules_version(version: u64) -> u64 {
+    version & ((1 << SIGNAL_SHIFT) - 1)
+}
+
+///
+/// What a version's blocks are checked by
+///
+/// hasher: Hashes the header (for proof of work and linking) with this rather
+///         than the network's hasher, if set
+/// state_root: Whether the header commits to the balances. Without it the state
+///             root is left empty
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rules {
+    pub hasher: Option<HashAlgorithm>,
+    pub state_root: bool
+}
+
+impl Rules {
+    ///
+    /// The rules of `FIRST_VERSION`
+    ///
+    pub fn first() -> Rules {
+        Rules { hasher: None, state_root: true }
+    }
+}
+
+///
+/// The rules of each version, and the height each version activates at
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Policy {
+    rules: BTreeMap<u64, Rules>,
+    activations: BTreeMap<usize, u64>
+}
+
+impl Policy {
+    ///
+    /// `FIRST_VERSION` from the genesis block on, and nothing else
+    ///
+    pub fn new() -> Policy {
+        let mut policy = Policy { rules: BTreeMap::new(), activations: BTreeMap::new() };
+        policy.rules.insert(FIRST_VERSION, Rules::first());
+        policy.activations.insert(1, FIRST_VERSION);
+        policy
+    }
+
+    ///
+    /// Blocks from `height` on are `version`, checked by `rules`. Versions and
+    /// heights only go up
+    ///
+    pub fn activate(&mut self, version: u64, height: usize, rules: Rules) -> Result<()> {
+        self.check_order(version, height)?;
+        self.define(version, rules)?;
+        self.activations.insert(height, version);
+        Ok(())
+    }
+
+    ///
+    /// Give `version` its rules without activating it yet, see `schedule`
+    ///
+    pub fn define(&mut self, version: u64, rules: Rules) -> Result<()> {
+        if self.rules.contains_key(&version) {
+            return Err(Error::Validation(format!("Version {} already has rules", version)));
+        }
+        if version != rules_version(version) {
+            return Err(Error::Validation(format!("Version {} has signal bits set", version)));
+        }
+        self.rules.insert(version, rules);
+        Ok(())
+    }
+
+    ///
+    /// Blocks from `height` on are `version`, which already has its rules
+    ///
+    pub fn schedule(&mut self, version: u64, height: usize) -> Result<()> {
+        if !self.rules.contains_key(&version) {
+            return Err(Error::Validation(format!("Version {} has no rules", version)));
+        }
+        self.check_order(version, height)?;
+        self.activations.insert(height, version);
+        Ok(())
+    }
+
+    ///
+    /// The height `version` activates at, if it's been activated
+    ///
+    pub fn activation(&self, version: u64) -> Option<usize> {
+        self.activations.iter().find(|&(_, activated)| *activated == version).map(|(height, _)| *height)
+    }
+
+    fn check_order(&self, version: u64, height: usize) -> Result<()> {
+        let (last_height, last_version) = self.activations
+                                              .iter()
+                                              .next_back()
+                                              .map(|(height, version)| (*height, *version))
+                                              .expect("invariant: FIRST_VERSION activates at genesis");
+        if version <= last_version {
+            return Err(Error::Validation(format!("Version {} must be above version {}", version, last_version)));
+        }
+        if height <= last_height {
+            return Err(Error::Validation(format!("Version {} must activate above height {}, where version {} does", version, last_height, last_version)));
+        }
+        Ok(())
+    }
+
+    ///
+    /// The version the block at `height` must be
+    ///
+    pub fn version_at(&self, height: usize) -> u64 {
+        self.activations.range(..height + 1).next_back().map(|(_, version)| *version).unwrap_or(FIRST_VERSION)
+    }
+
+    ///
+    /// The rules of `version`, if it's one of ours
+    ///
+    pub fn rules(&self, version: u64) -> Option<&Rules> {
+        self.rules.get(&version)
+    }
+
+    ///
+    /// The rules a block at `height` claiming `version` is checked by, or what's
+    /// wrong with its version. Signals don't matter
+    ///
+    pub fn check(&self, height: usize, version: u64) -> ::std::result::Result<&Rules, String> {
+        let version = rules_version(version);
+        let expected = self.version_at(height);
+        if version != expected {
+            return Err(format!("version is {}, blocks at height {} are version {}", version, height, expected));
+        }
+        Ok(self.rules(version).expect("invariant: activated versions have rules"))
+    }
+}
+
+impl Default for Policy {
+    fn default() -> Policy {
+        Policy::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lib::hasher::HashAlgorithm;
+    use lib::versions::*;
+
+    #[test]
+    fn activate() {
+        let mut policy = Policy::new();
+        assert_eq!(policy.version_at(1), FIRST_VERSION);
+        assert_eq!(policy.version_at(1000), FIRST_VERSION);
+
+        let second = Rules { hasher: Some(HashAlgorithm::Sha512), state_root: true };
+        policy.activate(2, 10, second.clone()).expect("activated");
+        assert_eq!(policy.version_at(9), FIRST_VERSION);
+        assert_eq!(policy.version_at(10), 2);
+        assert_eq!(policy.check(10, 2), Ok(&second));
+        assert_eq!(policy.check(9, FIRST_VERSION), Ok(&Rules::first()), "old blocks keep their rules");
+        assert!(policy.check(10, FIRST_VERSION).is_err(), "not upgraded");
+        assert!(policy.check(9, 2).is_err(), "upgraded too early");
+
+        assert!(policy.activate(2, 20, Rules::first()).is_err(), "versions go up");
+        assert!(policy.activate(3, 10, Rules::first()).is_err(), "heights go up");
+        assert!(policy.rules(3).is_none());
+        assert_eq!(policy.check(10, 2 | 1 << SIGNAL_SHIFT), Ok(&second), "signals are ignored");
+    }
+
+    #[test]
+    fn schedule() {
+        let mut policy = Policy::new();
+        assert!(policy.schedule(2, 10).is_err(), "no rules yet");
+        policy.define(2, Rules::first()).expect("defined");
+        assert!(policy.define(2, Rules::first()).is_err());
+        assert!(policy.define(1 << SIGNAL_SHIFT, Rules::first()).is_err());
+        assert_eq!(policy.activation(2), None);
+        assert_eq!(policy.version_at(10), FIRST_VERSION, "defined isn't active");
+
+        policy.schedule(2, 10).expect("scheduled");
+        assert_eq!(policy.activation(2), Some(10));
+        assert_eq!(policy.version_at(10), 2);
+    }
+}
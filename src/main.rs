@@ -17,55 +17,1061 @@ extern crate clap;
 mod lib;
 mod web;
 
-use clap::{Arg, App};
+use clap::{Arg, App, ErrorKind, SubCommand};
+use lib::export::Format;
+use lib::forks;
+use lib::genesis::Genesis;
+use lib::hasher::HashAlgorithm;
+use lib::import::Import;
+use lib::journal::{self, Event, Journal};
+use lib::lease::{Lease, DEFAULT_LEASE_SECS};
+use lib::memory::Caps;
+use lib::peers::Peers;
+use lib::rounds::RoundLog;
+use lib::rejections::RejectionLog;
+use lib::selection::Strategy;
+use lib::standard::Standardness;
+use lib::store::{self, BlockFile, ChainStore};
+use log4rs::append::console::ConsoleAppender;
+use log4rs::config::{Appender, Config, Root};
+use std::env;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use url::Url;
 
 ///
-/// Entry point. Starts logger, parses command line args and starts the web api
+/// Entry point. Parses command line args, starts logger and starts the web api
 /// 
 /// Note: The impl doesn't really make sense yet. Transactions can be added by anyone
 ///       and there is no communication between nodes (such as queued transactions),
 ///       only during consensus. It will be fleshed out in time.
 /// 
 fn main() {
+    let args = parse_args();
+
+    //before the logger starts, so stdout is only the export
+    if let Some(format) = args.export {
+        let dir = args.data_dir.as_ref().expect("chain export needs --data-dir");
+        export(dir, args.hasher, format);
+        return;
+    }
+    if args.verify {
+        let dir = args.data_dir.as_ref().expect("chain verify needs --data-dir");
+        verify(dir, args.hasher);
+        return;
+    }
+    if let Some(ref path) = args.import {
+        let dir = args.data_dir.as_ref().expect("chain import needs --data-dir");
+        import(dir, args.hasher, path);
+        return;
+    }
+    if let Some(ref path) = args.replay {
+        replay(path, args.difficulty, args.hasher);
+        return;
+    }
+    if let Some((ref a, ref b)) = args.compare {
+        compare(a, b, args.color, &args);
+        return;
+    }
+    if let Some(ref peer) = args.init {
+        let dir = args.data_dir.as_ref().expect("init needs --data-dir");
+        init(dir, peer, &args);
+        return;
+    }
+    if let Some((ref socket, ref command)) = args.ctl {
+        ctl(socket, command);
+        return;
+    }
+
     //env_logger::init().unwrap_or_else(|e| println!("Failed to init env_logger. {}", e));
-    log4rs::init_file("log4rs.yml", Default::default()).unwrap_or_else(|e| println!("Failed to init log4rs. {}", e));
+    if Path::new(LOG_CONFIG).exists() {
+        log4rs::init_file(LOG_CONFIG, Default::default()).unwrap_or_else(|e| println!("Failed to init log4rs. {}", e));
+    } else {
+        //a container needs no config file, its logs are what it prints
+        let stdout = Appender::builder().build("stdout", Box::new(ConsoleAppender::builder().build()));
+        Config::builder().appender(stdout)
+                         .build(Root::builder().appender("stdout").build(log::LogLevelFilter::Info))
+                         .map_err(|e| e.to_string())
+                         .and_then(|config| log4rs::init_config(config).map_err(|e| e.to_string()))
+                         .unwrap_or_else(|e| println!("Failed to init log4rs. {}", e));
+    }
     debug!("Started");
-    
-    let args = parse_args();
+    log_args(&args);
 
     //The state wrapper that allows Rocket to access the underlying lib::Blockchain
-    let blockchain_state = web::BlockchainState::new_with(args.difficulty); 
+    let genesis = match args.genesis {
+        Some(ref path) => Genesis::load(path).unwrap_or_else(|e| panic!("Failed to load genesis file {}. {}", path.display(), e)),
+        None => Genesis::default()
+    };
+    let mut blockchain = lib::blockchain::Blockchain::new_with_genesis(args.difficulty, args.hasher, &genesis);
+    blockchain.set_miner_address(args.miner_address);
+    blockchain.set_caps(args.caps.clone());
+    blockchain.set_selector(Arc::new(args.selection));
+    blockchain.set_max_block_transactions(args.max_block_transactions);
+    blockchain.set_prune_depth(args.prune_depth);
+    if let Some(ref path) = args.deployments {
+        let deployments = forks::load(path).unwrap_or_else(|e| panic!("Failed to load deployments file {}. {}", path.display(), e));
+        for deployment in deployments {
+            blockchain.add_deployment(deployment).unwrap_or_else(|e| panic!("Invalid deployment in {}. {}", path.display(), e));
+        }
+    }
+    let cluster = match args.cluster_id {
+        Some(ref id) => {
+            let dir = args.data_dir.as_ref().expect("--cluster-id needs --data-dir");
+            Some(web::cluster::Cluster::new(Lease::new(&dir.join(LEASE_FILE), id.clone(), DEFAULT_LEASE_SECS)))
+        },
+        None => None
+    };
+    let clustered = cluster.is_some();
+    let block_file = args.data_dir.as_ref().map(|dir| {
+        if clustered {
+            //other replicas may be writing, the first request decides who leads and catches up
+            return BlockFile::open_shared(dir).unwrap_or_else(|e| panic!("Failed to open {}. {}", dir.display(), e));
+        }
+        let mut block_file = BlockFile::open(dir).unwrap_or_else(|e| panic!("Failed to open {}. {}", dir.display(), e));
+        let dropped = store::restore(&mut block_file, &mut blockchain).unwrap_or_else(|e| panic!("Failed to load the chain from {}. {}", dir.display(), e));
+        if dropped > 0 {
+            warn!("Dropped {} damaged blocks from {}. Run consensus to resync them", dropped, dir.display());
+        }
+        block_file
+    });
+    let peers_file = args.data_dir.as_ref().map(|dir| dir.join(PEERS_FILE));
+    let peers = load_peers(peers_file.as_ref().map(|path| path.as_path()), &args.seeds, args.default_port);
+    let journal = args.journal.map(|path| {
+        let mut journal = Journal::open(&path).unwrap_or_else(|e| panic!("Failed to open journal {}. {}", path.display(), e));
+        //so a replay starts from the same chain
+        journal.record(&Event::Replaced(blockchain.chain().clone())).unwrap_or_else(|e| panic!("Failed to write journal {}. {}", path.display(), e));
+        journal
+    });
+    let rounds = args.data_dir.as_ref().map(|dir| {
+        let path = dir.join(ROUNDS_FILE);
+        RoundLog::open(&path).unwrap_or_else(|e| panic!("Failed to open {}. {}", path.display(), e))
+    });
+    let rejections = args.data_dir.as_ref().map(|dir| {
+        let path = dir.join(REJECTIONS_FILE);
+        RejectionLog::open(&path).unwrap_or_else(|e| panic!("Failed to open {}. {}", path.display(), e))
+    });
+    let mut blockchain_state = web::BlockchainState::new(blockchain, args.timeout); 
+    blockchain_state.admin_token = args.admin_token;
+    blockchain_state.lenient_content_type = args.lenient_content_type;
+    blockchain_state.tutorial_schema = args.tutorial_schema;
+    blockchain_state.network = args.network;
+    blockchain_state.default_port = args.default_port;
+    blockchain_state.peers = RwLock::new(peers);
+    blockchain_state.peers_file = peers_file;
+    blockchain_state.peers_per_round = args.peers_per_round;
+    if let Some(interval) = args.consensus_interval {
+        blockchain_state.schedule = web::schedule::Schedule::every(interval);
+    }
+    blockchain_state.max_pending_per_sender = args.max_pending_per_sender;
+    blockchain_state.standardness = args.standardness;
+    blockchain_state.mempool_sync = args.mempool_sync_interval;
+    blockchain_state.public_address = args.public_address;
+    blockchain_state.public_port = args.public_port;
+    blockchain_state.peer_listener = args.peer_listener;
+    blockchain_state.control_socket = args.control_socket;
+    blockchain_state.advertised_url = args.advertised_url;
+    blockchain_state.upnp = args.upnp;
+    blockchain_state.events_listener = args.events_listener;
+    blockchain_state.auto_mine = web::automine::AutoMine::new(args.auto_mine_pending, args.auto_mine_secs).unwrap_or_else(|e| panic!("Invalid auto-mining. {}", e));
+    blockchain_state.store = block_file.map(|block_file| Mutex::new(Box::new(block_file) as Box<store::ChainStore + Send>));
+    blockchain_state.journal = journal.map(Mutex::new);
+    blockchain_state.cluster = cluster;
+    blockchain_state.faucet = args.faucet_token.map(web::faucet::Faucet::new);
+    if let Some(rounds) = rounds {
+        blockchain_state.rounds = Mutex::new(rounds);
+    }
+    if let Some(rejections) = rejections {
+        blockchain_state.rejections = Mutex::new(rejections);
+    }
 
     //Start the API
     web::init(blockchain_state);
 }
 
+///
+/// Where the peers are saved in --data-dir
+/// 
+const PEERS_FILE: &'static str = "peers.json";
+
+///
+/// The lease replicas sharing --data-dir elect a leader with
+/// 
+const LEASE_FILE: &'static str = "leader.lease";
+
+///
+/// Where every consensus round is recorded in --data-dir, one json object a line
+/// 
+const ROUNDS_FILE: &'static str = "consensus.jsonl";
+
+///
+/// Where the transactions and chains we refused are logged in --data-dir, one
+/// json object a line
+/// 
+const REJECTIONS_FILE: &'static str = "rejections.jsonl";
+
+///
+/// The log4rs config read on startup. Without it everything at info and above
+/// goes to stdout
+/// 
+const LOG_CONFIG: &'static str = "log4rs.yml";
+
+///
+/// Every option can be given as an environment variable instead: LEARNNET_ and
+/// the option's name in upper case with _ for -, so --data-dir is
+/// LEARNNET_DATA_DIR. LEARNNET_PEERS takes a comma separated list of --seed
+/// 
+const ENV_PREFIX: &'static str = "LEARNNET_";
+
+///
+/// The options with a short form, which counts as giving them on the command line
+/// 
+const SHORT_OPTIONS: [(&'static str, &'static str); 3] = [("difficulty", "-d"), ("timeout", "-t"), ("miner-address", "-m")];
+
+///
+/// Where the public, peer and events listeners bind when run in a container and
+/// no address is given, so they're reachable through its published ports
+/// 
+const CONTAINER_ADDRESS: &'static str = "0.0.0.0";
+
+///
+/// The peers saved last run, if any. Seeds are only registered if none of them
+/// are good, so a node that was on the network reconnects to the peers it knew.
+/// Seeds given by host alone are on `default_port`
+/// 
+fn load_peers(path: Option<&Path>, seeds: &[String], default_port: u16) -> Peers {
+    let mut peers = match path {
+        Some(path) => Peers::load(path).unwrap_or_else(|e| panic!("Failed to load peers from {}. {}", path.display(), e)),
+        None => Peers::new()
+    };
+    if peers.len() > 0 {
+        info!("loaded {} peers, {} good", peers.len(), peers.good());
+    }
+    if peers.good() == 0 {
+        for seed in seeds {
+            let url = lib::peers::parse_node(seed, default_port).unwrap_or_else(|e| panic!("Invalid seed. {}", e));
+            info!("using seed {}", url);
+            peers.register(url);
+        }
+    }
+    peers
+}
+
+///
+/// `chain export`. Write the chain stored in `dir` to stdout
+/// 
+fn export(dir: &Path, hasher: HashAlgorithm, format: Format) {
+    let block_file = BlockFile::open(dir).unwrap_or_else(|e| panic!("Failed to open {}. {}", dir.display(), e));
+    let blocks = block_file.load().unwrap_or_else(|e| panic!("Failed to read the chain from {}. {}", dir.display(), e));
+    let mut blockchain = lib::blockchain::Blockchain::new_with_hasher(lib::blockchain::MIN_DIFFICULTY, hasher);
+    if !blocks.is_empty() {
+        blockchain.replace(blocks.into_iter().collect());
+    }
+    let stdout = io::stdout();
+    lib::export::export(&blockchain, format, &mut stdout.lock()).unwrap_or_else(|e| panic!("Failed to export. {}", e));
+}
+
+///
+/// `chain verify`. Check the chain stored in `dir` from genesis, exiting with 1
+/// if any of it is invalid
+/// 
+fn verify(dir: &Path, hasher: HashAlgorithm) {
+    let block_file = BlockFile::open(dir).unwrap_or_else(|e| panic!("Failed to open {}. {}", dir.display(), e));
+    let blockchain = lib::blockchain::Blockchain::new_with_hasher(lib::blockchain::MIN_DIFFICULTY, hasher);
+    let verification = store::verify(&block_file, &blockchain);
+    println!("verified {} of {} blocks", verification.valid, verification.length);
+    if let Some(ref error) = verification.error {
+        println!("{}", error);
+        std::process::exit(1);
+    }
+}
+
+///
+/// `chain import`. Add the blocks in the file at `path` to the chain stored in
+/// `dir`, printing progress, and exiting with 1 at the first invalid block. Run
+/// it again to carry on after an interruption
+/// 
+fn import(dir: &Path, hasher: HashAlgorithm, path: &Path) {
+    let mut block_file = BlockFile::open(dir).unwrap_or_else(|e| panic!("Failed to open {}. {}", dir.display(), e));
+    let mut import = Import::open(path).unwrap_or_else(|e| panic!("Failed to open {}. {}", path.display(), e));
+    //nothing stored yet, so the file's genesis block is the one to keep
+    import.adopt_genesis = block_file.len() == 0;
+    let mut blockchain = lib::blockchain::Blockchain::new_with_hasher(lib::blockchain::MIN_DIFFICULTY, hasher);
+    store::restore(&mut block_file, &mut blockchain).unwrap_or_else(|e| panic!("Failed to load the chain from {}. {}", dir.display(), e));
+    let mut printed = Instant::now();
+    let result = loop {
+        let blocks = match import.next_batch(lib::import::IMPORT_BATCH) {
+            Ok(ref blocks) if blocks.is_empty() => break Ok(()),
+            Ok(blocks) => blocks,
+            Err(e) => break Err(e)
+        };
+        //the blocks before a bad one are kept
+        let applied = import.apply(&mut blockchain, blocks);
+        if let Err(e) = store::sync(&mut block_file, &blockchain).and(applied) {
+            break Err(e);
+        }
+        if printed.elapsed() >= Duration::from_secs(lib::import::PROGRESS_SECS) {
+            let progress = import.progress("running", None);
+            println!("{:.1}% {:.0} blocks/s, {} imported", progress.percent, progress.blocks_per_sec, progress.imported);
+            printed = Instant::now();
+        }
+    };
+    let progress = import.progress(if result.is_ok() { "done" } else { "failed" }, None);
+    println!("imported {} blocks, skipped {}, length {}", progress.imported, progress.skipped, blockchain.len());
+    if let Err(e) = result {
+        println!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+///
+/// `init --from`. Set up the fresh data dir `dir` from `peer`'s chain, exiting
+/// with 1 if it can't, and print how to start a node on it
+/// 
+fn init(dir: &Path, peer: &Url, args: &Args) {
+    let peers_file = dir.join(PEERS_FILE);
+    match lib::bootstrap::init(dir, &peers_file, peer, args.timeout, &args.network, args.hasher, args.caps.peer_chain) {
+        Ok(initialized) => {
+            println!("stored {} blocks from {}, tip {}", initialized.height, peer, initialized.tip);
+            let hash = format!("{:?}", args.hasher).to_lowercase();
+            println!("start the node with --data-dir {} --network {} --hash {}", dir.display(), args.network, hash);
+        },
+        Err(e) => {
+            println!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+///
+/// `chain compare`. Check the chains at `a` and `b`, files or urls, and print how
+/// they differ. Exits with 1 if they do, like diff
+/// 
+fn compare(a: &str, b: &str, color: bool, args: &Args) {
+    let load = |source: &str| lib::compare::load(source, args.timeout, &args.network, args.caps.peer_chain)
+                                  .unwrap_or_else(|e| panic!("Failed to load the chain from {}. {}", source, e));
+    let comparison = lib::compare::compare(load(a), load(b), (a, b), args.hasher);
+    let stdout = io::stdout();
+    lib::compare::report(&comparison, color, &mut stdout.lock()).unwrap_or_else(|e| panic!("Failed to report. {}", e));
+    if !comparison.identical() {
+        std::process::exit(1);
+    }
+}
+
+///
+/// `replay`. Rebuild a node from its journal and print where it ended up
+/// 
+fn replay(path: &Path, difficulty: u64, hasher: HashAlgorithm) {
+    let events = journal::read(path).unwrap_or_else(|e| panic!("Failed to read journal {}. {}", path.display(), e));
+    let count = events.len();
+    let blockchain = journal::replay(events, difficulty, hasher).unwrap_or_else(|e| panic!("Replay failed. {}", e));
+    println!("replayed {} events", count);
+    println!("length {}", blockchain.len());
+    println!("tip {}", blockchain.hash_last_block());
+    println!("pending transactions {}", blockchain.current_transactions().len());
+    for (address, balance) in blockchain.state().balances() {
+        println!("balance {} {}", address, balance);
+    }
+}
+
+///
+/// `ctl`. Send `command` to the node listening on `socket` and print its reply,
+/// exiting with 1 if it refused
+/// 
+fn ctl(socket: &Path, command: &web::control::Command) {
+    let reply = web::control::request(socket, command).unwrap_or_else(|e| panic!("Failed to reach {}. {}", socket.display(), e));
+    println!("{}", reply.message);
+    if !reply.ok {
+        std::process::exit(1);
+    }
+}
+
 ///
 /// The supported command line arguments
 /// 
+/// genesis: File with the balances the genesis block allocates. Ignored when
+///          --data-dir already has a chain
+/// deployments: File of soft forks to signal for, see `lib::forks`
+/// faucet_token: Secret the X-Faucet-Token header must match on POST /faucet.
+///               None, and there's no faucet
+/// caps: Set by --max-mempool-bytes, --max-orphan-bytes and --max-peer-chain-bytes,
+///       see `lib::memory`
+/// prune_depth: Set by --prune-depth. How far below the tip transactions are
+///              indexed, see `Blockchain::set_prune_depth`
+/// standardness: Set by --standardness. The policy on which valid transactions
+///               are admitted to the mempool
+/// cluster_id: Set to share --data-dir with other replicas under this name
+/// export: Set by `chain export`. Export the stored chain instead of starting a node
+/// verify: Set by `chain verify`. Verify the stored chain instead of starting a node
+/// import: Set by `chain import`. Import this file of blocks instead of starting a node
+/// compare: Set by `chain compare`. Compare these chains instead of starting a node
+/// color: Set by `chain compare --color`. Whether its report is in color
+/// replay: Set by `replay`. Replay this journal instead of starting a node
+/// init: Set by `init --from`. Set up --data-dir from this peer instead of
+///       starting a node
+/// control_socket: Where to listen for `ctl` commands
+/// public_address: Set by --address. Where the public routes are served, over
+///                 Rocket.toml. 0.0.0.0 in a container unless ROCKET_ADDRESS is set
+/// public_port: Set by --port. The port the public routes are served on, over
+///              Rocket.toml
+/// ctl: Set by `ctl`. Send this command to the node on this socket instead of
+///      starting one
+/// 
 struct Args {
-    difficulty: u64
+    difficulty: u64,
+    timeout: Duration,
+    miner_address: String,
+    hasher: HashAlgorithm,
+    admin_token: Option<String>,
+    faucet_token: Option<String>,
+    data_dir: Option<PathBuf>,
+    journal: Option<PathBuf>,
+    genesis: Option<PathBuf>,
+    deployments: Option<PathBuf>,
+    lenient_content_type: bool,
+    tutorial_schema: bool,
+    network: String,
+    default_port: u16,
+    seeds: Vec<String>,
+    advertised_url: Option<Url>,
+    upnp: bool,
+    peers_per_round: usize,
+    consensus_interval: Option<Duration>,
+    mempool_sync_interval: Option<Duration>,
+    auto_mine_pending: Option<usize>,
+    auto_mine_secs: Option<u64>,
+    max_pending_per_sender: usize,
+    caps: Caps,
+    selection: Strategy,
+    max_block_transactions: Option<usize>,
+    prune_depth: Option<usize>,
+    standardness: Option<Standardness>,
+    peer_listener: Option<(String, u16)>,
+    events_listener: Option<(String, u16)>,
+    cluster_id: Option<String>,
+    export: Option<Format>,
+    verify: bool,
+    import: Option<PathBuf>,
+    compare: Option<(String, String)>,
+    color: bool,
+    replay: Option<PathBuf>,
+    init: Option<Url>,
+    control_socket: Option<PathBuf>,
+    public_address: Option<String>,
+    public_port: Option<u16>,
+    ctl: Option<(PathBuf, web::control::Command)>
+}
+
+///
+/// The command line, with an option added for each LEARNNET_ variable that isn't
+/// given on it already, so a node can be configured with nothing but its
+/// environment. What's on the command line wins
+/// 
+fn args_with_env() -> Vec<String> {
+    let vars: Vec<(String, String)> = env::vars().filter(|&(ref name, _)| name.starts_with(ENV_PREFIX)).collect();
+    let (args, ignored) = env_args(vars, env::args().collect());
+    //stderr, before the logger starts, so stdout is only an export
+    for name in ignored {
+        eprintln!("Ignoring {}, there's no such option. Subcommands only take theirs on the command line", name);
+    }
+    args
+}
+
+///
+/// `args`, argv[0] first, with the options `vars` give that aren't in it.
+/// Options that take no value are on when their variable is 1, true, yes or
+/// on, which options take one is up to `app`, and variables for options it
+/// doesn't have are left out
+/// 
+/// returns: The arguments, and the names of the variables left out
+fn env_args(mut vars: Vec<(String, String)>, args: Vec<String>) -> (Vec<String>, Vec<String>) {
+    let mut args = args.into_iter();
+    let mut with_env: Vec<String> = args.next().into_iter().collect();
+    let given: Vec<String> = args.collect();
+    let mut ignored = Vec::new();
+    vars.sort();
+    for (name, value) in vars {
+        let option = name[ENV_PREFIX.len()..].to_lowercase().replace('_', "-");
+        let (option, values): (String, Vec<&str>) = if option == "peers" {
+            (String::from("seed"), value.split(',').map(str::trim).collect())
+        } else {
+            (option, vec![value.trim()])
+        };
+        if on_command_line(&given, &option) {
+            continue;
+        }
+        match takes_value(&option) {
+            None => ignored.push(name.clone()),
+            Some(false) => if ["1", "true", "yes", "on"].contains(&value.trim().to_lowercase().as_str()) {
+                with_env.push(format!("--{}", option));
+            },
+            //with = so a value starting with - isn't taken for an option
+            Some(true) => for value in values.into_iter().filter(|value| !value.is_empty()) {
+                with_env.push(format!("--{}={}", option, value));
+            }
+        }
+    }
+    with_env.extend(given);
+    (with_env, ignored)
+}
+
+//whether `app` has --`option` and if it takes a value, by asking clap to
+//parse it alone: an option is missing its value, a flag isn't
+fn takes_value(option: &str) -> Option<bool> {
+    match app().get_matches_from_safe(vec![String::from("learnnet"), format!("--{}", option)]) {
+        Err(ref e) if e.kind == ErrorKind::UnknownArgument => None,
+        Err(ref e) if e.kind == ErrorKind::HelpDisplayed || e.kind == ErrorKind::VersionDisplayed => None,
+        Err(ref e) if e.kind == ErrorKind::EmptyValue => Some(true),
+        _ => Some(false)
+    }
+}
+
+//whether `option` is in `given`, in any of the ways clap takes it
+fn on_command_line(given: &[String], option: &str) -> bool {
+    let short = SHORT_OPTIONS.iter().find(|&&(long, _)| long == option).map(|&(_, short)| short);
+    given.iter().any(|arg| *arg == format!("--{}", option) || arg.starts_with(&format!("--{}=", option))
+                           || short.map(|short| arg.starts_with(short)).unwrap_or(false))
+}
+
+///
+/// Whether stdout is a terminal, rather than a file or a pipe, which escape
+/// codes would only clutter
+/// 
+#[cfg(unix)]
+fn stdout_is_terminal() -> bool {
+    use std::os::raw::c_int;
+    //from the C library every unix build links already
+    extern "C" {
+        fn isatty(fd: c_int) -> c_int;
+    }
+    unsafe { isatty(1) == 1 }
+}
+
+#[cfg(not(unix))]
+fn stdout_is_terminal() -> bool {
+    false
+}
+
+///
+/// Whether we're running in a Docker, Kubernetes or similar container
+/// 
+fn in_container() -> bool {
+    Path::new("/.dockerenv").exists()
+        || env::var_os("container").is_some()
+        || File::open("/proc/1/cgroup")
+               .and_then(|mut file| {
+                   let mut cgroup = String::new();
+                   file.read_to_string(&mut cgroup).map(|_| cgroup)
+               })
+               .map(|cgroup| ["docker", "kubepods", "containerd"].iter().any(|runtime| cgroup.contains(runtime)))
+               .unwrap_or(false)
+}
+
+///
+/// Every option and subcommand
+/// 
+fn app() -> App<'static, 'static> {
+    App::new("learnnet blockchain")
+            .version("0.1")
+            .author("Steven P. <steven.pack.code@gmail.com>")
+            .about("Learn the blockchain! Inspired by https://github.com/dvf/blockchain")                         
+            .arg(Arg::with_name("difficulty")
+                 .short("d")
+                 .long("difficulty")
+                 .help("Proof of work difficulty. 3 would mean a hash starting with 000")
+                 .takes_value(true))
+            .arg(Arg::with_name("timeout")
+                 .short("t")
+                 .long("timeout")
+                 .help("Seconds a request waits for the blockchain lock, or for each peer during consensus")
+                 .takes_value(true))
+            .arg(Arg::with_name("miner-address")
+                 .short("m")
+                 .long("miner-address")
+                 .help("Address that receives the reward for mined blocks")
+                 .takes_value(true))
+            .arg(Arg::with_name("hash")
+                 .long("hash")
+                 .help("Hash algorithm for the network. Every node must use the same one")
+                 .possible_values(&["sha256", "sha512"])
+                 .takes_value(true))
+            .arg(Arg::with_name("admin-token")
+                 .long("admin-token")
+                 .help("Secret for the X-Admin-Token header on admin routes. Admin routes are disabled without it")
+                 .takes_value(true))
+            .arg(Arg::with_name("faucet-token")
+                 .long("faucet-token")
+                 .help("Secret for the X-Faucet-Token header on POST /faucet, which hands out coins. For test networks only, there's no faucet without it")
+                 .takes_value(true))
+            .arg(Arg::with_name("data-dir")
+                 .long("data-dir")
+                 .help("Directory the chain is stored in and restored from. Without it the chain is lost on exit")
+                 .takes_value(true))
+            .arg(Arg::with_name("journal")
+                 .long("journal")
+                 .help("File to record transactions and blocks in, for the replay command")
+                 .takes_value(true))
+            .arg(Arg::with_name("genesis")
+                 .long("genesis")
+                 .help("Json file of starting balances, {\"allocations\": {\"address\": amount}}, paid out in the genesis block")
+                 .takes_value(true))
+            .arg(Arg::with_name("deployments")
+                 .long("deployments")
+                 .help("Json file of soft forks to signal for in mined blocks, each activating once enough blocks signal. Every node must use the same one")
+                 .takes_value(true))
+            .arg(Arg::with_name("lenient-content-type")
+                 .long("lenient-content-type")
+                 .help("Parse transaction bodies as json whatever their Content-Type says"))
+            .arg(Arg::with_name("tutorial-schema")
+                 .long("tutorial-schema")
+                 .help("Answer /chain and /mine with the Python tutorial's json, for clients written against it. Peers still get ours"))
+            .arg(Arg::with_name("network")
+                 .long("network")
+                 .help("Name of the network to join. Peers on other networks are refused")
+                 .takes_value(true))
+            .arg(Arg::with_name("seed")
+                 .long("seed")
+                 .help("Node to register on startup if none of the saved peers are good, a url or a host with or without a port. Can be given more than once")
+                 .takes_value(true)
+                 .multiple(true)
+                 .number_of_values(1))
+            .arg(Arg::with_name("default-port")
+                 .long("default-port")
+                 .help("Port of peers given by host alone, as in --seed 10.0.0.5. Defaults to --peer-port, or --port, or 8000")
+                 .takes_value(true))
+            .arg(Arg::with_name("advertised-url")
+                 .long("advertised-url")
+                 .help("Where other nodes can reach this one, when that isn't where it listens, as behind NAT or a proxy. Given to every known peer to register on startup")
+                 .takes_value(true))
+            .arg(Arg::with_name("upnp")
+                 .long("upnp")
+                 .help("Ask the router to forward our port with UPnP on startup, and advertise where it forwards from unless --advertised-url is given"))
+            .arg(Arg::with_name("peers-per-round")
+                 .long("peers-per-round")
+                 .help("Most peers asked for their chain in each consensus round")
+                 .takes_value(true))
+            .arg(Arg::with_name("consensus-interval")
+                 .long("consensus-interval")
+                 .help("Seconds between consensus rounds run in the background, give or take 10%. Without it consensus only runs on GET /nodes/resolve")
+                 .takes_value(true))
+            .arg(Arg::with_name("mempool-sync-interval")
+                 .long("mempool-sync-interval")
+                 .help("Seconds between pulling pending transactions we're missing from peers, give or take 10%. Without it peers' transactions only arrive in their blocks")
+                 .takes_value(true))
+            .arg(Arg::with_name("auto-mine-pending")
+                 .long("auto-mine-pending")
+                 .help("Mine by itself once this many transactions are waiting. Can be changed with PUT /admin/auto-mine")
+                 .takes_value(true))
+            .arg(Arg::with_name("auto-mine-secs")
+                 .long("auto-mine-secs")
+                 .help("Mine by itself once waiting transactions are this many seconds after the last block. Can be changed with PUT /admin/auto-mine")
+                 .takes_value(true))
+            .arg(Arg::with_name("max-pending-per-sender")
+                 .long("max-pending-per-sender")
+                 .help("Most transactions one sender may have waiting to be mined. More are refused with a 429")
+                 .takes_value(true))
+            .arg(Arg::with_name("max-mempool-bytes")
+                 .long("max-mempool-bytes")
+                 .help("Most the pending transactions may take in memory, roughly. More are refused with a 429")
+                 .takes_value(true))
+            .arg(Arg::with_name("max-orphan-bytes")
+                 .long("max-orphan-bytes")
+                 .help("Most the blocks abandoned in reorgs may take in memory, roughly. The lowest go first")
+                 .takes_value(true))
+            .arg(Arg::with_name("max-peer-chain-bytes")
+                 .long("max-peer-chain-bytes")
+                 .help("Biggest chain downloaded from a peer, in bytes of json. Bigger ones are dropped part way")
+                 .takes_value(true))
+            .arg(Arg::with_name("selection")
+                 .long("selection")
+                 .help("How transactions are picked for blocks we mine when more are waiting than --max-block-transactions: oldest first, shuffled, or taking turns between senders")
+                 .possible_values(&["fifo", "random", "per-sender"])
+                 .takes_value(true))
+            .arg(Arg::with_name("max-block-transactions")
+                 .long("max-block-transactions")
+                 .help("Most transactions a block we mine holds, besides the reward. The rest wait for the next block")
+                 .takes_value(true))
+            .arg(Arg::with_name("prune-depth")
+                 .long("prune-depth")
+                 .help("Only index transactions in the last this many blocks for lookups by id and payments to an address. Older ones are found by scanning their blocks, slower but with less memory")
+                 .takes_value(true))
+            .arg(Arg::with_name("standardness")
+                 .long("standardness")
+                 .help("Only admit standard transactions to the mempool: no dust, nothing too big. Blocks aren't held to it"))
+            .arg(Arg::with_name("dust")
+                 .long("dust")
+                 .help("Smallest amount a standard transaction may send")
+                 .requires("standardness")
+                 .takes_value(true))
+            .arg(Arg::with_name("peer-port")
+                 .long("peer-port")
+                 .help("Serve the routes only peers call (headers, mempool sync, registering) on this port instead, and not on the public one")
+                 .takes_value(true))
+            .arg(Arg::with_name("peer-address")
+                 .long("peer-address")
+                 .help("Interface --peer-port listens on. Defaults to localhost, or 0.0.0.0 in a container, use an internal address")
+                 .requires("peer-port")
+                 .takes_value(true))
+            .arg(Arg::with_name("events-port")
+                 .long("events-port")
+                 .help("Stream changes to the chain and mempool as Server-Sent Events from GET /events/sse on this port. Route /events/sse there from a reverse proxy")
+                 .takes_value(true))
+            .arg(Arg::with_name("events-address")
+                 .long("events-address")
+                 .help("Interface --events-port listens on. Defaults to localhost, or 0.0.0.0 in a container")
+                 .requires("events-port")
+                 .takes_value(true))
+            .arg(Arg::with_name("cluster-id")
+                 .long("cluster-id")
+                 .help("Name of this replica among others sharing --data-dir. The one holding the lease mines, the rest serve reads")
+                 .takes_value(true))
+            .arg(Arg::with_name("address")
+                 .long("address")
+                 .help("Interface the public routes are served on, instead of Rocket.toml's. Defaults to 0.0.0.0 in a container")
+                 .takes_value(true))
+            .arg(Arg::with_name("port")
+                 .long("port")
+                 .help("Port the public routes are served on, instead of Rocket.toml's")
+                 .takes_value(true))
+            .arg(Arg::with_name("control-socket")
+                 .long("control-socket")
+                 .help("Unix socket to take operator commands on, see `ctl`. Only the user running the node can connect")
+                 .takes_value(true))
+            .subcommand(SubCommand::with_name("ctl")
+                 .about("Send an operator command to a running node over its --control-socket")
+                 .arg(Arg::with_name("socket")
+                      .long("socket")
+                      .help("The node's --control-socket")
+                      .required(true)
+                      .takes_value(true))
+                 .subcommand(SubCommand::with_name("shutdown")
+                      .about("Stop the node once any write in progress is done"))
+                 .subcommand(SubCommand::with_name("snapshot")
+                      .about("Write the chain to a file, a block a line, for `chain import`")
+                      .arg(Arg::with_name("path")
+                           .help("Where to write it, on the node's machine")
+                           .required(true)
+                           .index(1)))
+                 .subcommand(SubCommand::with_name("difficulty")
+                      .about("Change the difficulty new blocks are mined at")
+                      .arg(Arg::with_name("difficulty")
+                           .required(true)
+                           .index(1)))
+                 .subcommand(SubCommand::with_name("clear-mempool")
+                      .about("Drop every pending transaction")))
+            .subcommand(SubCommand::with_name("replay")
+                 .about("Rebuild a node from a journal written with --journal")
+                 .arg(Arg::with_name("journal")
+                      .help("The journal to replay")
+                      .required(true)
+                      .index(1)))
+            .subcommand(SubCommand::with_name("init")
+                 .about("Set up a fresh --data-dir with a peer's chain, checked block by block, and the peer to start from. Give --network and --hash as the node will be started with")
+                 .arg(Arg::with_name("from")
+                      .long("from")
+                      .help("The peer to download the chain from")
+                      .required(true)
+                      .takes_value(true)))
+            .subcommand(SubCommand::with_name("chain")
+                 .about("Work with the chain stored in --data-dir")
+                 .subcommand(SubCommand::with_name("export")
+                      .about("Write every block and transaction to stdout as flat records")
+                      .arg(Arg::with_name("format")
+                           .long("format")
+                           .help("Output format")
+                           .possible_values(&["jsonl", "csv", "blocks"])
+                           .default_value("jsonl")))
+                 .subcommand(SubCommand::with_name("verify")
+                      .about("Check every stored block from genesis and report the first invalid one"))
+                 .subcommand(SubCommand::with_name("compare")
+                      .about("Check two chains, each a file from `chain export --format blocks`, a saved /chain response or a node's url, and show where and how they fork. Exits with 1 if they differ")
+                      .arg(Arg::with_name("a")
+                           .help("The first chain, its blocks marked -")
+                           .required(true)
+                           .index(1))
+                      .arg(Arg::with_name("b")
+                           .help("The second chain, its blocks marked +")
+                           .required(true)
+                           .index(2))
+                      .arg(Arg::with_name("color")
+                           .long("color")
+                           .help("Color the report. auto does when writing to a terminal, unless NO_COLOR is set or TERM is dumb")
+                           .possible_values(&["auto", "always", "never"])
+                           .default_value("auto")))
+                 .subcommand(SubCommand::with_name("import")
+                      .about("Add the blocks in a file from `chain export --format blocks`, checking each. Blocks already stored are skipped, so it can be run again after an interruption")
+                      .arg(Arg::with_name("file")
+                           .help("The file of blocks")
+                           .required(true)
+                           .index(1))))
 }
 
 fn parse_args() -> Args {
-    debug!("Parsing args...");
-
-    let matches = App::new("learnnet blockchain")
-                          .version("0.1")
-                          .author("Steven P. <steven.pack.code@gmail.com>")
-                          .about("Learn the blockchain! Inspired by https://github.com/dvf/blockchain")                         
-                          .arg(Arg::with_name("difficulty")
-                               .short("d")
-                               .long("difficulty")
-                               .help("Proof of work difficulty. 3 would mean a hash starting with 000")
-                               .takes_value(true))                         
-                          .get_matches();
+    let matches = app().get_matches_from(args_with_env());
 
     let difficulty: u64 = matches.value_of("difficulty").unwrap_or("3").parse().expect("difficulty must be valid integer");
 
-    info!("using difficulty {}", difficulty);
+    let timeout: u64 = matches.value_of("timeout").unwrap_or("10").parse().expect("timeout must be valid integer");
+
+    let miner_address = matches.value_of("miner-address").unwrap_or(lib::blockchain::DEFAULT_MINER_ADDRESS);
+
+    let peers_per_round: usize = matches.value_of("peers-per-round")
+                                        .map(|count| count.parse().expect("peers per round must be valid integer"))
+                                        .unwrap_or(lib::peers::DEFAULT_PEERS_PER_ROUND);
+
+    let consensus_interval: Option<Duration> = matches.value_of("consensus-interval")
+                                                      .map(|secs| Duration::from_secs(secs.parse().expect("consensus interval must be valid integer")));
+
+    let mempool_sync_interval: Option<Duration> = matches.value_of("mempool-sync-interval")
+                                                         .map(|secs| Duration::from_secs(secs.parse().expect("mempool sync interval must be valid integer")));
+
+    let auto_mine_pending: Option<usize> = matches.value_of("auto-mine-pending")
+                                                  .map(|count| count.parse().expect("auto mine pending must be valid integer"));
+
+    let auto_mine_secs: Option<u64> = matches.value_of("auto-mine-secs")
+                                               .map(|secs| secs.parse().expect("auto mine secs must be valid integer"));
+
+    let max_pending_per_sender: usize = matches.value_of("max-pending-per-sender")
+                                               .map(|count| count.parse().expect("max pending per sender must be valid integer"))
+                                               .unwrap_or(web::DEFAULT_MAX_PENDING_PER_SENDER);
+
+    let defaults = Caps::default();
+    let caps = Caps {
+        mempool: matches.value_of("max-mempool-bytes")
+                        .map(|bytes| bytes.parse().expect("max mempool bytes must be valid integer"))
+                        .unwrap_or(defaults.mempool),
+        orphans: matches.value_of("max-orphan-bytes")
+                        .map(|bytes| bytes.parse().expect("max orphan bytes must be valid integer"))
+                        .unwrap_or(defaults.orphans),
+        peer_chain: matches.value_of("max-peer-chain-bytes")
+                           .map(|bytes| bytes.parse().expect("max peer chain bytes must be valid integer"))
+                           .unwrap_or(defaults.peer_chain)
+    };
+
+    let selection = Strategy::from_name(matches.value_of("selection").unwrap_or("fifo")).expect("validated by clap");
+
+    let max_block_transactions: Option<usize> = matches.value_of("max-block-transactions")
+                                                       .map(|count| count.parse().expect("max block transactions must be valid integer"));
+
+    let prune_depth: Option<usize> = matches.value_of("prune-depth")
+                                            .map(|depth| depth.parse().expect("prune depth must be valid integer"));
+
+    let standardness = if matches.is_present("standardness") {
+        let dust = matches.value_of("dust")
+                          .map(|dust| dust.parse().expect("dust must be valid integer"))
+                          .unwrap_or(lib::standard::DEFAULT_DUST);
+        Some(Standardness { dust: dust, ..Standardness::default() })
+    } else {
+        None
+    };
+
+    let container = in_container();
+    let bind_address = if container { CONTAINER_ADDRESS } else { "localhost" };
+
+    let public_address: Option<String> = matches.value_of("address")
+                                                .map(String::from)
+                                                .or_else(|| if container && env::var_os("ROCKET_ADDRESS").is_none() { Some(String::from(CONTAINER_ADDRESS)) } else { None });
+
+    let public_port: Option<u16> = matches.value_of("port").map(|port| port.parse().expect("port must be valid port"));
+
+    let peer_listener: Option<(String, u16)> = matches.value_of("peer-port")
+                                               .map(|port| (String::from(matches.value_of("peer-address").unwrap_or(bind_address)),
+                                                            port.parse().expect("peer port must be valid port")));
+
+    //where nodes on our network are likeliest to listen: where we do
+    let default_port: u16 = matches.value_of("default-port")
+                                   .map(|port| port.parse().expect("default port must be valid port"))
+                                   .or_else(|| peer_listener.as_ref().map(|&(_, port)| port))
+                                   .or(public_port)
+                                   .unwrap_or(lib::protocol::DEFAULT_PORT);
+
+    let events_listener: Option<(String, u16)> = matches.value_of("events-port")
+                                                 .map(|port| (String::from(matches.value_of("events-address").unwrap_or(bind_address)),
+                                                              port.parse().expect("events port must be valid port")));
+
+    let ctl = matches.subcommand_matches("ctl").map(|ctl| {
+        let command = match ctl.subcommand() {
+            ("shutdown", _) => web::control::Command::Shutdown,
+            ("snapshot", Some(snapshot)) => web::control::Command::Snapshot { path: PathBuf::from(snapshot.value_of("path").expect("required by clap")) },
+            ("difficulty", Some(difficulty)) => web::control::Command::Difficulty {
+                difficulty: difficulty.value_of("difficulty").expect("required by clap").parse().expect("difficulty must be valid integer")
+            },
+            ("clear-mempool", _) => web::control::Command::ClearMempool,
+            _ => panic!("ctl needs a command: shutdown, snapshot, difficulty or clear-mempool")
+        };
+        (PathBuf::from(ctl.value_of("socket").expect("required by clap")), command)
+    });
+
+    let hasher = HashAlgorithm::from_name(matches.value_of("hash").unwrap_or("sha256")).expect("validated by clap");
+
+    let compare = matches.subcommand_matches("chain").and_then(|chain| chain.subcommand_matches("compare"));
+    let color = match compare.and_then(|compare| compare.value_of("color")).unwrap_or("auto") {
+        "always" => true,
+        "never" => false,
+        _ => stdout_is_terminal() && env::var_os("NO_COLOR").is_none() && env::var("TERM").map(|term| term != "dumb").unwrap_or(false)
+    };
+    let compare = compare.map(|compare| (String::from(compare.value_of("a").expect("required by clap")),
+                                          String::from(compare.value_of("b").expect("required by clap"))));
+
+    let export = matches.subcommand_matches("chain")
+                        .and_then(|chain| chain.subcommand_matches("export"))
+                        .map(|export| Format::from_name(export.value_of("format").unwrap_or("jsonl")).expect("validated by clap"));
 
     Args {
-        difficulty: difficulty
+        difficulty: difficulty,
+        timeout: Duration::from_secs(timeout),
+        miner_address: String::from(miner_address),
+        hasher: hasher,
+        admin_token: matches.value_of("admin-token").map(String::from),
+        faucet_token: matches.value_of("faucet-token").map(String::from),
+        data_dir: matches.value_of("data-dir").map(PathBuf::from),
+        journal: matches.value_of("journal").map(PathBuf::from),
+        genesis: matches.value_of("genesis").map(PathBuf::from),
+        deployments: matches.value_of("deployments").map(PathBuf::from),
+        lenient_content_type: matches.is_present("lenient-content-type"),
+        tutorial_schema: matches.is_present("tutorial-schema"),
+        network: String::from(matches.value_of("network").unwrap_or(lib::protocol::DEFAULT_NETWORK)),
+        default_port: default_port,
+        peers_per_round: peers_per_round,
+        consensus_interval: consensus_interval,
+        mempool_sync_interval: mempool_sync_interval,
+        auto_mine_pending: auto_mine_pending,
+        auto_mine_secs: auto_mine_secs,
+        max_pending_per_sender: max_pending_per_sender,
+        caps: caps,
+        selection: selection,
+        max_block_transactions: max_block_transactions,
+        prune_depth: prune_depth,
+        standardness: standardness,
+        peer_listener: peer_listener,
+        events_listener: events_listener,
+        cluster_id: matches.value_of("cluster-id").map(String::from),
+        seeds: matches.values_of("seed").map(|seeds| seeds.map(String::from).collect()).unwrap_or_default(),
+        advertised_url: matches.value_of("advertised-url")
+                               .map(|url| lib::peers::parse_node(url, default_port).unwrap_or_else(|e| panic!("Invalid advertised url. {}", e))),
+        upnp: matches.is_present("upnp"),
+        export: export,
+        verify: matches.subcommand_matches("chain").map(|chain| chain.is_present("verify")).unwrap_or(false),
+        import: matches.subcommand_matches("chain")
+                       .and_then(|chain| chain.subcommand_matches("import"))
+                       .and_then(|import| import.value_of("file"))
+                       .map(PathBuf::from),
+        compare: compare,
+        color: color,
+        replay: matches.subcommand_matches("replay").and_then(|replay| replay.value_of("journal")).map(PathBuf::from),
+        init: matches.subcommand_matches("init")
+                     .and_then(|init| init.value_of("from"))
+                     .map(|peer| lib::peers::parse_node(peer, default_port).unwrap_or_else(|e| panic!("Invalid peer to init from. {}", e))),
+        control_socket: matches.value_of("control-socket").map(PathBuf::from),
+        public_address: public_address,
+        public_port: public_port,
+        ctl: ctl
+    }
+}
+
+fn log_args(args: &Args) {
+    info!("using difficulty {}", args.difficulty);
+    info!("using timeout {}s", args.timeout.as_secs());
+    info!("using miner address {}", args.miner_address);
+    info!("using hash {:?}", args.hasher);
+    info!("using network {} at protocol version {}", args.network, lib::protocol::PROTOCOL_VERSION);
+    info!("using {} peers per consensus round", args.peers_per_round);
+    info!("using port {} for peers given by host alone", args.default_port);
+    if let Some(ref url) = args.advertised_url {
+        info!("using advertised url {}", url);
+    }
+    if args.upnp {
+        info!("using a UPnP port mapping");
+    }
+    if let Some(interval) = args.consensus_interval {
+        info!("using a consensus round every {}s", interval.as_secs());
+    }
+    if let Some(interval) = args.mempool_sync_interval {
+        info!("using mempool reconciliation every {}s", interval.as_secs());
+    }
+    if let Some(count) = args.auto_mine_pending {
+        info!("using auto-mining at {} pending transactions", count);
+    }
+    if let Some(secs) = args.auto_mine_secs {
+        info!("using auto-mining {}s after the last block", secs);
+    }
+    info!("using at most {} pending transactions per sender", args.max_pending_per_sender);
+    if let Some(max) = args.max_block_transactions {
+        info!("using at most {} transactions a block, picked {:?}", max, args.selection);
+    }
+    if let Some(depth) = args.prune_depth {
+        info!("using transaction indexes for the last {} blocks", depth);
+    }
+    info!("using at most {} bytes of pending transactions, {} bytes of orphans and {} byte peer chains",
+          args.caps.mempool, args.caps.orphans, args.caps.peer_chain);
+    if let Some(ref standardness) = args.standardness {
+        info!("using standardness, admitting at least {} and at most {} bytes", standardness.dust, standardness.max_size);
     }
-}
\ No newline at end of file
+    if let Some(ref address) = args.public_address {
+        info!("using public address {}", address);
+    }
+    if let Some(port) = args.public_port {
+        info!("using public port {}", port);
+    }
+    if let Some((ref address, port)) = args.peer_listener {
+        info!("using a separate peer listener on {}:{}", address, port);
+    }
+    if let Some((ref address, port)) = args.events_listener {
+        info!("using an events listener on {}:{}", address, port);
+    }
+    if let Some(ref path) = args.control_socket {
+        info!("using control socket {}", path.display());
+    }
+    if let Some(ref id) = args.cluster_id {
+        info!("using cluster id {}, leases last {}s", id, DEFAULT_LEASE_SECS);
+    }
+    match args.data_dir {
+        Some(ref dir) => info!("using data dir {}", dir.display()),
+        None => info!("using no data dir. The chain is kept in memory only")
+    }
+    if let Some(ref path) = args.journal {
+        info!("using journal {}", path.display());
+    }
+    if args.faucet_token.is_some() {
+        info!("using a faucet of up to {} coins per address every {}s", web::faucet::MAX_FAUCET_AMOUNT, web::faucet::FAUCET_INTERVAL_SECS);
+    }
+    if let Some(ref path) = args.genesis {
+        info!("using genesis file {}", path.display());
+    }
+    if let Some(ref path) = args.deployments {
+        info!("using deployments file {}", path.display());
+    }
+    if args.lenient_content_type {
+        info!("using lenient Content-Type for transactions");
+    }
+    if args.tutorial_schema {
+        info!("using the Python tutorial's json on /chain and /mine");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{app, env_args};
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| String::from(*value)).collect()
+    }
+
+    #[test]
+    fn env_args_from_vars() {
+        let vars = vec![
+            ("LEARNNET_UPNP", "yes"), ("LEARNNET_TUTORIAL_SCHEMA", "0"), ("LEARNNET_DIFFICULTY", "4"),
+            ("LEARNNET_PEERS", "http://node1:8000, node2"), ("LEARNNET_TIMEOUT", "5"),
+            ("LEARNNET_FROM", "http://node1:8000"), ("LEARNNET_HELP", "1")
+        ];
+        let vars = vars.into_iter().map(|(name, value)| (String::from(name), String::from(value))).collect();
+        let (args, ignored) = env_args(vars, strings(&["learnnet", "-t", "9", "chain", "verify"]));
+        assert_eq!(args, strings(&["learnnet", "--difficulty=4", "--seed=http://node1:8000", "--seed=node2", "--upnp", "-t", "9", "chain", "verify"]),
+                   "the command line's timeout wins, and a flag set to 0 is off");
+        assert_eq!(ignored, strings(&["LEARNNET_FROM", "LEARNNET_HELP"]), "init's option, and not an option at all");
+        assert!(app().get_matches_from_safe(args).is_ok());
+    }
+}
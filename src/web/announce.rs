@@ -0,0 +1,85 @@
+use lib::consensus::Consensus;
+use lib::error::Error;
+use lib::upnp::{self, DEFAULT_LEASE_SECS};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use url::Url;
+use web::BlockchainState;
+
+//A node behind NAT or a reverse proxy can't be reached where it listens, so it
+//tells its peers where it can be: `advertised_url`, which they register like any
+//other node. It's sent once on startup, to every peer we know, and never
+//learnt from peers, who only see where requests come from. On a home router it
+//can come from UPnP instead, which forwards our port and tells us the router's
+//address.
+
+///
+/// Ask every known peer to register us at `ours`, one at a time, without holding
+/// the peers lock while asking
+///
+/// returns: How many registered us
+pub fn announce(state: &BlockchainState, ours: &Url) -> Result<usize, Error> {
+    let nodes: Vec<Url> = state.read_peers()?.nodes().cloned().collect();
+    let mut registered = 0;
+    for node in nodes {
+        let sent = match Consensus::announce(node.as_str(), ours, state.timeout, &state.network) {
+            Ok(sent) => {
+                registered += 1;
+                sent
+            },
+            Err(e) => {
+                warn!("Couldn't register with {} as {}. {}", node, ours, e);
+                0
+            }
+        };
+        state.write_peers()?.exchanged(&node, sent, 0);
+    }
+    Ok(registered)
+}
+
+///
+/// Forward `port` on the router with UPnP, renewing the mapping in the
+/// background for as long as the process runs
+///
+/// returns: The url the router forwards to us, to advertise
+pub fn map_port(port: u16, timeout: Duration) -> Result<Url, Error> {
+    let (gateway, url) = upnp::open(port, DEFAULT_LEASE_SECS, timeout)?;
+    thread::spawn(move || loop {
+        thread::sleep(Duration::from_secs(DEFAULT_LEASE_SECS as u64 / 2));
+        if let Err(e) = gateway.map_port(port, DEFAULT_LEASE_SECS) {
+            warn!("Couldn't renew the UPnP mapping of port {}. {}", port, e);
+        }
+    });
+    Ok(url)
+}
+
+///
+/// Announce `state.advertised_url` in the background, if there is one
+///
+pub fn start(state: Arc<BlockchainState>) {
+    let ours = match state.advertised_url.clone() {
+        Some(ours) => ours,
+        None => return
+    };
+    thread::spawn(move || match announce(&state, &ours) {
+        Ok(registered) => info!("Registered with {} peers as {}", registered, ours),
+        Err(e) => warn!("Couldn't register with peers as {}. {}", ours, e)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use url::Url;
+    use web::BlockchainState;
+    use web::announce::*;
+
+    #[test]
+    fn unreachable_peers() {
+        let state = BlockchainState::new_with(1, Duration::from_secs(1));
+        state.write_peers().expect("lock").register(Url::parse("http://localhost:1").expect("valid url"));
+        let ours = Url::parse("http://node.example.com:8000").expect("valid url");
+        assert_eq!(announce(&state, &ours).expect("asked"), 0, "nothing listening there");
+    }
+}
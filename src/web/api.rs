@@ -1,25 +1,57 @@
 use lib::blockchain::*;
 use lib::transaction::*;
 use lib::consensus::*;
+use lib::error::{Error, Result};
+use lib::export::{self, Format};
+use lib::memory;
+use lib::peers::{parse_node, Peers};
+use lib::standard::Standardness;
+use lib::validation;
+use lib::qr::QrCode;
+use lib::rounds::PeerReport;
 use url::{Url};
+use url::percent_encoding::{utf8_percent_encode, PATH_SEGMENT_ENCODE_SET};
+use web::compat;
+use web::faucet::FAUCET_ADDRESS;
 use web::types::*;
+use std::cmp;
+use std::collections::BTreeMap;
 
 ///
 /// Mine a new block
 /// 
-pub fn mine(b: &mut Blockchain) -> Result<MineResult, String> {
-    match b.mine() {
-        Ok(mined_block) => {
-            Ok(MineResult {
-                message: "New Block Forged".into(),
-                index: mined_block.index,
-                transactions: mined_block.transactions.clone(),
-                proof: mined_block.proof,
-                previous_hash: mined_block.previous_hash.clone()
-            })
-        },
-        Err(e) => Err(format!("Failed to mind block. {:?}", e))
-    }    
+pub fn mine(b: &mut Blockchain) -> Result<MineResult> {
+    let mined_block = b.mine()?;
+    Ok(MineResult {
+        message: "New Block Forged".into(),
+        index: mined_block.header.index,
+        transactions: mined_block.transactions.clone(),
+        reward: mined_block.reward().cloned(),
+        nonce: mined_block.header.nonce,
+        previous_hash: mined_block.header.previous_hash.clone(),
+        merkle_root: mined_block.header.merkle_root.clone(),
+        state_root: mined_block.header.state_root.clone(),
+        size: mined_block.size()
+    })
+}
+
+///
+/// What `mine` would mine now, without mining it
+/// 
+pub fn mine_preview(b: &Blockchain) -> MinePreview {
+    let block = b.preview();
+    MinePreview {
+        index: block.header.index,
+        previous_hash: block.header.previous_hash.clone(),
+        difficulty: block.header.difficulty,
+        version: block.header.version,
+        expired: b.current_transactions().iter().filter(|txn| txn.is_expired(block.header.index)).count(),
+        reward: block.reward().cloned(),
+        merkle_root: block.header.merkle_root.clone(),
+        state_root: block.header.state_root.clone(),
+        size: block.size(),
+        transactions: block.transactions
+    }
 }
 
 ///
@@ -27,35 +59,407 @@ pub fn mine(b: &mut Blockchain) -> Result<MineResult, String> {
 /// 
 /// # Returns the index of the next block.
 /// 
-pub fn new_transaction(transaction: &Transaction, b: &mut Blockchain) -> String {   
-    let index = b.new_transaction(transaction.clone());
-    format!("Transaction added at block {}", index)
+/// A sender may have at most `max_pending` transactions waiting, so one wallet
+/// can't fill the pool, and the pool holds at most the blockchain's
+/// `caps().mempool` bytes. Resubmitting one that's already pending is fine.
+/// Anything `validation::admission` fails is refused, sender "0" included
+/// 
+pub fn new_transaction(transaction: &Transaction, max_pending: usize, b: &mut Blockchain) -> Result<String> {
+    let index = add_transaction(transaction, max_pending, b)?;
+    Ok(format!("Transaction added at block {}", index))
+}
+
+///
+/// `new_transaction`, returning just the index of the next block
+/// 
+pub fn add_transaction(transaction: &Transaction, max_pending: usize, b: &mut Blockchain) -> Result<usize> {
+    let checks = validation::admission(transaction, b);
+    if !validation::passed(&checks) {
+        return Err(Error::Validation(format!("Invalid transaction. {}", validation::reasons(&checks))));
+    }
+    let pending = b.current_transactions().iter().filter(|txn| txn.sender == transaction.sender).count();
+    if pending >= max_pending && !b.current_transactions().contains(transaction) {
+        return Err(Error::Limit(format!("{} already has {} pending transactions, the most allowed", transaction.sender, pending)));
+    }
+    let full = b.usage().mempool + memory::transaction_bytes(transaction) > b.caps().mempool;
+    if full && !b.current_transactions().contains(transaction) {
+        return Err(Error::Limit(format!("The mempool is full, it holds at most {} bytes", b.caps().mempool)));
+    }
+    Ok(b.new_transaction(transaction.clone()))
+}
+
+///
+/// Add faucet coins for `request.address`, once the faucet has granted them
+/// 
+pub fn faucet(request: &FaucetRequest, b: &mut Blockchain) -> FaucetResult {
+    let block = b.new_transaction(Transaction::new(FAUCET_ADDRESS.into(), request.address.clone(), request.amount));
+    info!("faucet sent {} to {}", request.amount, request.address);
+    FaucetResult {
+        message: format!("{} coins will be added at block {}", request.amount, block),
+        address: request.address.clone(),
+        amount: request.amount,
+        block: block
+    }
+}
+
+///
+/// Validate a transaction without adding it, so wallets can check before submitting.
+/// With a `standardness` policy, whether this node would admit it is checked too.
+/// It's valid exactly when `add_transaction` and the policy would take it
+/// 
+pub fn validate_transaction(transaction: &Transaction, standardness: Option<&Standardness>, b: &Blockchain) -> ValidationResponse {
+    let mut checks = validation::admission(transaction, b);
+    if let Some(standardness) = standardness {
+        checks.push(standardness.check(transaction));
+    }
+    ValidationResponse {
+        valid: validation::passed(&checks),
+        checks: checks,
+        advice: validation::advice(transaction, b)
+    }
+}
+
+///
+/// Change who receives the reward for subsequently mined blocks
+/// 
+pub fn miner_address(miner_address: &MinerAddress, b: &mut Blockchain) -> Result<MinerAddressResponse> {
+    let address = miner_address.miner_address.trim();
+    if address.is_empty() || address == "0" {
+        return Err(Error::Validation(format!("Invalid miner address '{}'", miner_address.miner_address)));
+    }
+    b.set_miner_address(address.into());
+    Ok(MinerAddressResponse {
+        message: String::from("Miner address updated"),
+        miner_address: b.miner_address().clone()
+    })
+}
+
+///
+/// Change the difficulty subsequently mined blocks are mined at
+/// 
+pub fn difficulty(difficulty: &Difficulty, b: &mut Blockchain) -> Result<DifficultyResponse> {
+    b.set_difficulty(difficulty.difficulty)?;
+    info!("difficulty changed to {}", difficulty.difficulty);
+    Ok(DifficultyResponse {
+        message: String::from("Difficulty updated"),
+        difficulty: b.difficulty()
+    })
+}
+
+///
+/// The tip of the chain
+/// 
+pub fn head(b: &Blockchain) -> HeadResult {
+    HeadResult {
+        header: &b.last_block().header,
+        hash: b.hash_last_block(),
+        length: b.len(),
+        work: b.work()
+    }
+}
+
+///
+/// The height to answer at, which has to be on the chain
+/// 
+fn checked_height(at_height: Option<usize>, b: &Blockchain) -> Result<usize> {
+    let height = at_height.unwrap_or(b.len());
+    if height == 0 || height > b.len() {
+        return Err(Error::Validation(format!("Height {} is not on the chain. The tip is {}", height, b.len())));
+    }
+    Ok(height)
+}
+
+///
+/// Balance of `address`, at the tip or just after block `at_height`
+/// 
+pub fn balance(address: &str, at_height: Option<usize>, b: &Blockchain) -> Result<BalanceResult> {
+    let height = checked_height(at_height, b)?;
+    let state = b.state_at(height).expect("validated height");
+    Ok(BalanceResult {
+        address: address.into(),
+        balance: state.balance(address),
+        height: height
+    })
+}
+
+///
+/// Payments to `address` in blocks above `since_height`, from the index of
+/// recipients rather than by scanning blocks, unless they're pruned from it
+/// 
+pub fn incoming<'a>(address: &str, since_height: Option<usize>, b: &'a Blockchain) -> Result<IncomingResult<'a>> {
+    let since_height = since_height.unwrap_or(0);
+    if since_height > b.len() {
+        return Err(Error::Validation(format!("Height {} is not on the chain. The tip is {}", since_height, b.len())));
+    }
+    let payments = b.incoming(address, since_height).into_iter().map(|(block, txn)| PaymentResult {
+        id: b.transaction_id(txn),
+        transaction: txn,
+        block: block.header.index
+    }).collect();
+    Ok(IncomingResult {
+        address: address.into(),
+        since_height: since_height,
+        height: b.len(),
+        payments: payments
+    })
+}
+
+///
+/// Scheme of payment request uris
+/// 
+pub const PAYMENT_URI_SCHEME: &'static str = "learnnet";
+
+///
+/// Pixels per module of a payment request's QR code
+/// 
+const QR_SCALE: usize = 8;
+
+///
+/// A request for `amount` to `to`, as a uri. The address is percent encoded, so
+/// it can hold spaces or a '?'
+/// 
+pub fn payment_request(to: &str, amount: Amount) -> Result<PaymentRequestResult> {
+    if amount <= 0 {
+        return Err(Error::Validation(format!("Amount {} is not positive", amount)));
+    }
+    Ok(PaymentRequestResult {
+        uri: format!("{}:{}?amount={}", PAYMENT_URI_SCHEME, utf8_percent_encode(to, PATH_SEGMENT_ENCODE_SET), amount),
+        to: to.into(),
+        amount: amount
+    })
+}
+
+///
+/// The payment request's uri as a QR code PNG
+/// 
+pub fn payment_request_qr(to: &str, amount: Amount) -> Result<Vec<u8>> {
+    let request = payment_request(to, amount)?;
+    Ok(QrCode::encode(request.uri.as_bytes())?.png(QR_SCALE))
+}
+
+///
+/// The block on our chain with header hash `hash`
+/// 
+pub fn block<'a>(hash: &str, b: &'a Blockchain) -> Option<BlockResult<'a>> {
+    b.block_by_hash(hash).map(|block| BlockResult {
+        block: block,
+        hash: hash.into(),
+        size: block.size()
+    })
+}
+
+///
+/// The transaction with id `id`, confirmed or pending
+/// 
+pub fn transaction<'a>(id: &str, b: &'a Blockchain) -> Option<TransactionResult<'a>> {
+    b.find_transaction(id).map(|(txn, block)| TransactionResult {
+        id: id.into(),
+        transaction: txn,
+        block: block.map(|block| block.header.index),
+        pending: block.is_none(),
+        size: txn.size()
+    })
+}
+
+///
+/// The receipt for a transaction that's in a block. None if it's unknown or only
+/// pending
+/// 
+pub fn receipt<'a>(id: &str, b: &'a Blockchain) -> Option<ReceiptResult<'a>> {
+    match b.find_transaction(id) {
+        Some((txn, Some(block))) => Some(receipt_in(id.into(), txn, block, b)),
+        _ => None
+    }
+}
+
+//`block` must be on our chain and hold `txn`
+fn receipt_in<'a>(id: String, txn: &'a Transaction, block: &'a Block, b: &Blockchain) -> ReceiptResult<'a> {
+    ReceiptResult {
+        id: id,
+        transaction: txn,
+        header: &block.header,
+        hash: b.hash_at(block.header.index).expect("invariant: the block is on our chain").into(),
+        proof: b.merkle_proof(block, txn).expect("invariant: the block holds the transaction"),
+        confirmations: b.len() - block.header.index + 1
+    }
+}
+
+///
+/// The balance of `address` at block `at_height` (the tip if None), with the
+/// receipt of every transaction that moved it, so a light client can check
+/// each is in a block and do the arithmetic itself. Scans every block up to it
+/// 
+pub fn balance_proof<'a>(address: &str, at_height: Option<usize>, b: &'a Blockchain) -> Result<BalanceProofResult<'a>> {
+    let height = checked_height(at_height, b)?;
+    let state = b.state_at(height).expect("validated height");
+    let receipts = b.chain()
+                    .iter()
+                    .take(height)
+                    .flat_map(|block| block.transactions.iter()
+                                           .filter(move |txn| txn.recipient == address || (txn.sender == address && !txn.is_reward()))
+                                           .map(move |txn| (block, txn)))
+                    .map(|(block, txn)| receipt_in(b.transaction_id(txn), txn, block, b))
+                    .collect();
+    Ok(BalanceProofResult {
+        address: address.into(),
+        balance: state.balance(address),
+        height: height,
+        hash: b.hash_at(height).expect("validated height").into(),
+        receipts: receipts
+    })
+}
+
+///
+/// Statistics about the chain up to the tip, or up to block `at_height`
+/// 
+pub fn stats(at_height: Option<usize>, b: &Blockchain) -> Result<StatsResult> {
+    let height = checked_height(at_height, b)?;
+    let state = b.state_at(height).expect("validated height");
+    let block = b.block_at(height).expect("validated height");
+    let (rewards, transactions): (Vec<&Transaction>, Vec<&Transaction>) = b.chain()
+                                                                           .iter()
+                                                                           .take(height)
+                                                                           .flat_map(|block| block.transactions.iter())
+                                                                           .partition(|txn| txn.is_reward());
+    Ok(StatsResult {
+        height: height,
+        tip: b.hash_at(height).expect("validated height").into(),
+        timestamp: block.header.timestamp,
+        difficulty: block.header.difficulty,
+        transactions: transactions.len(),
+        rewards: rewards.len(),
+        addresses: state.balances().len(),
+        supply: state.balances().values().fold(0, |supply: Amount, balance| supply.saturating_add(*balance)),
+        bytes: b.chain().iter().take(height).map(Block::size).sum()
+    })
+}
+
+///
+/// How many of the latest blocks /stats/mining averages over
+/// 
+pub const MINING_STATS_WINDOW: usize = 20;
+
+///
+/// Block times and hash rate over the last `MINING_STATS_WINDOW` blocks, and who
+/// mined how many. The genesis block wasn't mined, so it's left out
+/// 
+pub fn mining_stats(b: &Blockchain) -> MiningStatsResult {
+    let mined: Vec<&Block> = b.chain().iter().skip(1).collect();
+    let window = &mined[mined.len().saturating_sub(MINING_STATS_WINDOW + 1)..];
+    let (blocks, block_secs, hash_rate) = match (window.first(), window.last()) {
+        (Some(first), Some(last)) if window.len() > 1 => {
+            let intervals = window.len() - 1;
+            let elapsed = (last.header.timestamp - first.header.timestamp) as f64;
+            let hashes: f64 = window[1..].iter().map(|block| expected_hashes(block.header.difficulty)).sum();
+            (intervals, Some(elapsed / intervals as f64), if elapsed > 0.0 { Some(hashes / elapsed) } else { None })
+        },
+        _ => (0, None, None)
+    };
+
+    let mut counts: BTreeMap<&Address, usize> = BTreeMap::new();
+    for reward in mined.iter().filter_map(|block| block.reward()) {
+        *counts.entry(&reward.recipient).or_insert(0) += 1;
+    }
+    let mut miners: Vec<MinerStats> = counts.into_iter().map(|(address, blocks)| MinerStats { address: address.clone(), blocks: blocks }).collect();
+    miners.sort_by(|a, b| b.blocks.cmp(&a.blocks).then_with(|| a.address.cmp(&b.address)));
+
+    MiningStatsResult {
+        height: b.len(),
+        blocks: blocks,
+        block_secs: block_secs,
+        hash_rate: hash_rate,
+        miners: miners
+    }
+}
+
+//each leading '0' of a base64 hash is a 1 in 64 chance. Capped before f64 overflows
+fn expected_hashes(difficulty: u64) -> f64 {
+    64f64.powi(cmp::min(difficulty, 170) as i32)
+}
+
+///
+/// Every block and transaction as flat records
+/// 
+pub fn export(format: Format, b: &Blockchain) -> Result<String> {
+    let mut out = Vec::new();
+    export::export(b, format, &mut out)?;
+    String::from_utf8(out).map_err(|e| Error::Serialization(e.to_string()))
 }
 
 ///
-/// Return the whole blockchain (but not any pending transactions)
+/// The first block above height `after`, if there is one yet
 /// 
-pub fn chain(b: &Blockchain) -> ChainResult {    
-    let chain = b.chain();
-    ChainResult {
-        chain: chain,
-        length: chain.len()
+pub fn next_block(after: usize, b: &Blockchain) -> NextBlockResult {
+    NextBlockResult {
+        block: b.chain().iter().find(|block| block.header.index == after + 1),
+        length: b.len()
     }
 }
 
 ///
-/// Add a new node to be called during conensus (conflict resolution)
+/// Return just the block headers, for syncing without transactions
 /// 
-pub fn register_node(node_list: &NodeList, b: &mut Blockchain) -> Result<RegisterNodeResponse, String> {
+pub fn headers(b: &Blockchain) -> HeadersResult {
+    let headers: Vec<&BlockHeader> = b.chain().iter().map(|block| &block.header).collect();
+    HeadersResult {
+        length: headers.len(),
+        headers: headers
+    }
+}
+
+///
+/// How our chain compares with `peer`'s, which sent the headers `theirs`
+/// 
+pub fn chain_diff(peer: &str, theirs: &[BlockHeader], b: &Blockchain) -> DiffResult {
+    let diff = Consensus::diff(b, theirs);
+    DiffResult {
+        peer: peer.into(),
+        height: b.len(),
+        peer_height: theirs.len(),
+        common_height: diff.common_height,
+        ours: diff.ours,
+        theirs: diff.theirs
+    }
+}
+
+///
+/// Replace our chain with `chain` from `peer`, longer or not, as long as it's
+/// valid. For getting a node off a bad fork
+/// 
+pub fn adopt(peer: &str, chain: Chain, b: &mut Blockchain) -> Result<AdoptResult> {
+    if chain.is_empty() {
+        return Err(Error::Validation(format!("{} sent an empty chain", peer)));
+    }
+    let verification = b.verify(&chain);
+    if let Some(error) = verification.error {
+        return Err(Error::Validation(format!("{} sent an invalid chain, {}", peer, error)));
+    }
+    let previous_height = b.len();
+    b.replace(chain);
+    warn!("Adopted the chain of {} blocks from {}, replacing ours of {}", b.len(), peer, previous_height);
+    Ok(AdoptResult {
+        message: String::from("Our chain was replaced"),
+        peer: peer.into(),
+        previous_height: previous_height,
+        height: b.len()
+    })
+}
+
+///
+/// Add a new node to be called during conensus (conflict resolution). `ours`,
+/// where nodes reach us, is never added: a node that learnt it may pass it back.
+/// Nodes given by host alone are on `default_port`
+/// 
+pub fn register_node(node_list: &NodeList, default_port: u16, ours: Option<&Url>, peers: &mut Peers) -> Result<RegisterNodeResponse> {
    
     let mut node_urls = Vec::<Url>::with_capacity(node_list.nodes.len());
 
-    //Validate - all or nothing
+    //Validate - all or nothing. Host names and IPv6 addresses (in brackets) are fine
     for node in &node_list.nodes {
-        let parse_result = Url::parse(node);
-        if parse_result.is_err() {
-            warn!("Failed to parse {} {:?}", node, parse_result.err());
-            return Err(String::from("Failed to parse at least one node. All nodes must be valid"));
+        let parse_result = parse_node(node, default_port);
+        if let Err(ref e) = parse_result {
+            warn!("Failed to parse {} {}", node, e);
+            return Err(Error::Validation(String::from("Failed to parse at least one node. All nodes must be valid")));
         }
         let url = parse_result.expect("validated");
         node_urls.push(url);
@@ -63,27 +467,136 @@ pub fn register_node(node_list: &NodeList, b: &mut Blockchain) -> Result<Registe
 
     //Add
     for node_url in node_urls {
-        b.register_node(node_url);
+        if Some(&node_url) == ours {
+            debug!("Not registering {}, that's us", node_url);
+            continue;
+        }
+        peers.register(node_url);
     }      
 
     Ok(RegisterNodeResponse {
         message: String::from("New nodes have been added"),
-        total_nodes: b.nodes().len(),
+        total_nodes: peers.len(),
     })
 }
 
 ///
-/// Determine which node has the longest blockchain, and replace with that
-/// if it's not ours
+/// What we know about the node at `id`, its host and port, at `now`
+/// 
+pub fn node_stats<'a>(id: &str, peers: &'a Peers, now: i64) -> Option<NodeStatsResponse<'a>> {
+    let node = peers.nodes().find(|node| compat::netloc(node) == id)?;
+    let info = peers.info(node)?;
+    Some(NodeStatsResponse {
+        node: node.as_str(),
+        last_seen: info.last_seen,
+        failures: info.failures,
+        good: info.is_good(),
+        latency_ms: info.latency_ms,
+        height: info.height,
+        banned: info.is_banned(now),
+        stats: &info.stats
+    })
+}
+
+///
+/// Up to `count` of the registered nodes to call during consensus, most work
+/// and fastest first, with the addresses they last resolved to. None banned at `now`
+/// 
+pub fn nodes(peers: &Peers, count: usize, seed: u64, now: i64) -> Vec<Candidate> {
+    let mut nodes = peers.sample(count, seed, now);
+    peers.prioritize(&mut nodes);
+    nodes.into_iter()
+         .map(|node| Candidate {
+             work: peers.info(&node).and_then(|info| info.work),
+             addresses: peers.addresses(&node),
+             node: node.into_string()
+         })
+         .collect()
+}
+
+///
+/// Update each peer's reputation, latency, height and work from how it did in a
+/// consensus round, and return the chains we got
+/// 
+/// The chains come back in the order they were fetched in, so they line up
+/// with the round's reports
+/// 
+pub fn record_round(results: Vec<Fetched>, timestamp: i64, peers: &mut Peers) -> Vec<Chain> {
+    let mut chains = Vec::with_capacity(results.len());
+    for fetched in results {
+        let url = Url::parse(&fetched.node).ok();
+        if let Some(ref url) = url {
+            peers.exchanged(url, 0, fetched.bytes);
+        }
+        match fetched.result {
+            Ok(chain) => {
+                let latency = fetched.latency;
+                if let Some(ref url) = url {
+                    peers.seen(url, timestamp);
+                    peers.measured(url, latency.as_secs() * 1000 + (latency.subsec_nanos() / 1_000_000) as u64, chain.len(), chain_work(&chain));
+                }
+                chains.push(chain);
+            },
+            Err(_) => if let Some(ref url) = url {
+                peers.failed(url)
+            }
+        }
+    }
+    chains
+}
+
+///
+/// Mark the reports of the nodes whose chains `consensus` found invalid.
+/// `invalid` counts only the nodes that sent a chain, like `record_round`'s chains
+/// 
+pub fn mark_invalid(reports: &mut [PeerReport], invalid: &[usize]) {
+    let sent = reports.iter_mut().filter(|report| report.height.is_some());
+    for (i, report) in sent.enumerate() {
+        if invalid.contains(&i) {
+            report.outcome = Some(Outcome::Invalid);
+            report.error = Some(format!("Sent an invalid chain of {} blocks", report.height.unwrap_or(0)));
+        }
+    }
+}
+
+///
+/// Mark the report of the node whose chain `consensus` adopted, the only one
+/// it validated, and say why the other chains sent weren't checked, against our
+/// chain proving `work` before the round. `adopted` counts like
+/// `mark_invalid`'s `invalid`
+/// 
+pub fn mark_adopted(reports: &mut [PeerReport], adopted: Option<usize>, work: u64) {
+    let sent = reports.iter_mut().filter(|report| report.height.is_some());
+    for (i, report) in sent.enumerate() {
+        if adopted == Some(i) {
+            report.validated = true;
+            report.adopted = true;
+        } else if report.error.is_none() {
+            let (length, theirs) = (report.height.unwrap_or(0), report.work.unwrap_or(0));
+            report.error = Some(if theirs <= work {
+                format!("Sent a chain of {} blocks proving {} work, no more than ours of {}, so it wasn't checked", length, theirs, work)
+            } else {
+                format!("Sent a chain of {} blocks, not checked once another peer's was adopted", length)
+            });
+        }
+    }
+}
+
+///
+/// Determine which node's valid blockchain proves the most work, and replace
+/// ours with that if it's not ours
 /// 
-pub fn consensus(b: &mut Blockchain) -> ConsensusReponse {
+pub fn consensus(neighbour_chains: Vec<Chain>, b: &mut Blockchain) -> ConsensusReponse {
 
-    let replaced = Consensus::resolve_conflicts(b);
-    if replaced {
+    let (adopted, invalid) = Consensus::adopt(b, neighbour_chains);
+    if adopted.is_some() {
         ConsensusReponse {
             message: String::from("Our chain was replaced"),
             chain: None,
-            new_chain: Some(b.chain())
+            new_chain: Some(b.chain()),
+            adopted: adopted,
+            invalid: invalid,
+            peers: Vec::new()
         }
     }
     else
@@ -91,15 +604,27 @@ pub fn consensus(b: &mut Blockchain) -> ConsensusReponse {
          ConsensusReponse {
             message: String::from("Our chain is authoritative"),
             chain: Some(b.chain()),
-            new_chain: None
+            new_chain: None,
+            adopted: adopted,
+            invalid: invalid,
+            peers: Vec::new()
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use lib::blockchain::Blockchain;
+    use lib::blockchain::{Block, Blockchain, Chain, DEFAULT_MINER_ADDRESS};
+    use lib::consensus::{Candidate, Fetched, Outcome};
+    use lib::error::Error;
+    use lib::memory::{self, Caps};
+    use lib::peers::Peers;
+    use lib::rounds::PeerReport;
+    use lib::transaction::Transaction;
+    use std::time::Duration;
+    use url::Url;
     use web::api;
+    use web::types::{MinerAddress, MinerStats};
 
     #[test]
     fn mine() {
@@ -108,4 +633,187 @@ mod tests {
         assert!(result.is_ok(), format!("Failed to mine {:?}", result));
         println!("mine response: {:?}", result.unwrap());
     }
+
+    #[test]
+    fn mine_preview() {
+        let mut blockchain = Blockchain::new_with(1);
+        blockchain.new_transaction(Transaction::new("a".into(), "b".into(), 5));
+        blockchain.new_transaction(Transaction::new("a".into(), "c".into(), 5).expiring(1));
+        let preview = api::mine_preview(&blockchain);
+        assert_eq!((preview.index, preview.transactions.len(), preview.expired), (2, 2, 1), "the one expiring at block 1 is left out");
+        assert_eq!(blockchain.current_transactions().len(), 2, "nothing is dropped");
+
+        let mined = api::mine(&mut blockchain).expect("mined");
+        assert_eq!(mined.transactions, preview.transactions);
+        assert_eq!((mined.merkle_root, mined.state_root), (preview.merkle_root, preview.state_root));
+    }
+
+    #[test]
+    fn miner_address() {
+        let mut blockchain = Blockchain::new_with(1);
+        let update = MinerAddress { miner_address: "miner".into() };
+        assert!(api::miner_address(&update, &mut blockchain).is_ok());
+        let reward = api::mine(&mut blockchain).unwrap().reward.expect("expected a reward");
+        assert_eq!(reward.recipient, String::from("miner"));
+
+        let invalid = MinerAddress { miner_address: "0".into() };
+        assert!(api::miner_address(&invalid, &mut blockchain).is_err(), "'0' is reserved for rewards");
+    }
+
+    #[test]
+    fn pending_limit() {
+        let mut blockchain = Blockchain::new_with(1);
+        let txn = |amount| Transaction::new("a".into(), "b".into(), amount);
+        assert!(api::new_transaction(&txn(1), 2, &mut blockchain).is_ok());
+        assert!(api::new_transaction(&txn(2), 2, &mut blockchain).is_ok());
+        match api::new_transaction(&txn(3), 2, &mut blockchain) {
+            Err(Error::Limit(_)) => {},
+            other => panic!("expected a limit error, got {:?}", other)
+        }
+        assert!(api::new_transaction(&txn(1), 2, &mut blockchain).is_ok(), "already pending");
+        assert!(api::new_transaction(&Transaction::new("c".into(), "b".into(), 1), 2, &mut blockchain).is_ok(), "other senders are unaffected");
+    }
+
+    #[test]
+    fn mempool_cap() {
+        let mut blockchain = Blockchain::new_with(1);
+        let txn = |sender: &str| Transaction::new(sender.into(), "b".into(), 1);
+        blockchain.set_caps(Caps { mempool: memory::transaction_bytes(&txn("a")) * 2, ..Caps::default() });
+        assert!(api::new_transaction(&txn("a"), 64, &mut blockchain).is_ok());
+        assert!(api::new_transaction(&txn("c"), 64, &mut blockchain).is_ok());
+        match api::new_transaction(&txn("d"), 64, &mut blockchain) {
+            Err(Error::Limit(e)) => assert!(e.contains("mempool is full"), "got {}", e),
+            other => panic!("expected a limit error, got {:?}", other)
+        }
+        assert!(api::new_transaction(&txn("a"), 64, &mut blockchain).is_ok(), "already pending");
+        assert_eq!(blockchain.usage().mempool, memory::transaction_bytes(&txn("a")) * 2);
+    }
+
+    #[test]
+    fn record_round() {
+        let mut peers = Peers::new();
+        let up = Url::parse("http://localhost:9000").expect("valid url");
+        let down = Url::parse("http://localhost:9001").expect("valid url");
+        peers.register(up.clone());
+        peers.register(down.clone());
+        let results = vec![
+            Fetched { node: String::from(up.as_str()), result: Ok(Blockchain::new_with(1).into_chain()), outcome: Outcome::Sent, latency: Duration::from_millis(30), bytes: 1200 },
+            Fetched { node: String::from(down.as_str()), result: Err(Error::Network("refused".into())), outcome: Outcome::Unreachable, latency: Duration::from_secs(10), bytes: 0 }
+        ];
+        let chains = api::record_round(results, 42, &mut peers);
+        assert_eq!(chains.len(), 1);
+        assert_eq!(peers.info(&up).and_then(|info| info.last_seen), Some(42));
+        assert_eq!(peers.info(&up).and_then(|info| info.latency_ms), Some(30));
+        assert_eq!(peers.info(&up).and_then(|info| info.height), Some(1));
+        assert_eq!(peers.info(&down).map(|info| info.failures), Some(1));
+        assert_eq!(peers.info(&up).map(|info| (info.stats.requests, info.stats.bytes_received)), Some((1, 1200)));
+
+        let candidates = api::nodes(&peers, 2, 7, 42);
+        assert_eq!(candidates[0].work, None, "never heard from");
+        assert_eq!(candidates[1], Candidate { node: String::from(up.as_str()), work: Some(64), addresses: Vec::new() });
+    }
+
+    #[test]
+    fn adopt() {
+        let mut ours = Blockchain::new_with(1);
+        ours.mine().unwrap();
+        ours.mine().unwrap();
+        let theirs = Blockchain::new_with(1);
+        let adopted = api::adopt("http://localhost:9000", theirs.chain().clone(), &mut ours).expect("adopted");
+        assert_eq!((adopted.previous_height, adopted.height), (3, 1), "shorter, but adopted anyway");
+        assert_eq!(ours.hash_last_block(), theirs.hash_last_block());
+
+        let mut longer = Blockchain::new_with(1);
+        longer.mine().unwrap();
+        let mut blocks: Vec<Block> = longer.chain().iter().cloned().collect();
+        blocks[1].transactions.insert(Transaction::new("a".into(), "b".into(), 1000));
+        match api::adopt("http://localhost:9000", blocks.into_iter().collect(), &mut ours) {
+            Err(Error::Validation(_)) => {},
+            other => panic!("expected a validation error, got {:?}", other.map(|result| result.height))
+        }
+        assert_eq!(ours.len(), 1, "kept");
+        assert!(api::adopt("http://localhost:9000", Chain::new(), &mut ours).is_err());
+    }
+
+    #[test]
+    fn mining_stats() {
+        let mut blockchain = Blockchain::new_with(1);
+        assert_eq!(api::mining_stats(&blockchain).block_secs, None, "nothing mined");
+        blockchain.mine().unwrap();
+        blockchain.set_miner_address("alice".into());
+        blockchain.mine().unwrap();
+        blockchain.mine().unwrap();
+        //mined in the same second, spread them out
+        let mut blocks: Vec<Block> = blockchain.chain().iter().cloned().collect();
+        for (i, block) in blocks.iter_mut().enumerate() {
+            block.header.timestamp = 1000 + 10 * i as i64;
+        }
+        blockchain.replace(blocks.into_iter().collect());
+
+        let stats = api::mining_stats(&blockchain);
+        assert_eq!(stats.blocks, 2);
+        assert_eq!(stats.block_secs, Some(10.0));
+        assert_eq!(stats.hash_rate, Some(6.4), "64 hashes a block at difficulty 1");
+        assert_eq!(stats.miners, vec![
+            MinerStats { address: "alice".into(), blocks: 2 },
+            MinerStats { address: DEFAULT_MINER_ADDRESS.into(), blocks: 1 }
+        ]);
+    }
+
+    #[test]
+    fn mark_invalid() {
+        let results = vec![
+            Fetched { node: "http://localhost:9000".into(), result: Err(Error::Network("refused".into())), outcome: Outcome::Unreachable, latency: Duration::from_secs(10), bytes: 0 },
+            Fetched { node: "http://localhost:9001".into(), result: Ok(Blockchain::new_with(1).into_chain()), outcome: Outcome::Sent, latency: Duration::from_millis(30), bytes: 1200 },
+            Fetched { node: "not a url".into(), result: Ok(Blockchain::new_with(1).into_chain()), outcome: Outcome::Sent, latency: Duration::from_millis(20), bytes: 1200 }
+        ];
+        let mut reports: Vec<PeerReport> = results.iter().map(PeerReport::from).collect();
+        assert_eq!(api::record_round(results, 42, &mut Peers::new()).len(), 2, "lined up with the reports that have a height");
+        api::mark_invalid(&mut reports, &[1]);
+        assert_eq!(reports[0].error, Some(String::from("Network error. refused")), "unchanged");
+        assert!(reports[1].error.is_none());
+        assert_eq!(reports[2].error, Some(String::from("Sent an invalid chain of 1 blocks")));
+        assert_eq!(reports[2].outcome, Some(Outcome::Invalid));
+        assert_eq!(reports[2].latency_ms, 20);
+        assert!(!reports[1].validated && !reports[2].validated, "consensus hasn't checked either");
+    }
+
+    #[test]
+    fn mark_adopted() {
+        let fetched = |node: &str, blocks: usize| {
+            let mut chain = Blockchain::new_with(1);
+            for _ in 1..blocks {
+                chain.mine().expect("mined");
+            }
+            Fetched { node: node.into(), result: Ok(chain.into_chain()), outcome: Outcome::Sent, latency: Duration::from_millis(30), bytes: 1200 }
+        };
+        let results = vec![
+            Fetched { node: "http://localhost:9000".into(), result: Err(Error::Network("refused".into())), outcome: Outcome::Unreachable, latency: Duration::from_secs(10), bytes: 0 },
+            fetched("http://localhost:9001", 1),
+            fetched("http://localhost:9002", 3),
+            fetched("http://localhost:9003", 2)
+        ];
+        let mut reports: Vec<PeerReport> = results.iter().map(PeerReport::from).collect();
+        let mut ours = Blockchain::new_with(1);
+        ours.mine().expect("mined");
+        let result = api::consensus(api::record_round(results, 42, &mut Peers::new()), &mut ours);
+        assert_eq!(result.adopted, Some(1), "the most work, counting only the nodes that sent a chain");
+        api::mark_adopted(&mut reports, result.adopted, 128);
+        assert_eq!(reports.iter().map(|report| report.adopted).collect::<Vec<_>>(), vec![false, false, true, false]);
+        assert_eq!(reports.iter().map(|report| report.validated).collect::<Vec<_>>(), vec![false, false, true, false], "only the chain adopted was checked");
+        assert_eq!(reports[0].error, Some(String::from("Network error. refused")), "unchanged");
+        assert_eq!(reports[1].error, Some(String::from("Sent a chain of 1 blocks proving 64 work, no more than ours of 128, so it wasn't checked")));
+        assert!(reports[2].error.is_none());
+        assert_eq!(reports[3].error, Some(String::from("Sent a chain of 2 blocks proving 128 work, no more than ours of 128, so it wasn't checked")));
+    }
+
+    #[test]
+    fn stats_supply_saturates() {
+        let mut blockchain = Blockchain::new_with(1);
+        blockchain.new_transaction(Transaction::new("a".into(), "b".into(), i64::max_value()));
+        blockchain.new_transaction(Transaction::new("a".into(), "c".into(), i64::max_value()));
+        blockchain.mine().expect("mined");
+        let stats = api::stats(None, &blockchain).expect("stats");
+        assert_eq!(stats.supply, i64::max_value(), "capped rather than overflowing");
+    }
 }
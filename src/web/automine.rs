@@ -0,0 +1,158 @@
+use chrono::offset::Utc;
+use lib::blockchain::Blockchain;
+use lib::error::{Error, Result};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+use web::{self, BlockchainState};
+use web::compat::Schema;
+use web::types::AutoMineConfig;
+
+//For demos, a node can mine by itself once enough transactions are waiting, or
+//once waiting ones have sat there long enough, rather than someone calling /mine
+//or a miner grinding away all the time. Only blocks with transactions are mined.
+//It's checked a few times a second, and can be turned on, changed or off while
+//the node runs.
+
+///
+/// How often the mempool is checked
+///
+const CHECK_INTERVAL_MS: u64 = 500;
+
+///
+/// When to mine a block by itself, if ever
+///
+pub struct AutoMine {
+    config: Mutex<AutoMineConfig>
+}
+
+impl AutoMine {
+    pub fn off() -> AutoMine {
+        AutoMine { config: Mutex::new(AutoMineConfig { pending: None, interval_secs: None }) }
+    }
+
+    ///
+    /// Mining at `pending` transactions, or `interval_secs` after the last block
+    ///
+    pub fn new(pending: Option<usize>, interval_secs: Option<u64>) -> Result<AutoMine> {
+        let auto_mine = AutoMine::off();
+        auto_mine.set(AutoMineConfig { pending: pending, interval_secs: interval_secs })?;
+        Ok(auto_mine)
+    }
+
+    //only holds two numbers, nothing a panic could leave half changed
+    fn lock(&self) -> MutexGuard<AutoMineConfig> {
+        self.config.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn config(&self) -> AutoMineConfig {
+        self.lock().clone()
+    }
+
+    ///
+    /// Mine from now on as `config` says. Both left out turns it off
+    ///
+    pub fn set(&self, config: AutoMineConfig) -> Result<()> {
+        if config.pending == Some(0) {
+            return Err(Error::Validation(String::from("pending must be at least 1")));
+        }
+        if config.interval_secs == Some(0) {
+            return Err(Error::Validation(String::from("interval_secs must be at least 1")));
+        }
+        *self.lock() = config;
+        Ok(())
+    }
+
+    ///
+    /// Whether to mine with `pending` transactions waiting, `idle_secs` after the
+    /// last block
+    ///
+    pub fn due(&self, pending: usize, idle_secs: i64) -> bool {
+        let config = self.lock();
+        pending > 0 && (config.pending.map(|count| pending >= count).unwrap_or(false) ||
+                        config.interval_secs.map(|secs| idle_secs >= secs as i64).unwrap_or(false))
+    }
+}
+
+//genesis has the same timestamp on every node, long ago, so a node that hasn't
+//had a block yet has been idle since it started
+fn idle_secs(blockchain: &Blockchain, started: i64, now: i64) -> i64 {
+    match blockchain.len() {
+        1 => now - started,
+        _ => now - blockchain.last_block().header.timestamp
+    }
+}
+
+///
+/// Check `state`'s mempool every `CHECK_INTERVAL_MS` for as long as the process
+/// runs, mining when `state.auto_mine` is due
+///
+pub fn start(state: Arc<BlockchainState>) {
+    let started = Utc::now().timestamp();
+    thread::spawn(move || loop {
+        thread::sleep(Duration::from_millis(CHECK_INTERVAL_MS));
+        let due = match state.read() {
+            Ok(blockchain) => state.auto_mine.due(blockchain.current_transactions().len(),
+                                                  idle_secs(&blockchain, started, Utc::now().timestamp())),
+            Err(_) => false
+        };
+        if !due {
+            continue;
+        }
+        //a follower is always refused, no need for a failed job twice a second
+        if let Err(e) = state.lead() {
+            debug!("Not auto-mining. {}", e);
+            continue;
+        }
+        //someone's already mining them
+        let mut job = match state.jobs.start() {
+            Ok(job) => job,
+            Err(_) => continue
+        };
+        match web::mine_block(&state, Schema::Native) {
+            Ok((index, _)) => {
+                info!("Auto-mined block {}", index);
+                job.succeeded(index);
+            },
+            Err(e) => {
+                warn!("Auto-mining failed. {}", e.message);
+                job.failed(e.message.clone());
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::offset::Utc;
+    use lib::blockchain::Blockchain;
+    use web::automine::{idle_secs, AutoMine};
+    use web::types::AutoMineConfig;
+
+    #[test]
+    fn due() {
+        let auto_mine = AutoMine::off();
+        assert!(!auto_mine.due(1000, 1000));
+
+        auto_mine.set(AutoMineConfig { pending: Some(5), interval_secs: Some(30) }).expect("set");
+        assert!(!auto_mine.due(4, 10));
+        assert!(auto_mine.due(5, 10), "enough pending");
+        assert!(auto_mine.due(1, 30), "waited long enough");
+        assert!(!auto_mine.due(0, 60), "nothing to mine");
+
+        assert!(auto_mine.set(AutoMineConfig { pending: Some(0), interval_secs: None }).is_err());
+        assert_eq!(auto_mine.config().pending, Some(5), "unchanged");
+        auto_mine.set(AutoMineConfig { pending: None, interval_secs: Some(30) }).expect("set");
+        assert!(!auto_mine.due(100, 10));
+    }
+
+    #[test]
+    fn idle_since_start_until_a_block() {
+        let mut blockchain = Blockchain::new_with(1);
+        let now = Utc::now().timestamp();
+        assert_eq!(idle_secs(&blockchain, now - 5, now), 5, "not since genesis");
+        blockchain.mine().expect("mined");
+        let mined = blockchain.last_block().header.timestamp;
+        assert_eq!(idle_secs(&blockchain, now - 5, mined + 10), 10);
+    }
+}
@@ -0,0 +1,106 @@
+use rocket::request::{self, Request, FromRequest};
+use rocket::response::{self, Response, Responder};
+use rocket::http::{ContentType, Status};
+use rocket::Outcome::*;
+use std::io::{Cursor, Read};
+
+//Conditional GET. Read endpoints tag responses with an ETag derived from the tip of the
+//chain and answer 304 Not Modified when the client already has that version.
+
+///
+/// The entity tags from the If-None-Match header, if any
+/// 
+pub struct IfNoneMatch(Option<String>);
+
+impl IfNoneMatch {
+    ///
+    /// Does the client already have `etag`? Handles lists, weak tags and `*`
+    /// 
+    pub fn matches(&self, etag: &str) -> bool {
+        match self.0 {
+            None => false,
+            Some(ref header) => header.split(',')
+                                      .map(|tag| tag.trim())
+                                      .map(|tag| tag.trim_left_matches("W/"))
+                                      .any(|tag| tag == "*" || tag.trim_matches('"') == etag)
+        }
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for IfNoneMatch {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, ()> {
+        Success(IfNoneMatch(request.headers().get_one("If-None-Match").map(String::from)))
+    }
+}
+
+///
+/// A JSON body, either already serialized or serialized as it's sent
+/// 
+pub enum Body<'r> {
+    Json(String),
+    Stream(Box<Read + 'r>)
+}
+
+///
+/// A JSON response tagged with an ETag. No body means 304 Not Modified
+/// 
+pub struct Cached<'r> {
+    pub etag: String,
+    pub body: Option<Body<'r>>
+}
+
+impl<'r> Cached<'r> {
+    pub fn not_modified(etag: String) -> Cached<'r> {
+        Cached { etag: etag, body: None }
+    }
+
+    pub fn json(etag: String, body: String) -> Cached<'r> {
+        Cached { etag: etag, body: Some(Body::Json(body)) }
+    }
+
+    ///
+    /// A chunked response, for bodies too big to serialize up front
+    /// 
+    pub fn stream<R>(etag: String, body: R) -> Cached<'r> where R: Read + 'r {
+        Cached { etag: etag, body: Some(Body::Stream(Box::new(body))) }
+    }
+}
+
+impl<'r> Responder<'r> for Cached<'r> {
+    fn respond_to(self, _: &Request) -> response::Result<'r> {
+        let etag = format!("\"{}\"", self.etag);
+        match self.body {
+            None => Response::build()
+                        .status(Status::NotModified)
+                        .raw_header("ETag", etag)
+                        .ok(),
+            Some(Body::Json(body)) => Response::build()
+                        .header(ContentType::JSON)
+                        .raw_header("ETag", etag)
+                        .sized_body(Cursor::new(body))
+                        .ok(),
+            Some(Body::Stream(body)) => Response::build()
+                        .header(ContentType::JSON)
+                        .raw_header("ETag", etag)
+                        .streamed_body(body)
+                        .ok()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use web::cache::IfNoneMatch;
+
+    #[test]
+    fn matches() {
+        assert!(!IfNoneMatch(None).matches("abc"));
+        assert!(IfNoneMatch(Some(r#""abc""#.into())).matches("abc"));
+        assert!(IfNoneMatch(Some(r#"W/"abc""#.into())).matches("abc"));
+        assert!(IfNoneMatch(Some(r#""xyz", "abc""#.into())).matches("abc"));
+        assert!(IfNoneMatch(Some("*".into())).matches("abc"));
+        assert!(!IfNoneMatch(Some(r#""xyz""#.into())).matches("abc"));
+    }
+}
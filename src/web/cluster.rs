@@ -0,0 +1,164 @@
+use lib::error::{Error, Result};
+use lib::lease::{Claim, Lease};
+use rocket::{Request, Data, State};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::Outcome::*;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use web::BlockchainState;
+
+//Several replicas can serve one --data-dir behind a load balancer. The one holding
+//the lease mines and writes the store, the others serve reads and catch up from
+//the store as the leader writes to it.
+
+///
+/// How often requests renew the lease, or catch a follower up with the store
+///
+const CHECK_INTERVAL_MS: u64 = 1000;
+
+///
+/// What a replica is, going by its last claim on the lease
+///
+/// Leader: Holds the lease, and has since before the last check
+/// Promoted: Just took the lease for this term. The store has to be caught up
+///           with and repaired before writing to it
+/// Follower: Someone else holds the lease
+///
+#[derive(Debug, PartialEq)]
+pub enum Role {
+    Leader,
+    Promoted(u64),
+    Follower(String)
+}
+
+struct Status {
+    term: Option<u64>,
+    checked: Option<Instant>
+}
+
+///
+/// This replica's place in a cluster
+///
+pub struct Cluster {
+    lease: Lease,
+    status: Mutex<Status>
+}
+
+impl Cluster {
+    pub fn new(lease: Lease) -> Cluster {
+        Cluster {
+            lease: lease,
+            status: Mutex::new(Status { term: None, checked: None })
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        self.lease.holder()
+    }
+
+    //only holds a term and a time, nothing a panic could leave half changed
+    fn lock(&self) -> MutexGuard<Status> {
+        self.status.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    ///
+    /// Claim the lease at `now`
+    ///
+    pub fn elect(&self, now: i64) -> Result<Role> {
+        let claim = self.lease.claim(now)?;
+        let mut status = self.lock();
+        status.checked = Some(Instant::now());
+        Ok(match claim {
+            Claim::Ours(term) if status.term == Some(term) => Role::Leader,
+            Claim::Ours(term) => Role::Promoted(term),
+            Claim::Theirs(holder) => {
+                status.term = None;
+                Role::Follower(holder)
+            }
+        })
+    }
+
+    ///
+    /// The store is caught up for `term`, we lead from now on
+    ///
+    pub fn promoted(&self, term: u64) {
+        self.lock().term = Some(term);
+    }
+
+    ///
+    /// Whether we still hold the lease for the term we caught up in, at `now`.
+    /// Checked before writing to the store
+    ///
+    pub fn still_leading(&self, now: i64) -> Result<bool> {
+        let claim = self.lease.claim(now)?;
+        let status = self.lock();
+        Ok(match claim {
+            Claim::Ours(term) => status.term == Some(term),
+            Claim::Theirs(_) => false
+        })
+    }
+
+    ///
+    /// Whether it's been `CHECK_INTERVAL_MS` since the last claim
+    ///
+    pub fn due(&self) -> bool {
+        self.lock().checked.map(|checked| checked.elapsed() >= Duration::from_millis(CHECK_INTERVAL_MS)).unwrap_or(true)
+    }
+}
+
+///
+/// Renews the lease (or catches up with the store) on requests, at most every
+/// `CHECK_INTERVAL_MS`. A lease is only renewed while requests come in, so an
+/// idle leader hands over to the next replica that gets one
+///
+pub struct Election;
+
+impl Fairing for Election {
+    fn info(&self) -> Info {
+        Info {
+            name: "Cluster leader election",
+            kind: Kind::Request
+        }
+    }
+
+    fn on_request(&self, request: &mut Request, _: &Data) {
+        if let Success(state) = request.guard::<State<Arc<BlockchainState>>>() {
+            let due = state.cluster.as_ref().map(Cluster::due).unwrap_or(false);
+            if due {
+                match state.lead() {
+                    Ok(()) | Err(Error::Cluster(_)) => {},
+                    Err(e) => error!("Failed to check the cluster lease. {}", e)
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lib::lease::Lease;
+    use std::env;
+    use std::fs;
+    use web::cluster::*;
+
+    #[test]
+    fn elect() {
+        let path = env::temp_dir().join("learnnet-cluster-elect.lease");
+        let _ = fs::remove_file(&path);
+        let a = Cluster::new(Lease::new(&path, "a".into(), 10));
+        let b = Cluster::new(Lease::new(&path, "b".into(), 10));
+        assert!(a.due());
+
+        assert_eq!(a.elect(100).expect("elected"), Role::Promoted(1));
+        assert!(!a.still_leading(101).expect("claimed"), "not caught up yet");
+        a.promoted(1);
+        assert!(!a.due());
+        assert_eq!(a.elect(101).expect("elected"), Role::Leader);
+        assert!(a.still_leading(102).expect("claimed"));
+        assert_eq!(b.elect(102).expect("elected"), Role::Follower("a".into()));
+
+        assert_eq!(b.elect(120).expect("elected"), Role::Promoted(2));
+        assert!(!a.still_leading(121).expect("claimed"), "lost the lease");
+        assert_eq!(a.elect(121).expect("elected"), Role::Follower("b".into()));
+    }
+}
@@ -0,0 +1,211 @@
+use lib::blockchain::{Block, Chain};
+use lib::protocol::NETWORK_HEADER;
+use lib::transaction::{Amount, Transaction};
+use url::Url;
+use web::types::ConsensusReponse;
+use rocket::{Request, State};
+use rocket::request::{self, FromRequest};
+use rocket::Outcome::*;
+use std::sync::Arc;
+use web::BlockchainState;
+
+//The Python "learn blockchains by building one" tutorial node has simpler blocks:
+//index, timestamp, transactions, proof and previous_hash, with no header. Clients
+//written against it can use a node running with tutorial_schema, which answers
+//its /mine, /chain, /nodes/register and /nodes/resolve with its payloads. Its
+//POST /transactions/new is always there.
+//
+//That's as far as it goes. A tutorial node hashes the json of a whole block and
+//proves work on the previous proof, so neither kind of node accepts the other's
+//chain. Python nodes can talk to us, but a mixed network never agrees on a chain.
+
+///
+/// Which json a request gets. `Tutorial` when the node runs with
+/// tutorial_schema, except for peers, which are told apart by our network
+/// header and always get ours
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Schema {
+    Native,
+    Tutorial
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for Schema {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, ()> {
+        let tutorial = match request.guard::<State<Arc<BlockchainState>>>() {
+            Success(state) => state.tutorial_schema,
+            _ => false
+        };
+        if tutorial && request.headers().get_one(NETWORK_HEADER).is_none() {
+            Success(Schema::Tutorial)
+        } else {
+            Success(Schema::Native)
+        }
+    }
+}
+
+///
+/// A block the way the tutorial has it. The nonce is its proof
+///
+#[derive(Serialize)]
+pub struct TutorialBlock<'a> {
+    pub index: usize,
+    pub timestamp: f64,
+    pub transactions: Vec<TutorialTransaction<'a>>,
+    pub proof: u64,
+    pub previous_hash: &'a str
+}
+
+///
+/// A transaction without an expiry, which the tutorial doesn't have
+///
+#[derive(Serialize)]
+pub struct TutorialTransaction<'a> {
+    pub sender: &'a str,
+    pub recipient: &'a str,
+    pub amount: Amount
+}
+
+#[derive(Serialize)]
+pub struct TutorialChain<'a> {
+    pub chain: Vec<TutorialBlock<'a>>,
+    pub length: usize
+}
+
+#[derive(Serialize)]
+pub struct TutorialMineResult<'a> {
+    pub message: &'static str,
+    pub index: usize,
+    pub transactions: Vec<TutorialTransaction<'a>>,
+    pub proof: u64,
+    pub previous_hash: &'a str
+}
+
+impl<'a> From<&'a Transaction> for TutorialTransaction<'a> {
+    fn from(txn: &'a Transaction) -> TutorialTransaction<'a> {
+        TutorialTransaction {
+            sender: &txn.sender,
+            recipient: &txn.recipient,
+            amount: txn.amount
+        }
+    }
+}
+
+impl<'a> From<&'a Block> for TutorialBlock<'a> {
+    fn from(block: &'a Block) -> TutorialBlock<'a> {
+        TutorialBlock {
+            index: block.header.index,
+            //python's time() is a float
+            timestamp: block.header.timestamp as f64,
+            transactions: block.transactions.iter().map(TutorialTransaction::from).collect(),
+            proof: block.header.nonce,
+            previous_hash: &block.header.previous_hash
+        }
+    }
+}
+
+///
+/// POST /transactions/new
+///
+#[derive(Serialize)]
+pub struct TutorialTransactionResult {
+    pub message: String
+}
+
+///
+/// POST /nodes/register for tutorial clients, which lists the nodes rather than
+/// counting them
+///
+#[derive(Serialize)]
+pub struct TutorialRegisterResult {
+    pub message: String,
+    pub total_nodes: Vec<String>
+}
+
+///
+/// GET /nodes/resolve for tutorial clients. Only one of the chains is there
+///
+#[derive(Serialize)]
+pub struct TutorialConsensusResult<'a> {
+    pub message: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chain: Option<Vec<TutorialBlock<'a>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_chain: Option<Vec<TutorialBlock<'a>>>
+}
+
+///
+/// GET /chain for tutorial clients
+///
+pub fn chain<'a>(chain: &'a Chain) -> TutorialChain<'a> {
+    TutorialChain {
+        chain: chain.iter().map(TutorialBlock::from).collect(),
+        length: chain.len()
+    }
+}
+
+///
+/// GET /mine for tutorial clients, from the block just mined
+///
+pub fn mine_result<'a>(block: &'a Block) -> TutorialMineResult<'a> {
+    let block = TutorialBlock::from(block);
+    TutorialMineResult {
+        message: "New Block Forged",
+        index: block.index,
+        transactions: block.transactions,
+        proof: block.proof,
+        previous_hash: block.previous_hash
+    }
+}
+
+///
+/// A node the way the tutorial lists them, host and port
+///
+pub fn netloc(node: &Url) -> String {
+    let host = node.host_str().unwrap_or("");
+    match node.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => String::from(host)
+    }
+}
+
+pub fn transaction_result(index: usize) -> TutorialTransactionResult {
+    TutorialTransactionResult {
+        message: format!("Transaction will be added to Block {}", index)
+    }
+}
+
+pub fn consensus_result<'a>(result: &'a ConsensusReponse<'a>) -> TutorialConsensusResult<'a> {
+    TutorialConsensusResult {
+        message: &result.message,
+        chain: result.chain.map(|blocks| chain(blocks).chain),
+        new_chain: result.new_chain.map(|blocks| chain(blocks).chain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lib::blockchain::Blockchain;
+    use serde_json::{self, Value};
+    use web::compat;
+
+    #[test]
+    fn tutorial_chain() {
+        let mut blockchain = Blockchain::new_with(1);
+        blockchain.mine().expect("mined");
+        let json = serde_json::to_value(compat::chain(blockchain.chain())).expect("serialized");
+        assert_eq!(json["length"], 2);
+        let block = json["chain"][1].as_object().expect("a block");
+        let mut fields: Vec<&str> = block.keys().map(|key| key.as_str()).collect();
+        fields.sort();
+        assert_eq!(fields, vec!["index", "previous_hash", "proof", "timestamp", "transactions"]);
+        assert_eq!(block["proof"], blockchain.last_block().header.nonce);
+        assert!(block["timestamp"].is_f64());
+
+        let mined: Value = serde_json::to_value(compat::mine_result(blockchain.last_block())).expect("serialized");
+        assert_eq!(mined["message"], "New Block Forged");
+        assert_eq!(mined["transactions"], block["transactions"]);
+    }
+}
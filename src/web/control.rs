@@ -0,0 +1,260 @@
+use lib::error::{Error, Result};
+use lib::export::{self, Format};
+use lib::journal::Event;
+use serde_json;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Arc;
+use std::thread;
+use web::api;
+use web::types::Difficulty;
+use web::BlockchainState;
+
+//Operations that would be dangerous over the network go through a Unix socket
+//instead, which only the user running the node can connect to. Every frame is one
+//line of json: a client sends a command, the node answers with a reply, and the
+//client may send another. `learnnet ctl` is a client:
+//
+//  {"command": "difficulty", "difficulty": 4}
+//  {"ok": true, "message": "Difficulty is now 4"}
+
+///
+/// The longest frame read, anything longer closes the connection
+///
+pub const MAX_FRAME: u64 = 64 * 1024;
+
+///
+/// What a client can ask for
+///
+/// Shutdown: Exit, once any write in progress is done. Everything is already
+///           persisted as it happens
+/// Snapshot: Write the chain to `path` in the blocks export format, which
+///           `chain import` reads
+/// Difficulty: Change the difficulty subsequently mined blocks are mined at
+/// ClearMempool: Drop every pending transaction
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+pub enum Command {
+    Shutdown,
+    Snapshot { path: PathBuf },
+    Difficulty { difficulty: u64 },
+    ClearMempool
+}
+
+///
+/// The answer to a command
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Reply {
+    pub ok: bool,
+    pub message: String
+}
+
+///
+/// Carry out `command`, except for exiting on `Shutdown`, see `serve`
+///
+/// returns: What was done
+pub fn execute(state: &BlockchainState, command: &Command) -> Result<String> {
+    match *command {
+        Command::Shutdown => Ok(String::from("Shutting down")),
+        Command::Snapshot { ref path } => {
+            let blockchain = state.read()?;
+            //written aside first, so a failed snapshot never leaves half a file
+            let tmp = path.with_extension("tmp");
+            {
+                let mut file = File::create(&tmp)?;
+                export::export(&blockchain, Format::Blocks, &mut file)?;
+                file.sync_all()?;
+            }
+            fs::rename(&tmp, path)?;
+            Ok(format!("Wrote {} blocks to {}", blockchain.len(), path.display()))
+        },
+        Command::Difficulty { difficulty } => {
+            state.lead()?;
+            let mut blockchain = state.write()?;
+            let changed = api::difficulty(&Difficulty { difficulty: difficulty }, &mut blockchain)?;
+            Ok(format!("Difficulty is now {}", changed.difficulty))
+        },
+        Command::ClearMempool => {
+            state.lead()?;
+            let mut blockchain = state.write()?;
+            let dropped = blockchain.clear_transactions();
+            state.record(|| Event::Cleared)?;
+            warn!("Dropped {} pending transactions", dropped);
+            Ok(format!("Dropped {} pending transactions", dropped))
+        }
+    }
+}
+
+///
+/// Listen on `path`, replacing a socket left by an earlier run, and answer
+/// clients one at a time for as long as the process runs
+///
+pub fn start(state: Arc<BlockchainState>, path: PathBuf) -> Result<()> {
+    let listener = listen(&path)?;
+    info!("Control socket at {}", path.display());
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if let Err(e) = serve(&state, &path, stream) {
+                        warn!("Control connection failed. {}", e);
+                    }
+                },
+                Err(e) => warn!("Control connection failed. {}", e)
+            }
+        }
+    });
+    Ok(())
+}
+
+//mode_t, as the C library declares it
+#[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd", target_os = "openbsd", target_os = "netbsd", target_os = "dragonfly"))]
+type Mode = u16;
+#[cfg(not(any(target_os = "macos", target_os = "ios", target_os = "freebsd", target_os = "openbsd", target_os = "netbsd", target_os = "dragonfly")))]
+type Mode = u32;
+
+extern "C" {
+    fn umask(mask: Mode) -> Mode;
+}
+
+//only ever removes a socket, anything else at `path` is someone else's. The socket
+//is created as 0600 rather than chmod-ed after, so it's never open to others, not
+//even for a moment. The umask is the process' so this is meant for start up
+fn listen(path: &Path) -> Result<UnixListener> {
+    match fs::symlink_metadata(path) {
+        Ok(ref metadata) if metadata.file_type().is_socket() => fs::remove_file(path)?,
+        Ok(_) => return Err(Error::Validation(format!("{} exists and isn't a socket, not replacing it", path.display()))),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {},
+        Err(e) => return Err(Error::from(e))
+    }
+    let previous = unsafe { umask(0o177) };
+    let listener = UnixListener::bind(path);
+    unsafe { umask(previous) };
+    Ok(listener?)
+}
+
+//answers every frame a client sends, until it hangs up or asks to shut down
+fn serve(state: &BlockchainState, path: &Path, stream: UnixStream) -> Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+    loop {
+        let mut line = String::new();
+        if (&mut reader).take(MAX_FRAME).read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        if !line.ends_with('\n') {
+            return Err(Error::Validation(format!("Frames must be at most {} bytes, ending in a new line", MAX_FRAME)));
+        }
+        let command: Command = match serde_json::from_str(&line) {
+            Ok(command) => command,
+            Err(e) => {
+                write_frame(&mut writer, &Reply { ok: false, message: format!("Not a command. {}", e) })?;
+                continue;
+            }
+        };
+        let reply = match execute(state, &command) {
+            Ok(message) => Reply { ok: true, message: message },
+            Err(e) => Reply { ok: false, message: e.to_string() }
+        };
+        write_frame(&mut writer, &reply)?;
+        if reply.ok && command == Command::Shutdown {
+            shutdown(state, path);
+        }
+    }
+}
+
+//the WRITE lock is held from here on, so nothing is left half written
+fn shutdown(state: &BlockchainState, path: &Path) {
+    let held = state.write();
+    warn!("Shutting down, asked on the control socket");
+    let _ = fs::remove_file(path);
+    drop(held);
+    process::exit(0);
+}
+
+fn write_frame<W, T>(out: &mut W, value: &T) -> Result<()> where W: Write, T: ::serde::Serialize {
+    let mut frame = serde_json::to_vec(value)?;
+    frame.push(b'\n');
+    out.write_all(&frame)?;
+    Ok(())
+}
+
+///
+/// Send `command` to the node listening on `path` and wait for its reply
+///
+pub fn request(path: &Path, command: &Command) -> Result<Reply> {
+    let mut stream = UnixStream::connect(path)?;
+    write_frame(&mut stream, command)?;
+    let mut line = String::new();
+    BufReader::new(stream.take(MAX_FRAME)).read_line(&mut line)?;
+    if line.is_empty() {
+        return Err(Error::Network(format!("{} closed without a reply", path.display())));
+    }
+    Ok(serde_json::from_str(&line)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use lib::transaction::Transaction;
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::UnixStream;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use web::BlockchainState;
+    use web::control::*;
+
+    #[test]
+    fn execute_commands() {
+        let state = BlockchainState::new_with(1, Duration::from_secs(1));
+        state.write().expect("lock").new_transaction(Transaction::new("a".into(), "b".into(), 5));
+
+        assert_eq!(execute(&state, &Command::ClearMempool).expect("cleared"), "Dropped 1 pending transactions");
+        assert!(state.read().expect("lock").current_transactions().is_empty());
+        execute(&state, &Command::Difficulty { difficulty: 2 }).expect("changed");
+        assert_eq!(state.read().expect("lock").difficulty(), 2);
+        assert!(execute(&state, &Command::Difficulty { difficulty: 0 }).is_err());
+
+        let path = env::temp_dir().join("learnnet-control-execute_commands.jsonl");
+        execute(&state, &Command::Snapshot { path: path.clone() }).expect("written");
+        let mut snapshot = String::new();
+        File::open(&path).expect("written").read_to_string(&mut snapshot).expect("read");
+        assert_eq!(snapshot.lines().count(), 1, "just genesis");
+    }
+
+    #[test]
+    fn socket() {
+        let path = env::temp_dir().join("learnnet-control-socket.sock");
+        start(Arc::new(BlockchainState::new_with(1, Duration::from_secs(1))), path.clone()).expect("listening");
+        assert_eq!(fs::metadata(&path).expect("bound").permissions().mode() & 0o777, 0o600);
+
+        let reply = request(&path, &Command::Difficulty { difficulty: 3 }).expect("replied");
+        assert_eq!(reply, Reply { ok: true, message: String::from("Difficulty is now 3") });
+        let refused = request(&path, &Command::Difficulty { difficulty: 0 }).expect("replied");
+        assert!(!refused.ok);
+
+        let mut stream = UnixStream::connect(&path).expect("connected");
+        stream.write_all(b"{\"command\": \"launch-missiles\"}\n").expect("sent");
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line).expect("read");
+        assert!(line.contains("\"ok\":false"), "unknown commands are refused, got {}", line);
+    }
+
+    #[test]
+    fn only_replaces_sockets() {
+        let path = env::temp_dir().join("learnnet-control-only_replaces_sockets.sock");
+        File::create(&path).expect("created").write_all(b"not a socket").expect("written");
+        assert!(start(Arc::new(BlockchainState::new_with(1, Duration::from_secs(1))), path.clone()).is_err());
+        let mut kept = String::new();
+        File::open(&path).expect("kept").read_to_string(&mut kept).expect("read");
+        assert_eq!(kept, "not a socket");
+    }
+}
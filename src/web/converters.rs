@@ -1,25 +1,40 @@
 extern crate rocket;
 
 use web::types::*;
+use web::BlockchainState;
+use web::error::{ApiError, FieldError};
+use lib::protocol::{Limited, MAX_BODY_BYTES};
 use lib::transaction::Transaction;
-use serde_json;
+use serde_json::{self, Value};
 use serde;
-use rocket::{Request, Data};
+use base64;
+use rocket::{Request, Data, State};
 use rocket::data::{self, FromData};
-use rocket::http::{Status};
+use rocket::request::{self, FromRequest, FromParam, FromFormValue};
+use rocket::http::{Status, RawStr};
 use rocket::Outcome::*;
 use std::fmt::Debug;
+use std::sync::Arc;
+use std::ops::Deref;
 
-//Converters so Rocket methods can have strongly typed params. They are deserialized here
+//Converters so Rocket methods can have strongly typed params. They are deserialized here.
+//Bodies are read through `Limited`, so an absurd one is turned away before it's parsed
 
 fn deserialize<'a, T>(_: &Request, data: Data, type_name: String) -> data::Outcome<T, String>
     where for<'de> T: serde::Deserialize<'de> + Debug {
 
-    match serde_json::from_reader(data.open()) {
+    let mut body = Limited::new(data.open(), MAX_BODY_BYTES);
+    let parsed = serde_json::from_reader(&mut body);
+    match parsed {
         Ok(t) => {
             debug!("Successfully parsed {}. {:?}", type_name, t);
             Success(t)
         },
+        Err(_) if body.exceeded().is_some() => {
+            let exceeded = body.exceeded().unwrap_or_default();
+            warn!("Rejected {}, the body is {}", type_name, exceeded);
+            Failure((Status::PayloadTooLarge, format!("Couldn't parse {}, the body is {}", type_name, exceeded)))
+        },
         Err(e) => {
             error!("Failed to deserialize {} {:?}", type_name, e);
             Failure((Status::BadRequest, format!("Couldn't parse {}", type_name)))
@@ -28,17 +43,337 @@ fn deserialize<'a, T>(_: &Request, data: Data, type_name: String) -> data::Outco
 }
 
 
+///
+/// Transaction routes don't require a Content-Type, because plenty of tools
+/// send text/plain or nothing. They still get a 415 for anything but json,
+/// unless the node runs with lenient_content_type, in which case any body is
+/// parsed as json (and a 400 if it isn't).
+/// 
+/// Routes take a `Result` so the 400 says which fields are wrong, see
+/// `transaction_from`.
+/// 
 impl FromData for Transaction {
-    type Error = String;
-    fn from_data(req: &Request, data: Data) -> data::Outcome<Self, String> {        
-       deserialize(req, data, String::from("Transaction"))
+    type Error = ApiError;
+    fn from_data(req: &Request, data: Data) -> data::Outcome<Self, ApiError> {        
+        let is_json = req.content_type().map(|ct| ct.is_json()).unwrap_or(false);
+        if !is_json {
+            let lenient = match req.guard::<State<Arc<BlockchainState>>>() {
+                Success(state) => state.lenient_content_type,
+                _ => false
+            };
+            if !lenient {
+                let given = req.content_type().map(|ct| ct.to_string()).unwrap_or(String::from("none"));
+                warn!("Rejected Transaction with Content-Type {}", given);
+                let message = format!("Expected Content-Type application/json, got {}", given);
+                return Failure((Status::UnsupportedMediaType, ApiError::new(Status::UnsupportedMediaType, message)));
+            }
+        }
+        let mut body = Limited::new(data.open(), MAX_BODY_BYTES);
+        let json: serde_json::Result<Value> = serde_json::from_reader(&mut body);
+        let parsed = json.map_err(|e| match body.exceeded() {
+            Some(exceeded) => ApiError::new(Status::PayloadTooLarge, format!("Couldn't parse Transaction, the body is {}", exceeded)),
+            None => ApiError::new(Status::BadRequest, format!("Couldn't parse Transaction, the body isn't json. {}", e))
+        }).and_then(transaction_from);
+        match parsed {
+            Ok(transaction) => Success(transaction),
+            Err(e) => {
+                error!("Failed to deserialize Transaction. {}", e.message);
+                Failure((e.status, e))
+            }
+        }
     }
 }
 
+///
+/// A transaction from a json body, with an error for each field that's missing
+/// or isn't what it should be, rather than just the first one serde finds
+/// 
+fn transaction_from(body: Value) -> Result<Transaction, ApiError> {
+    let mut errors = Vec::new();
+    {
+        let fields = match body.as_object() {
+            Some(fields) => fields,
+            None => return Err(ApiError::new(Status::BadRequest, format!("Couldn't parse Transaction, expected an object, got {}", body)))
+        };
+        for name in &["sender", "recipient"] {
+            match fields.get(*name) {
+                None => errors.push(field_error(*name, String::from("missing"))),
+                Some(value) if !value.is_string() => errors.push(field_error(*name, format!("expected a string, got {}", value))),
+                _ => {}
+            }
+        }
+        match fields.get("amount") {
+            None => errors.push(field_error("amount", String::from("missing"))),
+            Some(value) if value.is_i64() => {},
+            Some(value) if value.is_u64() => errors.push(field_error("amount", format!("{} is too large", value))),
+            Some(value) if value.is_number() => errors.push(field_error("amount", format!("expected a whole number, got {}", value))),
+            Some(value) => errors.push(field_error("amount", format!("expected a number, got {}", value)))
+        }
+        match fields.get("expires_at") {
+            None | Some(&Value::Null) => {},
+            Some(value) if value.is_u64() => {},
+            Some(value) => errors.push(field_error("expires_at", format!("expected a block height, got {}", value)))
+        }
+    }
+    if !errors.is_empty() {
+        let names: Vec<&str> = errors.iter().map(|error| error.field).collect();
+        return Err(ApiError::new(Status::BadRequest, format!("Invalid Transaction. Check {}", names.join(", "))).with_fields(errors));
+    }
+    serde_json::from_value(body).map_err(|e| ApiError::new(Status::BadRequest, format!("Couldn't parse Transaction. {}", e)))
+}
+
+fn field_error(field: &'static str, reason: String) -> FieldError {
+    FieldError { field: field, reason: reason }
+}
+
 impl FromData for NodeList {
     type Error = String;
 
     fn from_data(req: &Request, data: Data) -> data::Outcome<Self, String> {        
        deserialize(req, data, String::from("NodeList"))
     }
-}
\ No newline at end of file
+}
+
+impl FromData for MinerAddress {
+    type Error = String;
+
+    fn from_data(req: &Request, data: Data) -> data::Outcome<Self, String> {        
+       deserialize(req, data, String::from("MinerAddress"))
+    }
+}
+
+impl FromData for Difficulty {
+    type Error = String;
+
+    fn from_data(req: &Request, data: Data) -> data::Outcome<Self, String> {        
+       deserialize(req, data, String::from("Difficulty"))
+    }
+}
+
+impl FromData for AutoMineConfig {
+    type Error = String;
+
+    fn from_data(req: &Request, data: Data) -> data::Outcome<Self, String> {        
+       deserialize(req, data, String::from("AutoMineConfig"))
+    }
+}
+
+impl FromData for ImportRequest {
+    type Error = String;
+
+    fn from_data(req: &Request, data: Data) -> data::Outcome<Self, String> {        
+       deserialize(req, data, String::from("ImportRequest"))
+    }
+}
+
+impl FromData for FaucetRequest {
+    type Error = String;
+
+    fn from_data(req: &Request, data: Data) -> data::Outcome<Self, String> {        
+       deserialize(req, data, String::from("FaucetRequest"))
+    }
+}
+
+impl FromData for AdoptRequest {
+    type Error = String;
+
+    fn from_data(req: &Request, data: Data) -> data::Outcome<Self, String> {        
+       deserialize(req, data, String::from("AdoptRequest"))
+    }
+}
+
+///
+/// Request guard for admin routes. The X-Admin-Token header must match the node's
+/// admin token. Nodes started without one have admin routes disabled.
+/// 
+pub struct Admin;
+
+impl<'a, 'r> FromRequest<'a, 'r> for Admin {
+    type Error = String;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, String> {
+        let state = match request.guard::<State<Arc<BlockchainState>>>() {
+            Success(state) => state,
+            _ => return Failure((Status::InternalServerError, String::from("No BlockchainState")))
+        };
+        match (state.admin_token.as_ref(), request.headers().get_one("X-Admin-Token")) {
+            (None, _) => {
+                warn!("Admin request to {} but no admin token is configured", request.uri());
+                Failure((Status::Forbidden, String::from("Admin routes are disabled")))
+            },
+            (Some(expected), Some(given)) if expected.as_str() == given => Success(Admin),
+            _ => {
+                warn!("Admin request to {} with missing or wrong X-Admin-Token", request.uri());
+                Failure((Status::Unauthorized, String::from("Invalid X-Admin-Token")))
+            }
+        }
+    }
+}
+
+///
+/// Longest address accepted in a url. Addresses are free text, but nothing real
+/// is this long
+/// 
+const MAX_ADDRESS_LEN: usize = 256;
+
+///
+/// A block hash from the url, e.g. /block/hash/<hash>. Base64 of a SHA-256 or
+/// SHA-512 digest
+/// 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockHash(pub String);
+
+///
+/// A transaction id from the url, e.g. /transaction/<txid>. Checked the same way
+/// as a `BlockHash`, since it's the transaction's hash
+/// 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxId(pub String);
+
+///
+/// An address from the url, e.g. /balance/<address>. Any text, as long as it
+/// isn't empty, too long or full of control characters
+/// 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Address(pub String);
+
+fn parse_hash(value: &str, type_name: &str) -> Result<String, String> {
+    match base64::decode(value) {
+        Ok(ref bytes) if bytes.len() == 32 || bytes.len() == 64 => Ok(value.to_string()),
+        Ok(bytes) => Err(format!("{} {} is {} bytes, expected 32 or 64", type_name, value, bytes.len())),
+        Err(_) => Err(format!("{} {} is not base64", type_name, value))
+    }
+}
+
+fn parse_address(value: &str) -> Result<String, String> {
+    if value.is_empty() {
+        Err(String::from("Address is empty"))
+    } else if value.len() > MAX_ADDRESS_LEN {
+        Err(format!("Address is longer than {} bytes", MAX_ADDRESS_LEN))
+    } else if value.chars().any(|c| c.is_control()) {
+        Err(String::from("Address contains control characters"))
+    } else {
+        Ok(value.to_string())
+    }
+}
+
+//path segments are percent encoded; query values are also '+' for space
+fn decode_param(param: &RawStr) -> Result<String, String> {
+    param.percent_decode().map(|s| s.into_owned()).map_err(|_| format!("{} is not valid utf-8", param))
+}
+
+fn decode_form_value(value: &RawStr) -> Result<String, String> {
+    value.url_decode().map_err(|_| format!("{} is not valid utf-8", value))
+}
+
+///
+/// A Rocket route whose param fails to convert is forwarded, and ends up a 404.
+/// Routes take `Result<BlockHash, String>` instead, and answer the error with a 400
+/// 
+impl<'a> FromParam<'a> for BlockHash {
+    type Error = String;
+
+    fn from_param(param: &'a RawStr) -> Result<Self, String> {
+        decode_param(param).and_then(|hash| parse_hash(&hash, "Block hash")).map(BlockHash)
+    }
+}
+
+impl<'a> FromParam<'a> for TxId {
+    type Error = String;
+
+    fn from_param(param: &'a RawStr) -> Result<Self, String> {
+        decode_param(param).and_then(|id| parse_hash(&id, "Transaction id")).map(TxId)
+    }
+}
+
+impl<'a> FromParam<'a> for Address {
+    type Error = String;
+
+    fn from_param(param: &'a RawStr) -> Result<Self, String> {
+        decode_param(param).and_then(|address| parse_address(&address)).map(Address)
+    }
+}
+
+impl<'v> FromFormValue<'v> for BlockHash {
+    type Error = String;
+
+    fn from_form_value(value: &'v RawStr) -> Result<Self, String> {
+        decode_form_value(value).and_then(|hash| parse_hash(&hash, "Block hash")).map(BlockHash)
+    }
+}
+
+impl<'v> FromFormValue<'v> for TxId {
+    type Error = String;
+
+    fn from_form_value(value: &'v RawStr) -> Result<Self, String> {
+        decode_form_value(value).and_then(|id| parse_hash(&id, "Transaction id")).map(TxId)
+    }
+}
+
+impl<'v> FromFormValue<'v> for Address {
+    type Error = String;
+
+    fn from_form_value(value: &'v RawStr) -> Result<Self, String> {
+        decode_form_value(value).and_then(|address| parse_address(&address)).map(Address)
+    }
+}
+
+impl Deref for BlockHash {
+    type Target = str;
+    fn deref(&self) -> &str { &self.0 }
+}
+
+impl Deref for TxId {
+    type Target = str;
+    fn deref(&self) -> &str { &self.0 }
+}
+
+impl Deref for Address {
+    type Target = str;
+    fn deref(&self) -> &str { &self.0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use web::converters::*;
+    use rocket::http::{RawStr, Status};
+    use serde_json;
+    use rocket::request::{FromParam, FromFormValue};
+    use lib::blockchain::Blockchain;
+
+    #[test]
+    fn block_hash() {
+        let hash = Blockchain::new_with(1).hash_last_block();
+        let encoded = hash.replace("/", "%2F").replace("+", "%2B");
+        assert_eq!(BlockHash::from_param(RawStr::from_str(&encoded)), Ok(BlockHash(hash.clone())));
+        assert_eq!(BlockHash::from_form_value(RawStr::from_str(&encoded)), Ok(BlockHash(hash)));
+        assert!(BlockHash::from_param(RawStr::from_str("not-base64!")).is_err());
+        assert!(TxId::from_param(RawStr::from_str("YWJj")).is_err(), "too short for a digest");
+    }
+
+    #[test]
+    fn transaction_fields() {
+        let parse = |json: &str| transaction_from(serde_json::from_str(json).expect("json"));
+        assert!(parse(r#"{"sender": "a", "recipient": "b", "amount": 5, "expires_at": null}"#).is_ok());
+
+        let error = parse(r#"{"sender": "a", "amount": "5"}"#).expect_err("invalid");
+        assert_eq!(error.status, Status::BadRequest);
+        let fields: Vec<&str> = error.fields.iter().map(|error| error.field).collect();
+        assert_eq!(fields, vec!["recipient", "amount"]);
+        assert_eq!(error.fields[0].reason, "missing");
+        assert_eq!(error.fields[1].reason, r#"expected a number, got "5""#);
+
+        let error = parse(r#"{"sender": 1, "recipient": "b", "amount": 1.5, "expires_at": -1}"#).expect_err("invalid");
+        assert_eq!(error.fields.len(), 3);
+        assert!(parse("[]").expect_err("not an object").fields.is_empty());
+    }
+
+    #[test]
+    fn address() {
+        assert_eq!(Address::from_param(RawStr::from_str("the%20miner")), Ok(Address("the miner".into())));
+        assert_eq!(Address::from_form_value(RawStr::from_str("the+miner")), Ok(Address("the miner".into())));
+        assert!(Address::from_param(RawStr::from_str("")).is_err());
+        assert!(Address::from_param(RawStr::from_str("a%0Ab")).is_err());
+        assert!(Address::from_param(RawStr::from_str(&"a".repeat(MAX_ADDRESS_LEN + 1))).is_err());
+    }
+}
@@ -0,0 +1,100 @@
+use lib::error::Error;
+use rocket::request::Request;
+use rocket::response::{self, Response, Responder};
+use rocket::http::{ContentType, Status};
+use serde_json;
+use std::io::Cursor;
+
+///
+/// An http error with a json body `{"error": message}`. Routes return it so `?`
+/// works on anything that fails with a `lib::error::Error`
+///
+/// fields: What's wrong with each field of a request body, if that's what's
+///         wrong. Sent as `"fields"` when there are any
+///
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: Status,
+    pub message: String,
+    pub fields: Vec<FieldError>
+}
+
+///
+/// A field of a request body that's missing or has the wrong type
+///
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub reason: String
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    error: &'a str,
+    #[serde(skip_serializing_if = "no_fields")]
+    fields: &'a [FieldError]
+}
+
+fn no_fields(fields: &&[FieldError]) -> bool {
+    fields.is_empty()
+}
+
+impl ApiError {
+    pub fn new(status: Status, message: String) -> ApiError {
+        ApiError { status: status, message: message, fields: Vec::new() }
+    }
+
+    pub fn with_fields(mut self, fields: Vec<FieldError>) -> ApiError {
+        self.fields = fields;
+        self
+    }
+}
+
+impl From<Error> for ApiError {
+    fn from(e: Error) -> ApiError {
+        let status = match e {
+            Error::Validation(_) => Status::BadRequest,
+            Error::Lock(_) | Error::Cluster(_) => Status::ServiceUnavailable,
+            Error::Network(_) => Status::BadGateway,
+            Error::Protocol(_) => Status::PreconditionFailed,
+            Error::Limit(_) => Status::TooManyRequests,
+            Error::Storage(_) | Error::Serialization(_) => Status::InternalServerError
+        };
+        ApiError::new(status, e.to_string())
+    }
+}
+
+impl<'r> Responder<'r> for ApiError {
+    fn respond_to(self, _: &Request) -> response::Result<'r> {
+        if self.status.code >= 500 {
+            error!("{} {}", self.status, self.message);
+        } else {
+            warn!("{} {}", self.status, self.message);
+        }
+        let body = serde_json::to_string(&ErrorBody { error: &self.message, fields: &self.fields })
+                        .unwrap_or_else(|_| String::from(r#"{"error": "unknown"}"#));
+        Response::build()
+            .status(self.status)
+            .header(ContentType::JSON)
+            .sized_body(Cursor::new(body))
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lib::error::Error;
+    use web::error::ApiError;
+    use rocket::http::Status;
+
+    #[test]
+    fn status() {
+        assert_eq!(ApiError::from(Error::Validation("bad".into())).status, Status::BadRequest);
+        assert_eq!(ApiError::from(Error::Lock("busy".into())).status, Status::ServiceUnavailable);
+        assert_eq!(ApiError::from(Error::Network("down".into())).status, Status::BadGateway);
+        assert_eq!(ApiError::from(Error::Protocol("v0".into())).status, Status::PreconditionFailed);
+        assert_eq!(ApiError::from(Error::Limit("slow down".into())).status, Status::TooManyRequests);
+        assert_eq!(ApiError::from(Error::Cluster("follower".into())).status, Status::ServiceUnavailable);
+        assert_eq!(ApiError::from(Error::Serialization("eh".into())).status, Status::InternalServerError);
+    }
+}
@@ -0,0 +1,355 @@
+use lib::blockchain::BlockHeader;
+use lib::error::{Error, Result};
+use lib::journal::Event;
+use serde_json;
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+use web::BlockchainState;
+
+//GET /events/sse streams what happens to the chain and mempool as Server-Sent
+//Events, for clients behind proxies that won't pass anything but plain http.
+//Rocket buffers the bodies it streams, so events wouldn't reach clients as they
+//happen: they're served on a listener of their own, a thread per subscriber
+//writing straight to its socket, which a reverse proxy can route /events/sse to.
+//Every event has an id, and a client that reconnects with Last-Event-ID gets
+//what it missed, as far back as the events kept:
+//
+//  id: 7
+//  event: block
+//  data: {"header": {..}, "transactions": [..]}
+
+///
+/// How many of the latest events are kept for clients to resume from
+///
+pub const MAX_EVENTS: usize = 1024;
+
+///
+/// How many clients may be connected at once, each holds a thread. They're
+/// counted from when they connect, so ones that never send a request count too
+///
+pub const MAX_SUBSCRIBERS: usize = 64;
+
+///
+/// How often an idle stream gets a comment, so proxies don't time it out
+///
+pub const KEEPALIVE_SECS: u64 = 15;
+
+///
+/// The longest request read, anything longer is dropped
+///
+const MAX_REQUEST_BYTES: u64 = 8 * 1024;
+
+///
+/// How long a client has to send each part of its request, and a subscriber to
+/// take each write, before it's dropped
+///
+const IO_TIMEOUT_SECS: u64 = 2 * KEEPALIVE_SECS;
+
+///
+/// An event as it's sent
+///
+/// kind: block (mined or imported here), transaction, reorg (our chain was
+///       replaced, `data` has its new height and tip) or cleared (the mempool
+///       was emptied)
+/// data: One line of json
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Published {
+    pub id: u64,
+    pub kind: &'static str,
+    pub data: String
+}
+
+impl Published {
+    fn frame(&self) -> String {
+        format!("id: {}\nevent: {}\ndata: {}\n\n", self.id, self.kind, self.data)
+    }
+}
+
+///
+/// The latest events, and the clients waiting for more
+///
+pub struct Events {
+    recent: Mutex<Recent>,
+    arrived: Condvar,
+    subscribers: AtomicUsize
+}
+
+struct Recent {
+    events: VecDeque<Published>,
+    next_id: u64
+}
+
+//the reorg event's data
+#[derive(Serialize)]
+struct Reorg<'a> {
+    height: usize,
+    tip: Option<&'a BlockHeader>
+}
+
+impl Events {
+    pub fn new() -> Events {
+        Events {
+            recent: Mutex::new(Recent { events: VecDeque::new(), next_id: 1 }),
+            arrived: Condvar::new(),
+            subscribers: AtomicUsize::new(0)
+        }
+    }
+
+    //only ever holds whole events
+    fn lock(&self) -> MutexGuard<Recent> {
+        self.recent.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    ///
+    /// Send `event` to every subscriber
+    ///
+    pub fn publish(&self, event: &Event) {
+        let (kind, data) = match *event {
+            Event::Transaction(ref txn) => ("transaction", serde_json::to_string(txn)),
+            Event::Mined(ref block) => ("block", serde_json::to_string(block)),
+            Event::Replaced(ref chain) => ("reorg", serde_json::to_string(&Reorg {
+                height: chain.len(),
+                tip: chain.last().map(|block| &block.header)
+            })),
+            Event::Cleared => ("cleared", Ok(String::from("{}")))
+        };
+        let data = match data {
+            Ok(data) => data,
+            Err(e) => {
+                error!("Couldn't publish a {} event. {}", kind, e);
+                return;
+            }
+        };
+        let mut recent = self.lock();
+        let id = recent.next_id;
+        recent.next_id += 1;
+        recent.events.push_back(Published { id: id, kind: kind, data: data });
+        if recent.events.len() > MAX_EVENTS {
+            recent.events.pop_front();
+        }
+        self.arrived.notify_all();
+    }
+
+    ///
+    /// The id of the last event published, 0 if there's been none
+    ///
+    pub fn latest(&self) -> u64 {
+        self.lock().next_id - 1
+    }
+
+    ///
+    /// The events after `last`, waiting up to `timeout` for one if there are none
+    ///
+    /// returns: None if some after `last` are no longer kept, or `last` is from
+    ///          before we restarted, so the client has to catch up another way
+    pub fn after(&self, last: u64, timeout: Duration) -> Option<Vec<Published>> {
+        let deadline = Instant::now() + timeout;
+        let mut recent = self.lock();
+        loop {
+            if last >= recent.next_id || recent.events.front().map(|first| last + 1 < first.id).unwrap_or(false) {
+                return None;
+            }
+            let events: Vec<Published> = recent.events.iter().filter(|event| event.id > last).cloned().collect();
+            let now = Instant::now();
+            if !events.is_empty() || now >= deadline {
+                return Some(events);
+            }
+            recent = self.arrived.wait_timeout(recent, deadline - now).unwrap_or_else(|poisoned| poisoned.into_inner()).0;
+        }
+    }
+}
+
+///
+/// Listen on `address`:`port` and stream events to every client that connects,
+/// for as long as the process runs
+///
+/// returns: Where it's listening, for port 0
+pub fn start(state: Arc<BlockchainState>, address: &str, port: u16) -> Result<SocketAddr> {
+    let listener = TcpListener::bind((address, port))?;
+    let local = listener.local_addr()?;
+    info!("Serving events on {}", local);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("Events connection failed. {}", e);
+                    continue;
+                }
+            };
+            //counted before its thread starts, so clients that connect and
+            //send nothing can't take more threads than subscribers could
+            let subscribed = Subscribed(state.clone());
+            if state.events.subscribers.fetch_add(1, Ordering::SeqCst) >= MAX_SUBSCRIBERS {
+                refuse(stream);
+                continue;
+            }
+            thread::spawn(move || {
+                if let Err(e) = serve(&subscribed.0, stream) {
+                    debug!("Events subscriber left. {}", e);
+                }
+            });
+        }
+    });
+    Ok(local)
+}
+
+//counts a subscriber for as long as it's held, once it's been added
+struct Subscribed(Arc<BlockchainState>);
+
+impl Drop for Subscribed {
+    fn drop(&mut self) {
+        self.0.events.subscribers.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+//turns a client away on the accepting thread, so it mustn't block
+fn refuse(mut stream: TcpStream) {
+    let _ = stream.set_nonblocking(true);
+    let _ = stream.write_all(b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+}
+
+//answers one request, streaming until the client hangs up
+fn serve(state: &BlockchainState, mut stream: TcpStream) -> Result<()> {
+    let timeout = Some(Duration::from_secs(IO_TIMEOUT_SECS));
+    stream.set_read_timeout(timeout)?;
+    stream.set_write_timeout(timeout)?;
+    let (path, last_event_id) = read_request(&stream)?;
+    if path != "/events/sse" {
+        stream.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")?;
+        return Ok(());
+    }
+    let events = &state.events;
+    //X-Accel-Buffering stops nginx holding events back
+    stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nX-Accel-Buffering: no\r\nConnection: keep-alive\r\n\r\n")?;
+    let mut last = last_event_id.unwrap_or_else(|| events.latest());
+    loop {
+        let frames = match events.after(last, Duration::from_secs(KEEPALIVE_SECS)) {
+            Some(ref published) if published.is_empty() => String::from(": keepalive\n\n"),
+            Some(published) => {
+                last = published.last().map(|event| event.id).unwrap_or(last);
+                published.iter().map(Published::frame).collect()
+            },
+            None => {
+                last = events.latest();
+                format!("id: {}\nevent: resync\ndata: {{\"reason\": \"events were missed, fetch /chain again\"}}\n\n", last)
+            }
+        };
+        stream.write_all(frames.as_bytes())?;
+        stream.flush()?;
+    }
+}
+
+//the path and Last-Event-ID of a GET request
+fn read_request(stream: &TcpStream) -> Result<(String, Option<u64>)> {
+    let mut reader = BufReader::new(stream.take(MAX_REQUEST_BYTES));
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (method, target) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    if method != "GET" {
+        return Err(Error::Validation(format!("Only GET is served, not {:?}", method)));
+    }
+    let path = String::from(target.split('?').next().unwrap_or(""));
+    let mut last_event_id = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        let mut pair = header.splitn(2, ':');
+        let (name, value) = (pair.next().unwrap_or(""), pair.next().unwrap_or(""));
+        if name.trim().to_lowercase() == "last-event-id" {
+            last_event_id = value.trim().parse().ok();
+        }
+    }
+    Ok((path, last_event_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use lib::journal::Event;
+    use lib::transaction::Transaction;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpStream;
+    use std::sync::Arc;
+    use std::sync::atomic::Ordering;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use web::BlockchainState;
+    use web::events::*;
+
+    #[test]
+    fn publish_and_resume() {
+        let events = Events::new();
+        events.publish(&Event::Transaction(Transaction::new("a".into(), "b".into(), 5)));
+        events.publish(&Event::Cleared);
+        let quick = Duration::from_millis(10);
+
+        let all = events.after(0, quick).expect("kept");
+        assert_eq!(all.iter().map(|event| (event.id, event.kind)).collect::<Vec<_>>(), vec![(1, "transaction"), (2, "cleared")]);
+        assert_eq!(events.after(1, quick).expect("kept").len(), 1);
+        assert!(events.after(2, quick).expect("kept").is_empty(), "nothing new");
+        assert_eq!(events.after(5, quick), None, "from before a restart");
+
+        for _ in 0..MAX_EVENTS {
+            events.publish(&Event::Cleared);
+        }
+        assert_eq!(events.after(0, quick), None, "the first are gone");
+        assert_eq!(events.after(events.latest() - 1, quick).expect("kept").len(), 1);
+    }
+
+    #[test]
+    fn stream() {
+        let mut state = BlockchainState::new_with(1, Duration::from_secs(1));
+        state.events_listener = Some((String::from("127.0.0.1"), 0));
+        let state = Arc::new(state);
+        state.record(|| Event::Cleared).expect("recorded");
+        let address = start(state.clone(), "127.0.0.1", 0).expect("listening");
+
+        let mut client = TcpStream::connect(address).expect("connected");
+        client.set_read_timeout(Some(Duration::from_secs(5))).expect("timeout");
+        client.write_all(b"GET /events/sse HTTP/1.1\r\nHost: node\r\nLast-Event-ID: 0\r\n\r\n").expect("sent");
+        let mut reader = BufReader::new(client);
+        let mut lines = Vec::new();
+        while !lines.contains(&String::from("event: cleared")) {
+            let mut line = String::new();
+            reader.read_line(&mut line).expect("an event");
+            lines.push(String::from(line.trim_right()));
+        }
+        assert_eq!(lines[0], "HTTP/1.1 200 OK");
+        assert!(lines.contains(&String::from("id: 1")), "resumed from the start, got {:?}", lines);
+
+        let mut lost = TcpStream::connect(address).expect("connected");
+        lost.write_all(b"GET /chain HTTP/1.1\r\n\r\n").expect("sent");
+        let mut status = String::new();
+        BufReader::new(lost).read_line(&mut status).expect("a response");
+        assert_eq!(status.trim_right(), "HTTP/1.1 404 Not Found");
+    }
+
+    #[test]
+    fn idle_connections_count() {
+        let state = Arc::new(BlockchainState::new_with(1, Duration::from_secs(1)));
+        let address = start(state.clone(), "127.0.0.1", 0).expect("listening");
+        let subscribers = || state.events.subscribers.load(Ordering::SeqCst);
+        let idle: Vec<TcpStream> = (0..MAX_SUBSCRIBERS).map(|_| TcpStream::connect(address).expect("connected")).collect();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while subscribers() < MAX_SUBSCRIBERS && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(subscribers(), MAX_SUBSCRIBERS, "counted without sending a request");
+
+        let mut turned_away = TcpStream::connect(address).expect("connected");
+        turned_away.set_read_timeout(Some(Duration::from_secs(5))).expect("timeout");
+        let mut status = String::new();
+        BufReader::new(&mut turned_away).read_line(&mut status).expect("a response");
+        assert_eq!(status.trim_right(), "HTTP/1.1 503 Service Unavailable");
+        drop(idle);
+    }
+}
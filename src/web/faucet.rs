@@ -0,0 +1,124 @@
+use lib::error::{Error, Result};
+use lib::transaction::{Address, Amount};
+use rocket::{Request, State};
+use rocket::http::Status;
+use rocket::request::{self, FromRequest};
+use rocket::Outcome::*;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use web::BlockchainState;
+
+//On a test network, students get coins from the faucet instead of waiting for the
+//instructor to mine to them. Faucet coins come from `FAUCET_ADDRESS`, not "0",
+//since a block only pays one mining reward, and go in the next block. The faucet is off unless the node has a faucet token, so a
+//node meant to hold real value never has one.
+
+///
+/// Where faucet coins come from. The network lets it overdraw, like anyone
+///
+pub const FAUCET_ADDRESS: &'static str = "faucet";
+
+///
+/// The most one request may ask for
+///
+pub const MAX_FAUCET_AMOUNT: Amount = 100;
+
+///
+/// How long an address waits between requests
+///
+pub const FAUCET_INTERVAL_SECS: i64 = 3600;
+
+///
+/// Header the faucet token goes in
+///
+pub const FAUCET_TOKEN_HEADER: &'static str = "X-Faucet-Token";
+
+///
+/// Hands out coins, at most once every `FAUCET_INTERVAL_SECS` to an address
+///
+pub struct Faucet {
+    token: String,
+    granted: Mutex<HashMap<Address, i64>>
+}
+
+impl Faucet {
+    pub fn new(token: String) -> Faucet {
+        Faucet { token: token, granted: Mutex::new(HashMap::new()) }
+    }
+
+    //only holds times, nothing a panic could leave half changed
+    fn lock(&self) -> MutexGuard<HashMap<Address, i64>> {
+        self.granted.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    ///
+    /// Give `amount` to `address` at `now` (seconds since the epoch), if it may
+    /// have it. Recorded straight away, so the caller must add the transaction
+    ///
+    pub fn grant(&self, address: &str, amount: Amount, now: i64) -> Result<()> {
+        if address.trim().is_empty() || address == "0" || address == FAUCET_ADDRESS {
+            return Err(Error::Validation(format!("Can't send faucet coins to \"{}\"", address)));
+        }
+        if amount <= 0 || amount > MAX_FAUCET_AMOUNT {
+            return Err(Error::Validation(format!("Faucet amount must be between 1 and {}, got {}", MAX_FAUCET_AMOUNT, amount)));
+        }
+        let mut granted = self.lock();
+        if let Some(last) = granted.get(address) {
+            let wait = last + FAUCET_INTERVAL_SECS - now;
+            if wait > 0 {
+                return Err(Error::Limit(format!("{} got faucet coins recently. Try again in {}s", address, wait)));
+            }
+        }
+        granted.insert(address.into(), now);
+        Ok(())
+    }
+}
+
+///
+/// Request guard for POST /faucet. The X-Faucet-Token header must match the
+/// node's faucet token. Nodes started without one have no faucet
+///
+pub struct FaucetToken;
+
+impl<'a, 'r> FromRequest<'a, 'r> for FaucetToken {
+    type Error = String;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, String> {
+        let state = match request.guard::<State<Arc<BlockchainState>>>() {
+            Success(state) => state,
+            _ => return Failure((Status::InternalServerError, String::from("No BlockchainState")))
+        };
+        match (state.faucet.as_ref(), request.headers().get_one(FAUCET_TOKEN_HEADER)) {
+            (None, _) => Failure((Status::Forbidden, String::from("This node has no faucet"))),
+            (Some(faucet), Some(given)) if faucet.token.as_str() == given => Success(FaucetToken),
+            _ => {
+                warn!("Faucet request with missing or wrong {}", FAUCET_TOKEN_HEADER);
+                Failure((Status::Unauthorized, format!("Invalid {}", FAUCET_TOKEN_HEADER)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lib::error::Error;
+    use web::faucet::*;
+
+    #[test]
+    fn grant() {
+        let faucet = Faucet::new("secret".into());
+        assert!(faucet.grant("alice", 10, 100).is_ok());
+        match faucet.grant("alice", 10, 101) {
+            Err(Error::Limit(_)) => {},
+            other => panic!("expected a limit error, got {:?}", other)
+        }
+        assert!(faucet.grant("bob", MAX_FAUCET_AMOUNT, 101).is_ok(), "other addresses are unaffected");
+        assert!(faucet.grant("alice", 10, 100 + FAUCET_INTERVAL_SECS).is_ok());
+
+        assert!(faucet.grant("carol", MAX_FAUCET_AMOUNT + 1, 100).is_err());
+        assert!(faucet.grant("carol", 0, 100).is_err());
+        assert!(faucet.grant("0", 10, 100).is_err());
+        assert!(faucet.grant(FAUCET_ADDRESS, 10, 100).is_err());
+        assert!(faucet.grant("carol", 10, 100).is_ok(), "refused requests don't count");
+    }
+}
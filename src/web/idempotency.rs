@@ -0,0 +1,129 @@
+use lib::error::{Error, Result};
+use lib::transaction::Transaction;
+use rocket::Request;
+use rocket::http::Status;
+use rocket::request::{self, FromRequest};
+use rocket::Outcome::*;
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+
+//A client that sends a transaction over bad Wi-Fi and never sees the answer will
+//send it again, and the same transaction twice is two payments. With an
+//Idempotency-Key header, the node remembers what it answered for a while and a
+//retry with the same key gets that answer back instead of a second transaction.
+//Keys aren't scoped to a client, so they should be random.
+
+///
+/// Header the key goes in
+///
+pub const IDEMPOTENCY_KEY_HEADER: &'static str = "Idempotency-Key";
+
+///
+/// How long an answer is remembered
+///
+pub const IDEMPOTENCY_TTL_SECS: i64 = 600;
+
+///
+/// The most answers remembered at once. The oldest is forgotten first
+///
+pub const MAX_IDEMPOTENCY_KEYS: usize = 10000;
+
+///
+/// The longest key accepted
+///
+pub const MAX_IDEMPOTENCY_KEY_LEN: usize = 255;
+
+struct Answered {
+    at: i64,
+    transaction: Transaction,
+    body: String
+}
+
+///
+/// The answers to recent requests that had an Idempotency-Key, by key
+///
+pub struct Idempotency {
+    answered: Mutex<HashMap<String, Answered>>
+}
+
+impl Idempotency {
+    pub fn new() -> Idempotency {
+        Idempotency { answered: Mutex::new(HashMap::new()) }
+    }
+
+    //only holds finished answers, nothing a panic could leave half changed
+    fn lock(&self) -> MutexGuard<HashMap<String, Answered>> {
+        self.answered.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    ///
+    /// The answer already given for `key` at `now` (seconds since the epoch), if
+    /// it's still remembered. A key sent again with a different transaction is an
+    /// error, rather than that transaction being dropped
+    ///
+    pub fn answered(&self, key: &str, transaction: &Transaction, now: i64) -> Result<Option<String>> {
+        let mut answered = self.lock();
+        answered.retain(|_, answer| answer.at + IDEMPOTENCY_TTL_SECS > now);
+        match answered.get(key) {
+            Some(answer) if answer.transaction == *transaction => Ok(Some(answer.body.clone())),
+            Some(_) => Err(Error::Validation(format!("{} {} was already used for a different transaction", IDEMPOTENCY_KEY_HEADER, key))),
+            None => Ok(None)
+        }
+    }
+
+    ///
+    /// Remember `body` as the answer for `key` at `now`
+    ///
+    pub fn answer(&self, key: String, transaction: Transaction, body: String, now: i64) {
+        let mut answered = self.lock();
+        answered.retain(|_, answer| answer.at + IDEMPOTENCY_TTL_SECS > now);
+        if answered.len() >= MAX_IDEMPOTENCY_KEYS && !answered.contains_key(&key) {
+            let oldest = answered.iter().min_by_key(|&(_, answer)| answer.at).map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                answered.remove(&oldest);
+            }
+        }
+        answered.insert(key, Answered { at: now, transaction: transaction, body: body });
+    }
+}
+
+///
+/// The Idempotency-Key header, if any
+///
+pub struct IdempotencyKey(pub Option<String>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for IdempotencyKey {
+    type Error = String;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, String> {
+        match request.headers().get_one(IDEMPOTENCY_KEY_HEADER) {
+            None => Success(IdempotencyKey(None)),
+            Some(key) if key.trim().is_empty() || key.len() > MAX_IDEMPOTENCY_KEY_LEN => {
+                Failure((Status::BadRequest, format!("{} must have 1 to {} characters", IDEMPOTENCY_KEY_HEADER, MAX_IDEMPOTENCY_KEY_LEN)))
+            },
+            Some(key) => Success(IdempotencyKey(Some(String::from(key))))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lib::error::Error;
+    use lib::transaction::Transaction;
+    use web::idempotency::*;
+
+    #[test]
+    fn answered() {
+        let idempotency = Idempotency::new();
+        let txn = Transaction::new("a".into(), "b".into(), 5);
+        assert_eq!(idempotency.answered("key", &txn, 100).expect("answered"), None);
+
+        idempotency.answer("key".into(), txn.clone(), "first".into(), 100);
+        assert_eq!(idempotency.answered("key", &txn, 101).expect("answered"), Some("first".into()));
+        match idempotency.answered("key", &Transaction::new("a".into(), "b".into(), 6), 101) {
+            Err(Error::Validation(_)) => {},
+            other => panic!("expected a validation error, got {:?}", other)
+        }
+        assert_eq!(idempotency.answered("key", &txn, 100 + IDEMPOTENCY_TTL_SECS).expect("answered"), None, "forgotten");
+    }
+}
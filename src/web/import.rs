@@ -0,0 +1,105 @@
+use lib::error::Error;
+use lib::import::{Import, Progress, IMPORT_BATCH, PROGRESS_SECS};
+use lib::journal::Event;
+use rocket::http::Status;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+use web::error::ApiError;
+use web::BlockchainState;
+
+//POST /admin/import runs an import (see lib::import) in the background, taking
+//the WRITE lock a batch at a time so the node keeps answering in between. Each
+//batch is persisted before the next is read, so after a crash the same import
+//skips what was already stored.
+
+///
+/// The import running, or how the last one went
+///
+pub struct Imports {
+    progress: Mutex<Option<Progress>>
+}
+
+impl Imports {
+    pub fn new() -> Imports {
+        Imports { progress: Mutex::new(None) }
+    }
+
+    //only ever holds a whole Progress
+    fn lock(&self) -> MutexGuard<Option<Progress>> {
+        self.progress.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    ///
+    /// The running import, or the last one. None if none ever ran
+    ///
+    pub fn progress(&self) -> Option<Progress> {
+        self.lock().clone()
+    }
+
+    fn set(&self, progress: Progress) {
+        *self.lock() = Some(progress);
+    }
+}
+
+///
+/// Start importing the blocks at `path`, unless an import is already running
+///
+pub fn start(state: Arc<BlockchainState>, path: PathBuf) -> Result<Progress, ApiError> {
+    state.lead()?;
+    let mut import = Import::open(&path).map_err(|e| ApiError::new(Status::BadRequest, format!("Can't read {}. {}", path.display(), e)))?;
+    let progress = import.progress("running", None);
+    {
+        let mut current = state.imports.lock();
+        if let Some(ref running) = *current {
+            if running.state == "running" {
+                return Err(ApiError::new(Status::Conflict, format!("Already importing {}", running.path)));
+            }
+        }
+        *current = Some(progress.clone());
+    }
+    info!("Importing blocks from {}", path.display());
+    thread::spawn(move || {
+        let finished = match run(&state, &mut import) {
+            Ok(()) => import.progress("done", None),
+            Err(e) => import.progress("failed", Some(e.to_string()))
+        };
+        match finished.error {
+            None => info!("Imported {} blocks from {}, skipped {} we had. Our chain is {} blocks", finished.imported, finished.path, finished.skipped, finished.height),
+            Some(ref e) => error!("Import from {} stopped after {} blocks. {}", finished.path, finished.imported, e)
+        }
+        state.imports.set(finished);
+    });
+    Ok(progress)
+}
+
+fn run(state: &BlockchainState, import: &mut Import<BufReader<File>>) -> Result<(), Error> {
+    let mut logged = Instant::now();
+    loop {
+        let blocks = import.next_batch(IMPORT_BATCH)?;
+        if blocks.is_empty() {
+            return Ok(());
+        }
+        {
+            state.lead()?;
+            let mut blockchain = state.write()?;
+            let before = blockchain.len();
+            let applied = import.apply(&mut blockchain, blocks);
+            //the blocks before a bad one are kept
+            state.persist(&blockchain)?;
+            for height in before + 1..blockchain.len() + 1 {
+                state.record(|| Event::Mined(blockchain.block_at(height).expect("just imported").clone()))?;
+            }
+            applied?;
+        }
+        let progress = import.progress("running", None);
+        if logged.elapsed() >= Duration::from_secs(PROGRESS_SECS) {
+            info!("Importing from {}: {:.1}%, {:.0} blocks/s, {} blocks imported", progress.path, progress.percent, progress.blocks_per_sec, progress.imported);
+            logged = Instant::now();
+        }
+        state.imports.set(progress);
+    }
+}
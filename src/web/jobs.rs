@@ -0,0 +1,224 @@
+use chrono::offset::Utc;
+use rocket::request::Request;
+use rocket::response::{self, Response, Responder};
+use rocket::http::{ContentType, Header, Status};
+use serde_json;
+use std::collections::BTreeMap;
+use std::io::Cursor;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use web::types::JobResult;
+
+//Only one request mines at a time. Proof of work ties up a worker and holds the
+//WRITE lock, so a second /mine would just queue up behind the first and then
+//grind a block on top of it. It's told which job is running instead.
+
+/// 
+/// How many finished jobs are remembered for /mine/job/<id>
+/// 
+const JOB_HISTORY: usize = 100;
+
+struct Job {
+    started: Instant,
+    started_at: i64,
+    elapsed: Option<Duration>,
+    outcome: Option<Result<usize, String>>
+}
+
+struct Jobs {
+    next_id: u64,
+    active: Option<u64>,
+    history: BTreeMap<u64, Job>
+}
+
+/// 
+/// The mining job in progress, and the last `JOB_HISTORY` that finished.
+/// Managed by Rocket
+/// 
+pub struct MiningJobs {
+    jobs: Mutex<Jobs>
+}
+
+impl MiningJobs {
+    pub fn new() -> MiningJobs {
+        MiningJobs {
+            jobs: Mutex::new(Jobs {
+                next_id: 1,
+                active: None,
+                history: BTreeMap::new()
+            })
+        }
+    }
+
+    //a panic while mining is handled by JobGuard, the table itself is always consistent
+    fn lock(&self) -> MutexGuard<Jobs> {
+        self.jobs.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// 
+    /// Start a mining job, unless one is already running
+    /// 
+    /// returns: The new job, which finishes when it's dropped. Or `Busy` with
+    ///          the running job
+    pub fn start(&self) -> Result<JobGuard, Busy> {
+        let mut jobs = self.lock();
+        if let Some(active) = jobs.active {
+            return Err(Busy { job: active });
+        }
+        let id = jobs.next_id;
+        jobs.next_id += 1;
+        jobs.active = Some(id);
+        jobs.history.insert(id, Job {
+            started: Instant::now(),
+            started_at: Utc::now().timestamp(),
+            elapsed: None,
+            outcome: None
+        });
+        while jobs.history.len() > JOB_HISTORY {
+            let oldest = *jobs.history.keys().next().expect("not empty");
+            jobs.history.remove(&oldest);
+        }
+        Ok(JobGuard { jobs: self, id: id, outcome: None })
+    }
+
+    /// 
+    /// The status of job `id`, if it's running or recent enough to remember
+    /// 
+    pub fn status(&self, id: u64) -> Option<JobResult> {
+        let jobs = self.lock();
+        jobs.history.get(&id).map(|job| {
+            let (state, block, error) = match job.outcome {
+                None => ("running", None, None),
+                Some(Ok(index)) => ("done", Some(index), None),
+                Some(Err(ref e)) => ("failed", None, Some(e.clone()))
+            };
+            let elapsed = job.elapsed.unwrap_or_else(|| job.started.elapsed());
+            JobResult {
+                id: id,
+                state: state.into(),
+                started: job.started_at,
+                elapsed_ms: elapsed.as_secs() * 1000 + (elapsed.subsec_nanos() / 1_000_000) as u64,
+                block: block,
+                error: error
+            }
+        })
+    }
+
+    /// 
+    /// The status of the job in progress, if there is one
+    /// 
+    pub fn running(&self) -> Option<JobResult> {
+        let active = self.lock().active;
+        active.and_then(|id| self.status(id))
+    }
+
+    fn finish(&self, id: u64, outcome: Result<usize, String>) {
+        let mut jobs = self.lock();
+        if jobs.active == Some(id) {
+            jobs.active = None;
+        }
+        if let Some(job) = jobs.history.get_mut(&id) {
+            job.elapsed = Some(job.started.elapsed());
+            job.outcome = Some(outcome);
+        }
+    }
+}
+
+/// 
+/// The running job. Set its outcome with `succeeded`/`failed`; dropping it
+/// finishes the job, as failed if neither was called (e.g. mining panicked)
+/// 
+pub struct JobGuard<'a> {
+    jobs: &'a MiningJobs,
+    id: u64,
+    outcome: Option<Result<usize, String>>
+}
+
+impl<'a> JobGuard<'a> {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn succeeded(&mut self, index: usize) {
+        self.outcome = Some(Ok(index));
+    }
+
+    pub fn failed(&mut self, reason: String) {
+        self.outcome = Some(Err(reason));
+    }
+}
+
+impl<'a> Drop for JobGuard<'a> {
+    fn drop(&mut self) {
+        let outcome = self.outcome.take().unwrap_or_else(|| Err(String::from("Mining stopped unexpectedly")));
+        self.jobs.finish(self.id, outcome);
+    }
+}
+
+/// 
+/// 409 Conflict for a /mine while job `job` is running. The body names the
+/// job and the Location header points at its status
+/// 
+#[derive(Debug)]
+pub struct Busy {
+    pub job: u64
+}
+
+#[derive(Serialize)]
+struct BusyBody {
+    error: String,
+    job: u64
+}
+
+impl<'r> Responder<'r> for Busy {
+    fn respond_to(self, _: &Request) -> response::Result<'r> {
+        info!("Rejected /mine, job {} is running", self.job);
+        let body = serde_json::to_string(&BusyBody {
+            error: format!("Already mining, job {}", self.job),
+            job: self.job
+        }).map_err(|_| Status::InternalServerError)?;
+        Response::build()
+            .status(Status::Conflict)
+            .header(ContentType::JSON)
+            .header(Header::new("Location", format!("/mine/job/{}", self.job)))
+            .sized_body(Cursor::new(body))
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use web::jobs::*;
+
+    #[test]
+    fn one_job_at_a_time() {
+        let jobs = MiningJobs::new();
+        {
+            let mut job = jobs.start().ok().expect("nothing running");
+            assert_eq!(job.id(), 1);
+            assert_eq!(jobs.start().err().expect("busy").job, 1);
+            assert_eq!(jobs.status(1).expect("known").state, "running");
+            job.succeeded(2);
+        }
+        let done = jobs.status(1).expect("known");
+        assert_eq!(done.state, "done");
+        assert_eq!(done.block, Some(2));
+
+        {
+            let job = jobs.start().ok().expect("first job finished");
+            assert_eq!(job.id(), 2);
+        }
+        assert_eq!(jobs.status(2).expect("known").state, "failed", "dropped without an outcome");
+        assert!(jobs.status(3).is_none());
+    }
+
+    #[test]
+    fn forgets_old_jobs() {
+        let jobs = MiningJobs::new();
+        for _ in 0..JOB_HISTORY + 1 {
+            jobs.start().ok().expect("nothing running").succeeded(1);
+        }
+        assert!(jobs.status(1).is_none());
+        assert!(jobs.status(JOB_HISTORY as u64 + 1).is_some());
+    }
+}
@@ -0,0 +1,233 @@
+use lib::memory::{Caps, Usage};
+use rocket::{Request, Data, Response, State};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::Outcome::*;
+use std::cell::Cell;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use web::types::RouteStatsResult;
+
+//Per-route request statistics, so it's obvious when slow routes (consensus, long
+//polls) are holding up everything else.
+
+/// 
+/// How many recent latencies each route keeps for its percentiles
+/// 
+const LATENCY_SAMPLES: usize = 1000;
+
+/// 
+/// Requests that didn't match a route (404s) are counted under this name
+/// 
+const UNMATCHED: &'static str = "unmatched";
+
+//Rocket handles a request on one worker thread from on_request to on_response, so
+//the start time can live in a thread local
+thread_local!(static STARTED: Cell<Option<Instant>> = Cell::new(None));
+
+#[derive(Debug, Default)]
+struct RouteStats {
+    requests: u64,
+    client_errors: u64,
+    server_errors: u64,
+    latencies: VecDeque<Duration>
+}
+
+impl RouteStats {
+    fn record(&mut self, status: u16, elapsed: Duration) {
+        self.requests += 1;
+        if status >= 500 {
+            self.server_errors += 1;
+        } else if status >= 400 {
+            self.client_errors += 1;
+        }
+        if self.latencies.len() == LATENCY_SAMPLES {
+            self.latencies.pop_front();
+        }
+        self.latencies.push_back(elapsed);
+    }
+
+    /// 
+    /// The `p`th percentile of the recent latencies, in milliseconds
+    /// 
+    fn percentile(&self, p: usize) -> f64 {
+        if self.latencies.is_empty() {
+            return 0.0;
+        }
+        let mut sorted: Vec<Duration> = self.latencies.iter().cloned().collect();
+        sorted.sort();
+        millis(sorted[(sorted.len() - 1) * p / 100])
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs() as f64 * 1000.0 + duration.subsec_nanos() as f64 / 1_000_000.0
+}
+
+/// 
+/// Counts, error counts and latencies for each route, keyed by e.g. "GET /chain".
+/// Managed by Rocket and filled in by the `RequestTimer` fairing
+/// 
+#[derive(Debug, Default)]
+pub struct RequestStats {
+    routes: Mutex<BTreeMap<String, RouteStats>>
+}
+
+impl RequestStats {
+    pub fn new() -> RequestStats {
+        RequestStats::default()
+    }
+
+    //statistics aren't worth failing a request over, a panic mid-update loses at most one sample
+    fn lock(&self) -> MutexGuard<BTreeMap<String, RouteStats>> {
+        self.routes.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn record(&self, route: String, status: u16, elapsed: Duration) {
+        self.lock().entry(route).or_insert_with(RouteStats::default).record(status, elapsed);
+    }
+
+    /// 
+    /// A snapshot of every route's statistics
+    /// 
+    pub fn summary(&self) -> Vec<RouteStatsResult> {
+        self.lock().iter().map(|(route, stats)| RouteStatsResult {
+            route: route.clone(),
+            requests: stats.requests,
+            client_errors: stats.client_errors,
+            server_errors: stats.server_errors,
+            error_rate: (stats.client_errors + stats.server_errors) as f64 / stats.requests as f64,
+            p50_ms: stats.percentile(50),
+            p95_ms: stats.percentile(95)
+        }).collect()
+    }
+
+    /// 
+    /// The statistics in the Prometheus text format
+    /// 
+    pub fn prometheus(&self) -> String {
+        let summary = self.summary();
+        let mut out = String::new();
+        writeln!(out, "# HELP learnnet_requests_total Requests handled, by route").unwrap();
+        writeln!(out, "# TYPE learnnet_requests_total counter").unwrap();
+        for route in summary.iter() {
+            writeln!(out, "learnnet_requests_total{{route=\"{}\"}} {}", route.route, route.requests).unwrap();
+        }
+        writeln!(out, "# HELP learnnet_request_errors_total Requests answered with a 4xx or 5xx, by route").unwrap();
+        writeln!(out, "# TYPE learnnet_request_errors_total counter").unwrap();
+        for route in summary.iter() {
+            writeln!(out, "learnnet_request_errors_total{{route=\"{}\",class=\"4xx\"}} {}", route.route, route.client_errors).unwrap();
+            writeln!(out, "learnnet_request_errors_total{{route=\"{}\",class=\"5xx\"}} {}", route.route, route.server_errors).unwrap();
+        }
+        writeln!(out, "# HELP learnnet_request_latency_ms Latency of recent requests, by route").unwrap();
+        writeln!(out, "# TYPE learnnet_request_latency_ms summary").unwrap();
+        for route in summary.iter() {
+            writeln!(out, "learnnet_request_latency_ms{{route=\"{}\",quantile=\"0.5\"}} {}", route.route, route.p50_ms).unwrap();
+            writeln!(out, "learnnet_request_latency_ms{{route=\"{}\",quantile=\"0.95\"}} {}", route.route, route.p95_ms).unwrap();
+        }
+        out
+    }
+}
+
+/// 
+/// Approximately how much memory the chain, the mempool and the orphans hold,
+/// and the caps on them, in the Prometheus text format
+/// 
+pub fn memory_prometheus(usage: &Usage, caps: &Caps) -> String {
+    let mut out = String::new();
+    writeln!(out, "# HELP learnnet_memory_bytes Approximate memory held, by part").unwrap();
+    writeln!(out, "# TYPE learnnet_memory_bytes gauge").unwrap();
+    for &(part, bytes) in &[("chain", usage.chain), ("mempool", usage.mempool), ("orphans", usage.orphans)] {
+        writeln!(out, "learnnet_memory_bytes{{part=\"{}\"}} {}", part, bytes).unwrap();
+    }
+    writeln!(out, "# HELP learnnet_memory_cap_bytes The most each part may hold, the chain has no cap").unwrap();
+    writeln!(out, "# TYPE learnnet_memory_cap_bytes gauge").unwrap();
+    for &(part, bytes) in &[("mempool", caps.mempool), ("orphans", caps.orphans)] {
+        writeln!(out, "learnnet_memory_cap_bytes{{part=\"{}\"}} {}", part, bytes).unwrap();
+    }
+    out
+}
+
+/// 
+/// Times every request and records it against its route in the managed
+/// `RequestStats`
+/// 
+pub struct RequestTimer;
+
+impl Fairing for RequestTimer {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request statistics",
+            kind: Kind::Request | Kind::Response
+        }
+    }
+
+    fn on_request(&self, _: &mut Request, _: &Data) {
+        STARTED.with(|started| started.set(Some(Instant::now())));
+    }
+
+    fn on_response(&self, request: &Request, response: &mut Response) {
+        let started = STARTED.with(|started| {
+            let value = started.get();
+            started.set(None);
+            value
+        });
+        let elapsed = match started {
+            Some(started) => started.elapsed(),
+            None => return
+        };
+        let route = match request.route() {
+            Some(route) => format!("{} {}", route.method, route.uri.path()),
+            None => String::from(UNMATCHED)
+        };
+        if let Success(stats) = request.guard::<State<RequestStats>>() {
+            stats.record(route, response.status().code, elapsed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use web::metrics::*;
+    use std::time::Duration;
+
+    #[test]
+    fn summary() {
+        let stats = RequestStats::new();
+        for ms in 1..101 {
+            stats.record(String::from("GET /chain"), 200, Duration::from_millis(ms));
+        }
+        stats.record(String::from("POST /transaction/new"), 400, Duration::from_millis(5));
+        stats.record(String::from("POST /transaction/new"), 503, Duration::from_millis(7));
+
+        let summary = stats.summary();
+        assert_eq!(summary.len(), 2);
+        assert_eq!(summary[0].route, "GET /chain");
+        assert_eq!(summary[0].requests, 100);
+        assert_eq!(summary[0].error_rate, 0.0);
+        assert_eq!(summary[0].p50_ms, 50.0);
+        assert_eq!(summary[0].p95_ms, 95.0);
+        assert_eq!(summary[1].client_errors, 1);
+        assert_eq!(summary[1].server_errors, 1);
+        assert_eq!(summary[1].error_rate, 1.0);
+
+        let prometheus = stats.prometheus();
+        assert!(prometheus.contains("learnnet_requests_total{route=\"GET /chain\"} 100"));
+        assert!(prometheus.contains("learnnet_request_errors_total{route=\"POST /transaction/new\",class=\"5xx\"} 1"));
+    }
+
+    #[test]
+    fn keeps_recent_latencies() {
+        let stats = RequestStats::new();
+        for _ in 0..LATENCY_SAMPLES {
+            stats.record(String::from("GET /mine"), 200, Duration::from_secs(10));
+        }
+        for _ in 0..LATENCY_SAMPLES {
+            stats.record(String::from("GET /mine"), 200, Duration::from_millis(1));
+        }
+        let summary = stats.summary();
+        assert_eq!(summary[0].requests, 2 * LATENCY_SAMPLES as u64);
+        assert_eq!(summary[0].p95_ms, 1.0, "old samples are forgotten");
+    }
+}
@@ -1,134 +1,2447 @@
 mod converters;
 mod api;
 mod types;
+mod cache;
+mod error;
+mod metrics;
+mod jobs;
+mod protocol;
+mod stream;
+mod compat;
+mod presentation;
+pub mod cluster;
+pub mod schedule;
+pub mod faucet;
+pub mod automine;
+pub mod sync;
+pub mod idempotency;
+pub mod control;
+pub mod import;
+pub mod announce;
+pub mod events;
 
-use std::fmt::{Display};
+use chrono::offset::Utc;
 use rocket;
 use rocket::{State};
-use rocket::response::content;
+use rocket::config::{Config, Environment};
+use rocket::response::{content, status};
+use rocket::http::{ContentType, Status};
 use lib::blockchain::*;
 use lib::transaction::*;
-use std::sync::{RwLock};
+use lib::consensus::{Candidate, Consensus, Outcome};
+use lib::error::Error;
+use lib::export::Format;
+use lib::forks;
+use lib::dot;
+use lib::journal::{Event, Journal};
+use lib::mempool::{self, MAX_FETCH};
+use lib::peers::{self, Peers, DEFAULT_PEERS_PER_ROUND};
+use lib::protocol::{DEFAULT_NETWORK, DEFAULT_PORT};
+use lib::rounds::{PeerReport, Round, RoundLog, MAX_ROUNDS};
+use lib::rejections::{Kind, Rejection, RejectionLog, MAX_REJECTIONS};
+use lib::standard::Standardness;
+use lib::store::{self, ChainStore};
+use std::cmp;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use url::Url;
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockResult, TryLockError};
+use std::time::{Duration, Instant};
+use std::thread;
 use web::types::*;
+use web::converters::{self, Admin, BlockHash, TxId};
+use web::cache::{Cached, IfNoneMatch};
+use web::error::ApiError;
+use web::metrics::{RequestStats, RequestTimer};
+use web::jobs::{Busy, MiningJobs};
+use web::protocol::{Peer, ProtocolHeaders};
+use web::stream::ChainStream;
+use web::cluster::{Cluster, Election, Role};
+use web::compat::{self, Schema};
+use web::presentation::Presentation;
+use web::schedule::Schedule;
+use web::faucet::{Faucet, FaucetToken, FAUCET_ADDRESS};
+use web::automine::AutoMine;
+use web::idempotency::{Idempotency, IdempotencyKey};
+use web::import::Imports;
+use web::events::Events;
 use serde_json;
 use serde::Serialize;
 
 ///
-/// A RwLock around `Blockchain`
+/// A RwLock around `Blockchain`, and another around the `Peers`
 /// 
 /// It's passed to API methods exposed by Rocket and unlocked for reading or
-/// writing as required
+/// writing as required. The peers have their own lock so registering a node
+/// never waits on mining or chain reads.
+/// 
+/// timeout: How long a request waits for a lock (and for each peer during
+///          consensus) before giving up
+/// admin_token: Secret the X-Admin-Token header must match on admin routes.
+///              None disables admin routes
+/// store: Where new blocks are written, while holding the WRITE lock. None keeps
+///        the chain in memory only
+/// journal: Where every change is recorded for replay, while holding the WRITE
+///          lock. None records nothing
+/// lenient_content_type: Parse transaction bodies as json whatever their
+///                       Content-Type says
+/// network: The network this node is on, sent to and checked against peers
+/// default_port: The port of peers registered by host alone, see `parse_node`
+/// peers_file: Where the peers are saved whenever they change. None keeps them
+///             in memory only
+/// peers_per_round: How many peers each consensus round asks for their chain
+/// max_pending_per_sender: How many transactions one sender may have pending
+/// cluster: Set when replicas share `store`. Only the leader writes, the rest
+///          serve reads. None is a node on its own
+/// tutorial_schema: Answer /chain and /mine with the Python tutorial's json,
+///                  except to peers
+/// schedule: When consensus runs without being asked, and how it last went
+/// rounds: The last consensus rounds, also written to a file if the log has one
+/// rejections: The last transactions and chains we refused, see `rejections`
+/// faucet: Hands out coins on a test network. None has no faucet
+/// jobs: The mining job in progress, whether a request or auto_mine started it
+/// auto_mine: When to mine without being asked. Off unless configured
+/// mempool_sync: How often to reconcile our pending transactions with peers'.
+///               None only answers their requests
+/// standardness: Policy on which transactions are admitted to the mempool, on top
+///               of the consensus rules. None admits any valid transaction
+/// public_address: The interface the public routes are served on. None takes
+///                 Rocket.toml's, or ROCKET_ADDRESS
+/// public_port: The port they're served on. None takes Rocket.toml's, or
+///              ROCKET_PORT
+/// peer_listener: The address and port peer routes are served on, away from the
+///                public ones. None serves everything on one port
+/// control_socket: Where the Unix socket for operator commands is, see
+///                 `control`. None has no socket
+/// imports: The chain import running in the background, or the last one
+/// advertised_url: Where other nodes reach us, which behind NAT or a proxy isn't
+///                 where we listen. We register there with our peers on startup.
+///                 None never registers itself
+/// upnp: Forward our port on the router with UPnP on startup, and advertise
+///       where it forwards from unless `advertised_url` is set
+/// events: The latest changes, for GET /events/sse
+/// events_listener: The address and port events are served on, see `events`.
+///                  None serves no events, and publishes none
 /// 
 pub struct BlockchainState {
-    pub blockchain: RwLock<Blockchain>
+    pub blockchain: RwLock<Blockchain>,
+    pub peers: RwLock<Peers>,
+    pub timeout: Duration,
+    pub admin_token: Option<String>,
+    pub store: Option<Mutex<Box<ChainStore + Send>>>,
+    pub journal: Option<Mutex<Journal>>,
+    pub lenient_content_type: bool,
+    pub network: String,
+    pub default_port: u16,
+    pub peers_file: Option<PathBuf>,
+    pub peers_per_round: usize,
+    pub max_pending_per_sender: usize,
+    pub cluster: Option<Cluster>,
+    pub tutorial_schema: bool,
+    pub schedule: Schedule,
+    pub rounds: Mutex<RoundLog>,
+    pub rejections: Mutex<RejectionLog>,
+    pub faucet: Option<Faucet>,
+    pub jobs: MiningJobs,
+    pub auto_mine: AutoMine,
+    pub mempool_sync: Option<Duration>,
+    pub idempotency: Idempotency,
+    pub standardness: Option<Standardness>,
+    pub public_address: Option<String>,
+    pub public_port: Option<u16>,
+    pub peer_listener: Option<(String, u16)>,
+    pub control_socket: Option<PathBuf>,
+    pub imports: Imports,
+    pub advertised_url: Option<Url>,
+    pub upnp: bool,
+    pub events: Events,
+    pub events_listener: Option<(String, u16)>
 }
 
+///
+/// How many transactions one sender may have pending, unless configured otherwise
+/// 
+pub const DEFAULT_MAX_PENDING_PER_SENDER: usize = 64;
+
+///
+/// How often a waiting request retries the lock
+/// 
+const LOCK_RETRY_MS: u64 = 10;
+
+///
+/// How long /chain/next waits for a block by default, and at most
+/// 
+const NEXT_BLOCK_DEFAULT_WAIT_SECS: u64 = 30;
+const NEXT_BLOCK_MAX_WAIT_SECS: u64 = 120;
+
+///
+/// How often /chain/next checks for a new block
+/// 
+const NEXT_BLOCK_POLL_MS: u64 = 250;
+
+///
+/// How many rounds GET /consensus/history lists without ?last
+/// 
+const HISTORY_DEFAULT_ROUNDS: usize = 20;
+
+///
+/// How many rejections GET /admin/rejections lists without ?last
+/// 
+const REJECTIONS_DEFAULT: usize = 50;
+
+///
+/// How many pending transactions and reorgs GET /admin/overview lists
+/// 
+const OVERVIEW_TRANSACTIONS: usize = 20;
+const OVERVIEW_REORGS: usize = 10;
+
+///
+/// The operator dashboard. It only holds the page, everything on it comes from
+/// admin routes, with the admin token typed into it
+/// 
+const DASHBOARD: &'static str = include_str!("dashboard.html");
+
 impl BlockchainState {
-    pub fn new_with(difficulty: u64) -> BlockchainState {
+    pub fn new_with(difficulty: u64, timeout: Duration) -> BlockchainState {
+        Self::new(Blockchain::new_with(difficulty), timeout)
+    }
+
+    pub fn new(blockchain: Blockchain, timeout: Duration) -> BlockchainState {
         BlockchainState {
-            blockchain: RwLock::new(Blockchain::new_with(difficulty))
+            blockchain: RwLock::new(blockchain),
+            peers: RwLock::new(Peers::new()),
+            timeout: timeout,
+            admin_token: None,
+            store: None,
+            journal: None,
+            lenient_content_type: false,
+            network: String::from(DEFAULT_NETWORK),
+            default_port: DEFAULT_PORT,
+            peers_file: None,
+            peers_per_round: DEFAULT_PEERS_PER_ROUND,
+            max_pending_per_sender: DEFAULT_MAX_PENDING_PER_SENDER,
+            cluster: None,
+            tutorial_schema: false,
+            schedule: Schedule::manual(),
+            rounds: Mutex::new(RoundLog::in_memory()),
+            rejections: Mutex::new(RejectionLog::in_memory()),
+            faucet: None,
+            jobs: MiningJobs::new(),
+            auto_mine: AutoMine::off(),
+            mempool_sync: None,
+            idempotency: Idempotency::new(),
+            standardness: None,
+            public_address: None,
+            public_port: None,
+            peer_listener: None,
+            control_socket: None,
+            imports: Imports::new(),
+            advertised_url: None,
+            upnp: false,
+            events: Events::new(),
+            events_listener: None
+        }
+    }
+
+    ///
+    /// Refuse `transaction` if our mempool policy doesn't admit it
+    /// 
+    pub fn admit(&self, transaction: &Transaction) -> Result<(), Error> {
+        match self.standardness {
+            Some(ref standardness) => standardness.admit(transaction),
+            None => Ok(())
+        }
+    }
+
+    ///
+    /// Log that we refused something. Failing to log it doesn't fail what
+    /// refused it
+    /// 
+    pub fn reject(&self, kind: Kind, hash: Option<String>, reason: String, source: Option<String>) {
+        debug!("Rejected a {:?} from {}. {}", kind, source.as_ref().map(|source| source.as_str()).unwrap_or("unknown"), reason);
+        let rejection = Rejection { at: Utc::now().timestamp(), kind: kind, hash: hash, reason: reason, source: source };
+        if let Err(e) = self.rejections.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).record(rejection) {
+            error!("Failed to log a rejection. {}", e);
+        }
+    }
+
+    ///
+    /// Record an event in the journal, if there is one, and publish it to events
+    /// subscribers, if they're served. The event is only built when it will be
+    /// recorded or published
+    /// 
+    pub fn record<F>(&self, event: F) -> Result<(), Error> where F: FnOnce() -> Event {
+        if self.journal.is_none() && self.events_listener.is_none() {
+            return Ok(());
+        }
+        let event = event();
+        if self.events_listener.is_some() {
+            self.events.publish(&event);
+        }
+        if let Some(ref journal) = self.journal {
+            let mut journal = journal.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            journal.record(&event)?;
+        }
+        Ok(())
+    }
+
+    ///
+    /// Write any new blocks to the store, if there is one. Call with the WRITE
+    /// lock held so the store sees changes in the same order as the chain
+    /// 
+    pub fn persist(&self, blockchain: &Blockchain) -> Result<(), Error> {
+        if let Some(ref cluster) = self.cluster {
+            if !cluster.still_leading(Utc::now().timestamp())? {
+                return Err(Error::Cluster(String::from("Lost the lease before writing to the store. The change is dropped when this node catches up")));
+            }
+        }
+        if let Some(ref store) = self.store {
+            let mut store = store.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            store::sync(&mut **store, blockchain)?;
+        }
+        Ok(())
+    }
+
+    ///
+    /// Check this node may write: it isn't in a cluster, or it holds the lease.
+    /// Renews the lease. A node that just became leader first catches up with
+    /// the store, cutting off anything the last leader left half written; a
+    /// follower catches up with what the leader wrote
+    /// 
+    /// returns: `Error::Cluster` on followers, so write routes can `?` it
+    pub fn lead(&self) -> Result<(), Error> {
+        let cluster = match self.cluster {
+            Some(ref cluster) => cluster,
+            None => return Ok(())
+        };
+        match cluster.elect(Utc::now().timestamp())? {
+            Role::Leader => Ok(()),
+            Role::Promoted(term) => {
+                info!("{} is the leader for term {}", cluster.id(), term);
+                self.catch_up(true)?;
+                cluster.promoted(term);
+                Ok(())
+            },
+            Role::Follower(leader) => {
+                self.catch_up(false)?;
+                Err(Error::Cluster(format!("{} is the leader. {} only serves reads", leader, cluster.id())))
+            }
+        }
+    }
+
+    fn catch_up(&self, repair: bool) -> Result<(), Error> {
+        if let Some(ref store) = self.store {
+            let mut blockchain = self.write()?;
+            let mut store = store.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            store.reload(repair)?;
+            if store::follow(&**store, &mut blockchain)? {
+                debug!("Caught up with the store at {} blocks", blockchain.len());
+                self.record(|| Event::Replaced(blockchain.chain().clone()))?;
+            }
+        }
+        Ok(())
+    }
+
+    ///
+    /// Save the peers, if there's a peers file. Call while holding the peers
+    /// WRITE lock
+    /// 
+    pub fn save_peers(&self, peers: &Peers) -> Result<(), Error> {
+        if let Some(ref path) = self.peers_file {
+            peers.save(path)?;
+        }
+        Ok(())
+    }
+
+    ///
+    /// A sample of `peers_per_round` peers to talk to, picked from `seed`. Peers
+    /// not looked up in the last `RESOLVE_TTL_SECS` are looked up again first,
+    /// without holding the peers lock, since DNS can be slow
+    /// 
+    pub fn candidates(&self, seed: u64) -> Result<Vec<Candidate>, Error> {
+        let now = Utc::now().timestamp();
+        let stale: Vec<Url> = {
+            let peers = self.read_peers()?;
+            peers.sample(self.peers_per_round, seed, now).into_iter().filter(|node| peers.needs_resolving(node, now)).collect()
+        };
+        let lookups: Vec<(Url, Vec<SocketAddr>)> = stale.into_iter().map(|node| {
+            let addresses = peers::resolve(&node).unwrap_or_else(|e| {
+                warn!("Couldn't resolve {}. {}", node, e);
+                Vec::new()
+            });
+            (node, addresses)
+        }).collect();
+        let mut peers = self.write_peers()?;
+        for (node, addresses) in lookups {
+            peers.resolved(&node, addresses, now);
+        }
+        Ok(api::nodes(&peers, self.peers_per_round, seed, now))
+    }
+
+    ///
+    /// Get the READ lock, waiting at most `timeout`
+    /// 
+    pub fn read(&self) -> Result<RwLockReadGuard<Blockchain>, Error> {
+        Self::acquire("READ", self.timeout, || self.blockchain.try_read())
+    }
+
+    ///
+    /// Get the WRITE lock, waiting at most `timeout`
+    /// 
+    pub fn write(&self) -> Result<RwLockWriteGuard<Blockchain>, Error> {
+        Self::acquire("WRITE", self.timeout, || self.blockchain.try_write())
+    }
+
+    ///
+    /// Get the peers READ lock, waiting at most `timeout`
+    /// 
+    pub fn read_peers(&self) -> Result<RwLockReadGuard<Peers>, Error> {
+        Self::acquire("peers READ", self.timeout, || self.peers.try_read())
+    }
+
+    ///
+    /// Get the peers WRITE lock, waiting at most `timeout`
+    /// 
+    pub fn write_peers(&self) -> Result<RwLockWriteGuard<Peers>, Error> {
+        Self::acquire("peers WRITE", self.timeout, || self.peers.try_write())
+    }
+
+    ///
+    /// A handler that panics while holding the lock poisons it, which would fail
+    /// every later request. The `Blockchain` is only mutated by whole operations
+    /// (insert a block, replace the chain), so it's safe to take it back and carry on.
+    /// 
+    fn acquire<G, F>(name: &str, timeout: Duration, mut try_lock: F) -> Result<G, Error>
+        where F: FnMut() -> TryLockResult<G> {
+        
+        let started = Instant::now();
+        loop {
+            match try_lock() {
+                Ok(guard) => return Ok(guard),
+                Err(TryLockError::Poisoned(poisoned)) => {
+                    warn!("{} lock was poisoned by a panicked request. Recovering", name);
+                    return Ok(poisoned.into_inner());
+                },
+                Err(TryLockError::WouldBlock) => {
+                    if started.elapsed() >= timeout {
+                        return Err(Error::Lock(format!("Timed out after {:?} waiting for {} lock", timeout, name)));
+                    }
+                    thread::sleep(Duration::from_millis(LOCK_RETRY_MS));
+                }
+            }
+        }
+    }
+}
+
+///
+/// Start Rocket. With a `peer_listener`, peer routes are served there instead of
+/// alongside the public ones
+/// 
+pub fn init(mut blockchain_state: BlockchainState) {
+    let public = public_listener(&blockchain_state);
+    if blockchain_state.upnp {
+        //peers reach us on the peer listener, when there is one
+        let port = blockchain_state.peer_listener.as_ref().map(|&(_, port)| port).unwrap_or(public.config().port);
+        match announce::map_port(port, blockchain_state.timeout) {
+            Ok(url) => {
+                info!("Mapped port {} with UPnP, the router forwards {}", port, url);
+                if blockchain_state.advertised_url.is_none() {
+                    blockchain_state.advertised_url = Some(url);
+                }
+            },
+            Err(e) => warn!("Couldn't map port {} with UPnP, nodes outside our network may not reach us. {}", port, e)
         }
     }
+    let blockchain_state = Arc::new(blockchain_state);
+    schedule::start(blockchain_state.clone());
+    automine::start(blockchain_state.clone());
+    if let Some(interval) = blockchain_state.mempool_sync {
+        sync::start(blockchain_state.clone(), interval);
+    }
+    if blockchain_state.advertised_url.is_some() {
+        announce::start(blockchain_state.clone());
+    }
+    if let Some((address, port)) = blockchain_state.events_listener.clone() {
+        events::start(blockchain_state.clone(), &address, port).unwrap_or_else(|e| panic!("Failed to serve events on {}:{}. {}", address, port, e));
+    }
+    if let Some(path) = blockchain_state.control_socket.clone() {
+        control::start(blockchain_state.clone(), path.clone()).unwrap_or_else(|e| panic!("Failed to open the control socket {}. {}", path.display(), e));
+    }
+    let (public, peer) = listeners(public, blockchain_state);
+    if let Some(peer) = peer {
+        thread::spawn(move || {
+            let e = peer.launch();
+            error!("Peer listener stopped. {}", e);
+        });
+    }
+    public.launch();
+}
+
+///
+/// Build (but don't launch) Rocket with all routes mounted
+/// 
+pub fn rocket(blockchain_state: BlockchainState) -> rocket::Rocket {
+    mount(rocket::ignite(), Arc::new(blockchain_state), all_routes())
+}
+
+///
+/// Rocket configured as Rocket.toml and the ROCKET_ variables say, with the
+/// `public_address` and `public_port` over them
+/// 
+fn public_listener(blockchain_state: &BlockchainState) -> rocket::Rocket {
+    let ignited = rocket::ignite();
+    if blockchain_state.public_address.is_none() && blockchain_state.public_port.is_none() {
+        return ignited;
+    }
+    let mut config = ignited.config().clone();
+    if let Some(ref address) = blockchain_state.public_address {
+        config.set_address(address.clone()).unwrap_or_else(|e| panic!("Invalid address {}. {}", address, e));
+    }
+    if let Some(port) = blockchain_state.public_port {
+        config.set_port(port);
+    }
+    rocket::custom(config, true)
+}
+
+///
+/// The `public` listener, and the peer listener if `peer_listener` is set. Each
+/// counts its own requests
+/// 
+fn listeners(public: rocket::Rocket, blockchain_state: Arc<BlockchainState>) -> (rocket::Rocket, Option<rocket::Rocket>) {
+    let (address, port) = match blockchain_state.peer_listener {
+        Some((ref address, port)) => (address.clone(), port),
+        None => return (mount(public, blockchain_state, all_routes()), None)
+    };
+    let config = Environment::active()
+                     .map_err(|e| e.to_string())
+                     .and_then(|environment| Config::build(environment).address(address.clone()).port(port).finalize().map_err(|e| e.to_string()))
+                     .unwrap_or_else(|e| panic!("Invalid peer listener {}:{}. {}", address, port, e));
+    info!("Serving peers on {}:{}", address, port);
+    let peer = mount(rocket::custom(config, true), blockchain_state.clone(), peer_routes());
+    (mount(public, blockchain_state, public_routes()), Some(peer))
+}
+
+//shared, so background work like scheduled consensus can use it too
+fn mount(rocket: rocket::Rocket, blockchain_state: Arc<BlockchainState>, routes: Vec<rocket::Route>) -> rocket::Rocket {
+    rocket.manage(blockchain_state)
+          .manage(RequestStats::new())
+          .attach(RequestTimer)
+          .attach(ProtocolHeaders)
+          .attach(Election)
+          .mount("/", routes)
+}
+
+fn all_routes() -> Vec<rocket::Route> {
+    let mut routes = shared_routes();
+    routes.extend(peer_only_routes());
+    routes.extend(public_only_routes());
+    routes
+}
+
+fn public_routes() -> Vec<rocket::Route> {
+    let mut routes = shared_routes();
+    routes.extend(public_only_routes());
+    routes
+}
+
+fn peer_routes() -> Vec<rocket::Route> {
+    let mut routes = shared_routes();
+    routes.extend(peer_only_routes());
+    routes
+}
+
+///
+/// Served to peers and the public alike. Wallets and explorers read the chain too
+/// 
+fn shared_routes() -> Vec<rocket::Route> {
+    routes![
+        chain,
+        head
+    ]
 }
 
 ///
-/// Start Rocket
+/// What only other nodes call: syncing headers and mempools, registering
 /// 
-pub fn init(blockchain_state: BlockchainState) {
-    rocket::ignite()
-        .manage(blockchain_state)
-        .mount("/", routes![
-    
-            mine, 
-            new_transaction,
-            chain,
-            register_node,
-            consensus 
-            
-        ]).launch();
+fn peer_only_routes() -> Vec<rocket::Route> {
+    routes![
+        headers,
+        mempool_digest,
+        mempool,
+        register_node
+    ]
+}
+
+fn public_only_routes() -> Vec<rocket::Route> {
+    routes![
+        mine,
+        mine_preview,
+        mining_job,
+        new_transaction,
+        new_transaction_tutorial,
+        faucet,
+        validate_transaction,
+        verify,
+        chain_diff,
+        adopt,
+        next_block,
+        block_by_hash,
+        transaction,
+        receipt,
+        balance,
+        balance_at,
+        balance_proof,
+        balance_proof_at,
+        incoming,
+        incoming_since,
+        payment_request,
+        payment_request_qr,
+        stats,
+        stats_at,
+        mining_stats,
+        forks,
+        export,
+        visualize,
+        consensus,
+        node_stats,
+        consensus_status,
+        consensus_history,
+        consensus_history_last,
+        miner_address,
+        difficulty,
+        auto_mine,
+        set_auto_mine,
+        metrics,
+        requests,
+        rejections,
+        rejections_matching,
+        dashboard,
+        admin_overview,
+        start_import,
+        import_progress
+    ]
 }
 
 ///
-/// Typical return type of serialized JSON, or an http error code
+/// Typical return type of serialized JSON, or an http error
 /// 
-type JsonResult = Result<content::Json<String>, u32>;
+type JsonResult = Result<content::Json<String>, ApiError>;
 
 ///
 /// Routes. Responsible for getting read/write lock on `BlockchainState`, then
 /// converting to a `JsonResult`
 /// 
 
+///
+/// Mining runs as a job, one at a time. A /mine while a job is running is a 409
+/// naming that job, rather than waiting to mine the next block on top of it
+/// 
 #[get("/mine")]
-pub fn mine(state: State<BlockchainState>) -> JsonResult {
-     match state.blockchain.write() {
-        Ok(mut blockchain) => match api::mine(&mut blockchain) {
-            Ok(result) => to_json_result(result),
-            Err(_) => Err(500)
+pub fn mine(schema: Schema, state: State<Arc<BlockchainState>>) -> Result<JsonResult, Busy> {
+    let mut job = state.jobs.start()?;
+    let mined = mine_block(&state, schema);
+    match mined {
+        Ok((index, _)) => job.succeeded(index),
+        Err(ref e) => job.failed(e.message.clone())
+    }
+    Ok(mined.map(|(_, json)| json))
+}
+
+fn mine_block(state: &BlockchainState, schema: Schema) -> Result<(usize, content::Json<String>), ApiError> {
+    state.lead()?;
+    let mut blockchain = state.write()?;
+    let result = api::mine(&mut blockchain)?;
+    state.persist(&blockchain)?;
+    state.record(|| Event::Mined(blockchain.last_block().clone()))?;
+    let index = result.index;
+    let json = match schema {
+        Schema::Native => to_json_result(result)?,
+        Schema::Tutorial => to_json_result(compat::mine_result(blockchain.last_block()))?
+    };
+    Ok((index, json))
+}
+
+///
+/// What /mine would mine right now, without mining it
+/// 
+#[get("/mine/preview")]
+pub fn mine_preview(state: State<Arc<BlockchainState>>) -> JsonResult {
+    let blockchain = state.read()?;
+    to_json_result(api::mine_preview(&blockchain))
+}
+
+#[get("/mine/job/<id>")]
+pub fn mining_job(id: u64, state: State<Arc<BlockchainState>>) -> JsonResult {
+    match state.jobs.status(id) {
+        Some(result) => to_json_result(result),
+        None => Err(ApiError::new(Status::NotFound, format!("No mining job {}", id)))
+    }
+}
+
+///
+/// Adds a pending transaction. With an Idempotency-Key, a retry gets the first
+/// answer back rather than adding it again. A transaction we refuse is logged,
+/// see GET /admin/rejections
+/// 
+#[post("/transaction/new", data = "<transaction>")]
+pub fn new_transaction(transaction: Result<Transaction, ApiError>, key: IdempotencyKey, remote: Option<SocketAddr>, state: State<Arc<BlockchainState>>) -> JsonResult {
+    let transaction = rejected(&state, None, remote, transaction)?;
+    let added = add_pending(&transaction, key, &state);
+    rejected(&state, Some(&transaction), remote, added)
+}
+
+fn add_pending(transaction: &Transaction, key: IdempotencyKey, state: &BlockchainState) -> JsonResult {
+    state.lead()?;
+    state.admit(transaction)?;
+    let now = Utc::now().timestamp();
+    let mut blockchain = state.write()?;
+    //checked under the write lock, so two retries at once can't both add it
+    if let Some(ref key) = key.0 {
+        if let Some(body) = state.idempotency.answered(key, transaction, now)? {
+            debug!("Answering {} {} again", idempotency::IDEMPOTENCY_KEY_HEADER, key);
+            return Ok(content::Json(body));
+        }
+    }
+    let result = to_json_result(api::new_transaction(transaction, state.max_pending_per_sender, &mut blockchain)?)?;
+    if let Some(key) = key.0 {
+        state.idempotency.answer(key, transaction.clone(), result.0.clone(), now);
+    }
+    state.record(|| Event::Transaction(transaction.clone()))?;
+    Ok(result)
+}
+
+//logs a transaction route's error as a rejection when the transaction was
+//refused for what it is, rather than because we were busy or aren't leading.
+//`transaction` is None when the body didn't parse
+fn rejected<T>(state: &BlockchainState, transaction: Option<&Transaction>, remote: Option<SocketAddr>, result: Result<T, ApiError>) -> Result<T, ApiError> {
+    if let Err(ref e) = result {
+        if e.status.code >= 400 && e.status.code < 500 {
+            //the write lock is released by now
+            let hash = transaction.and_then(|txn| state.read().ok().map(|blockchain| blockchain.transaction_id(txn)));
+            state.reject(Kind::Transaction, hash, e.message.clone(), remote.map(|remote| remote.ip().to_string()));
         }
-        Err(e) => no_read_lock(e)
-    }   
+    }
+    result
+}
+
+///
+/// A digest of our pending transactions, for peers to work out which they're missing
+/// 
+#[get("/mempool/digest")]
+pub fn mempool_digest(state: State<Arc<BlockchainState>>) -> JsonResult {
+    let blockchain = state.read()?;
+    to_json_result(mempool::digest(&blockchain))
+}
+
+///
+/// Our pending transactions behind the prefixes of a digest, at most `MAX_FETCH`
+/// of them
+/// 
+#[get("/mempool?<query>")]
+pub fn mempool(query: PendingQuery, state: State<Arc<BlockchainState>>) -> JsonResult {
+    let prefixes: Vec<String> = query.prefixes.split(',').filter(|prefix| !prefix.is_empty()).map(String::from).collect();
+    if prefixes.len() > MAX_FETCH {
+        return Err(Error::Validation(format!("Ask for at most {} transactions at a time, not {}", MAX_FETCH, prefixes.len())).into());
+    }
+    let blockchain = state.read()?;
+    to_json_result(PendingResult { transactions: mempool::matching(&blockchain, &prefixes) })
 }
 
-#[post("/transaction/new", format = "application/json", data = "<transaction>")]
-pub fn new_transaction(transaction: Transaction, state: State<BlockchainState>) -> JsonResult {
-     match state.blockchain.write() {
-        Ok(mut blockchain) => to_json_result(api::new_transaction(&transaction, &mut blockchain)),
-        Err(e) => no_read_lock(e)
-    }   
+///
+/// Coins for a student on a test network, from `FAUCET_ADDRESS`
+/// 
+#[post("/faucet", format = "application/json", data = "<request>")]
+pub fn faucet(_token: FaucetToken, request: FaucetRequest, state: State<Arc<BlockchainState>>) -> JsonResult {
+    state.lead()?;
+    let mut blockchain = state.write()?;
+    //granted while holding the lock, so a refused lock doesn't use up the address's turn
+    state.faucet.as_ref().expect("checked by FaucetToken").grant(&request.address, request.amount, Utc::now().timestamp())?;
+    let result = api::faucet(&request, &mut blockchain);
+    state.record(|| Event::Transaction(Transaction::new(FAUCET_ADDRESS.into(), request.address.clone(), request.amount)))?;
+    to_json_result(result)
+}
+
+///
+/// The Python tutorial's path and answer for adding a transaction, a 201 with
+/// the block it will go in
+/// 
+#[post("/transactions/new", data = "<transaction>")]
+pub fn new_transaction_tutorial(transaction: Result<Transaction, ApiError>, remote: Option<SocketAddr>, state: State<Arc<BlockchainState>>) -> Result<status::Custom<content::Json<String>>, ApiError> {
+    let transaction = rejected(&state, None, remote, transaction)?;
+    let added = add_pending_tutorial(&transaction, &state);
+    rejected(&state, Some(&transaction), remote, added)
+}
+
+fn add_pending_tutorial(transaction: &Transaction, state: &BlockchainState) -> Result<status::Custom<content::Json<String>>, ApiError> {
+    state.lead()?;
+    state.admit(transaction)?;
+    let mut blockchain = state.write()?;
+    let index = api::add_transaction(transaction, state.max_pending_per_sender, &mut blockchain)?;
+    let result = to_json_result(compat::transaction_result(index))?;
+    state.record(|| Event::Transaction(transaction.clone()))?;
+    Ok(status::Custom(Status::Created, result))
 }
 
+#[post("/transaction/validate", data = "<transaction>")]
+pub fn validate_transaction(transaction: Result<Transaction, ApiError>, state: State<Arc<BlockchainState>>) -> JsonResult {
+    let transaction = transaction?;
+    let blockchain = state.read()?;
+    to_json_result(api::validate_transaction(&transaction, state.standardness.as_ref(), &blockchain))
+}
+
+///
+/// Typical return type of a read endpoint that supports conditional GET
+/// 
+type CachedResult<'r> = Result<Cached<'r>, ApiError>;
+
+///
+/// The ETag is the hash of the tip, so an unchanged chain is answered with a 304
+/// before anything is serialized. Otherwise a snapshot of the chain is streamed
+/// a block at a time, after the READ lock is released. ?pretty=true and
+/// ?fields= are for reading it in curl, see `presentation`
+/// 
 #[get("/chain")]
-pub fn chain(state: State<BlockchainState>) -> JsonResult {
-     match state.blockchain.read() {
-        Ok(blockchain) => to_json_result(api::chain(&blockchain)),
-        Err(e) => no_read_lock(e)
-    }   
+pub fn chain(peer: Result<Peer, Error>, schema: Schema, presentation: Result<Presentation, Error>, if_none_match: IfNoneMatch, state: State<Arc<BlockchainState>>) -> CachedResult<'static> {
+    peer?;
+    let presentation = presentation?;
+    let (etag, snapshot) = {
+        let blockchain = state.read()?;
+        (blockchain.hash_last_block(), blockchain.snapshot())
+    };
+    if if_none_match.matches(&etag) {
+        return Ok(Cached::not_modified(etag));
+    }
+    if schema == Schema::Tutorial {
+        return Ok(Cached::json(etag, presentation.to_string(&compat::chain(&snapshot), "chain")?));
+    }
+    Ok(Cached::stream(etag, ChainStream::presented(snapshot, presentation)))
 }
 
-#[post("/nodes/register", format = "application/json", data="<node_list>")]
-pub fn register_node(node_list: NodeList, state: State<BlockchainState>) -> JsonResult {
-    match state.blockchain.write() {
-        Ok(mut blockchain) => {
-            match api::register_node(&node_list, &mut blockchain) {
-                Ok(response) => to_json_result(response),
-                Err(e) => { 
-                    error!("Failed to add node. {}", e);
-                    Err(400) 
-                }
-            }
+#[get("/chain/head")]
+pub fn head(peer: Result<Peer, Error>, if_none_match: IfNoneMatch, state: State<Arc<BlockchainState>>) -> CachedResult<'static> {
+    peer?;
+    let blockchain = state.read()?;
+    to_cached_result(&if_none_match, blockchain.hash_last_block(), || api::head(&blockchain))
+}
+
+#[get("/chain/headers")]
+pub fn headers(peer: Result<Peer, Error>, state: State<Arc<BlockchainState>>) -> JsonResult {
+    peer?;
+    let blockchain = state.read()?;
+    to_json_result(api::headers(&blockchain))
+}
+
+///
+/// Where a peer's chain and ours part ways, for working out why consensus keeps
+/// switching between them. Admin only, it has us request any url we're given
+/// 
+#[get("/chain/diff?<query>")]
+pub fn chain_diff(_admin: Admin, query: DiffQuery, state: State<Arc<BlockchainState>>) -> JsonResult {
+    let peer = Url::parse(&query.peer).map_err(|e| Error::Validation(format!("Invalid peer url {}. {}", query.peer, e)))?;
+    //fetched without holding any lock, like consensus
+    let theirs = Consensus::get_headers(peer.as_str(), state.timeout, &state.network)?;
+    let blockchain = state.read()?;
+    to_json_result(api::chain_diff(peer.as_str(), &theirs, &blockchain))
+}
+
+///
+/// Download `peer`'s chain and take it if it's valid, whatever its length.
+/// Consensus would only take a longer one, so this gets a node off a bad fork
+/// 
+#[post("/admin/consensus/adopt", format = "application/json", data = "<request>")]
+pub fn adopt(_admin: Admin, request: AdoptRequest, state: State<Arc<BlockchainState>>) -> JsonResult {
+    state.lead()?;
+    let peer = Url::parse(&request.peer).map_err(|e| Error::Validation(format!("Invalid peer url {}. {}", request.peer, e)))?;
+    //fetched without holding any lock, like consensus
+    let limit = state.read()?.caps().peer_chain;
+    let chain = Consensus::get_one(peer.as_str(), state.timeout, &state.network, limit)?;
+    let mut blockchain = state.write()?;
+    let result = api::adopt(peer.as_str(), chain, &mut blockchain)?;
+    state.persist(&blockchain)?;
+    state.record(|| Event::Replaced(blockchain.chain().clone()))?;
+    to_json_result(result)
+}
+
+///
+/// Check our chain, and the stored one, from genesis. Useful after a crash or
+/// disk trouble
+/// 
+#[get("/chain/verify")]
+pub fn verify(state: State<Arc<BlockchainState>>) -> JsonResult {
+    let blockchain = state.read()?;
+    let memory = blockchain.verify(blockchain.chain());
+    let stored = match state.store {
+        Some(ref store) => {
+            let store = store.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            Some(store::verify(&**store, &blockchain))
         },
-        Err(e) => no_write_lock(e)
-    }    
+        None => None
+    };
+    let valid = memory.error.is_none() && stored.as_ref().map(|stored| stored.error.is_none()).unwrap_or(true);
+    to_json_result(VerifyResult { valid: valid, memory: memory, store: stored })
 }
 
-#[get("/nodes/resolve")]
-pub fn consensus(state: State<BlockchainState>) -> JsonResult  {
-    match state.blockchain.write() {
-        Ok(mut blockchain) => to_json_result(api::consensus(&mut blockchain)),
-        Err(e) => no_write_lock(e)
-    }    
+///
+/// Long poll. Holds the request until there's a block above `after` or the timeout
+/// passes. The read lock is only held while checking, never while waiting.
+/// Each waiting client ties up one of Rocket's workers.
+/// 
+#[get("/chain/next?<query>")]
+pub fn next_block(peer: Result<Peer, Error>, query: NextBlockQuery, state: State<Arc<BlockchainState>>) -> JsonResult {
+    peer?;
+    let wait = query.timeout.unwrap_or(NEXT_BLOCK_DEFAULT_WAIT_SECS).min(NEXT_BLOCK_MAX_WAIT_SECS);
+    let deadline = Instant::now() + Duration::from_secs(wait);
+    loop {
+        let blockchain = state.read()?;
+        if blockchain.len() > query.after || Instant::now() >= deadline {
+            return to_json_result(api::next_block(query.after, &blockchain));
+        }
+        drop(blockchain);
+        thread::sleep(Duration::from_millis(NEXT_BLOCK_POLL_MS));
+    }
 }
 
-fn no_read_lock<T, E>(err: E) -> Result<T, u32> where E : Display {
-    error!("Failed to get READ lock {}", err);
-    Err(500)
+///
+/// Typed params arrive as a `Result`, so a malformed one is a 400 rather than
+/// Rocket forwarding it to a 404. Takes ?pretty=true and ?fields= like /chain
+/// 
+#[get("/block/hash/<hash>")]
+pub fn block_by_hash(hash: Result<BlockHash, String>, presentation: Result<Presentation, Error>, state: State<Arc<BlockchainState>>) -> JsonResult {
+    let hash = hash.map_err(Error::Validation)?;
+    let presentation = presentation?;
+    let blockchain = state.read()?;
+    match api::block(&hash, &blockchain) {
+        Some(result) => Ok(content::Json(presentation.to_string(&result, "block")?)),
+        None => Err(ApiError::new(Status::NotFound, format!("No block with hash {}", &*hash)))
+    }
 }
 
-fn no_write_lock<T, E>(err: E) -> Result<T, u32> where E : Display {
-    error!("Failed to get WRITE lock {}", err);
-    Err(500)
+#[get("/transaction/<id>")]
+pub fn transaction(id: Result<TxId, String>, state: State<Arc<BlockchainState>>) -> JsonResult {
+    let id = id.map_err(Error::Validation)?;
+    let blockchain = state.read()?;
+    match api::transaction(&id, &blockchain) {
+        Some(result) => to_json_result(result),
+        None => Err(ApiError::new(Status::NotFound, format!("No transaction with id {}", &*id)))
+    }
 }
 
 ///
-/// Given a response, serialize to a Json string, or return 500 if it fails
+/// Proof that a transaction is in a block, see `ReceiptResult`. Pending
+/// transactions have no receipt yet
 /// 
-fn to_json_result<T>(response: T) -> JsonResult 
-    where T: Serialize {
-    match serde_json::to_string(&response) {
-        Ok(serialized) => Ok(content::Json(serialized)),
-        Err(e) => {
-            error!("Failed to serialize {}", e);
-            Err(500)
+#[get("/transaction/<id>/receipt")]
+pub fn receipt(id: Result<TxId, String>, state: State<Arc<BlockchainState>>) -> JsonResult {
+    let id = id.map_err(Error::Validation)?;
+    let blockchain = state.read()?;
+    match api::receipt(&id, &blockchain) {
+        Some(result) => to_json_result(result),
+        None => Err(ApiError::new(Status::NotFound, format!("No transaction with id {} in a block", &*id)))
+    }
+}
+
+///
+/// Each of these has a `?at_height=` twin. The plain route ranks lower, so it
+/// only answers when there's no query
+/// 
+#[get("/balance/<address>", rank = 2)]
+pub fn balance(address: Result<converters::Address, String>, state: State<Arc<BlockchainState>>) -> JsonResult {
+    balance_at(address, AtHeightQuery { at_height: None }, state)
+}
+
+#[get("/balance/<address>?<query>")]
+pub fn balance_at(address: Result<converters::Address, String>, query: AtHeightQuery, state: State<Arc<BlockchainState>>) -> JsonResult {
+    let address = address.map_err(Error::Validation)?;
+    let blockchain = state.read()?;
+    to_json_result(api::balance(&address, query.at_height, &blockchain)?)
+}
+
+#[get("/balance/<address>/proof", rank = 2)]
+pub fn balance_proof(address: Result<converters::Address, String>, state: State<Arc<BlockchainState>>) -> JsonResult {
+    balance_proof_at(address, AtHeightQuery { at_height: None }, state)
+}
+
+///
+/// For light clients, which have the headers but don't trust a node's sums
+/// 
+#[get("/balance/<address>/proof?<query>")]
+pub fn balance_proof_at(address: Result<converters::Address, String>, query: AtHeightQuery, state: State<Arc<BlockchainState>>) -> JsonResult {
+    let address = address.map_err(Error::Validation)?;
+    let blockchain = state.read()?;
+    to_json_result(api::balance_proof(&address, query.at_height, &blockchain)?)
+}
+
+#[get("/address/<address>/incoming", rank = 2)]
+pub fn incoming(address: Result<converters::Address, String>, state: State<Arc<BlockchainState>>) -> JsonResult {
+    incoming_since(address, IncomingQuery { since_height: None }, state)
+}
+
+///
+/// For merchants to poll for payments: pass the `height` of the last answer as
+/// `since_height` to get only the new ones
+/// 
+#[get("/address/<address>/incoming?<query>")]
+pub fn incoming_since(address: Result<converters::Address, String>, query: IncomingQuery, state: State<Arc<BlockchainState>>) -> JsonResult {
+    let address = address.map_err(Error::Validation)?;
+    let blockchain = state.read()?;
+    to_json_result(api::incoming(&address, query.since_height, &blockchain)?)
+}
+
+///
+/// For point-of-sale demos: the uri to show a customer, or the same as a QR code
+/// 
+#[get("/payment-request?<query>")]
+pub fn payment_request(query: PaymentRequestQuery) -> JsonResult {
+    let to = query.to.map_err(Error::Validation)?;
+    to_json_result(api::payment_request(&to, query.amount)?)
+}
+
+#[get("/payment-request/qr?<query>")]
+pub fn payment_request_qr(query: PaymentRequestQuery) -> Result<content::Content<Vec<u8>>, ApiError> {
+    let to = query.to.map_err(Error::Validation)?;
+    Ok(content::Content(ContentType::PNG, api::payment_request_qr(&to, query.amount)?))
+}
+
+#[get("/stats", rank = 2)]
+pub fn stats(state: State<Arc<BlockchainState>>) -> JsonResult {
+    stats_at(AtHeightQuery { at_height: None }, state)
+}
+
+#[get("/stats?<query>")]
+pub fn stats_at(query: AtHeightQuery, state: State<Arc<BlockchainState>>) -> JsonResult {
+    let blockchain = state.read()?;
+    to_json_result(api::stats(query.at_height, &blockchain)?)
+}
+
+///
+/// Block times, hash rate and who mined what, for the classroom leaderboard
+/// 
+#[get("/stats/mining")]
+pub fn mining_stats(state: State<Arc<BlockchainState>>) -> JsonResult {
+    let blockchain = state.read()?;
+    to_json_result(api::mining_stats(&blockchain))
+}
+
+///
+/// Where each soft fork deployment is at, and how many blocks signal for it
+/// 
+#[get("/forks")]
+pub fn forks(state: State<Arc<BlockchainState>>) -> JsonResult {
+    let blockchain = state.read()?;
+    let height = blockchain.len();
+    to_json_result(ForksResult {
+        height: height,
+        version: blockchain.policy().version_at(height + 1),
+        deployments: forks::status(&blockchain)
+    })
+}
+
+///
+/// Chain history for loading into pandas or a spreadsheet
+/// 
+#[get("/export?<query>")]
+pub fn export(query: ExportQuery, state: State<Arc<BlockchainState>>) -> Result<content::Content<String>, ApiError> {
+    let format = Format::from_name(&query.format)
+                    .ok_or_else(|| Error::Validation(format!("Unknown format '{}'. Expected jsonl, csv or blocks", query.format)))?;
+    let blockchain = state.read()?;
+    let content_type = match format {
+        Format::Jsonl | Format::Blocks => ContentType::new("application", "x-ndjson"),
+        Format::Csv => ContentType::new("text", "csv")
+    };
+    Ok(content::Content(content_type, api::export(format, &blockchain)?))
+}
+
+///
+/// Our chain and any orphaned forks as a Graphviz DOT graph. Render it with
+/// `dot -Tsvg`
+/// 
+#[get("/visualize")]
+pub fn visualize(state: State<Arc<BlockchainState>>) -> Result<content::Content<String>, ApiError> {
+    let blockchain = state.read()?;
+    Ok(content::Content(ContentType::new("text", "vnd.graphviz"), dot::graph(&blockchain)))
+}
+
+#[post("/nodes/register", format = "application/json", data="<node_list>")]
+pub fn register_node(peer: Result<Peer, Error>, schema: Schema, node_list: NodeList, state: State<Arc<BlockchainState>>) -> Result<status::Custom<content::Json<String>>, ApiError> {
+    peer?;
+    let mut peers = state.write_peers()?;
+    let result = api::register_node(&node_list, state.default_port, state.advertised_url.as_ref(), &mut peers)?;
+    state.save_peers(&peers)?;
+    match schema {
+        Schema::Native => Ok(status::Custom(Status::Ok, to_json_result(result)?)),
+        Schema::Tutorial => Ok(status::Custom(Status::Created, to_json_result(compat::TutorialRegisterResult {
+            message: result.message,
+            total_nodes: peers.nodes().map(compat::netloc).collect()
+        })?))
+    }
+}
+
+///
+/// What we've asked of the peer at `id`, its host and port, how much of what it
+/// sent we rejected, and whether that got it banned. Admin only, like the
+/// other operator endpoints
+/// 
+#[get("/nodes/<id>/stats")]
+pub fn node_stats(_admin: Admin, id: String, state: State<Arc<BlockchainState>>) -> JsonResult {
+    let peers = state.read_peers()?;
+    match api::node_stats(&id, &peers, Utc::now().timestamp()) {
+        Some(stats) => to_json_result(stats),
+        None => Err(ApiError::new(Status::NotFound, format!("No peer {}", id)))
+    }
+}
+
+///
+/// Neighbour chains are downloaded without holding any lock, so a slow or
+/// unresponsive peer can't block mining or reads. Only a sample of
+/// `peers_per_round` peers is asked, so a round costs the same however many
+/// peers are registered. The highest and fastest peers are asked first, and
+/// the round stops early once we have a chain longer than the rest last sent.
+/// The WRITE lock is only taken to compare and (maybe) replace our chain.
+/// 
+#[get("/nodes/resolve")]
+pub fn consensus(schema: Schema, state: State<Arc<BlockchainState>>) -> JsonResult  {
+    resolve(&state, |result| match schema {
+        Schema::Native => to_json_result(result),
+        Schema::Tutorial => to_json_result(compat::consensus_result(&result))
+    })
+}
+
+///
+/// A round of consensus, answered by `respond` while the WRITE lock is held.
+/// Recorded in the schedule's status and the round log, however it went
+/// 
+pub fn resolve<T, F>(state: &BlockchainState, respond: F) -> Result<T, ApiError>
+    where F: FnOnce(ConsensusReponse) -> Result<T, ApiError> {
+    let started = Instant::now();
+    let mut round = Round {
+        started: Utc::now().timestamp(),
+        duration_ms: 0,
+        height: 0,
+        new_height: 0,
+        replaced: false,
+        peers: Vec::new(),
+        error: None
+    };
+    let resolved = resolve_round(state, &mut round, respond);
+    let outcome = match resolved {
+        Ok(_) if round.replaced => String::from("Our chain was replaced"),
+        Ok(_) => String::from("Our chain is authoritative"),
+        Err(ref e) => {
+            round.error = Some(e.message.clone());
+            format!("Failed. {}", e.message)
         }
+    };
+    let duration = started.elapsed();
+    round.duration_ms = duration.as_secs() * 1000 + duration.subsec_nanos() as u64 / 1_000_000;
+    state.schedule.finished(round.started, duration, outcome);
+    rejected_chains(state, &round.peers, round.started);
+    //the round is over either way, losing its record is no reason to fail it
+    if let Err(e) = state.rounds.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).record(round) {
+        error!("Failed to record the consensus round. {}", e);
     }
-}
\ No newline at end of file
+    resolved
+}
+
+//logs the peers that answered with a chain we won't take, rather than failing
+//to answer, and counts it against them
+fn rejected_chains(state: &BlockchainState, reports: &[PeerReport], now: i64) {
+    let rejected: Vec<&PeerReport> = reports.iter().filter(|report| match report.outcome {
+        Some(outcome) => outcome != Outcome::Sent && !outcome.is_failure(),
+        None => false
+    }).collect();
+    if rejected.is_empty() {
+        return;
+    }
+    for report in rejected.iter() {
+        let reason = report.error.clone().unwrap_or_else(|| format!("{:?}", report.outcome));
+        state.reject(Kind::Chain, None, reason, Some(report.node.clone()));
+    }
+    let mut peers = match state.write_peers() {
+        Ok(peers) => peers,
+        Err(e) => {
+            error!("Couldn't count rejected chains against their peers. {}", e);
+            return;
+        }
+    };
+    for report in rejected {
+        if let Ok(url) = Url::parse(&report.node) {
+            peers.misbehaved(&url, now);
+        }
+    }
+    if let Err(e) = state.save_peers(&peers) {
+        error!("Failed to save the peers after rejecting their chains. {}", e);
+    }
+}
+
+//fills in `round` as far as it gets
+fn resolve_round<T, F>(state: &BlockchainState, round: &mut Round, respond: F) -> Result<T, ApiError>
+    where F: FnOnce(ConsensusReponse) -> Result<T, ApiError> {
+    state.lead()?;
+    round.height = state.read()?.len();
+    round.new_height = round.height;
+    let now = Utc::now();
+    let seed = (now.timestamp() as u64) ^ (now.timestamp_subsec_nanos() as u64) << 32;
+    let candidates = state.candidates(seed)?;
+    //no lock is held while fetching, each chain is checked block by block as it
+    //arrives, by a verifier following our rules
+    let (verifier, limit) = {
+        let blockchain = state.read()?;
+        (blockchain.verifier(), blockchain.caps().peer_chain)
+    };
+    let results = Consensus::fetch(candidates.as_slice(), state.timeout, &state.network, &verifier, limit, |chain| {
+        state.read().map(|blockchain| chain_work(chain) > blockchain.work() && blockchain.meets_difficulty(chain)).unwrap_or(false)
+    });
+    round.peers = results.iter().map(PeerReport::from).collect();
+    let neighbour_chains = {
+        let mut peers = state.write_peers()?;
+        let chains = api::record_round(results, Utc::now().timestamp(), &mut peers);
+        //the peers are updated in memory, failing to save them is no reason to drop the chains we got
+        if let Err(e) = state.save_peers(&peers) {
+            error!("Failed to save the peers after a consensus round. {}", e);
+        }
+        chains
+    };
+    let mut blockchain = state.write()?;
+    let (tip, work) = (blockchain.hash_last_block(), blockchain.work());
+    let mut result = api::consensus(neighbour_chains, &mut blockchain);
+    api::mark_invalid(&mut round.peers, &result.invalid);
+    api::mark_adopted(&mut round.peers, result.adopted, work);
+    result.peers = round.peers.clone();
+    let response = respond(result);
+    round.replaced = blockchain.hash_last_block() != tip;
+    round.new_height = blockchain.len();
+    if round.replaced {
+        state.persist(&blockchain)?;
+        state.record(|| Event::Replaced(blockchain.chain().clone()))?;
+    }
+    response
+}
+
+///
+/// When consensus last ran and how it went, and when it runs next
+/// 
+#[get("/consensus/status")]
+pub fn consensus_status(state: State<Arc<BlockchainState>>) -> JsonResult {
+    to_json_result(state.schedule.status())
+}
+
+///
+/// The last `HISTORY_DEFAULT_ROUNDS` consensus rounds, newest first: who was
+/// asked, what they sent, and how it went
+/// 
+#[get("/consensus/history", rank = 2)]
+pub fn consensus_history(state: State<Arc<BlockchainState>>) -> JsonResult {
+    consensus_history_last(HistoryQuery { last: None }, state)
+}
+
+///
+/// GET /consensus/history?last=N for the last N rounds, at most `MAX_ROUNDS`
+/// 
+#[get("/consensus/history?<query>")]
+pub fn consensus_history_last(query: HistoryQuery, state: State<Arc<BlockchainState>>) -> JsonResult {
+    let count = query.last.unwrap_or(HISTORY_DEFAULT_ROUNDS);
+    let rounds = state.rounds.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    to_json_result(ConsensusHistoryResult { rounds: rounds.last(cmp::min(count, MAX_ROUNDS)) })
+}
+
+#[put("/config/miner-address", format = "application/json", data = "<miner_address>")]
+pub fn miner_address(_admin: Admin, miner_address: MinerAddress, state: State<Arc<BlockchainState>>) -> JsonResult {
+    let mut blockchain = state.write()?;
+    to_json_result(api::miner_address(&miner_address, &mut blockchain)?)
+}
+
+///
+/// When the node mines by itself
+/// 
+#[get("/admin/auto-mine")]
+pub fn auto_mine(_admin: Admin, state: State<Arc<BlockchainState>>) -> JsonResult {
+    to_json_result(state.auto_mine.config())
+}
+
+///
+/// Mine once `pending` transactions are waiting, or once waiting transactions
+/// are `interval_secs` after the last block. Takes effect straight away
+/// 
+#[put("/admin/auto-mine", format = "application/json", data = "<config>")]
+pub fn set_auto_mine(_admin: Admin, config: AutoMineConfig, state: State<Arc<BlockchainState>>) -> JsonResult {
+    state.auto_mine.set(config)?;
+    info!("auto-mining changed to {:?}", state.auto_mine.config());
+    to_json_result(state.auto_mine.config())
+}
+
+#[put("/admin/difficulty", format = "application/json", data = "<difficulty>")]
+pub fn difficulty(_admin: Admin, difficulty: Difficulty, state: State<Arc<BlockchainState>>) -> JsonResult {
+    let mut blockchain = state.write()?;
+    to_json_result(api::difficulty(&difficulty, &mut blockchain)?)
+}
+
+///
+/// Request statistics, and roughly how much memory the node holds, for a
+/// Prometheus scraper
+/// 
+#[get("/metrics")]
+pub fn metrics(stats: State<RequestStats>, state: State<Arc<BlockchainState>>) -> Result<content::Plain<String>, ApiError> {
+    let memory = {
+        let blockchain = state.read()?;
+        metrics::memory_prometheus(&blockchain.usage(), blockchain.caps())
+    };
+    Ok(content::Plain(stats.prometheus() + &memory))
+}
+
+///
+/// The last `REJECTIONS_DEFAULT` transactions and chains we refused, newest
+/// first, with why and who sent them
+/// 
+#[get("/admin/rejections", rank = 2)]
+pub fn rejections(admin: Admin, state: State<Arc<BlockchainState>>) -> JsonResult {
+    rejections_matching(admin, RejectionsQuery { last: None, hash: None, source: None }, state)
+}
+
+///
+/// GET /admin/rejections?hash=<transaction id> for why a transaction was
+/// refused, ?source= for what a client or peer sent, ?last=N for more
+/// 
+#[get("/admin/rejections?<query>")]
+pub fn rejections_matching(_admin: Admin, query: RejectionsQuery, state: State<Arc<BlockchainState>>) -> JsonResult {
+    let count = cmp::min(query.last.unwrap_or(REJECTIONS_DEFAULT), MAX_REJECTIONS);
+    let log = state.rejections.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let matching = log.last(MAX_REJECTIONS).into_iter()
+                      .filter(|rejection| query.hash.is_none() || rejection.hash == query.hash)
+                      .filter(|rejection| query.source.is_none() || rejection.source == query.source)
+                      .take(count)
+                      .collect();
+    to_json_result(RejectionsResult { rejections: matching })
+}
+
+///
+/// Request statistics for each route, as json
+/// 
+#[get("/admin/requests")]
+pub fn requests(_admin: Admin, stats: State<RequestStats>) -> JsonResult {
+    to_json_result(stats.summary())
+}
+
+///
+/// A page for operators: height, peers, mempool, mining and reorgs, with buttons
+/// for mining and consensus
+/// 
+#[get("/admin")]
+pub fn dashboard() -> content::Html<&'static str> {
+    content::Html(DASHBOARD)
+}
+
+///
+/// Everything the dashboard shows, in one request
+/// 
+#[get("/admin/overview")]
+pub fn admin_overview(_admin: Admin, state: State<Arc<BlockchainState>>) -> JsonResult {
+    let blockchain = state.read()?;
+    let peers = state.read_peers()?;
+    let rounds = state.rounds.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let peer_overviews = peers.nodes().map(|node| {
+        let info = peers.info(node).cloned().unwrap_or_default();
+        PeerOverview {
+            node: node.to_string(),
+            good: info.is_good(),
+            height: info.height,
+            latency_ms: info.latency_ms,
+            failures: info.failures,
+            last_seen: info.last_seen
+        }
+    }).collect();
+    to_json_result(AdminOverview {
+        height: blockchain.len(),
+        tip: blockchain.hash_last_block(),
+        difficulty: blockchain.difficulty(),
+        miner_address: blockchain.miner_address(),
+        pending: blockchain.current_transactions().len(),
+        transactions: blockchain.current_transactions().iter().take(OVERVIEW_TRANSACTIONS).collect(),
+        peers: peer_overviews,
+        auto_mine: state.auto_mine.config(),
+        mining: state.jobs.running(),
+        consensus: state.schedule.status(),
+        reorgs: rounds.last(MAX_ROUNDS).into_iter().filter(|round| round.replaced).take(OVERVIEW_REORGS).collect()
+    })
+}
+
+///
+/// Import a file of blocks on the node's machine, like `chain import`, in the
+/// background. Follow it with GET /admin/import
+/// 
+#[post("/admin/import", format = "application/json", data = "<request>")]
+pub fn start_import(_admin: Admin, request: ImportRequest, state: State<Arc<BlockchainState>>) -> Result<status::Custom<content::Json<String>>, ApiError> {
+    let progress = import::start(state.inner().clone(), PathBuf::from(request.path))?;
+    Ok(status::Custom(Status::Accepted, to_json_result(progress)?))
+}
+
+///
+/// How the running import is going, or how the last one went
+/// 
+#[get("/admin/import")]
+pub fn import_progress(_admin: Admin, state: State<Arc<BlockchainState>>) -> JsonResult {
+    match state.imports.progress() {
+        Some(progress) => to_json_result(progress),
+        None => Err(ApiError::new(Status::NotFound, String::from("No import has run")))
+    }
+}
+
+///
+/// Given a response, serialize to a Json string, or return 500 if it fails
+/// 
+fn to_json_result<T>(response: T) -> JsonResult 
+    where T: Serialize {
+    let serialized = serde_json::to_string(&response).map_err(Error::from)?;
+    Ok(content::Json(serialized))
+}
+
+///
+/// 304 if the client has `etag`, otherwise serialize the response
+/// 
+fn to_cached_result<'r, T, F>(if_none_match: &IfNoneMatch, etag: String, response: F) -> CachedResult<'r>
+    where T: Serialize, F: FnOnce() -> T {
+    if if_none_match.matches(&etag) {
+        return Ok(Cached::not_modified(etag));
+    }
+    match to_json_result(response()) {
+        Ok(json) => Ok(Cached::json(etag, json.0)),
+        Err(e) => Err(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use web::{self, BlockchainState};
+    use lib::error::Error;
+    use lib::blockchain::{Blockchain, DEFAULT_MINER_ADDRESS};
+    use lib::export::{self, Format};
+    use lib::forks::Deployment;
+    use lib::hasher::HashAlgorithm;
+    use lib::lease::Lease;
+    use lib::merkle::{self, ProofStep};
+    use lib::protocol;
+    use lib::store::{BlockFile, ChainStore};
+    use lib::transaction::Transaction;
+    use lib::standard::{Standardness, DEFAULT_DUST};
+    use lib::versions::Rules;
+    use lib::testvectors;
+    use rocket::local::Client;
+    use rocket::http::{ContentType, Header, Status};
+    use serde_json::{self, Value};
+    use std::env;
+    use std::fs;
+    use std::iter;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+    use url::Url;
+    use web::cluster::Cluster;
+    use web::faucet::Faucet;
+
+    fn client() -> Client {
+        client_with(|_| {})
+    }
+
+    //a client for a node set up by `f`
+    fn client_with<F: FnOnce(&mut BlockchainState)>(f: F) -> Client {
+        let mut state = BlockchainState::new_with(1, Duration::from_secs(1));
+        f(&mut state);
+        Client::new(web::rocket(state)).expect("valid rocket instance")
+    }
+
+    fn get_json(client: &Client, uri: &str) -> Value {
+        let mut response = client.get(uri).dispatch();
+        assert_eq!(response.status(), Status::Ok, "GET {}", uri);
+        let body = response.body_string().expect("expected a body");
+        serde_json::from_str(body.as_str()).expect("expected a json body")
+    }
+
+    fn post_json(client: &Client, uri: &str, json: &str) -> Value {
+        let mut response = client.post(uri)
+                                 .header(ContentType::JSON)
+                                 .body(json)
+                                 .dispatch();
+        assert_eq!(response.status(), Status::Ok, "POST {}", uri);
+        let body = response.body_string().expect("expected a body");
+        serde_json::from_str(body.as_str()).expect("expected a json body")
+    }
+
+    #[test]
+    fn lifecycle() {
+        let client = client();
+
+        //submit transaction
+        let added = post_json(&client, "/transaction/new", r#"{"sender": "a", "recipient": "b", "amount": 5}"#);
+        assert_eq!(added, Value::String("Transaction added at block 2".into()));
+
+        //mine
+        let mined = get_json(&client, "/mine");
+        assert_eq!(mined["message"], "New Block Forged");
+        assert_eq!(mined["index"], 2);
+        let transactions = mined["transactions"].as_array().expect("transactions array");
+        assert_eq!(transactions.len(), 2, "submitted transaction plus the mining reward");
+        assert!(transactions.iter().any(|t| t["sender"] == "a" && t["recipient"] == "b" && t["amount"] == 5));
+
+        assert_eq!(mined["reward"]["sender"], "0");
+
+        //verify chain
+        let chain = get_json(&client, "/chain");
+        assert_eq!(chain["length"], 2);
+        assert_eq!(chain["chain"][1]["header"]["nonce"], mined["nonce"]);
+        assert_eq!(chain["chain"][1]["header"]["previous_hash"], mined["previous_hash"]);
+        assert_eq!(chain["chain"][1]["header"]["merkle_root"], mined["merkle_root"]);
+
+        //headers only
+        let headers = get_json(&client, "/chain/headers");
+        assert_eq!(headers["length"], 2);
+        assert_eq!(headers["headers"][1], chain["chain"][1]["header"]);
+
+        //register peer (nothing listening there)
+        let registered = post_json(&client, "/nodes/register", r#"{"nodes": ["http://localhost:1"]}"#);
+        assert_eq!(registered["message"], "New nodes have been added");
+        assert_eq!(registered["total_nodes"], 1);
+
+        //resolve. The peer is unreachable so we keep our own chain
+        let resolved = get_json(&client, "/nodes/resolve");
+        assert_eq!(resolved["message"], "Our chain is authoritative");
+        assert_eq!(resolved["chain"].as_array().expect("chain array").len(), 2);
+        assert!(resolved["new_chain"].is_null());
+        assert_eq!(resolved["peers"][0]["outcome"], "unreachable", "the round carries on without it");
+        assert_eq!(resolved["peers"][0]["validated"], false);
+        assert_eq!(resolved["peers"][0]["adopted"], false);
+    }
+
+    #[test]
+    fn consensus_status() {
+        let client = client();
+        let status = get_json(&client, "/consensus/status");
+        assert!(status["last_run"].is_null());
+        assert!(status["interval_secs"].is_null(), "only runs when asked");
+
+        get_json(&client, "/nodes/resolve");
+        let status = get_json(&client, "/consensus/status");
+        assert!(status["last_run"].is_i64());
+        assert_eq!(status["last_outcome"], "Our chain is authoritative");
+    }
+
+    #[test]
+    fn consensus_history() {
+        let client = client();
+        assert_eq!(get_json(&client, "/consensus/history")["rounds"].as_array().expect("rounds").len(), 0);
+
+        post_json(&client, "/nodes/register", r#"{"nodes": ["http://localhost:1"]}"#);
+        get_json(&client, "/nodes/resolve");
+        get_json(&client, "/nodes/resolve");
+        let history = get_json(&client, "/consensus/history");
+        assert_eq!(history["rounds"].as_array().expect("rounds").len(), 2);
+        let round = &history["rounds"][0];
+        assert_eq!(round["height"], 1);
+        assert_eq!(round["replaced"], false);
+        assert_eq!(round["peers"][0]["node"], "http://localhost:1/");
+        assert!(round["peers"][0]["height"].is_null());
+        assert!(round["peers"][0]["error"].is_string(), "nothing listening");
+
+        assert_eq!(get_json(&client, "/consensus/history?last=1")["rounds"].as_array().expect("rounds").len(), 1);
+    }
+
+    #[test]
+    fn node_stats() {
+        let client = client_with(|state| state.admin_token = Some(String::from("secret")));
+        let get = |uri: &str, token: &str| client.get(uri)
+                                                 .header(Header::new("X-Admin-Token", String::from(token)))
+                                                 .dispatch();
+        post_json(&client, "/nodes/register", r#"{"nodes": ["http://localhost:1"]}"#);
+        get_json(&client, "/nodes/resolve");
+        assert_eq!(client.get("/nodes/localhost:1/stats").dispatch().status(), Status::Unauthorized);
+        assert_eq!(get("/nodes/localhost:1/stats", "wrong").status(), Status::Unauthorized);
+        let mut response = get("/nodes/localhost:1/stats", "secret");
+        assert_eq!(response.status(), Status::Ok);
+        let stats: Value = serde_json::from_str(&response.body_string().expect("expected a body")).expect("expected a json body");
+        assert_eq!(stats["node"], "http://localhost:1/");
+        assert_eq!(stats["failures"], 1);
+        assert_eq!(stats["banned"], false);
+        assert_eq!(stats["stats"]["requests"], 1);
+        assert_eq!(stats["stats"]["invalid"], 0, "not answering isn't misbehaving");
+        assert_eq!(get("/nodes/localhost:2/stats", "secret").status(), Status::NotFound);
+    }
+
+    #[test]
+    fn chain_diff() {
+        let client = client_with(|state| state.admin_token = Some(String::from("secret")));
+        let get = |uri: &str, token: &str| client.get(uri)
+                                                 .header(Header::new("X-Admin-Token", String::from(token)))
+                                                 .dispatch()
+                                                 .status();
+        assert_eq!(get("/chain/diff?peer=http://localhost:1", "wrong"), Status::Unauthorized);
+        assert_eq!(get("/chain/diff?peer=not%20a%20url", "secret"), Status::BadRequest);
+        assert_eq!(get("/chain/diff?peer=http://localhost:1", "secret"), Status::BadGateway, "nothing listening");
+    }
+
+    #[test]
+    fn admin_adopt() {
+        let client = client_with(|state| state.admin_token = Some(String::from("secret")));
+        let post = |peer: &str, token: &str| client.post("/admin/consensus/adopt")
+                                                   .header(ContentType::JSON)
+                                                   .header(Header::new("X-Admin-Token", String::from(token)))
+                                                   .body(format!(r#"{{"peer": "{}"}}"#, peer))
+                                                   .dispatch()
+                                                   .status();
+        assert_eq!(post("http://localhost:1", "wrong"), Status::Unauthorized);
+        assert_eq!(post("not a url", "secret"), Status::BadRequest);
+        assert_eq!(post("http://localhost:1", "secret"), Status::BadGateway, "nothing listening");
+    }
+
+    #[test]
+    fn admin_auto_mine() {
+        let client = client_with(|state| state.admin_token = Some(String::from("secret")));
+        let put = |json: &str| client.put("/admin/auto-mine")
+                                     .header(ContentType::JSON)
+                                     .header(Header::new("X-Admin-Token", "secret"))
+                                     .body(String::from(json))
+                                     .dispatch()
+                                     .status();
+        assert_eq!(put(r#"{"pending": 5, "interval_secs": 10}"#), Status::Ok);
+        assert_eq!(put(r#"{"pending": 0}"#), Status::BadRequest);
+        let mut response = client.get("/admin/auto-mine").header(Header::new("X-Admin-Token", "secret")).dispatch();
+        let config: Value = serde_json::from_str(&response.body_string().expect("a body")).expect("json");
+        assert_eq!(config["pending"], 5);
+        assert_eq!(config["interval_secs"], 10);
+        assert_eq!(client.get("/admin/auto-mine").dispatch().status(), Status::Unauthorized);
+    }
+
+    #[test]
+    fn rejections() {
+        let client = client_with(|state| {
+            state.admin_token = Some(String::from("secret"));
+            state.max_pending_per_sender = 1;
+        });
+        let post = |json: &str| client.post("/transaction/new")
+                                      .header(ContentType::JSON)
+                                      .remote("192.0.2.7:4000".parse().expect("an address"))
+                                      .body(String::from(json))
+                                      .dispatch()
+                                      .status();
+        assert_eq!(post(r#"{"sender": "a", "recipient": "b", "amount": 5}"#), Status::Ok);
+        assert_eq!(post(r#"{"sender": "a", "recipient": "b", "amount": 6}"#), Status::TooManyRequests);
+        assert_eq!(post(r#"{"sender": "a"}"#), Status::BadRequest);
+
+        let get = |uri: &str| {
+            let mut response = client.get(uri).header(Header::new("X-Admin-Token", "secret")).dispatch();
+            assert_eq!(response.status(), Status::Ok, "GET {}", uri);
+            serde_json::from_str::<Value>(&response.body_string().expect("a body")).expect("json")["rejections"].clone()
+        };
+        let rejections = get("/admin/rejections");
+        assert_eq!(rejections.as_array().expect("rejections").len(), 2, "the one added isn't");
+        assert!(rejections[0]["hash"].is_null(), "the body didn't parse");
+        let id = Blockchain::new_with(1).transaction_id(&Transaction::new("a".into(), "b".into(), 6));
+        assert_eq!(rejections[1]["hash"], Value::from(id.clone()));
+        assert_eq!(rejections[1]["kind"], "transaction");
+        assert_eq!(rejections[1]["source"], "192.0.2.7");
+        assert!(rejections[1]["reason"].as_str().expect("a reason").contains("pending"));
+
+        let found = get(&format!("/admin/rejections?hash={}", id));
+        assert_eq!(found.as_array().expect("rejections").len(), 1);
+        assert_eq!(get("/admin/rejections?last=1").as_array().expect("rejections").len(), 1);
+        assert_eq!(client.get("/admin/rejections").dispatch().status(), Status::Unauthorized);
+    }
+
+    #[test]
+    fn admin_difficulty() {
+        let client = client_with(|state| state.admin_token = Some(String::from("secret")));
+        let put = |token: &str, difficulty: u64| client.put("/admin/difficulty")
+                                                       .header(ContentType::JSON)
+                                                       .header(Header::new("X-Admin-Token", String::from(token)))
+                                                       .body(format!(r#"{{"difficulty": {}}}"#, difficulty))
+                                                       .dispatch()
+                                                       .status();
+
+        assert_eq!(put("wrong", 2), Status::Unauthorized);
+        assert_eq!(put("secret", 60), Status::BadRequest, "longer than a hash could ever meet");
+        assert_eq!(put("secret", 2), Status::Ok);
+        let mined = get_json(&client, "/mine");
+        let chain = get_json(&client, "/chain");
+        assert_eq!(chain["chain"][1]["header"]["difficulty"], 2, "new difficulty recorded in the header");
+        assert_eq!(chain["chain"][1]["header"]["nonce"], mined["nonce"]);
+    }
+
+    #[test]
+    fn request_stats() {
+        let client = client_with(|state| state.admin_token = Some(String::from("secret")));
+        get_json(&client, "/chain");
+        get_json(&client, "/chain");
+        assert_eq!(client.get("/stats?at_height=9").dispatch().status(), Status::BadRequest);
+
+        let mut response = client.get("/admin/requests")
+                                 .header(Header::new("X-Admin-Token", "secret"))
+                                 .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let routes: Value = serde_json::from_str(&response.body_string().expect("expected a body")).expect("json");
+        let route = |name: &str| routes.as_array().unwrap().iter().find(|route| route["route"] == name).cloned().expect(name);
+        assert_eq!(route("GET /chain")["requests"], 2);
+        assert_eq!(route("GET /stats")["client_errors"], 1);
+
+        let mut metrics = client.get("/metrics").dispatch();
+        assert_eq!(metrics.status(), Status::Ok);
+        let metrics = metrics.body_string().expect("expected a body");
+        assert!(metrics.contains("learnnet_requests_total{route=\"GET /chain\"} 2"));
+        assert!(metrics.contains("learnnet_memory_bytes{part=\"mempool\"} 0"));
+    }
+
+    #[test]
+    fn admin_disabled() {
+        let client = client();
+        let response = client.put("/admin/difficulty")
+                             .header(ContentType::JSON)
+                             .header(Header::new("X-Admin-Token", "anything"))
+                             .body(r#"{"difficulty": 2}"#)
+                             .dispatch();
+        assert_eq!(response.status(), Status::Forbidden, "no admin token configured");
+    }
+
+    #[test]
+    fn validate_transaction() {
+        let client = client();
+        let validated = post_json(&client, "/transaction/validate", r#"{"sender": "a", "recipient": "b", "amount": 5}"#);
+        assert_eq!(validated["valid"], true, "overdrawing isn't refused");
+        let balance = validated["advice"].as_array().unwrap().iter().find(|check| check["name"] == "balance").unwrap().clone();
+        assert_eq!(balance["passed"], false);
+
+        //dry run only
+        let mined = get_json(&client, "/mine");
+        assert_eq!(mined["transactions"].as_array().unwrap().len(), 1, "only the reward was mined");
+    }
+
+    #[test]
+    fn validate_agrees_with_new() {
+        let client = client_with(|state| state.standardness = Some(Standardness::default()));
+        let bodies = [r#"{"sender": "a", "recipient": "b", "amount": 5}"#.to_string(),
+                      r#"{"sender": "0", "recipient": "b", "amount": 5}"#.to_string(),
+                      r#"{"sender": "a", "recipient": "", "amount": 5}"#.to_string(),
+                      r#"{"sender": "a", "recipient": "a", "amount": 5}"#.to_string(),
+                      r#"{"sender": "a", "recipient": "b", "amount": 0}"#.to_string(),
+                      r#"{"sender": "a", "recipient": "b", "amount": 5, "expires_at": 1}"#.to_string(),
+                      r#"{"sender": "a", "recipient": "b", "amount": 5, "expires_at": 2}"#.to_string(),
+                      format!(r#"{{"sender": "a", "recipient": "b", "amount": {}}}"#, DEFAULT_DUST - 1)];
+        for body in bodies.iter() {
+            let validated = post_json(&client, "/transaction/validate", body);
+            let added = client.post("/transaction/new").header(ContentType::JSON).body(body.clone()).dispatch().status();
+            assert_eq!(validated["valid"].as_bool(), Some(added == Status::Ok), "{} was valid: {}, added: {}", body, validated["valid"], added);
+        }
+    }
+
+    #[test]
+    fn standardness() {
+        let client = client_with(|state| state.standardness = Some(Standardness::default()));
+        let post = |uri: &str, amount: i64| client.post(uri)
+                                                  .header(ContentType::JSON)
+                                                  .body(format!(r#"{{"sender": "a", "recipient": "b", "amount": {}}}"#, amount))
+                                                  .dispatch();
+        let mut dust = post("/transaction/new", DEFAULT_DUST - 1);
+        assert_eq!(dust.status(), Status::BadRequest);
+        assert!(dust.body_string().expect("body").contains("dust"));
+        assert_eq!(post("/transaction/new", DEFAULT_DUST).status(), Status::Ok);
+
+        let validated: Value = serde_json::from_str(&post("/transaction/validate", 1).body_string().expect("body")).expect("json");
+        let standard = validated["checks"].as_array().unwrap().iter().find(|check| check["name"] == "standard").unwrap().clone();
+        assert_eq!(standard["passed"], false);
+
+        //policy isn't consensus, a block with dust in it is still valid
+        let mut miner = Blockchain::new_with(1);
+        miner.new_transaction(Transaction::new("a".into(), "b".into(), 1));
+        miner.mine().expect("mined");
+        assert!(miner.valid_chain(miner.chain()));
+    }
+
+    #[test]
+    fn next_block() {
+        let client = client();
+        let genesis = get_json(&client, "/chain/next?after=0");
+        assert_eq!(genesis["block"]["header"]["index"], 1, "genesis is already there");
+
+        let timed_out = get_json(&client, "/chain/next?after=1&timeout=0");
+        assert!(timed_out["block"].is_null(), "nothing mined yet");
+        assert_eq!(timed_out["length"], 1);
+
+        get_json(&client, "/mine");
+        let next = get_json(&client, "/chain/next?after=1&timeout=0");
+        assert_eq!(next["block"]["header"]["index"], 2);
+    }
+
+    #[test]
+    fn verify() {
+        let client = client();
+        get_json(&client, "/mine");
+        let verified = get_json(&client, "/chain/verify");
+        assert_eq!(verified["valid"], true);
+        assert_eq!(verified["memory"]["valid"], 2);
+        assert_eq!(verified["store"], Value::Null);
+    }
+
+    #[test]
+    fn conditional_get() {
+        let client = client();
+        for uri in ["/chain", "/chain/head"].iter() {
+            let response = client.get(*uri).dispatch();
+            assert_eq!(response.status(), Status::Ok);
+            let etag = response.headers().get_one("ETag").expect("expected an ETag").to_string();
+
+            let response = client.get(*uri).header(Header::new("If-None-Match", etag.clone())).dispatch();
+            assert_eq!(response.status(), Status::NotModified, "{} unchanged", uri);
+        }
+
+        let etag = client.get("/chain").dispatch().headers().get_one("ETag").unwrap().to_string();
+        get_json(&client, "/mine");
+        let response = client.get("/chain").header(Header::new("If-None-Match", etag.clone())).dispatch();
+        assert_eq!(response.status(), Status::Ok, "chain changed after mining");
+        assert_ne!(response.headers().get_one("ETag").unwrap(), etag.as_str());
+
+        let head = get_json(&client, "/chain/head");
+        assert_eq!(head["length"], 2);
+        assert_eq!(head["header"]["index"], 2);
+    }
+
+    #[test]
+    fn export() {
+        let client = client();
+        get_json(&client, "/mine");
+        let mut response = client.get("/export?format=csv").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::new("text", "csv")));
+        let body = response.body_string().expect("expected a body");
+        assert_eq!(body.lines().count(), 4, "header, 2 blocks and the reward");
+
+        let response = client.get("/export?format=xml").dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+    }
+
+    #[test]
+    fn visualize() {
+        let client = client();
+        get_json(&client, "/mine");
+        let mut response = client.get("/visualize").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let body = response.body_string().expect("expected a body");
+        assert!(body.starts_with("digraph"));
+        assert_eq!(body.matches(" -> ").count(), 1);
+    }
+
+    #[test]
+    fn mempool() {
+        let client = client();
+        post_json(&client, "/transaction/new", r#"{"sender": "a", "recipient": "b", "amount": 5}"#);
+        post_json(&client, "/transaction/new", r#"{"sender": "c", "recipient": "d", "amount": 1}"#);
+        let digest = get_json(&client, "/mempool/digest");
+        let prefixes = digest["prefixes"].as_array().expect("prefixes");
+        assert_eq!(prefixes.len(), 2);
+
+        let prefix = prefixes[0].as_str().expect("a prefix").replace("+", "%2B").replace("/", "%2F");
+        let pending = get_json(&client, &format!("/mempool?prefixes={}", prefix));
+        assert_eq!(pending["transactions"].as_array().expect("transactions").len(), 1);
+        assert_eq!(get_json(&client, "/mempool?prefixes=")["transactions"].as_array().expect("transactions").len(), 0);
+    }
+
+    #[test]
+    fn faucet() {
+        let post = |client: &Client, token: &str, json: &str| client.post("/faucet")
+                                                                  .header(ContentType::JSON)
+                                                                  .header(Header::new("X-Faucet-Token", String::from(token)))
+                                                                  .body(String::from(json))
+                                                                  .dispatch()
+                                                                  .status();
+        let request = r#"{"address": "alice", "amount": 10}"#;
+        assert_eq!(post(&client(), "secret", request), Status::Forbidden, "no faucet");
+
+        let client = client_with(|state| state.faucet = Some(Faucet::new(String::from("secret"))));
+        assert_eq!(post(&client, "wrong", request), Status::Unauthorized);
+        assert_eq!(post(&client, "secret", r#"{"address": "alice", "amount": 1000000}"#), Status::BadRequest);
+        assert_eq!(post(&client, "secret", request), Status::Ok);
+        assert_eq!(post(&client, "secret", request), Status::TooManyRequests);
+
+        get_json(&client, "/mine");
+        assert_eq!(get_json(&client, "/balance/alice")["balance"], 10);
+    }
+
+    #[test]
+    fn only_the_faucet_mints() {
+        let client = client();
+        for uri in ["/transaction/new", "/transactions/new"].iter() {
+            for body in [r#"{"sender": "0", "recipient": "mallory", "amount": 1000000}"#,
+                         r#"{"sender": "", "recipient": "mallory", "amount": 5}"#,
+                         r#"{"sender": "a", "recipient": "", "amount": 5}"#,
+                         r#"{"sender": "a", "recipient": "mallory", "amount": 0}"#,
+                         r#"{"sender": "a", "recipient": "mallory", "amount": -5}"#].iter() {
+                assert_eq!(client.post(*uri).header(ContentType::JSON).body(*body).dispatch().status(), Status::BadRequest, "POST {} {}", uri, body);
+            }
+        }
+        get_json(&client, "/mine");
+        assert_eq!(get_json(&client, "/balance/mallory")["balance"], 0);
+    }
+
+    #[test]
+    fn mining_stats() {
+        let client = client();
+        get_json(&client, "/mine");
+        get_json(&client, "/mine");
+        let stats = get_json(&client, "/stats/mining");
+        assert_eq!(stats["height"], 3);
+        assert_eq!(stats["blocks"], 1);
+        assert_eq!(stats["miners"][0]["address"], DEFAULT_MINER_ADDRESS);
+        assert_eq!(stats["miners"][0]["blocks"], 2);
+    }
+
+    #[test]
+    fn import() {
+        let mut theirs = Blockchain::new_with(1);
+        theirs.mine().expect("mined");
+        theirs.mine().expect("mined");
+        let path = env::temp_dir().join("learnnet-web-import.jsonl");
+        {
+            let mut file = fs::File::create(&path).expect("created");
+            export::export(&theirs, Format::Blocks, &mut file).expect("exported");
+        }
+        let mut ours = Blockchain::new_with(1);
+        ours.replace(theirs.chain().iter().take(1).cloned().collect());
+        let mut state = BlockchainState::new(ours, Duration::from_secs(1));
+        state.admin_token = Some("secret".into());
+        let client = Client::new(web::rocket(state)).expect("valid rocket instance");
+        let start = |path: &str| client.post("/admin/import")
+                                       .header(ContentType::JSON)
+                                       .header(Header::new("X-Admin-Token", "secret"))
+                                       .body(format!(r#"{{"path": "{}"}}"#, path))
+                                       .dispatch()
+                                       .status();
+        assert_eq!(client.get("/admin/import").header(Header::new("X-Admin-Token", "secret")).dispatch().status(), Status::NotFound);
+        assert_eq!(start("/nowhere/blocks.jsonl"), Status::BadRequest);
+        assert_eq!(start(path.to_str().expect("utf-8 path")), Status::Accepted);
+
+        let mut progress = Value::Null;
+        for _ in 0..100 {
+            let mut response = client.get("/admin/import").header(Header::new("X-Admin-Token", "secret")).dispatch();
+            progress = serde_json::from_str(&response.body_string().expect("body")).expect("json");
+            if progress["state"] != "running" {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(progress["state"], "done");
+        assert_eq!(progress["imported"], 2);
+        assert_eq!(progress["percent"], 100.0);
+        assert_eq!(get_json(&client, "/chain")["length"], 3);
+    }
+
+    #[test]
+    fn dashboard() {
+        let client = client_with(|state| state.admin_token = Some("secret".into()));
+        let mut page = client.get("/admin").dispatch();
+        assert_eq!(page.status(), Status::Ok);
+        assert_eq!(page.content_type(), Some(ContentType::HTML));
+        assert!(page.body_string().expect("body").contains("/admin/overview"));
+
+        assert_eq!(client.get("/admin/overview").dispatch().status(), Status::Unauthorized);
+        post_json(&client, "/transaction/new", r#"{"sender": "a", "recipient": "b", "amount": 5}"#);
+        post_json(&client, "/nodes/register", r#"{"nodes": ["http://localhost:1"]}"#);
+        let mut response = client.get("/admin/overview").header(Header::new("X-Admin-Token", "secret")).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let overview: Value = serde_json::from_str(&response.body_string().expect("body")).expect("json");
+        assert_eq!(overview["height"], 1);
+        assert_eq!(overview["pending"], 1);
+        assert_eq!(overview["peers"].as_array().expect("peers").len(), 1);
+        assert!(overview["mining"].is_null());
+        assert_eq!(overview["reorgs"].as_array().expect("reorgs").len(), 0);
+    }
+
+    #[test]
+    fn peer_listener() {
+        let mut state = BlockchainState::new_with(1, Duration::from_secs(1));
+        state.peer_listener = Some((String::from("localhost"), 8001));
+        let (public, peer) = web::listeners(Arc::new(state));
+        let public = Client::new(public).expect("valid rocket instance");
+        let peer = Client::new(peer.expect("a peer listener")).expect("valid rocket instance");
+
+        for client in &[&public, &peer] {
+            assert_eq!(client.get("/chain").dispatch().status(), Status::Ok, "both serve the chain");
+        }
+        assert_eq!(peer.get("/mempool/digest").dispatch().status(), Status::Ok);
+        assert_eq!(public.get("/mempool/digest").dispatch().status(), Status::NotFound);
+        assert_eq!(public.get("/stats").dispatch().status(), Status::Ok);
+        assert_eq!(peer.get("/stats").dispatch().status(), Status::NotFound);
+        assert_eq!(peer.get("/admin/overview").dispatch().status(), Status::NotFound, "no admin routes for peers");
+    }
+
+    #[test]
+    fn forks() {
+        let mut blockchain = Blockchain::new_with(1);
+        let mut deployment = Deployment::new("sha512".into(), 1, 2, Rules { hasher: Some(HashAlgorithm::Sha512), state_root: true });
+        deployment.window = 2;
+        deployment.threshold = 2;
+        blockchain.add_deployment(deployment).unwrap();
+        let client = Client::new(web::rocket(BlockchainState::new(blockchain, Duration::from_secs(1)))).expect("valid rocket instance");
+
+        let forks = get_json(&client, "/forks");
+        assert_eq!(forks["version"], 1);
+        assert_eq!(forks["deployments"][0]["state"], "signaling");
+        get_json(&client, "/mine");
+        get_json(&client, "/mine");
+        let forks = get_json(&client, "/forks");
+        assert_eq!(forks["deployments"][0]["signaled"], 1, "block 3 starts a window");
+        get_json(&client, "/mine");
+        let forks = get_json(&client, "/forks");
+        assert_eq!(forks["deployments"][0]["state"], "locked_in");
+        assert_eq!(forks["deployments"][0]["activates_at"], 7);
+    }
+
+    #[test]
+    fn at_height() {
+        let client = client();
+        let mined = get_json(&client, "/mine");
+        let uri = format!("/balance/{}", DEFAULT_MINER_ADDRESS.replace(" ", "%20"));
+        assert_eq!(get_json(&client, &uri)["balance"], 1);
+        assert_eq!(get_json(&client, &format!("{}?at_height=1", uri))["balance"], 0);
+
+        let stats = get_json(&client, "/stats");
+        assert_eq!(stats["height"], 2);
+        assert_eq!(stats["rewards"], 1);
+        assert_eq!(stats["supply"], 1);
+        let genesis = get_json(&client, "/stats?at_height=1");
+        assert_eq!(genesis["rewards"], 0);
+        assert_eq!(genesis["supply"], 0);
+        assert!(mined["size"].as_u64().expect("a size") > 0);
+        assert_eq!(stats["bytes"].as_u64(), genesis["bytes"].as_u64().and_then(|bytes| mined["size"].as_u64().map(|size| bytes + size)));
+
+        let response = client.get("/stats?at_height=3").dispatch();
+        assert_eq!(response.status(), Status::BadRequest, "above the tip");
+    }
+
+    #[test]
+    fn incoming() {
+        let client = client();
+        post_json(&client, "/transaction/new", r#"{"sender": "a", "recipient": "shop", "amount": 5}"#);
+        get_json(&client, "/mine");
+        let all = get_json(&client, "/address/shop/incoming");
+        assert_eq!(all["height"], 2);
+        assert_eq!(all["payments"].as_array().unwrap().len(), 1);
+        assert_eq!(all["payments"][0]["block"], 2);
+        assert_eq!(all["payments"][0]["transaction"]["amount"], 5);
+
+        let since = get_json(&client, "/address/shop/incoming?since_height=2");
+        assert!(since["payments"].as_array().unwrap().is_empty(), "nothing new");
+
+        let response = client.get("/address/shop/incoming?since_height=3").dispatch();
+        assert_eq!(response.status(), Status::BadRequest, "above the tip");
+    }
+
+    #[test]
+    fn payment_request() {
+        let client = client();
+        let request = get_json(&client, "/payment-request?to=my+shop&amount=5");
+        assert_eq!(request["uri"], "learnnet:my%20shop?amount=5");
+        assert_eq!(request["to"], "my shop");
+
+        let mut response = client.get("/payment-request/qr?to=shop&amount=5").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::PNG));
+        assert!(response.body_bytes().expect("expected a body").starts_with(b"\x89PNG"));
+
+        let response = client.get("/payment-request?to=shop&amount=0").dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        let response = client.get("/payment-request?to=&amount=5").dispatch();
+        assert_eq!(response.status(), Status::BadRequest, "empty address");
+    }
+
+    //hashes are base64, which needs escaping in a path
+    fn escape(hash: &str) -> String {
+        hash.replace("/", "%2F").replace("+", "%2B")
+    }
+
+    #[test]
+    fn lookup_by_hash() {
+        let client = client();
+        let txn = Transaction::new("a".into(), "b".into(), 5);
+        let id = escape(&Blockchain::new_with(1).transaction_id(&txn));
+        post_json(&client, "/transaction/new", r#"{"sender": "a", "recipient": "b", "amount": 5}"#);
+        let pending = get_json(&client, &format!("/transaction/{}", id));
+        assert_eq!(pending["pending"], true);
+        assert_eq!(pending["block"], Value::Null);
+
+        get_json(&client, "/mine");
+        let confirmed = get_json(&client, &format!("/transaction/{}", id));
+        assert_eq!(confirmed["pending"], false);
+        assert_eq!(confirmed["block"], 2);
+        assert_eq!(confirmed["transaction"]["amount"], 5);
+
+        let head = get_json(&client, "/chain/head");
+        assert_eq!(head["work"], 2 * 64, "two blocks at difficulty 1");
+        let block = get_json(&client, &format!("/block/hash/{}", escape(head["hash"].as_str().unwrap())));
+        assert_eq!(block["block"]["header"]["index"], 2);
+        assert_eq!(block["hash"], head["hash"]);
+
+        let unknown = escape(&Blockchain::new_with(1).transaction_id(&Transaction::new("x".into(), "y".into(), 1)));
+        assert_eq!(client.get(format!("/block/hash/{}", unknown)).dispatch().status(), Status::NotFound);
+        assert_eq!(client.get(format!("/transaction/{}", unknown)).dispatch().status(), Status::NotFound);
+        assert_eq!(client.get("/block/hash/not-a-hash").dispatch().status(), Status::BadRequest);
+        assert_eq!(client.get("/transaction/not-a-hash").dispatch().status(), Status::BadRequest);
+        assert_eq!(client.get("/balance/a%0Ab").dispatch().status(), Status::BadRequest);
+    }
+
+    #[test]
+    fn pretty_and_fields() {
+        let client = client();
+        get_json(&client, "/mine");
+
+        let mut response = client.get("/chain?pretty=true&fields=index,previous_hash").dispatch();
+        let body = response.body_string().expect("a body");
+        assert!(body.lines().count() > 2, "indented, got {}", body);
+        let chain: Value = serde_json::from_str(&body).expect("valid json");
+        assert_eq!(chain["length"], 2);
+        assert_eq!(chain["chain"][1]["header"]["index"], 2);
+        assert!(chain["chain"][1]["header"]["nonce"].is_null(), "left out");
+        assert!(chain["chain"][1]["transactions"].is_null(), "left out");
+
+        let head = get_json(&client, "/chain/head");
+        let block = get_json(&client, &format!("/block/hash/{}?fields=transactions", escape(head["hash"].as_str().unwrap())));
+        assert_eq!(block["block"]["transactions"].as_array().map(|txns| txns.len()), Some(1), "the reward");
+        assert!(block["block"]["header"].is_null(), "left out");
+        assert_eq!(block["hash"], head["hash"]);
+
+        assert_eq!(client.get("/chain?fields=index,txns").dispatch().status(), Status::BadRequest);
+    }
+
+    #[test]
+    fn receipt() {
+        let client = client();
+        let txn = Transaction::new("a".into(), "b".into(), 5);
+        let leaf = Blockchain::new_with(1).transaction_id(&txn);
+        let path = format!("/transaction/{}/receipt", escape(&leaf));
+        post_json(&client, "/transaction/new", r#"{"sender": "a", "recipient": "b", "amount": 5}"#);
+        post_json(&client, "/transaction/new", r#"{"sender": "c", "recipient": "d", "amount": 7}"#);
+        assert_eq!(client.get(path.clone()).dispatch().status(), Status::NotFound, "pending");
+
+        get_json(&client, "/mine");
+        assert_eq!(get_json(&client, &path)["confirmations"], 1);
+        get_json(&client, "/mine");
+        let receipt = get_json(&client, &path);
+        assert_eq!(receipt["confirmations"], 2);
+        assert_eq!(receipt["transaction"]["amount"], 5);
+        assert_eq!(receipt["header"]["index"], 2);
+
+        //checked with nothing but the receipt
+        let steps: Vec<ProofStep> = receipt["proof"].as_array().unwrap().iter().map(|step| ProofStep {
+            hash: step["hash"].as_str().unwrap().into(),
+            side: if step["side"] == "left" { "left" } else { "right" }
+        }).collect();
+        assert!(!steps.is_empty(), "two transactions and a reward");
+        assert!(merkle::verify(&HashAlgorithm::default(), &leaf, &steps, receipt["header"]["merkle_root"].as_str().unwrap()));
+    }
+
+    #[test]
+    fn balance_proof() {
+        let client = client();
+        post_json(&client, "/transaction/new", r#"{"sender": "a", "recipient": "b", "amount": 5}"#);
+        get_json(&client, "/mine");
+        post_json(&client, "/transaction/new", r#"{"sender": "b", "recipient": "c", "amount": 2}"#);
+        post_json(&client, "/transaction/new", r#"{"sender": "c", "recipient": "d", "amount": 1}"#);
+        get_json(&client, "/mine");
+
+        let proof = get_json(&client, "/balance/b/proof");
+        assert_eq!(proof["balance"], 3);
+        assert_eq!(proof["height"], 3);
+        let receipts = proof["receipts"].as_array().unwrap();
+        assert_eq!(receipts.len(), 2, "not c to d, or the rewards");
+        //the client's own arithmetic, over transactions it checked are in blocks
+        let mut balance = 0;
+        for receipt in receipts.iter() {
+            let txn = &receipt["transaction"];
+            let steps: Vec<ProofStep> = receipt["proof"].as_array().unwrap().iter().map(|step| ProofStep {
+                hash: step["hash"].as_str().unwrap().into(),
+                side: if step["side"] == "left" { "left" } else { "right" }
+            }).collect();
+            assert!(merkle::verify(&HashAlgorithm::default(), receipt["id"].as_str().unwrap(), &steps, receipt["header"]["merkle_root"].as_str().unwrap()));
+            let amount = txn["amount"].as_i64().unwrap();
+            balance += if txn["recipient"] == "b" { amount } else { -amount };
+        }
+        assert_eq!(proof["balance"], balance);
+
+        assert_eq!(get_json(&client, "/balance/b/proof?at_height=2")["receipts"].as_array().unwrap().len(), 1);
+        assert_eq!(client.get("/balance/b/proof?at_height=9").dispatch().status(), Status::BadRequest);
+    }
+
+    #[test]
+    fn transaction_content_type() {
+        let txn = r#"{"sender": "a", "recipient": "b", "amount": 1}"#;
+        let post = |client: &Client, content_type: Option<ContentType>, body: &str| {
+            let mut request = client.post("/transaction/new").body(body);
+            if let Some(content_type) = content_type {
+                request = request.header(content_type);
+            }
+            request.dispatch().status()
+        };
+
+        let strict = client();
+        assert_eq!(post(&strict, Some(ContentType::JSON), txn), Status::Ok);
+        assert_eq!(post(&strict, Some(ContentType::Plain), txn), Status::UnsupportedMediaType);
+        assert_eq!(post(&strict, None, txn), Status::UnsupportedMediaType);
+
+        let lenient = client_with(|state| state.lenient_content_type = true);
+        assert_eq!(post(&lenient, Some(ContentType::Plain), txn), Status::Ok);
+        assert_eq!(post(&lenient, None, txn), Status::Ok);
+        assert_eq!(post(&lenient, None, "not json"), Status::BadRequest);
+    }
+
+    #[test]
+    fn idempotency_key() {
+        let client = client();
+        let post = |key: &str, amount: i64| client.post("/transaction/new")
+                                                  .header(ContentType::JSON)
+                                                  .header(Header::new("Idempotency-Key", String::from(key)))
+                                                  .body(format!(r#"{{"sender": "a", "recipient": "b", "amount": {}}}"#, amount))
+                                                  .dispatch();
+        let mut first = post("retry-me", 5);
+        assert_eq!(first.status(), Status::Ok);
+        let mut retry = post("retry-me", 5);
+        assert_eq!(retry.status(), Status::Ok);
+        assert_eq!(retry.body_string(), first.body_string());
+        assert_eq!(post("retry-me", 6).status(), Status::BadRequest, "same key, different transaction");
+        assert_eq!(post("another", 7).status(), Status::Ok);
+
+        let digest = get_json(&client, "/mempool/digest");
+        assert_eq!(digest["prefixes"].as_array().expect("prefixes").len(), 2, "the retry wasn't added");
+    }
+
+    #[test]
+    fn garbage_bodies() {
+        let client = client();
+        let bodies = [("/transaction/new", r#"{"sender": "a", "recipient": "b", "amount": 5, "expires_at": 30}"#),
+                      ("/nodes/register", r#"{"nodes": ["http://10.0.0.5:8000", "10.0.0.6", "[::1]:9000"]}"#)];
+        for &(uri, valid) in bodies.iter() {
+            for body in testvectors::mutations(valid.as_bytes(), 150) {
+                let status = client.post(uri).header(ContentType::JSON).body(body.clone()).dispatch().status();
+                //a refusal at worst, never a failure of ours
+                assert!(status.code < 500, "POST {} {:?} answered {}", uri, String::from_utf8_lossy(&body), status);
+            }
+        }
+        for &(uri, body) in [("/transaction/new", r#"{"sender": "a", "recipient": "b", "amount": 18446744073709551616}"#),
+                             ("/transaction/new", r#"[{"sender": "a"}]"#),
+                             ("/nodes/register", r#"{"nodes": "10.0.0.5"}"#),
+                             ("/nodes/register", r#"{"nodes": [5]}"#),
+                             ("/nodes/register", r#"{"nodes": ["http://"]}"#)].iter() {
+            assert_eq!(client.post(uri).header(ContentType::JSON).body(body).dispatch().status(), Status::BadRequest, "POST {} {}", uri, body);
+        }
+    }
+
+    #[test]
+    fn transaction_field_errors() {
+        let client = client();
+        let mut response = client.post("/transaction/new")
+                                 .header(ContentType::JSON)
+                                 .body(r#"{"sender": "a", "amount": "5"}"#)
+                                 .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        let body: Value = serde_json::from_str(&response.body_string().expect("body")).expect("json");
+        let fields: Vec<&str> = body["fields"].as_array().expect("fields").iter().map(|error| error["field"].as_str().expect("field")).collect();
+        assert_eq!(fields, vec!["recipient", "amount"]);
+        assert!(body["error"].as_str().expect("error").contains("recipient"));
+
+        let mut response = client.post("/transaction/validate").header(ContentType::JSON).body("{}").dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        assert!(response.body_string().expect("body").contains("sender"));
+    }
+
+    #[test]
+    fn pending_limit() {
+        let client = client_with(|state| state.max_pending_per_sender = 1);
+        let post = |amount: i64| client.post("/transaction/new")
+                                       .header(ContentType::JSON)
+                                       .body(format!(r#"{{"sender": "a", "recipient": "b", "amount": {}}}"#, amount))
+                                       .dispatch()
+                                       .status();
+        assert_eq!(post(1), Status::Ok);
+        assert_eq!(post(2), Status::TooManyRequests);
+        get_json(&client, "/mine");
+        assert_eq!(post(2), Status::Ok, "mined transactions don't count");
+    }
+
+    #[test]
+    fn cluster() {
+        let dir = env::temp_dir().join("learnnet-web-cluster");
+        let _ = fs::remove_dir_all(&dir);
+        let replica = |id: &str| {
+            let store = BlockFile::open_shared(&dir).expect("opened");
+            client_with(|state| {
+                state.store = Some(Mutex::new(Box::new(store) as Box<ChainStore + Send>));
+                state.cluster = Some(Cluster::new(Lease::new(&dir.join("leader.lease"), id.into(), 10)));
+            })
+        };
+        let leader = replica("a");
+        let follower = replica("b");
+
+        get_json(&leader, "/mine");
+        let response = follower.get("/mine").dispatch();
+        assert_eq!(response.status(), Status::ServiceUnavailable, "only the leader mines");
+        assert_eq!(get_json(&follower, "/chain"), get_json(&leader, "/chain"), "followers catch up with the store");
+    }
+
+    #[test]
+    fn invalid_transaction() {
+        let client = client();
+        let response = client.post("/transaction/new")
+                             .header(ContentType::JSON)
+                             .body(r#"{"sender": "a"}"#)
+                             .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+    }
+
+    #[test]
+    fn ipv6_and_host_name_nodes() {
+        let client = client();
+        let registered = post_json(&client, "/nodes/register", r#"{"nodes": ["http://[::1]:1", "localhost:2"]}"#);
+        assert_eq!(registered["total_nodes"], 2);
+        let resolved = get_json(&client, "/nodes/resolve");
+        assert_eq!(resolved["message"], "Our chain is authoritative", "unreachable at every address");
+    }
+
+    #[test]
+    fn advertised_url() {
+        let client = client_with(|state| state.advertised_url = Some(Url::parse("http://node.example.com:8000").expect("valid url")));
+        let registered = post_json(&client, "/nodes/register", r#"{"nodes": ["node.example.com:8000", "localhost:2"]}"#);
+        assert_eq!(registered["total_nodes"], 1, "not ourselves");
+    }
+
+    #[test]
+    fn host_alone_nodes() {
+        let client = client_with(|state| state.default_port = 9000);
+        let registered = post_json(&client, "/nodes/register", r#"{"nodes": ["10.0.0.5", "http://10.0.0.5:9000/", "10.0.0.5:8000"]}"#);
+        assert_eq!(registered["total_nodes"], 2, "10.0.0.5 is on 9000");
+    }
+
+    #[test]
+    fn invalid_node() {
+        let client = client();
+        let response = client.post("/nodes/register")
+                             .header(ContentType::JSON)
+                             .body(r#"{"nodes": ["not a url"]}"#)
+                             .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+    }
+
+    #[test]
+    fn absurd_bodies() {
+        let client = client();
+        let post = |uri: &'static str, body: String| client.post(uri).header(ContentType::JSON).body(body).dispatch().status();
+        let nested: String = iter::repeat('[').take(100000).collect();
+        assert_eq!(post("/transaction/new", nested.clone()), Status::PayloadTooLarge, "too deep to parse");
+        assert_eq!(post("/nodes/register", nested), Status::PayloadTooLarge, "too deep to parse");
+
+        let long: String = iter::repeat('x').take(protocol::MAX_BODY_BYTES as usize).collect();
+        let huge = format!(r#"{{"sender": "{}", "recipient": "b", "amount": 5}}"#, long);
+        assert_eq!(post("/transaction/new", huge), Status::PayloadTooLarge);
+        assert_eq!(get_json(&client, "/mempool/digest")["prefixes"].as_array().expect("prefixes").len(), 0);
+    }
+
+    #[test]
+    fn recovers_poisoned_lock() {
+        let state = Arc::new(BlockchainState::new_with(1, Duration::from_millis(100)));
+        let panicking_state = state.clone();
+        let result = thread::spawn(move || {
+            let _blockchain = panicking_state.blockchain.write().unwrap();
+            panic!("handler panicked while holding the WRITE lock");
+        }).join();
+        assert!(result.is_err(), "expected the thread to panic");
+        assert!(state.blockchain.read().is_err(), "expected the lock to be poisoned");
+        
+        assert!(state.read().is_ok(), "READ lock should be recovered");
+        assert!(state.write().is_ok(), "WRITE lock should be recovered");
+    }
+
+    #[test]
+    fn mining_jobs() {
+        let client = client();
+        get_json(&client, "/mine");
+        let job = get_json(&client, "/mine/job/1");
+        assert_eq!(job["state"], "done");
+        assert_eq!(job["block"], 2);
+        assert_eq!(client.get("/mine/job/2").dispatch().status(), Status::NotFound);
+    }
+
+    #[test]
+    fn protocol_headers() {
+        let client = client();
+        let version = protocol::PROTOCOL_VERSION.to_string();
+        let response = client.get("/chain").dispatch();
+        assert_eq!(response.status(), Status::Ok, "clients don't need the headers");
+        assert_eq!(response.headers().get_one(protocol::NETWORK_HEADER), Some(protocol::DEFAULT_NETWORK));
+        assert_eq!(response.headers().get_one(protocol::VERSION_HEADER), Some(version.as_str()));
+
+        let get = |network: &str, version: &str| client.get("/chain/headers")
+                                                      .header(Header::new(protocol::NETWORK_HEADER, String::from(network)))
+                                                      .header(Header::new(protocol::VERSION_HEADER, String::from(version)))
+                                                      .dispatch()
+                                                      .status();
+        assert_eq!(get(protocol::DEFAULT_NETWORK, &version), Status::Ok);
+        assert_eq!(get("classroom", &version), Status::PreconditionFailed);
+        assert_eq!(get(protocol::DEFAULT_NETWORK, "0"), Status::PreconditionFailed);
+    }
+
+    #[test]
+    fn tutorial_schema() {
+        let client = client_with(|state| state.tutorial_schema = true);
+        let mined = get_json(&client, "/mine");
+        assert_eq!(mined["index"], 2);
+        assert!(mined["proof"].is_u64());
+        assert!(mined.get("nonce").is_none());
+
+        let chain = get_json(&client, "/chain");
+        assert_eq!(chain["length"], 2);
+        assert_eq!(chain["chain"][1]["proof"], mined["proof"]);
+        assert!(chain["chain"][1].get("header").is_none());
+
+        let version = protocol::PROTOCOL_VERSION.to_string();
+        let mut response = client.get("/chain")
+                                 .header(Header::new(protocol::NETWORK_HEADER, String::from(protocol::DEFAULT_NETWORK)))
+                                 .header(Header::new(protocol::VERSION_HEADER, version))
+                                 .dispatch();
+        let peer: Value = serde_json::from_str(&response.body_string().expect("expected a body")).expect("expected a json body");
+        assert_eq!(peer["chain"][1]["header"]["nonce"], mined["proof"], "peers get our schema");
+
+        let mut response = client.post("/transactions/new")
+                                 .header(ContentType::JSON)
+                                 .body(r#"{"sender": "a", "recipient": "b", "amount": 5}"#)
+                                 .dispatch();
+        assert_eq!(response.status(), Status::Created);
+        let added: Value = serde_json::from_str(&response.body_string().expect("expected a body")).expect("expected a json body");
+        assert_eq!(added["message"], "Transaction will be added to Block 3");
+
+        let mut response = client.post("/nodes/register")
+                                 .header(ContentType::JSON)
+                                 .body(r#"{"nodes": ["http://localhost:5000"]}"#)
+                                 .dispatch();
+        assert_eq!(response.status(), Status::Created);
+        let registered: Value = serde_json::from_str(&response.body_string().expect("expected a body")).expect("expected a json body");
+        assert_eq!(registered["total_nodes"].as_array().unwrap().len(), 1);
+        assert_eq!(registered["total_nodes"][0], "localhost:5000");
+    }
+
+    #[test]
+    fn tutorial_resolve() {
+        let client = client_with(|state| state.tutorial_schema = true);
+        let resolved = get_json(&client, "/nodes/resolve");
+        assert_eq!(resolved["message"], "Our chain is authoritative");
+        assert_eq!(resolved["chain"][0]["index"], 1);
+        assert!(resolved.get("new_chain").is_none());
+    }
+
+    #[test]
+    fn register_while_mining() {
+        let state = BlockchainState::new_with(1, Duration::from_millis(50));
+        let _blockchain = state.write().unwrap();
+        assert!(state.write_peers().is_ok(), "peers shouldn't wait on the blockchain WRITE lock");
+    }
+
+    #[test]
+    fn lock_timeout() {
+        let state = BlockchainState::new_with(1, Duration::from_millis(50));
+        let _blockchain = state.write().unwrap();
+        match state.read() {
+            Err(Error::Lock(_)) => {},
+            _ => panic!("READ should give up while WRITE is held")
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use lib::error::{Error, Result};
+use rocket::Outcome::*;
+use rocket::Request;
+use rocket::http::{RawStr, Status};
+use rocket::request::{self, FromRequest};
+use serde::Serialize;
+use serde_json::{self, Value};
+use std::collections::BTreeSet;
+use std::io::Write;
+
+//Responses are compact json, for programs. Someone reading them in curl can add
+//?pretty=true to have them indented, and ?fields=index,previous_hash to leave out
+//what they don't need, like every block's transactions when they only want to
+//see the headers link up. Fields name what's in a block, or in its header, so
+//the same names work whichever schema the chain is answered in.
+
+///
+/// What a block can be cut down to. `header` keeps the whole header, `proof` is
+/// the nonce in the tutorial's schema
+///
+pub const BLOCK_FIELDS: [&'static str; 11] = ["header", "transactions", "index", "previous_hash", "merkle_root", "state_root",
+                                              "timestamp", "difficulty", "nonce", "version", "proof"];
+
+///
+/// How a client asked for blocks to be written, from the query string. Other
+/// parameters are left to the route
+///
+/// pretty: Indent the json
+/// fields: Only these of each block. None has all of them
+///
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Presentation {
+    pub pretty: bool,
+    pub fields: Option<BTreeSet<String>>
+}
+
+impl Presentation {
+    pub fn parse(query: Option<&str>) -> Result<Presentation> {
+        let mut presentation = Presentation::default();
+        for pair in query.unwrap_or("").split('&').filter(|pair| !pair.is_empty()) {
+            let mut pair = pair.splitn(2, '=');
+            let (name, value) = (pair.next().unwrap_or(""), pair.next());
+            let value = match value {
+                Some(value) => RawStr::from_str(value).url_decode().map_err(|e| Error::Validation(format!("Invalid {}. {}", name, e)))?,
+                None => String::new()
+            };
+            match name {
+                "pretty" => presentation.pretty = match value.as_str() {
+                    "" | "true" | "1" => true,
+                    "false" | "0" => false,
+                    other => return Err(Error::Validation(format!("pretty is true or false, not {}", other)))
+                },
+                "fields" => {
+                    let fields: BTreeSet<String> = value.split(',').map(str::trim).filter(|field| !field.is_empty()).map(String::from).collect();
+                    if let Some(unknown) = fields.iter().find(|field| !BLOCK_FIELDS.contains(&field.as_str())) {
+                        return Err(Error::Validation(format!("Unknown field {}, blocks have {}", unknown, BLOCK_FIELDS.join(", "))));
+                    }
+                    presentation.fields = if fields.is_empty() { None } else { Some(fields) };
+                },
+                _ => {}
+            }
+        }
+        Ok(presentation)
+    }
+
+    ///
+    /// `response` as json, with the block, or the array of blocks, at `blocks`
+    /// cut down to `fields`
+    ///
+    pub fn to_string<T>(&self, response: &T, blocks: &str) -> Result<String> where T: Serialize {
+        if self.fields.is_none() {
+            return Ok(if self.pretty { serde_json::to_string_pretty(response)? } else { serde_json::to_string(response)? });
+        }
+        let mut value = serde_json::to_value(response)?;
+        if let Some(at) = value.as_object_mut().and_then(|object| object.get_mut(blocks)) {
+            match *at {
+                Value::Array(ref mut array) => for block in array.iter_mut() {
+                    self.cut(block);
+                },
+                ref mut block => self.cut(block)
+            }
+        }
+        Ok(if self.pretty { serde_json::to_string_pretty(&value)? } else { serde_json::to_string(&value)? })
+    }
+
+    ///
+    /// Write one block, cut down to `fields`
+    ///
+    pub fn write_block<W, T>(&self, out: W, block: &T) -> Result<()> where W: Write, T: Serialize {
+        if self.fields.is_none() {
+            return self.write(out, block);
+        }
+        let mut value = serde_json::to_value(block)?;
+        self.cut(&mut value);
+        self.write(out, &value)
+    }
+
+    fn write<W, T>(&self, out: W, value: &T) -> Result<()> where W: Write, T: Serialize {
+        if self.pretty {
+            serde_json::to_writer_pretty(out, value)?;
+        } else {
+            serde_json::to_writer(out, value)?;
+        }
+        Ok(())
+    }
+
+    //keeps the fields asked for, in the block and its header, dropping a header
+    //that's left empty
+    fn cut(&self, block: &mut Value) {
+        let fields = match self.fields {
+            Some(ref fields) => fields,
+            None => return
+        };
+        if let Value::Object(ref mut block) = *block {
+            let keep = |name: &String| fields.contains(name);
+            if !fields.contains("header") {
+                let empty = match block.get_mut("header") {
+                    Some(&mut Value::Object(ref mut header)) => {
+                        let cut = header.iter().filter(|&(name, _)| keep(name)).map(|(name, value)| (name.clone(), value.clone())).collect();
+                        *header = cut;
+                        header.is_empty()
+                    },
+                    _ => false
+                };
+                if empty {
+                    block.remove("header");
+                }
+            }
+            let cut = block.iter().filter(|&(name, _)| name == "header" || keep(name)).map(|(name, value)| (name.clone(), value.clone())).collect();
+            *block = cut;
+        }
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for Presentation {
+    type Error = Error;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Error> {
+        match Presentation::parse(request.uri().query()) {
+            Ok(presentation) => Success(presentation),
+            Err(e) => Failure((Status::BadRequest, e))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{self, Value};
+    use web::presentation::*;
+
+    fn json(text: &str) -> Value {
+        serde_json::from_str(text).expect("json")
+    }
+
+    #[test]
+    fn parse_and_cut() {
+        let presentation = Presentation::parse(Some("pretty=true&fields=index%2Cprevious_hash&other=1")).expect("parsed");
+        assert!(presentation.pretty);
+        let response = json(r#"{"block": {"header": {"index": 2, "previous_hash": "abc", "nonce": 7}, "transactions": []}, "hash": "def"}"#);
+        let cut = presentation.to_string(&response, "block").expect("written");
+        assert!(cut.contains('\n'), "indented");
+        assert_eq!(json(&cut), json(r#"{"block": {"header": {"index": 2, "previous_hash": "abc"}}, "hash": "def"}"#));
+
+        let tutorial = Presentation::parse(Some("fields=transactions,proof")).expect("parsed");
+        let chain = json(r#"{"chain": [{"index": 1, "proof": 100, "transactions": []}], "length": 1}"#);
+        let cut = tutorial.to_string(&chain, "chain").expect("written");
+        assert_eq!(json(&cut), json(r#"{"chain": [{"proof": 100, "transactions": []}], "length": 1}"#));
+
+        assert_eq!(Presentation::parse(None).expect("parsed"), Presentation::default());
+        assert!(Presentation::parse(Some("fields=index,txns")).is_err(), "a typo");
+        assert!(Presentation::parse(Some("pretty=very")).is_err());
+    }
+}
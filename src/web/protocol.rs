@@ -0,0 +1,64 @@
+use lib::error::Error;
+use lib::protocol::{self, NETWORK_HEADER, VERSION_HEADER, PROTOCOL_VERSION};
+use rocket::{Request, Response, State};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::{Header, Status};
+use rocket::request::{self, FromRequest};
+use rocket::Outcome::*;
+use std::sync::Arc;
+use web::BlockchainState;
+
+//Network and version negotiation. Every response says which network and protocol
+//version this node is on. Peer routes check the same headers on the way in.
+
+/// 
+/// Request guard for routes peers call. Requests with our headers, or with none
+/// (browsers, curl), get through. A peer that sends a different network or
+/// version gets `Error::Protocol`, so routes take `Result<Peer, Error>` and
+/// answer it with a 412
+/// 
+pub struct Peer;
+
+impl<'a, 'r> FromRequest<'a, 'r> for Peer {
+    type Error = Error;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Error> {
+        let network = request.headers().get_one(NETWORK_HEADER);
+        let version = request.headers().get_one(VERSION_HEADER);
+        if network.is_none() && version.is_none() {
+            return Success(Peer);
+        }
+        let state = match request.guard::<State<Arc<BlockchainState>>>() {
+            Success(state) => state,
+            _ => return Failure((Status::InternalServerError, Error::Protocol(String::from("No BlockchainState"))))
+        };
+        match protocol::check(&state.network, network, version) {
+            Ok(()) => Success(Peer),
+            Err(e) => {
+                warn!("Rejected {} from {:?}. {}", request.uri(), request.remote(), e);
+                Failure((Status::PreconditionFailed, e))
+            }
+        }
+    }
+}
+
+/// 
+/// Adds our network and version headers to every response
+/// 
+pub struct ProtocolHeaders;
+
+impl Fairing for ProtocolHeaders {
+    fn info(&self) -> Info {
+        Info {
+            name: "Network and protocol version headers",
+            kind: Kind::Response
+        }
+    }
+
+    fn on_response(&self, request: &Request, response: &mut Response) {
+        if let Success(state) = request.guard::<State<Arc<BlockchainState>>>() {
+            response.set_header(Header::new(NETWORK_HEADER, state.network.clone()));
+            response.set_header(Header::new(VERSION_HEADER, PROTOCOL_VERSION.to_string()));
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use chrono::offset::Utc;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+use web::{self, BlockchainState};
+use web::types::ConsensusStatusResult;
+
+//Consensus doesn't have to wait for someone to GET /nodes/resolve. Given an
+//interval, a thread runs it on its own, a little early or late each time so nodes
+//started together don't all ask each other at once.
+
+///
+/// How far a run may be from the interval, either way, in percent of it
+///
+const JITTER_PERCENT: u64 = 10;
+
+struct Status {
+    last_run: Option<i64>,
+    last_outcome: Option<String>,
+    last_duration: Option<Duration>,
+    next_run: Option<i64>
+}
+
+///
+/// When consensus runs by itself, if it does, and how the last run went.
+/// Manual runs count too
+///
+pub struct Schedule {
+    interval: Option<Duration>,
+    status: Mutex<Status>
+}
+
+impl Schedule {
+    ///
+    /// Only when someone asks for it
+    ///
+    pub fn manual() -> Schedule {
+        Schedule::new(None)
+    }
+
+    pub fn every(interval: Duration) -> Schedule {
+        Schedule::new(Some(interval))
+    }
+
+    fn new(interval: Option<Duration>) -> Schedule {
+        Schedule {
+            interval: interval,
+            status: Mutex::new(Status { last_run: None, last_outcome: None, last_duration: None, next_run: None })
+        }
+    }
+
+    pub fn interval(&self) -> Option<Duration> {
+        self.interval
+    }
+
+    //only holds times and a message, nothing a panic could leave half changed
+    fn lock(&self) -> MutexGuard<Status> {
+        self.status.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    ///
+    /// A run that started at `started` (seconds since the epoch) is over
+    ///
+    pub fn finished(&self, started: i64, duration: Duration, outcome: String) {
+        let mut status = self.lock();
+        status.last_run = Some(started);
+        status.last_duration = Some(duration);
+        status.last_outcome = Some(outcome);
+    }
+
+    ///
+    /// The next run is at `at`
+    ///
+    pub fn planned(&self, at: i64) {
+        self.lock().next_run = Some(at);
+    }
+
+    pub fn status(&self) -> ConsensusStatusResult {
+        let status = self.lock();
+        ConsensusStatusResult {
+            interval_secs: self.interval.map(|interval| interval.as_secs()),
+            last_run: status.last_run,
+            last_outcome: status.last_outcome.clone(),
+            last_duration_ms: status.last_duration.map(|duration| duration.as_secs() * 1000 + duration.subsec_nanos() as u64 / 1_000_000),
+            next_run: status.next_run
+        }
+    }
+}
+
+///
+/// `interval`, give or take up to `JITTER_PERCENT`, picked by `seed`
+///
+pub fn jittered(interval: Duration, seed: u64) -> Duration {
+    let millis = interval.as_secs() * 1000 + interval.subsec_nanos() as u64 / 1_000_000;
+    let spread = millis * JITTER_PERCENT / 100;
+    if spread == 0 {
+        return interval;
+    }
+    Duration::from_millis(millis - spread + seed % (spread * 2 + 1))
+}
+
+///
+/// Run consensus on `state`'s schedule, in the background, for as long as the
+/// process runs. Does nothing without an interval
+///
+pub fn start(state: Arc<BlockchainState>) {
+    let interval = match state.schedule.interval() {
+        Some(interval) => interval,
+        None => return
+    };
+    info!("Running consensus every {:?}, give or take {}%", interval, JITTER_PERCENT);
+    thread::spawn(move || loop {
+        let now = Utc::now();
+        let wait = jittered(interval, now.timestamp_subsec_nanos() as u64);
+        state.schedule.planned(now.timestamp() + wait.as_secs() as i64);
+        thread::sleep(wait);
+        //followers fail here until they lead, which is fine
+        if let Err(e) = web::resolve(&state, |_| Ok(())) {
+            info!("Scheduled consensus didn't run. {}", e.message);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use web::schedule::*;
+
+    #[test]
+    fn jitter() {
+        let interval = Duration::from_secs(60);
+        for seed in 0..1000 {
+            let wait = jittered(interval, seed * 7919);
+            assert!(wait >= Duration::from_secs(54) && wait <= Duration::from_secs(66), "{:?}", wait);
+        }
+        assert!(jittered(interval, 1) != jittered(interval, 2));
+        assert_eq!(jittered(Duration::from_millis(5), 3), Duration::from_millis(5), "too short to jitter");
+    }
+
+    #[test]
+    fn status() {
+        let schedule = Schedule::every(Duration::from_secs(30));
+        assert!(schedule.status().last_run.is_none());
+        schedule.planned(130);
+        schedule.finished(100, Duration::from_millis(1500), String::from("Our chain is authoritative"));
+        let status = schedule.status();
+        assert_eq!(status.interval_secs, Some(30));
+        assert_eq!(status.last_run, Some(100));
+        assert_eq!(status.last_duration_ms, Some(1500));
+        assert_eq!(status.next_run, Some(130));
+        assert!(Schedule::manual().status().interval_secs.is_none());
+    }
+}
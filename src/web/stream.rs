@@ -0,0 +1,129 @@
+use lib::blockchain::Chain;
+use std::cmp;
+use std::io::{self, Read, Write};
+use web::presentation::Presentation;
+
+//GET /chain is the biggest response by far. Serializing it into one String holds the
+//whole chain twice, so it's written out a block at a time instead.
+
+///
+/// The body of GET /chain, `{"chain": [..], "length": n}`, serialized one block
+/// at a time as it's read. Works from a snapshot, so no lock is held while
+/// it's sent
+///
+pub struct ChainStream {
+    chain: Chain,
+    presentation: Presentation,
+    //the position of the next block to write
+    next: usize,
+    started: bool,
+    finished: bool,
+    buffer: Vec<u8>,
+    position: usize
+}
+
+impl ChainStream {
+    ///
+    /// The body with each block written as `presentation` says
+    ///
+    pub fn presented(chain: Chain, presentation: Presentation) -> ChainStream {
+        ChainStream {
+            chain: chain,
+            presentation: presentation,
+            next: 0,
+            started: false,
+            finished: false,
+            buffer: Vec::new(),
+            position: 0
+        }
+    }
+
+    ///
+    /// Serialize the next piece of the body into the buffer
+    ///
+    /// returns: false once everything has been written
+    fn fill(&mut self) -> io::Result<bool> {
+        self.buffer.clear();
+        self.position = 0;
+        if self.finished {
+            return Ok(false);
+        }
+        if !self.started {
+            self.started = true;
+            //blocks start on lines of their own, when it's for reading
+            let start: &[u8] = if self.presentation.pretty { b"{\"chain\": [\n" } else { b"{\"chain\":[" };
+            self.buffer.extend_from_slice(start);
+            return Ok(true);
+        }
+        match self.chain.get(self.next) {
+            Some(block) => {
+                if self.next > 0 {
+                    let separator: &[u8] = if self.presentation.pretty { b",\n" } else { b"," };
+                    self.buffer.extend_from_slice(separator);
+                }
+                self.presentation.write_block(&mut self.buffer, block).map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+                self.next += 1;
+            },
+            None if self.presentation.pretty => {
+                write!(self.buffer, "\n],\n\"length\": {}}}\n", self.chain.len())?;
+                self.finished = true;
+            },
+            None => {
+                write!(self.buffer, "],\"length\":{}}}", self.chain.len())?;
+                self.finished = true;
+            }
+        }
+        Ok(true)
+    }
+}
+
+impl Read for ChainStream {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.buffer.len() {
+            if !self.fill()? {
+                return Ok(0);
+            }
+        }
+        let count = cmp::min(out.len(), self.buffer.len() - self.position);
+        out[..count].copy_from_slice(&self.buffer[self.position..self.position + count]);
+        self.position += count;
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lib::blockchain::Blockchain;
+    use serde_json::{self, Value};
+    use std::io::Read;
+    use web::presentation::Presentation;
+    use web::stream::ChainStream;
+
+    #[test]
+    fn whole_chain() {
+        let mut blockchain = Blockchain::new_with(1);
+        blockchain.mine().expect("mined");
+        blockchain.mine().expect("mined");
+
+        let mut body = String::new();
+        ChainStream::presented(blockchain.snapshot(), Presentation::default()).read_to_string(&mut body).expect("streamed");
+        let streamed: Value = serde_json::from_str(&body).expect("valid json");
+        assert_eq!(streamed["length"], 3);
+        assert_eq!(streamed["chain"], serde_json::to_value(blockchain.chain()).expect("serialized"));
+    }
+
+    #[test]
+    fn small_reads() {
+        let blockchain = Blockchain::new_with(1);
+        let mut whole = String::new();
+        ChainStream::presented(blockchain.snapshot(), Presentation::default()).read_to_string(&mut whole).expect("streamed");
+
+        let mut stream = ChainStream::presented(blockchain.snapshot(), Presentation::default());
+        let mut body = Vec::new();
+        let mut byte = [0u8; 1];
+        while stream.read(&mut byte).expect("read") == 1 {
+            body.push(byte[0]);
+        }
+        assert_eq!(String::from_utf8(body).expect("utf8"), whole);
+    }
+}
@@ -0,0 +1,101 @@
+use chrono::offset::Utc;
+use lib::consensus::Candidate;
+use lib::error::Error;
+use lib::journal::Event;
+use lib::mempool::{self, Digest};
+use lib::rejections::Kind;
+use lib::transaction::Transaction;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use url::Url;
+use web::api;
+use web::schedule::jittered;
+use web::BlockchainState;
+
+//Given an interval, a thread reconciles our mempool with a sample of peers: it
+//compares digests with each and pulls in what we're missing, see lib::mempool.
+//Nothing is pushed, each node fetches for itself.
+
+///
+/// Reconcile with `state.peers_per_round` peers, one at a time
+///
+/// returns: How many transactions were added
+pub fn sync(state: &BlockchainState) -> Result<usize, Error> {
+    state.lead()?;
+    let now = Utc::now();
+    let seed = (now.timestamp() as u64) ^ (now.timestamp_subsec_nanos() as u64) << 32;
+    let candidates = state.candidates(seed)?;
+    let mut ours = mempool::digest(&*state.read()?);
+    let mut added = 0;
+    for candidate in candidates {
+        //no lock is held while fetching, like consensus
+        let (fetched, received) = fetch_missing(state, &candidate, &ours);
+        if let Ok(url) = Url::parse(&candidate.node) {
+            let mut peers = state.write_peers()?;
+            for bytes in received {
+                peers.exchanged(&url, 0, bytes);
+            }
+            //answered, with something that isn't what we asked for
+            match fetched {
+                Err(Error::Serialization(_)) | Err(Error::Protocol(_)) | Err(Error::Limit(_)) => {
+                    peers.misbehaved(&url, now.timestamp());
+                },
+                _ => {}
+            }
+        }
+        let transactions = match fetched {
+            Ok(ref transactions) if transactions.is_empty() => continue,
+            Ok(transactions) => transactions,
+            Err(e) => {
+                debug!("Couldn't reconcile mempools with {}. {}", candidate.node, e);
+                continue;
+            }
+        };
+        let mut blockchain = state.write()?;
+        for txn in mempool::acceptable(&blockchain, transactions) {
+            match state.admit(&txn).and_then(|_| api::add_transaction(&txn, state.max_pending_per_sender, &mut blockchain)) {
+                Ok(_) => {
+                    state.record(|| Event::Transaction(txn.clone()))?;
+                    added += 1;
+                },
+                Err(e) => state.reject(Kind::Transaction, Some(blockchain.transaction_id(&txn)), e.to_string(), Some(candidate.node.clone()))
+            }
+        }
+        ours = mempool::digest(&blockchain);
+    }
+    Ok(added)
+}
+
+//what `candidate` has pending that we're missing, and the bytes each request
+//to it took
+fn fetch_missing(state: &BlockchainState, candidate: &Candidate, ours: &Digest) -> (Result<Vec<Transaction>, Error>, Vec<u64>) {
+    let (theirs, digest_bytes) = match mempool::get_digest(candidate, state.timeout, &state.network) {
+        Ok(got) => got,
+        Err(e) => return (Err(e), vec![0])
+    };
+    let missing = mempool::missing(ours, &theirs);
+    if missing.is_empty() {
+        return (Ok(Vec::new()), vec![digest_bytes]);
+    }
+    match mempool::get_transactions(candidate, &missing, state.timeout, &state.network) {
+        Ok((transactions, bytes)) => (Ok(transactions), vec![digest_bytes, bytes]),
+        Err(e) => (Err(e), vec![digest_bytes, 0])
+    }
+}
+
+///
+/// Reconcile every `interval`, give or take, for as long as the process runs
+///
+pub fn start(state: Arc<BlockchainState>, interval: Duration) {
+    info!("Reconciling mempools every {:?}", interval);
+    thread::spawn(move || loop {
+        thread::sleep(jittered(interval, Utc::now().timestamp_subsec_nanos() as u64));
+        match sync(&state) {
+            Ok(0) => {},
+            Ok(added) => info!("Added {} transactions from peers' mempools", added),
+            //followers fail here until they lead, which is fine
+            Err(e) => debug!("Mempool reconciliation didn't run. {}", e)
+        }
+    });
+}
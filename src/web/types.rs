@@ -1,5 +1,13 @@
 use lib::blockchain::*;
 use lib::transaction::*;
+use lib::consensus::DiffBlock;
+use lib::forks::DeploymentStatus;
+use lib::merkle::ProofStep;
+use lib::peers::PeerStats;
+use lib::rounds::{PeerReport, Round};
+use lib::rejections::Rejection;
+use lib::validation::Check;
+use web::converters;
 
 use std::collections::BTreeSet;
 
@@ -11,27 +19,583 @@ pub struct NodeList {
     pub nodes: Vec<String>
 }
 
+///
+/// Strongly typed miner address for /config/miner-address
+/// 
+#[derive(Debug, Deserialize)]
+pub struct MinerAddress {
+    pub miner_address: Address
+}
+
+///
+/// Strongly typed difficulty for /admin/difficulty
+/// 
+#[derive(Debug, Deserialize)]
+pub struct Difficulty {
+    pub difficulty: u64
+}
+
+///
+/// Strongly typed response for previewing the next block
+/// 
+/// transactions: Every pending transaction that hasn't expired, and the reward.
+///               Transactions pay no fees, so none are left out for paying too
+///               little, and the reward is the same for every block
+/// expired: Pending transactions left out because they've expired
+/// size: The block's size in bytes, encoded
+/// 
+#[derive(Debug, Serialize)]
+pub struct MinePreview {
+    pub index: usize,
+    pub previous_hash: String,
+    pub difficulty: u64,
+    pub version: u64,
+    pub transactions: BTreeSet<Transaction>,
+    pub reward: Option<Transaction>,
+    pub expired: usize,
+    pub merkle_root: String,
+    pub state_root: String,
+    pub size: usize
+}
+
 ///
 /// Strongly typed response for mining
 /// 
+/// size: The block's size in bytes, encoded
+/// 
 #[derive(Debug,Serialize)]
 pub struct MineResult {
     pub message: String,
     pub index: usize,
     pub transactions: BTreeSet<Transaction>,
-    pub proof: u64,
-    pub previous_hash: String
+    pub reward: Option<Transaction>,
+    pub nonce: u64,
+    pub previous_hash: String,
+    pub merkle_root: String,
+    pub state_root: String,
+    pub size: usize
+}
+
+///
+/// Strongly typed response for a mining job's status
+/// 
+/// state: running, done or failed
+/// started: When the job started, in seconds since the epoch
+/// block: Index of the block it mined, once done
+/// error: Why it failed
+/// 
+#[derive(Debug, Serialize)]
+pub struct JobResult {
+    pub id: u64,
+    pub state: String,
+    pub started: i64,
+    pub elapsed_ms: u64,
+    pub block: Option<usize>,
+    pub error: Option<String>
+}
+
+///
+/// Strongly typed response for dry-run validating a transaction
+/// 
+/// valid: Whether /transaction/new would add it, i.e. every one of `checks` passed
+/// advice: Checks a node doesn't refuse transactions for, like the balance
+/// 
+#[derive(Serialize)]
+pub struct ValidationResponse {
+    pub valid: bool,
+    pub checks: Vec<Check>,
+    pub advice: Vec<Check>
+}
+
+///
+/// Query for /chain/next
+/// 
+/// after: Height the client already has
+/// timeout: Seconds to wait for a new block (optional)
+/// 
+#[derive(Debug, FromForm)]
+pub struct NextBlockQuery {
+    pub after: usize,
+    pub timeout: Option<u64>
 }
 
 ///
-/// Strongly typed response for requesting the blockchain
+/// Strongly typed response for waiting on the next block. `block` is None if
+/// nothing was mined before the timeout
 /// 
 #[derive(Serialize)]
-pub struct ChainResult<'a> {
-    pub chain: &'a BTreeSet<Block>,
+pub struct NextBlockResult<'a> {
+    pub block: Option<&'a Block>,
     pub length: usize
 }
 
+///
+/// Query for endpoints that can look back in time
+/// 
+/// at_height: Answer as of this block. The tip if not given
+/// 
+#[derive(Debug, FromForm)]
+pub struct AtHeightQuery {
+    pub at_height: Option<usize>
+}
+
+///
+/// Query for polling payments to an address
+/// 
+/// since_height: Only payments in blocks above this one. All of them if not given
+/// 
+#[derive(Debug, FromForm)]
+pub struct IncomingQuery {
+    pub since_height: Option<usize>
+}
+
+///
+/// Strongly typed response for polling payments to an address
+/// 
+/// height: The tip, to pass as since_height next time
+/// 
+#[derive(Serialize)]
+pub struct IncomingResult<'a> {
+    pub address: Address,
+    pub since_height: usize,
+    pub height: usize,
+    pub payments: Vec<PaymentResult<'a>>
+}
+
+///
+/// A payment to an address, and the block it's in
+/// 
+#[derive(Serialize)]
+pub struct PaymentResult<'a> {
+    pub id: String,
+    pub transaction: &'a Transaction,
+    pub block: usize
+}
+
+///
+/// Query for /payment-request
+/// 
+/// to: The address to pay
+/// amount: How much to ask for
+/// 
+#[derive(Debug, FromForm)]
+pub struct PaymentRequestQuery {
+    pub to: Result<converters::Address, String>,
+    pub amount: Amount
+}
+
+///
+/// Strongly typed response for a payment request
+/// 
+/// uri: learnnet:<to>?amount=<amount>, what a wallet would scan
+/// 
+#[derive(Serialize)]
+pub struct PaymentRequestResult {
+    pub uri: String,
+    pub to: Address,
+    pub amount: Amount
+}
+
+///
+/// Strongly typed response for requesting a balance
+/// 
+#[derive(Serialize)]
+pub struct BalanceResult {
+    pub address: Address,
+    pub balance: Amount,
+    pub height: usize
+}
+
+///
+/// Strongly typed response for requesting chain statistics
+/// 
+/// transactions: Confirmed transactions, not counting rewards
+/// supply: Coins in existence, i.e. the sum of all balances, capped at the
+/// largest amount rather than overflowing
+/// difficulty: The difficulty the block at `height` was mined at
+/// bytes: The size of the blocks up to `height`, encoded
+/// 
+#[derive(Serialize)]
+pub struct StatsResult {
+    pub height: usize,
+    pub tip: String,
+    pub timestamp: i64,
+    pub difficulty: u64,
+    pub transactions: usize,
+    pub rewards: usize,
+    pub addresses: usize,
+    pub supply: Amount,
+    pub bytes: usize
+}
+
+///
+/// Strongly typed response for GET /stats/mining
+/// 
+/// blocks: How many of the latest mined blocks the averages are over
+/// block_secs: The average time between them. None until two blocks are mined
+/// hash_rate: Hashes a second it took to mine them, going by their difficulty.
+///            None if they were all mined within a second
+/// miners: Blocks each reward address mined, over the whole chain, most first
+/// 
+#[derive(Serialize)]
+pub struct MiningStatsResult {
+    pub height: usize,
+    pub blocks: usize,
+    pub block_secs: Option<f64>,
+    pub hash_rate: Option<f64>,
+    pub miners: Vec<MinerStats>
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct MinerStats {
+    pub address: Address,
+    pub blocks: usize
+}
+
+///
+/// Strongly typed response for GET /admin/overview, everything the dashboard shows
+/// 
+/// pending: How many transactions are waiting. `transactions` lists the first few
+/// mining: The mining job in progress, if there is one
+/// reorgs: The latest consensus rounds that replaced our chain, newest first
+/// 
+#[derive(Serialize)]
+pub struct AdminOverview<'a> {
+    pub height: usize,
+    pub tip: String,
+    pub difficulty: u64,
+    pub miner_address: &'a str,
+    pub pending: usize,
+    pub transactions: Vec<&'a Transaction>,
+    pub peers: Vec<PeerOverview>,
+    pub auto_mine: AutoMineConfig,
+    pub mining: Option<JobResult>,
+    pub consensus: ConsensusStatusResult,
+    pub reorgs: Vec<&'a Round>
+}
+
+///
+/// A peer, as the dashboard lists it
+/// 
+#[derive(Serialize)]
+pub struct PeerOverview {
+    pub node: String,
+    pub good: bool,
+    pub height: Option<usize>,
+    pub latency_ms: Option<u64>,
+    pub failures: u32,
+    pub last_seen: Option<i64>
+}
+
+///
+/// Strongly typed response for GET /forks
+/// 
+/// version: The version of the rules the next block follows
+/// 
+#[derive(Serialize)]
+pub struct ForksResult {
+    pub height: usize,
+    pub version: u64,
+    pub deployments: Vec<DeploymentStatus>
+}
+
+///
+/// Query for /export
+/// 
+/// format: jsonl or csv
+/// 
+#[derive(Debug, FromForm)]
+pub struct ExportQuery {
+    pub format: String
+}
+
+///
+/// Strongly typed response for requesting the tip of the chain
+/// 
+/// work: The hashes the whole chain took to mine, on average
+/// 
+#[derive(Serialize)]
+pub struct HeadResult<'a> {
+    pub header: &'a BlockHeader,
+    pub hash: String,
+    pub length: usize,
+    pub work: u64
+}
+
+///
+/// Strongly typed response for looking up a block by its hash
+/// 
+/// size: In bytes, encoded
+/// 
+#[derive(Serialize)]
+pub struct BlockResult<'a> {
+    pub block: &'a Block,
+    pub hash: String,
+    pub size: usize
+}
+
+///
+/// Strongly typed response for looking up a transaction by its id
+/// 
+/// block: Index of the block it's in. None while it's pending
+/// size: In bytes, encoded
+/// 
+#[derive(Serialize)]
+pub struct TransactionResult<'a> {
+    pub id: String,
+    pub transaction: &'a Transaction,
+    pub block: Option<usize>,
+    pub pending: bool,
+    pub size: usize
+}
+
+///
+/// Strongly typed response for a transaction's receipt, everything needed to
+/// check it's in a block without asking a node: hash the transaction with its
+/// proof up to the header's merkle root, and check the header meets its difficulty
+/// 
+/// hash: Of the header
+/// confirmations: The blocks from its block up to our tip, its block included
+/// 
+#[derive(Serialize)]
+pub struct ReceiptResult<'a> {
+    pub id: String,
+    pub transaction: &'a Transaction,
+    pub header: &'a BlockHeader,
+    pub hash: String,
+    pub proof: Vec<ProofStep>,
+    pub confirmations: usize
+}
+
+///
+/// Strongly typed response for the proof of a balance. Check each receipt, then
+/// add up what was received and take away what was sent (rewards only credit):
+/// that's the balance. A node could still leave receipts out, only the whole
+/// state hashes to a header's state root; a state tree would make a path to it
+/// enough
+/// 
+/// height: The block the balance is at
+/// hash: That block's hash, to find it among the headers the client has
+/// receipts: Of every transaction to or from the address up to `height`,
+///           lowest first
+/// 
+#[derive(Serialize)]
+pub struct BalanceProofResult<'a> {
+    pub address: Address,
+    pub balance: Amount,
+    pub height: usize,
+    pub hash: String,
+    pub receipts: Vec<ReceiptResult<'a>>
+}
+
+///
+/// Query for /chain/diff
+/// 
+/// peer: Url of the node to compare with
+/// 
+#[derive(Debug, FromForm)]
+pub struct DiffQuery {
+    pub peer: String
+}
+
+///
+/// Strongly typed body for POST /admin/import
+/// 
+/// path: The file of blocks to import, on the node's machine
+/// 
+#[derive(Debug, Deserialize)]
+pub struct ImportRequest {
+    pub path: String
+}
+
+///
+/// When a node mines by itself, for PUT and GET /admin/auto-mine. It's off
+/// with neither
+/// 
+/// pending: Mine once this many transactions are waiting
+/// interval_secs: Mine once waiting transactions are this long after the last block
+/// 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AutoMineConfig {
+    #[serde(default)]
+    pub pending: Option<usize>,
+    #[serde(default)]
+    pub interval_secs: Option<u64>
+}
+
+///
+/// Query for /mempool
+/// 
+/// prefixes: Starts of transaction ids from a digest, comma separated
+/// 
+#[derive(Debug, FromForm)]
+pub struct PendingQuery {
+    pub prefixes: String
+}
+
+///
+/// Strongly typed response for /mempool
+/// 
+#[derive(Serialize)]
+pub struct PendingResult<'a> {
+    pub transactions: Vec<&'a Transaction>
+}
+
+///
+/// Strongly typed request for /faucet
+/// 
+#[derive(Debug, Deserialize)]
+pub struct FaucetRequest {
+    pub address: Address,
+    pub amount: Amount
+}
+
+///
+/// Strongly typed response for /faucet
+/// 
+/// block: The block the coins will go in
+/// 
+#[derive(Serialize)]
+pub struct FaucetResult {
+    pub message: String,
+    pub address: Address,
+    pub amount: Amount,
+    pub block: usize
+}
+
+///
+/// Strongly typed peer for /admin/consensus/adopt
+/// 
+#[derive(Debug, Deserialize)]
+pub struct AdoptRequest {
+    pub peer: String
+}
+
+///
+/// Strongly typed response for adopting a peer's chain
+/// 
+/// previous_height, height: The length of our chain before and after
+/// 
+#[derive(Serialize)]
+pub struct AdoptResult {
+    pub message: String,
+    pub peer: String,
+    pub previous_height: usize,
+    pub height: usize
+}
+
+///
+/// Strongly typed response for comparing our chain with a peer's
+/// 
+/// height, peer_height: The length of each chain
+/// ours, theirs: The blocks above `common_height` on each side
+/// 
+#[derive(Serialize)]
+pub struct DiffResult {
+    pub peer: String,
+    pub height: usize,
+    pub peer_height: usize,
+    pub common_height: usize,
+    pub ours: Vec<DiffBlock>,
+    pub theirs: Vec<DiffBlock>
+}
+
+///
+/// Strongly typed response for GET /consensus/status
+/// 
+/// interval_secs: How often consensus runs by itself. None if it only runs when asked
+/// last_run, next_run: Seconds since the epoch
+/// last_outcome: Whether our chain was replaced, or why the run failed
+/// 
+#[derive(Debug, Serialize)]
+pub struct ConsensusStatusResult {
+    pub interval_secs: Option<u64>,
+    pub last_run: Option<i64>,
+    pub last_outcome: Option<String>,
+    pub last_duration_ms: Option<u64>,
+    pub next_run: Option<i64>
+}
+
+///
+/// Query for GET /consensus/history
+/// 
+/// last: How many rounds to list
+/// 
+#[derive(Debug, FromForm)]
+pub struct HistoryQuery {
+    pub last: Option<usize>
+}
+
+///
+/// Query for GET /admin/rejections
+/// 
+/// last: How many rejections to list
+/// hash: Only those of the transaction with this id
+/// source: Only those from this client address or peer url
+/// 
+#[derive(Debug, FromForm)]
+pub struct RejectionsQuery {
+    pub last: Option<usize>,
+    pub hash: Option<String>,
+    pub source: Option<String>
+}
+
+///
+/// Strongly typed response for GET /admin/rejections, newest first
+/// 
+#[derive(Serialize)]
+pub struct RejectionsResult<'a> {
+    pub rejections: Vec<&'a Rejection>
+}
+
+///
+/// Strongly typed response for GET /consensus/history, newest round first
+/// 
+#[derive(Serialize)]
+pub struct ConsensusHistoryResult<'a> {
+    pub rounds: Vec<&'a Round>
+}
+
+///
+/// Strongly typed response for verifying the chain
+/// 
+/// memory: The chain being served
+/// store: The chain on disk. None without a data dir
+/// 
+#[derive(Serialize)]
+pub struct VerifyResult {
+    pub valid: bool,
+    pub memory: Verification,
+    pub store: Option<Verification>
+}
+
+///
+/// Strongly typed response for requesting the block headers
+/// 
+#[derive(Serialize)]
+pub struct HeadersResult<'a> {
+    pub headers: Vec<&'a BlockHeader>,
+    pub length: usize
+}
+
+///
+/// One route's request statistics, for /admin/requests
+/// 
+/// client_errors: 4xx responses
+/// server_errors: 5xx responses
+/// p50_ms, p95_ms: Latency percentiles over the route's recent requests
+/// 
+#[derive(Debug, Serialize)]
+pub struct RouteStatsResult {
+    pub route: String,
+    pub requests: u64,
+    pub client_errors: u64,
+    pub server_errors: u64,
+    pub error_rate: f64,
+    pub p50_ms: f64,
+    pub p95_ms: f64
+}
+
 ///
 /// Strongly typed response for registering a node
 /// 
@@ -41,9 +605,59 @@ pub struct RegisterNodeResponse {
     pub total_nodes: usize
 }
 
+///
+/// Strongly typed response for GET /nodes/<id>/stats: what we know about a peer
+/// and how it's behaved
+/// 
+/// good: It hasn't failed `MAX_FAILURES` rounds in a row
+/// banned: It's banned now, see `stats.banned_until`
+/// 
+#[derive(Serialize)]
+pub struct NodeStatsResponse<'a> {
+    pub node: &'a str,
+    pub last_seen: Option<i64>,
+    pub failures: u32,
+    pub good: bool,
+    pub latency_ms: Option<u64>,
+    pub height: Option<usize>,
+    pub banned: bool,
+    pub stats: &'a PeerStats
+}
+
+///
+/// Strongly typed response for changing the miner address
+/// 
+#[derive(Serialize)]
+pub struct MinerAddressResponse {
+    pub message: String,
+    pub miner_address: Address
+}
+
+///
+/// Strongly typed response for changing the difficulty
+/// 
+#[derive(Serialize)]
+pub struct DifficultyResponse {
+    pub message: String,
+    pub difficulty: u64
+}
+
+///
+/// adopted: Position of the chain we were sent that replaced ours. Not sent,
+///          the peer's report says so
+/// invalid: Positions of the chains we were sent that were longer but invalid.
+///          Not sent, only recorded
+/// peers: How asking each node went, whether or not it sent a chain: which
+///        supplied the chain adopted, or why each wasn't
+/// 
 #[derive(Serialize)]
 pub struct ConsensusReponse<'a> {
     pub message: String,
     pub chain: Option<&'a Chain>,
-    pub new_chain: Option<&'a Chain>
+    pub new_chain: Option<&'a Chain>,
+    #[serde(skip_serializing)]
+    pub adopted: Option<usize>,
+    #[serde(skip_serializing)]
+    pub invalid: Vec<usize>,
+    pub peers: Vec<PeerReport>
 }
\ No newline at end of file